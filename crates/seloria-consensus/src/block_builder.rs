@@ -1,6 +1,8 @@
-use seloria_core::{merkle_root, Block, BlockHeader, Hash, PublicKey, Transaction};
+use seloria_core::{
+    compute_receipts_root, merkle_root, Block, BlockHeader, Hash, PublicKey, Transaction,
+};
 use seloria_mempool::Mempool;
-use seloria_state::{ChainState, Storage};
+use seloria_state::{BlockStore, ChainState, Storage};
 use seloria_vm::{ExecutionResult, Executor};
 use tracing::{debug, info};
 
@@ -13,6 +15,9 @@ pub struct BlockBuilderConfig {
     pub max_transactions: usize,
     /// Chain ID
     pub chain_id: u64,
+    /// Include transactions that pass validation but fail execution. They are
+    /// charged their fee and recorded with a failed receipt.
+    pub include_failed_txs: bool,
 }
 
 impl Default for BlockBuilderConfig {
@@ -20,6 +25,7 @@ impl Default for BlockBuilderConfig {
         BlockBuilderConfig {
             max_transactions: 1000,
             chain_id: 1,
+            include_failed_txs: true,
         }
     }
 }
@@ -35,7 +41,7 @@ impl BlockBuilder {
     }
 
     /// Build a new block from mempool transactions
-    pub async fn build_block<S: Storage + Clone>(
+    pub async fn build_block<S: Storage>(
         &self,
         state: &ChainState<S>,
         mempool: &Mempool,
@@ -58,18 +64,25 @@ impl BlockBuilder {
         let pending_txs = mempool.get_transactions(self.config.max_transactions).await;
         debug!("Got {} transactions from mempool", pending_txs.len());
 
-        // Execute transactions on a working copy and collect successful ones
-        let mut working_state = state.clone();
+        // Execute transactions on an overlay and collect the included ones
+        let mut working_state = state.overlay();
         let executor = Executor::new(timestamp, next_height);
-        let mut successful_txs = Vec::new();
+        let mut included_txs = Vec::new();
+        let mut receipts = Vec::new();
 
         for tx in pending_txs {
+            // Excluded transactions must leave no trace (not even the fee),
+            // otherwise the state root would not match re-execution
+            let checkpoint = working_state.checkpoint();
             let result = executor.execute_transaction(&tx, &mut working_state);
-            if result.success {
-                successful_txs.push(tx);
+            if result.success || (result.valid && self.config.include_failed_txs) {
+                working_state.commit_checkpoint(checkpoint);
+                receipts.push(result.to_receipt());
+                included_txs.push(tx);
             } else {
+                working_state.revert_to_checkpoint(checkpoint);
                 debug!(
-                    "Transaction {} failed: {:?}",
+                    "Transaction {} excluded: {:?}",
                     result.tx_hash,
                     result.error
                 );
@@ -77,30 +90,34 @@ impl BlockBuilder {
         }
 
         info!(
-            "Executed {} successful transactions for block {}",
-            successful_txs.len(),
+            "Included {} transactions in block {}",
+            included_txs.len(),
             next_height
         );
 
         // Compute merkle roots
         let tx_hashes: Result<Vec<Hash>, _> =
-            successful_txs.iter().map(|tx| tx.hash()).collect();
+            included_txs.iter().map(|tx| tx.hash()).collect();
         let tx_root = merkle_root(&tx_hashes?);
+        let receipts_root = compute_receipts_root(&receipts)?;
 
+        working_state.end_block(next_height);
         let state_root = working_state.compute_state_root()?;
 
         // Create block header
         let header = BlockHeader {
             chain_id: self.config.chain_id,
             height: next_height,
+            view: 0,
             prev_hash,
             timestamp,
             tx_root,
             state_root,
+            receipts_root,
             proposer_pubkey: proposer,
         };
 
-        let block = Block::new(header, successful_txs);
+        let block = Block::new(header, included_txs);
 
         debug!("Built block {} with hash {}", next_height, block.hash()?);
 
@@ -152,37 +169,21 @@ impl BlockBuilder {
         Ok(())
     }
 
-    /// Re-execute block transactions and verify state root
-    pub fn verify_execution<S: Storage + Clone>(
+    /// Re-execute block transactions and verify state and receipts roots
+    pub fn verify_execution<S: Storage>(
         &self,
         block: &Block,
         state: &ChainState<S>,
     ) -> Result<(), ConsensusError> {
-        let executor = Executor::new(block.header.timestamp, block.header.height);
-        let mut state_copy = state.clone();
-
-        // Execute all transactions
-        for tx in &block.txs {
-            let result = executor.execute_transaction(tx, &mut state_copy);
-            if !result.success {
-                return Err(ConsensusError::ExecutionFailed(
-                    result.error.unwrap_or_else(|| "Unknown error".to_string()),
-                ));
-            }
-        }
-
-        // Verify state root
-        let computed_root = state_copy.compute_state_root()?;
-        if computed_root != block.header.state_root {
-            return Err(ConsensusError::InvalidStateRoot);
-        }
-
+        // The overlay is dropped, discarding its writes
+        let mut overlay = state.overlay();
+        self.execute_block(block, &mut overlay)?;
         Ok(())
     }
 
     /// Apply a block by re-executing transactions and updating state.
     /// Returns execution results for event emission.
-    pub fn apply_block<S: Storage + Clone>(
+    pub fn apply_block<S: Storage>(
         &self,
         state: &mut ChainState<S>,
         block: &Block,
@@ -190,27 +191,67 @@ impl BlockBuilder {
         // Validate header and tx root against current state
         self.validate_block(block, state)?;
 
-        let mut working_state = state.clone();
+        let mut overlay = state.overlay();
+        let results = self.execute_block(block, &mut overlay)?;
+
+        let receipts = results.iter().map(|r| r.to_receipt()).collect();
+        overlay.apply_block(block.clone(), receipts)?;
+        let diff = overlay.into_diff();
+        state.apply_diff(diff);
+
+        Ok(results)
+    }
+
+    /// Rebuild state by applying and persisting the blocks in `blocks`
+    /// above the current height, in order. Returns the number of blocks
+    /// applied.
+    pub fn replay_blocks<S: Storage>(
+        &self,
+        state: &mut ChainState<S>,
+        blocks: &dyn BlockStore,
+    ) -> Result<u64, ConsensusError> {
+        let start = state.current_height();
+        while let Some(stored) = blocks.get_block(state.current_height() + 1)? {
+            self.apply_block(state, &stored.block)?;
+            state.persist_state()?;
+        }
+
+        let replayed = state.current_height() - start;
+        info!("Replayed {} blocks up to height {}", replayed, state.current_height());
+        Ok(replayed)
+    }
+
+    /// Execute block transactions on `state` and check the header roots.
+    /// Every transaction must be valid; valid transactions that fail are
+    /// accepted since their receipts are committed by the header.
+    fn execute_block<S: Storage>(
+        &self,
+        block: &Block,
+        state: &mut ChainState<S>,
+    ) -> Result<Vec<ExecutionResult>, ConsensusError> {
         let executor = Executor::new(block.header.timestamp, block.header.height);
-        let mut results = Vec::new();
+        let mut results = Vec::with_capacity(block.txs.len());
 
         for tx in &block.txs {
-            let result = executor.execute_transaction(tx, &mut working_state);
-            if !result.success {
+            let result = executor.execute_transaction(tx, state);
+            if !result.valid {
                 return Err(ConsensusError::ExecutionFailed(
                     result.error.unwrap_or_else(|| "Unknown error".to_string()),
                 ));
             }
             results.push(result);
         }
+        state.end_block(block.header.height);
 
-        let computed_root = working_state.compute_state_root()?;
+        let computed_root = state.compute_state_root()?;
         if computed_root != block.header.state_root {
             return Err(ConsensusError::InvalidStateRoot);
         }
 
-        working_state.apply_block(block.clone())?;
-        *state = working_state;
+        let receipts: Vec<_> = results.iter().map(|r| r.to_receipt()).collect();
+        if compute_receipts_root(&receipts)? != block.header.receipts_root {
+            return Err(ConsensusError::InvalidReceiptsRoot);
+        }
 
         Ok(results)
     }
@@ -221,10 +262,10 @@ mod tests {
     use super::*;
     use seloria_core::{
         AgentCertificate, Capability, GenesisConfig, KeyPair, Op, SignedAgentCertificate,
-        hash_blake3,
+        hash_blake3, NATIVE_TOKEN_ID,
     };
     use seloria_mempool::MempoolConfig;
-    use seloria_state::MemoryStorage;
+    use seloria_state::{MemoryBlockStore, MemoryStorage, StoredBlock};
 
     async fn setup_test_env() -> (
         ChainState<MemoryStorage>,
@@ -244,6 +285,7 @@ mod tests {
             initial_balances: vec![(agent.public, 1_000_000)],
             trusted_issuers: vec![issuer.public],
             validators: vec![proposer.public],
+            validator_stake: 0,
         };
         state.init_genesis(&config).unwrap();
 
@@ -256,7 +298,7 @@ mod tests {
             vec![Capability::TxSubmit],
             Hash::ZERO,
         );
-        let signed_cert = SignedAgentCertificate::new(cert, &issuer.secret).unwrap();
+        let signed_cert = SignedAgentCertificate::new(cert, 1, &issuer.secret).unwrap();
         state.register_agent(signed_cert);
 
         let mempool = Mempool::new(MempoolConfig::default());
@@ -288,6 +330,7 @@ mod tests {
         // Add transaction to mempool
         let receiver = KeyPair::generate();
         let tx = Transaction::new_signed(
+            1,
             agent.public,
             1,
             100,
@@ -310,6 +353,124 @@ mod tests {
         assert_eq!(block.txs.len(), 1);
     }
 
+    fn failing_tx(agent: &KeyPair) -> Transaction {
+        // Passes validation but fails because pool tokens must differ
+        Transaction::new_signed(
+            1,
+            agent.public,
+            1,
+            100,
+            vec![Op::PoolCreate {
+                token_a: NATIVE_TOKEN_ID,
+                token_b: NATIVE_TOKEN_ID,
+                amount_a: 10,
+                amount_b: 10,
+            }],
+            &agent.secret,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_failed_transaction_included_with_receipt() {
+        let (mut state, mempool, agent, proposer, builder) = setup_test_env().await;
+        mempool.add(failing_tx(&agent)).await.unwrap();
+
+        let block = builder
+            .build_block(&state, &mempool, proposer.public, 1000)
+            .await
+            .unwrap();
+        assert_eq!(block.txs.len(), 1);
+        assert_ne!(block.header.receipts_root, Hash::ZERO);
+
+        let pre_state = state.clone();
+        let results = builder.apply_block(&mut state, &block).unwrap();
+        assert!(!results[0].success);
+        assert_eq!(results[0].fee_used, 100);
+        assert_eq!(state.get_balance(&agent.public), 1_000_000 - 100);
+
+        let record = state.get_receipt(&block.txs[0].hash().unwrap()).unwrap();
+        assert_eq!(record.block_height, 1);
+        assert_eq!(record.index, 0);
+        assert!(!record.receipt.success);
+
+        // A tampered receipts root is rejected
+        let mut tampered = block.clone();
+        tampered.header.receipts_root = Hash::ZERO;
+        assert!(matches!(
+            builder.verify_execution(&tampered, &pre_state),
+            Err(ConsensusError::InvalidReceiptsRoot)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_excluded_transaction_leaves_no_trace() {
+        let (mut state, mempool, agent, proposer, _) = setup_test_env().await;
+        let builder = BlockBuilder::new(BlockBuilderConfig {
+            chain_id: 1,
+            include_failed_txs: false,
+            ..Default::default()
+        });
+        mempool.add(failing_tx(&agent)).await.unwrap();
+
+        let block = builder
+            .build_block(&state, &mempool, proposer.public, 1000)
+            .await
+            .unwrap();
+
+        assert!(block.txs.is_empty());
+        assert_eq!(block.header.state_root, state.compute_state_root().unwrap());
+        builder.apply_block(&mut state, &block).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_blocks_rebuilds_state() {
+        let (mut state, mempool, agent, proposer, builder) = setup_test_env().await;
+        let mut replayed = state.clone();
+        replayed.set_block_store(MemoryBlockStore::new());
+
+        let receiver = KeyPair::generate();
+        let tx = Transaction::new_signed(
+            1,
+            agent.public,
+            1,
+            100,
+            vec![Op::Transfer {
+                to: receiver.public,
+                amount: 1000,
+            }],
+            &agent.secret,
+        )
+        .unwrap();
+        mempool.add(tx).await.unwrap();
+
+        let mut blocks = MemoryBlockStore::new();
+        for timestamp in [1000, 2000] {
+            let block = builder
+                .build_block(&state, &mempool, proposer.public, timestamp)
+                .await
+                .unwrap();
+            let results = builder.apply_block(&mut state, &block).unwrap();
+            state.persist_state().unwrap();
+            let tx_hashes: Vec<Hash> = block.txs.iter().map(|tx| tx.hash().unwrap()).collect();
+            mempool.remove_committed(&tx_hashes).await;
+            blocks
+                .put_block(&StoredBlock {
+                    block,
+                    receipts: results.iter().map(|r| r.to_receipt()).collect(),
+                })
+                .unwrap();
+        }
+
+        assert_eq!(builder.replay_blocks(&mut replayed, &blocks).unwrap(), 2);
+        assert_eq!(replayed.current_height(), 2);
+        assert_eq!(
+            replayed.compute_state_root().unwrap(),
+            state.compute_state_root().unwrap()
+        );
+        assert_eq!(replayed.get_balance(&receiver.public), 1000);
+    }
+
     #[tokio::test]
     async fn test_validate_block() {
         let (mut state, mempool, _, proposer, builder) = setup_test_env().await;
@@ -328,6 +489,7 @@ mod tests {
             initial_balances: vec![],
             trusted_issuers: vec![issuer.public],
             validators: vec![proposer.public],
+            validator_stake: 0,
         };
         fresh_state.init_genesis(&config).unwrap();
 
@@ -0,0 +1,315 @@
+use seloria_core::Block;
+use seloria_state::{ChainState, Storage};
+use seloria_vm::ExecutionResult;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{info, warn};
+
+use crate::block_builder::{BlockBuilder, BlockBuilderConfig};
+use crate::error::ConsensusError;
+use crate::net::BlockRangeResponse;
+use crate::proposer::ValidatorEndpoint;
+use crate::qc::{verify_block_qc, verify_block_qc_at};
+
+/// Maximum number of blocks served by one `GET /blocks` request
+pub const MAX_BLOCKS_PER_REQUEST: u64 = 100;
+
+/// A block applied while catching up, with its execution results
+pub type AppliedBlock = (Block, Vec<ExecutionResult>);
+
+/// Fetches committed blocks a validator missed from its peers and applies
+/// them in order, checking each block's QC first
+pub struct BlockSyncer {
+    endpoints: Vec<ValidatorEndpoint>,
+    client: reqwest::Client,
+    /// Held while catching up so concurrent callers don't fetch the same blocks
+    running: Mutex<()>,
+}
+
+impl BlockSyncer {
+    pub fn new(endpoints: Vec<ValidatorEndpoint>) -> Self {
+        BlockSyncer {
+            endpoints,
+            client: reqwest::Client::new(),
+            running: Mutex::new(()),
+        }
+    }
+
+    /// Fetch and apply the committed blocks up to `target` that are missing
+    /// locally. Stops early if no peer serves the next block with a valid QC
+    /// or a block fails to apply. Returns the applied blocks in order.
+    pub async fn catch_up<S: Storage>(
+        &self,
+        state: &RwLock<ChainState<S>>,
+        target: u64,
+    ) -> Vec<AppliedBlock> {
+        let _running = self.running.lock().await;
+        let mut applied = Vec::new();
+
+        loop {
+            let from = state.read().await.current_height() + 1;
+            if from > target {
+                break;
+            }
+            let to = target.min(from + MAX_BLOCKS_PER_REQUEST - 1);
+
+            let Some(blocks) = self.fetch_blocks(state, from, to).await else {
+                warn!("No peer served blocks {} to {}; catch-up stopped", from, to);
+                break;
+            };
+            let mut chain_state = state.write().await;
+            match apply_blocks(&mut chain_state, blocks) {
+                Ok(batch) if !batch.is_empty() => applied.extend(batch),
+                Ok(_) => break,
+                Err(e) => {
+                    warn!("Catch-up stopped at height {}: {}", chain_state.current_height() + 1, e);
+                    break;
+                }
+            }
+        }
+
+        if !applied.is_empty() {
+            info!(
+                "Caught up {} blocks to height {}",
+                applied.len(),
+                state.read().await.current_height()
+            );
+        }
+        applied
+    }
+
+    /// Fetch blocks `from..=to` from the first peer that serves at least
+    /// `from` in order, with a valid QC on block `from`. The QCs of later
+    /// blocks are checked as they are applied, since the blocks before them
+    /// may change the validator set.
+    async fn fetch_blocks<S: Storage>(
+        &self,
+        state: &RwLock<ChainState<S>>,
+        from: u64,
+        to: u64,
+    ) -> Option<Vec<Block>> {
+        let validators = state.read().await.validator_set();
+
+        for endpoint in &self.endpoints {
+            let url = format!(
+                "{}/blocks?from={}&to={}",
+                endpoint.address.trim_end_matches('/'),
+                from,
+                to
+            );
+            let response = match self.client.get(&url).send().await {
+                Ok(response) if response.status().is_success() => response,
+                Ok(response) => {
+                    warn!("Validator {} refused blocks: {}", endpoint.pubkey, response.status());
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Failed to reach validator {}: {}", endpoint.pubkey, e);
+                    continue;
+                }
+            };
+            let blocks = match response.json::<BlockRangeResponse>().await {
+                Ok(body) => body.blocks,
+                Err(e) => {
+                    warn!("Invalid blocks response from {}: {}", endpoint.pubkey, e);
+                    continue;
+                }
+            };
+
+            let checked = blocks.iter().enumerate().try_for_each(|(offset, block)| {
+                if block.header.height != from + offset as u64 {
+                    return Err(ConsensusError::HeightMismatch {
+                        expected: from + offset as u64,
+                        got: block.header.height,
+                    });
+                }
+                if offset == 0 {
+                    verify_block_qc(block, &validators)?;
+                }
+                Ok(())
+            });
+            match checked {
+                Ok(()) if !blocks.is_empty() => return Some(blocks),
+                Ok(()) => {}
+                Err(e) => warn!("Invalid blocks from {}: {}", endpoint.pubkey, e),
+            }
+        }
+        None
+    }
+}
+
+/// Apply fetched blocks on top of `state`, skipping blocks it already has.
+/// Each block's QC is checked against the validator set left by the block
+/// before it.
+fn apply_blocks<S: Storage>(
+    state: &mut ChainState<S>,
+    blocks: Vec<Block>,
+) -> Result<Vec<AppliedBlock>, ConsensusError> {
+    let block_builder = BlockBuilder::new(BlockBuilderConfig {
+        chain_id: state.chain_id,
+        ..Default::default()
+    });
+
+    let mut applied = Vec::new();
+    for block in blocks {
+        // Blocks committed while these were being fetched
+        if block.header.height <= state.current_height() {
+            continue;
+        }
+        verify_block_qc_at(&block, state)?;
+        let results = block_builder.apply_block(state, &block)?;
+        state.persist_state()?;
+        applied.push((block, results));
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seloria_core::{
+        hash_blake3, testing, AgentCertificate, BlockHeader, Capability, Evidence, GenesisConfig,
+        Hash, KeyPair, Op, SignedAgentCertificate, Transaction,
+    };
+    use seloria_mempool::{Mempool, MempoolConfig};
+    use seloria_state::{MemoryBlockStore, MemoryStorage};
+
+    use crate::evidence::find_equivocations;
+
+    /// Evidence of `offender` signing two blocks at height 1
+    fn equivocation(offender: &KeyPair) -> Evidence {
+        let blocks: Vec<Block> = [1, 2]
+            .into_iter()
+            .map(|timestamp| {
+                let header = BlockHeader {
+                    timestamp,
+                    ..testing::header(1, 1)
+                };
+                testing::certified_block(header, [offender])
+            })
+            .collect();
+        find_equivocations(&blocks[0], &blocks[1]).remove(0)
+    }
+
+    #[tokio::test]
+    async fn test_apply_missed_blocks() {
+        let validator = KeyPair::generate();
+        let genesis = GenesisConfig {
+            chain_id: 1,
+            timestamp: 0,
+            initial_balances: vec![],
+            trusted_issuers: vec![],
+            validators: vec![validator.public],
+            validator_stake: 0,
+        };
+        let mut leader = ChainState::new(MemoryStorage::new());
+        leader.init_genesis(&genesis).unwrap();
+        let mut lagging = leader.clone();
+        lagging.set_block_store(MemoryBlockStore::new());
+
+        let builder = BlockBuilder::new(BlockBuilderConfig::default());
+        let mempool = Mempool::new(MempoolConfig::default());
+        let mut blocks = Vec::new();
+        for timestamp in [1000, 2000, 3000] {
+            let mut block = builder
+                .build_block(&leader, &mempool, validator.public, timestamp)
+                .await
+                .unwrap();
+            block.qc = Some(testing::commit_qc(&block.header, [&validator]));
+            verify_block_qc(&block, &leader.validator_set()).unwrap();
+            builder.apply_block(&mut leader, &block).unwrap();
+            leader.persist_state().unwrap();
+            blocks.push(block);
+        }
+
+        // The lagging node already has block 1
+        builder.apply_block(&mut lagging, &blocks[0]).unwrap();
+        lagging.persist_state().unwrap();
+
+        let applied = apply_blocks(&mut lagging, blocks.clone()).unwrap();
+        assert_eq!(applied.len(), 2);
+        assert_eq!(lagging.current_height(), 3);
+        assert_eq!(
+            lagging.compute_state_root().unwrap(),
+            leader.compute_state_root().unwrap()
+        );
+        assert!(lagging.get_block(3).unwrap().unwrap().qc.is_some());
+
+        // A gap in the fetched blocks is rejected
+        let mut fresh = ChainState::new(MemoryStorage::new());
+        fresh.set_block_store(MemoryBlockStore::new());
+        fresh.init_genesis(&genesis).unwrap();
+        assert!(matches!(
+            apply_blocks(&mut fresh, blocks[1..].to_vec()),
+            Err(ConsensusError::HeightMismatch { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_blocks_after_slashing_are_checked_against_the_new_set() {
+        let validators: Vec<_> = (0..4).map(|_| KeyPair::generate()).collect();
+        let issuer = KeyPair::generate();
+        let reporter = KeyPair::generate();
+        let genesis = GenesisConfig {
+            chain_id: 1,
+            timestamp: 0,
+            initial_balances: vec![(reporter.public, 1_000_000)],
+            trusted_issuers: vec![issuer.public],
+            validators: validators.iter().map(|v| v.public).collect(),
+            validator_stake: 0,
+        };
+        let mut leader = ChainState::new(MemoryStorage::new());
+        leader.init_genesis(&genesis).unwrap();
+        let cert = AgentCertificate::new(
+            hash_blake3(issuer.public.as_bytes()),
+            reporter.public,
+            0,
+            1_000_000,
+            vec![Capability::TxSubmit],
+            Hash::ZERO,
+        );
+        leader.register_agent(SignedAgentCertificate::new(cert, 1, &issuer.secret).unwrap());
+        leader.persist_state().unwrap();
+        let mut lagging = leader.clone();
+        lagging.set_block_store(MemoryBlockStore::new());
+
+        // Block 1 slashes validator 3 and removes it from the set
+        let builder = BlockBuilder::new(BlockBuilderConfig::default());
+        let mempool = Mempool::new(MempoolConfig::default());
+        let tx = Transaction::new_signed(
+            1,
+            reporter.public,
+            1,
+            100,
+            vec![Op::SubmitEvidence {
+                evidence: Box::new(equivocation(&validators[3])),
+            }],
+            &reporter.secret,
+        )
+        .unwrap();
+        mempool.add(tx).await.unwrap();
+        let mut first = builder
+            .build_block(&leader, &mempool, validators[0].public, 1000)
+            .await
+            .unwrap();
+        first.qc = Some(testing::commit_qc(&first.header, &validators[..3]));
+        builder.apply_block(&mut leader, &first).unwrap();
+        leader.persist_state().unwrap();
+        assert_eq!(leader.validators.len(), 3);
+        assert_eq!(leader.get_balance(&reporter.public), 1_000_000 - 100);
+
+        // Validator 3 no longer counts towards block 2's quorum
+        let second = builder
+            .build_block(&leader, &mempool, validators[0].public, 2000)
+            .await
+            .unwrap();
+        let mut forged = second.clone();
+        forged.qc = Some(testing::commit_qc(&forged.header, &validators[1..]));
+        assert!(apply_blocks(&mut lagging, vec![first, forged]).is_err());
+        assert_eq!(lagging.current_height(), 1);
+
+        let mut certified = second;
+        certified.qc = Some(testing::commit_qc(&certified.header, &validators[..3]));
+        assert_eq!(apply_blocks(&mut lagging, vec![certified]).unwrap().len(), 1);
+        assert_eq!(lagging.current_height(), 2);
+    }
+}
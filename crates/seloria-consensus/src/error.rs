@@ -11,8 +11,8 @@ pub enum ConsensusError {
     #[error("Invalid quorum certificate: {0}")]
     InvalidQc(String),
 
-    #[error("Insufficient signatures: have {have}, need {need}")]
-    InsufficientSignatures { have: usize, need: usize },
+    #[error("Insufficient voting power: have {have}, need {need}")]
+    InsufficientVotingPower { have: u64, need: u64 },
 
     #[error("Invalid signature from validator")]
     InvalidSignature,
@@ -26,9 +26,18 @@ pub enum ConsensusError {
     #[error("Invalid state root")]
     InvalidStateRoot,
 
+    #[error("Invalid receipts root")]
+    InvalidReceiptsRoot,
+
     #[error("Transaction execution failed: {0}")]
     ExecutionFailed(String),
 
+    #[error("Safety rule violated: {0}")]
+    SafetyViolation(String),
+
+    #[error("Safety WAL error: {0}")]
+    SafetyWal(String),
+
     #[error("Validator not found: {0}")]
     ValidatorNotFound(String),
 
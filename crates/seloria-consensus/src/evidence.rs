@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use seloria_core::{Block, BlockHeader, Evidence, Hash, PublicKey, ValidatorSignature, VotePhase};
+use tracing::warn;
+
+/// Maximum number of pieces of evidence held for submission
+pub const MAX_PENDING_EVIDENCE: usize = 1024;
+
+/// Evidence against every validator with a vote in both `votes` (for
+/// `header`) and `other_votes` (for `other_header`), all cast in `phase`.
+/// Pairs that do not prove an equivocation are skipped.
+pub(crate) fn conflicting_votes(
+    phase: VotePhase,
+    header: &BlockHeader,
+    votes: &[ValidatorSignature],
+    other_header: &BlockHeader,
+    other_votes: &[ValidatorSignature],
+) -> Vec<Evidence> {
+    let mut found = Vec::new();
+    let mut offenders = HashSet::new();
+    for vote in votes {
+        let Some(other_vote) = other_votes
+            .iter()
+            .find(|v| v.validator_pubkey == vote.validator_pubkey)
+        else {
+            continue;
+        };
+        if !offenders.insert(vote.validator_pubkey) {
+            continue;
+        }
+        let evidence = match Evidence::new(
+            phase,
+            header.clone(),
+            vote.clone(),
+            other_header.clone(),
+            other_vote.clone(),
+        ) {
+            Ok(evidence) => evidence,
+            Err(e) => {
+                warn!("Failed to build evidence: {}", e);
+                continue;
+            }
+        };
+        if evidence.verify(header.chain_id).is_ok() {
+            found.push(evidence);
+        }
+    }
+    found
+}
+
+/// Evidence against every validator whose vote is in the quorum
+/// certificates of both blocks, when they are different blocks proposed at
+/// the same height and view with QCs of the same phase
+pub fn find_equivocations(block: &Block, other: &Block) -> Vec<Evidence> {
+    match (&block.qc, &other.qc) {
+        (Some(qc), Some(other_qc)) if qc.phase == other_qc.phase => conflicting_votes(
+            qc.phase,
+            &block.header,
+            &qc.signatures,
+            &other.header,
+            &other_qc.signatures,
+        ),
+        _ => Vec::new(),
+    }
+}
+
+/// Equivocation evidence this node detected, held until it is submitted
+/// on-chain with a `SubmitEvidence` transaction. Shared by the proposer and
+/// the RPC handlers.
+#[derive(Default)]
+pub struct EvidencePool {
+    pending: Mutex<Vec<(Hash, Evidence)>>,
+}
+
+impl EvidencePool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add evidence, returning whether it was new
+    pub fn add(&self, evidence: Evidence) -> bool {
+        let id = match evidence.id() {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Failed to hash evidence: {}", e);
+                return false;
+            }
+        };
+        let mut pending = self.pending.lock().expect("evidence pool");
+        if pending.iter().any(|(existing, _)| *existing == id) {
+            return false;
+        }
+        warn!(
+            "Validator {} equivocated at height {}",
+            evidence.offender(),
+            evidence.height()
+        );
+        if pending.len() >= MAX_PENDING_EVIDENCE {
+            pending.remove(0);
+        }
+        pending.push((id, evidence));
+        true
+    }
+
+    /// Evidence against the `slashable` validators. Evidence against
+    /// validators that left the set and have no stake left can no longer be
+    /// submitted and is dropped.
+    pub fn pending(&self, slashable: &[PublicKey]) -> Vec<Evidence> {
+        let mut pending = self.pending.lock().expect("evidence pool");
+        pending.retain(|(_, evidence)| slashable.contains(&evidence.offender()));
+        pending.iter().map(|(_, evidence)| evidence.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seloria_core::{testing, KeyPair};
+
+    fn certified_block(timestamp: u64, signers: &[KeyPair]) -> Block {
+        let header = BlockHeader {
+            timestamp,
+            ..testing::header(1, 7)
+        };
+        testing::certified_block(header, signers)
+    }
+
+    #[test]
+    fn test_conflicting_certificates_yield_evidence() {
+        let validators: Vec<_> = (0..4).map(|_| KeyPair::generate()).collect();
+        // Validator 1 signed both blocks
+        let block = certified_block(1, &validators[..2]);
+        let other = certified_block(2, &validators[1..]);
+
+        let found = find_equivocations(&block, &other);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].offender(), validators[1].public);
+        found[0].verify(1).unwrap();
+
+        // A block does not conflict with itself
+        assert!(find_equivocations(&block, &block).is_empty());
+
+        let pool = EvidencePool::new();
+        assert!(pool.add(found[0].clone()));
+        assert!(!pool.add(find_equivocations(&other, &block).remove(0)));
+        let all: Vec<_> = validators.iter().map(|v| v.public).collect();
+        assert_eq!(pool.pending(&all).len(), 1);
+
+        // Evidence against a removed validator is dropped
+        assert!(pool.pending(&all[2..]).is_empty());
+        assert!(pool.pending(&all).is_empty());
+    }
+}
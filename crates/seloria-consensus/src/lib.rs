@@ -4,17 +4,28 @@
 //! quorum certificate management.
 
 pub mod block_builder;
+pub mod catchup;
 pub mod events;
 pub mod error;
+pub mod evidence;
 pub mod net;
+pub mod pacemaker;
 pub mod proposer;
 pub mod qc;
+pub mod safety_wal;
 pub mod validator;
 
 pub use block_builder::{BlockBuilder, BlockBuilderConfig};
+pub use catchup::{AppliedBlock, BlockSyncer, MAX_BLOCKS_PER_REQUEST};
 pub use events::BlockEventSink;
 pub use error::ConsensusError;
-pub use net::{CommitRequest, CommitResponse, ProposeRequest, ProposeResponse};
+pub use evidence::{find_equivocations, EvidencePool, MAX_PENDING_EVIDENCE};
+pub use net::{
+    BlockRangeResponse, CommitRequest, CommitResponse, ProposeRequest, TimeoutResponse,
+    TimeoutVote, VoteRequest, VoteResponse,
+};
+pub use pacemaker::{leader_for, Pacemaker, DEFAULT_VIEW_TIMEOUT_MS};
 pub use proposer::{Proposer, ProposerConfig, ValidatorEndpoint};
-pub use qc::{verify_qc, QcBuilder};
+pub use qc::{verify_block_qc, verify_block_qc_at, verify_qc, QcBuilder};
+pub use safety_wal::{SafetyWal, SignedVote};
 pub use validator::Validator;
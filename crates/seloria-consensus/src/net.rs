@@ -1,14 +1,31 @@
-use seloria_core::{Block, PublicKey, Sig};
+use seloria_core::{Block, PublicKey, QuorumCertificate, Sig, TimeoutCertificate, VotePhase};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProposeRequest {
     pub block: Block,
+    /// Certificate that moved the height to the block's view (`None` in
+    /// view 0)
+    #[serde(default)]
+    pub timeout_cert: Option<TimeoutCertificate>,
+    /// Prepare QC from an earlier view for the block, when the leader
+    /// re-proposes it
+    #[serde(default)]
+    pub justify: Option<QuorumCertificate>,
 }
 
+/// Asks validators to vote in the phase after the one `qc` certifies
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ProposeResponse {
+pub struct VoteRequest {
+    pub qc: QuorumCertificate,
+}
+
+/// A validator's vote on a proposal or phase QC
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoteResponse {
     pub validator_pubkey: PublicKey,
+    pub phase: VotePhase,
+    /// Signature over `vote_message(chain_id, phase, block_hash)`
     pub signature: Sig,
 }
 
@@ -23,3 +40,27 @@ pub struct CommitResponse {
     pub height: u64,
     pub hash: String,
 }
+
+/// Committed blocks returned by `GET /blocks`, in height order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockRangeResponse {
+    pub blocks: Vec<Block>,
+}
+
+/// A validator's vote to time out the leader of `view` at `height`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeoutVote {
+    pub height: u64,
+    pub view: u64,
+    pub validator_pubkey: PublicKey,
+    /// Signature over `timeout_message(chain_id, height, view)`
+    pub signature: Sig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeoutResponse {
+    /// Height being decided on the receiving node
+    pub height: u64,
+    /// Current view at that height
+    pub view: u64,
+}
@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use seloria_core::{timeout_message, verify, PublicKey, TimeoutCertificate, ValidatorSet};
+use tracing::{debug, info};
+
+use crate::error::ConsensusError;
+use crate::net::TimeoutVote;
+
+/// Default time a validator waits for the leader of a view before voting to
+/// time it out
+pub const DEFAULT_VIEW_TIMEOUT_MS: u64 = 10_000;
+
+/// Leader of `view` at `height`. Validators take turns by height, and each
+/// view that times out passes the turn to the next validator.
+pub fn leader_for(validators: &[PublicKey], height: u64, view: u64) -> Option<PublicKey> {
+    if validators.is_empty() {
+        return None;
+    }
+    let index = height.wrapping_add(view) % validators.len() as u64;
+    validators.get(index as usize).copied()
+}
+
+/// Tracks the view of the height being decided and collects timeout votes.
+///
+/// Every height starts in view 0. A validator that sees no block within the
+/// view timeout votes to time the view out; a quorum of those votes forms a
+/// [`TimeoutCertificate`] that moves everyone to the next view, whose leader
+/// then proposes. Shared by the proposer loop and the RPC handlers.
+pub struct Pacemaker {
+    timeout: Duration,
+    inner: Mutex<ViewState>,
+}
+
+struct ViewState {
+    height: u64,
+    view: u64,
+    /// When the current view started
+    started: Instant,
+    /// Certificate that moved the height to the current view
+    timeout_cert: Option<TimeoutCertificate>,
+    /// Whether this node has voted to time out the current view
+    timed_out: bool,
+    /// Timeout votes at this height, by view
+    votes: BTreeMap<u64, TimeoutCertificate>,
+}
+
+impl ViewState {
+    fn new(height: u64) -> Self {
+        ViewState {
+            height,
+            view: 0,
+            started: Instant::now(),
+            timeout_cert: None,
+            timed_out: false,
+            votes: BTreeMap::new(),
+        }
+    }
+
+    /// Start tracking `height` if the chain has moved on to it
+    fn enter_height(&mut self, height: u64) {
+        if height > self.height {
+            *self = ViewState::new(height);
+        }
+    }
+
+    /// Move past the view certified as timed out by `tc`
+    fn advance(&mut self, tc: TimeoutCertificate) {
+        self.view = tc.view + 1;
+        self.started = Instant::now();
+        self.timed_out = false;
+        self.votes = self.votes.split_off(&self.view);
+        self.timeout_cert = Some(tc);
+    }
+}
+
+impl Pacemaker {
+    pub fn new(timeout: Duration) -> Self {
+        Pacemaker {
+            timeout,
+            inner: Mutex::new(ViewState::new(0)),
+        }
+    }
+
+    /// Current view at `height`
+    pub fn view(&self, height: u64) -> u64 {
+        let mut inner = self.lock();
+        inner.enter_height(height);
+        if inner.height == height {
+            inner.view
+        } else {
+            0
+        }
+    }
+
+    /// Certificate that moved `height` to its current view, to be shown to
+    /// validators with a proposal
+    pub fn timeout_cert(&self, height: u64) -> Option<TimeoutCertificate> {
+        let mut inner = self.lock();
+        inner.enter_height(height);
+        if inner.height == height {
+            inner.timeout_cert.clone()
+        } else {
+            None
+        }
+    }
+
+    /// Whether this node has voted to time out `view` at `height`, after
+    /// which it no longer signs blocks for that view
+    pub fn has_timed_out(&self, height: u64, view: u64) -> bool {
+        let inner = self.lock();
+        inner.height == height && inner.view == view && inner.timed_out
+    }
+
+    /// If the current view at `height` has run past the timeout and this
+    /// node has not voted to time it out yet, record the vote and return
+    /// the view to time out
+    pub fn take_timeout(&self, height: u64) -> Option<u64> {
+        let mut inner = self.lock();
+        inner.enter_height(height);
+        if inner.height != height || inner.timed_out || inner.started.elapsed() < self.timeout {
+            return None;
+        }
+        inner.timed_out = true;
+        Some(inner.view)
+    }
+
+    /// Add a validator's timeout vote for `height`, the height this node is
+    /// deciding. Returns the timeout certificate if the vote completes a
+    /// quorum, moving to the next view. Votes for other heights are ignored,
+    /// so a vote for a far-off height cannot reset the view.
+    pub fn add_timeout(
+        &self,
+        chain_id: u64,
+        height: u64,
+        vote: &TimeoutVote,
+        validators: &ValidatorSet,
+    ) -> Result<Option<TimeoutCertificate>, ConsensusError> {
+        if !validators.contains(&vote.validator_pubkey) {
+            return Err(ConsensusError::ValidatorNotFound(
+                vote.validator_pubkey.to_hex(),
+            ));
+        }
+        let message = timeout_message(chain_id, vote.height, vote.view);
+        verify(&vote.validator_pubkey, &message, &vote.signature)?;
+
+        if vote.height != height {
+            debug!(
+                "Ignoring timeout for height {} at height {}",
+                vote.height, height
+            );
+            return Ok(None);
+        }
+        let mut inner = self.lock();
+        inner.enter_height(vote.height);
+        if vote.height != inner.height || vote.view < inner.view {
+            debug!(
+                "Ignoring stale timeout for height {} view {}",
+                vote.height, vote.view
+            );
+            return Ok(None);
+        }
+
+        let tc = inner
+            .votes
+            .entry(vote.view)
+            .or_insert_with(|| TimeoutCertificate::new(chain_id, vote.height, vote.view));
+        if tc
+            .signatures
+            .iter()
+            .any(|vs| vs.validator_pubkey == vote.validator_pubkey)
+        {
+            return Ok(None);
+        }
+        tc.add_signature(vote.validator_pubkey, vote.signature);
+        if !validators.has_quorum(tc.signatures.iter().map(|vs| &vs.validator_pubkey)) {
+            return Ok(None);
+        }
+
+        let tc = tc.clone();
+        info!(
+            "View {} at height {} timed out; moving to view {}",
+            tc.view,
+            tc.height,
+            tc.view + 1
+        );
+        inner.advance(tc.clone());
+        Ok(Some(tc))
+    }
+
+    /// Move to the view after the one `tc` certifies as timed out, if this
+    /// node is not already past it
+    pub fn advance(
+        &self,
+        tc: &TimeoutCertificate,
+        validators: &ValidatorSet,
+    ) -> Result<(), ConsensusError> {
+        tc.verify_quorum(validators)
+            .map_err(|e| ConsensusError::InvalidQc(e.to_string()))?;
+
+        let mut inner = self.lock();
+        inner.enter_height(tc.height);
+        if tc.height == inner.height && tc.view >= inner.view {
+            info!(
+                "Moving to view {} at height {} on a timeout certificate",
+                tc.view + 1,
+                tc.height
+            );
+            inner.advance(tc.clone());
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ViewState> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for Pacemaker {
+    fn default() -> Self {
+        Pacemaker::new(Duration::from_millis(DEFAULT_VIEW_TIMEOUT_MS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seloria_core::{sign, KeyPair};
+
+    fn timeout_vote(validator: &KeyPair, height: u64, view: u64) -> TimeoutVote {
+        TimeoutVote {
+            height,
+            view,
+            validator_pubkey: validator.public,
+            signature: sign(&validator.secret, &timeout_message(1, height, view)),
+        }
+    }
+
+    #[test]
+    fn test_leader_rotates_with_view() {
+        let validators: Vec<_> = (0..4).map(|_| KeyPair::generate().public).collect();
+        assert_eq!(leader_for(&validators, 5, 0), Some(validators[1]));
+        assert_eq!(leader_for(&validators, 5, 1), Some(validators[2]));
+        assert_eq!(leader_for(&validators, 5, 3), Some(validators[0]));
+        assert_eq!(leader_for(&[], 5, 0), None);
+    }
+
+    #[test]
+    fn test_timeout_quorum_advances_view() {
+        let validators: Vec<_> = (0..4).map(|_| KeyPair::generate()).collect();
+        let pubkeys: Vec<_> = validators.iter().map(|v| v.public).collect();
+        let set = ValidatorSet::equal(&pubkeys);
+        let pacemaker = Pacemaker::new(Duration::ZERO);
+
+        assert_eq!(pacemaker.view(3), 0);
+        assert_eq!(pacemaker.take_timeout(3), Some(0));
+        assert_eq!(pacemaker.take_timeout(3), None);
+        assert!(pacemaker.has_timed_out(3, 0));
+
+        for validator in &validators[..2] {
+            let vote = timeout_vote(validator, 3, 0);
+            assert!(pacemaker.add_timeout(1, 3, &vote, &set).unwrap().is_none());
+        }
+        // Duplicate votes don't count
+        let duplicate = timeout_vote(&validators[1], 3, 0);
+        assert!(pacemaker.add_timeout(1, 3, &duplicate, &set).unwrap().is_none());
+        // A vote signed for another view is rejected
+        let mut forged = timeout_vote(&validators[2], 3, 1);
+        forged.view = 0;
+        assert!(pacemaker.add_timeout(1, 3, &forged, &set).is_err());
+
+        let vote = timeout_vote(&validators[2], 3, 0);
+        let tc = pacemaker.add_timeout(1, 3, &vote, &set).unwrap().unwrap();
+        assert_eq!(pacemaker.view(3), 1);
+        assert!(!pacemaker.has_timed_out(3, 1));
+        tc.verify_quorum(&set).unwrap();
+
+        // Another node catches up with the certificate
+        let other = Pacemaker::default();
+        other.advance(&tc, &set).unwrap();
+        assert_eq!(other.view(3), 1);
+        assert!(other.timeout_cert(3).is_some());
+        // Three of five validators are short of a quorum
+        let larger = ValidatorSet::equal(&[pubkeys, vec![KeyPair::generate().public]].concat());
+        assert!(other.advance(&tc, &larger).is_err());
+
+        // The next height starts over at view 0
+        assert_eq!(pacemaker.view(4), 0);
+        assert!(pacemaker.timeout_cert(4).is_none());
+    }
+
+    #[test]
+    fn test_far_future_timeout_vote_is_ignored() {
+        let validators: Vec<_> = (0..4).map(|_| KeyPair::generate()).collect();
+        let pubkeys: Vec<_> = validators.iter().map(|v| v.public).collect();
+        let set = ValidatorSet::equal(&pubkeys);
+        let pacemaker = Pacemaker::new(Duration::ZERO);
+        assert_eq!(pacemaker.take_timeout(3), Some(0));
+
+        // A vote for a height far ahead does not move the pacemaker there
+        let vote = timeout_vote(&validators[0], u64::MAX, 0);
+        assert!(pacemaker.add_timeout(1, 3, &vote, &set).unwrap().is_none());
+        assert!(pacemaker.has_timed_out(3, 0));
+
+        // Timeout votes for height 3 still form a certificate
+        for validator in &validators[..2] {
+            let vote = timeout_vote(validator, 3, 0);
+            assert!(pacemaker.add_timeout(1, 3, &vote, &set).unwrap().is_none());
+        }
+        let vote = timeout_vote(&validators[2], 3, 0);
+        assert!(pacemaker.add_timeout(1, 3, &vote, &set).unwrap().is_some());
+        assert_eq!(pacemaker.view(3), 1);
+    }
+}
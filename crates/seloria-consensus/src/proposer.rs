@@ -1,7 +1,10 @@
 use std::sync::Arc;
 use std::time::Duration;
 
-use seloria_core::{Block, Hash, PublicKey, SecretKey};
+use seloria_core::{
+    sign, timeout_message, Block, Hash, PublicKey, QuorumCertificate, SecretKey, Sig,
+    ValidatorSet, VotePhase,
+};
 use seloria_mempool::Mempool;
 use seloria_state::{ChainState, Storage};
 use tokio::sync::RwLock;
@@ -11,8 +14,11 @@ use tracing::{error, info, warn};
 use crate::block_builder::{BlockBuilder, BlockBuilderConfig};
 use crate::events::BlockEventSink;
 use crate::error::ConsensusError;
+use crate::evidence::EvidencePool;
+use crate::pacemaker::{leader_for, Pacemaker, DEFAULT_VIEW_TIMEOUT_MS};
 use crate::qc::QcBuilder;
-use crate::net::{CommitRequest, ProposeRequest, ProposeResponse};
+use crate::net::{CommitRequest, ProposeRequest, TimeoutVote, VoteRequest, VoteResponse};
+use crate::validator::Validator;
 
 #[derive(Debug, Clone)]
 pub struct ValidatorEndpoint {
@@ -31,29 +37,31 @@ impl ValidatorEndpoint {
 pub struct ProposerConfig {
     /// Round time in milliseconds
     pub round_time_ms: u64,
-    /// Number of validators
-    pub num_validators: usize,
-    /// Quorum threshold
-    pub threshold: usize,
     /// Chain ID
     pub chain_id: u64,
     /// Max transactions per block
     pub max_block_txs: usize,
+    /// Include transactions that fail execution with a failed receipt
+    pub include_failed_txs: bool,
+    /// Time to wait for the leader of a view before voting to time it out
+    pub view_timeout_ms: u64,
 }
 
 impl Default for ProposerConfig {
     fn default() -> Self {
         ProposerConfig {
             round_time_ms: 2000,
-            num_validators: 4,
-            threshold: 3,
             chain_id: 1,
             max_block_txs: 1000,
+            include_failed_txs: true,
+            view_timeout_ms: DEFAULT_VIEW_TIMEOUT_MS,
         }
     }
 }
 
-/// Block proposer that builds and broadcasts blocks
+/// Block proposer that builds and broadcasts blocks. The validator set and
+/// voting power are read from the state each round, so they follow the set
+/// chosen at each epoch boundary.
 pub struct Proposer<S: Storage> {
     config: ProposerConfig,
     public_key: PublicKey,
@@ -61,9 +69,13 @@ pub struct Proposer<S: Storage> {
     block_builder: BlockBuilder,
     state: Arc<RwLock<ChainState<S>>>,
     mempool: Arc<Mempool>,
-    validators: Vec<PublicKey>,
     validator_endpoints: Vec<ValidatorEndpoint>,
     event_sink: Option<Arc<dyn BlockEventSink>>,
+    pacemaker: Arc<Pacemaker>,
+    /// Casts this node's own votes under the safety rules
+    validator: Arc<Validator>,
+    /// Equivocations detected while collecting votes
+    evidence: Arc<EvidencePool>,
 }
 
 impl<S: Storage + Send + Sync + Clone + 'static> Proposer<S> {
@@ -74,13 +86,24 @@ impl<S: Storage + Send + Sync + Clone + 'static> Proposer<S> {
         secret_key: SecretKey,
         state: Arc<RwLock<ChainState<S>>>,
         mempool: Arc<Mempool>,
-        validators: Vec<PublicKey>,
     ) -> Self {
         let block_builder = BlockBuilder::new(BlockBuilderConfig {
             chain_id: config.chain_id,
             max_transactions: config.max_block_txs,
+            include_failed_txs: config.include_failed_txs,
         });
 
+        let pacemaker = Arc::new(Pacemaker::new(Duration::from_millis(config.view_timeout_ms)));
+        let validator = Arc::new(Validator::new(
+            public_key,
+            secret_key.clone(),
+            BlockBuilder::new(BlockBuilderConfig {
+                chain_id: config.chain_id,
+                max_transactions: config.max_block_txs,
+                include_failed_txs: config.include_failed_txs,
+            }),
+        ));
+
         Proposer {
             config,
             public_key,
@@ -88,9 +111,11 @@ impl<S: Storage + Send + Sync + Clone + 'static> Proposer<S> {
             block_builder,
             state,
             mempool,
-            validators,
             validator_endpoints: Vec::new(),
             event_sink: None,
+            pacemaker,
+            validator,
+            evidence: Arc::new(EvidencePool::new()),
         }
     }
 
@@ -102,20 +127,41 @@ impl<S: Storage + Send + Sync + Clone + 'static> Proposer<S> {
         self.event_sink = Some(sink);
     }
 
-    /// Check if we are the leader for the current height
+    /// Share a pacemaker with the RPC handlers receiving timeout votes
+    pub fn set_pacemaker(&mut self, pacemaker: Arc<Pacemaker>) {
+        self.pacemaker = pacemaker;
+    }
+
+    /// Share the validator that votes on proposals from other leaders, so
+    /// this node's votes as leader follow the same safety rules
+    pub fn set_validator(&mut self, validator: Arc<Validator>) {
+        self.validator = validator;
+    }
+
+    /// Share an evidence pool with the RPC handlers serving detected
+    /// equivocations
+    pub fn set_evidence_pool(&mut self, evidence: Arc<EvidencePool>) {
+        self.evidence = evidence;
+    }
+
+    /// Current validators with their voting power
+    async fn validator_set(&self) -> ValidatorSet {
+        self.state.read().await.validator_set()
+    }
+
+    /// Whether blocks need votes from other validators, rather than the
+    /// single-validator fast path
+    async fn is_committee(&self) -> bool {
+        self.state.read().await.validators.len() > 1 && !self.validator_endpoints.is_empty()
+    }
+
+    /// Check if we are the leader for the current height and view
     pub async fn is_current_leader(&self) -> bool {
         let state = self.state.read().await;
         let next_height = state.current_height() + 1;
-        self.is_leader_for_height(next_height)
-    }
-
-    /// Check if we are the leader for a given height
-    fn is_leader_for_height(&self, height: u64) -> bool {
-        if self.validators.is_empty() {
-            return false;
-        }
-        let leader_index = (height as usize) % self.validators.len();
-        self.validators.get(leader_index) == Some(&self.public_key)
+        let view = self.pacemaker.view(next_height);
+        leader_for(&state.validators, next_height, view) == Some(self.public_key)
+            && !self.pacemaker.has_timed_out(next_height, view)
     }
 
     /// Get the current timestamp
@@ -136,46 +182,98 @@ impl<S: Storage + Send + Sync + Clone + 'static> Proposer<S> {
         let timestamp = Self::current_timestamp();
         let state = self.state.read().await;
 
-        let block = self
+        let mut block = self
             .block_builder
             .build_block(&*state, &self.mempool, self.public_key, timestamp)
             .await?;
+        block.header.view = self.pacemaker.view(block.header.height);
 
         info!(
-            "Proposed block {} at height {}",
+            "Proposed block {} at height {} view {}",
             block.hash()?,
-            block.header.height
+            block.header.height,
+            block.header.view
         );
 
         Ok(block)
     }
 
-    /// Finalize a block with collected signatures
+    /// Finalize a block with this node's own commit vote (single-validator
+    /// fast path). The vote goes through the validator's safety rules, so a
+    /// restarted node does not sign a second block in the same view.
     pub async fn finalize_block(&self, mut block: Block) -> Result<Block, ConsensusError> {
         let block_hash = block.hash()?;
 
         // Create QC builder
-        let mut qc_builder =
-            QcBuilder::new(block_hash, &self.validators, self.config.threshold);
+        let validators = self.validator_set().await;
+        let mut qc_builder = QcBuilder::new(block.header.chain_id, block_hash, &validators)
+            .with_view(block.header.view);
 
         // Add our own signature
-        let our_sig = seloria_core::sign(&self.secret_key, block_hash.as_bytes());
+        let our_sig = self.validator.commit_own_block(&block)?;
         qc_builder.add_signature(self.public_key, our_sig)?;
 
-        // Collect signatures from other validators if configured
-        if !self.validator_endpoints.is_empty() {
-            self.collect_signatures(&block, &mut qc_builder).await;
+        block.qc = Some(qc_builder.build()?);
+
+        Ok(block)
+    }
+
+    /// Block to propose in the current view, with the QC justifying it
+    /// when it re-proposes a block prepared in an earlier view
+    async fn next_proposal(&self) -> Result<(Block, Option<QuorumCertificate>), ConsensusError> {
+        let height = self.state.read().await.current_height() + 1;
+        let view = self.pacemaker.view(height);
+        if let Some((block, justify)) = self.validator.reproposal(height, view) {
+            info!(
+                "Re-proposing block {} at height {} view {}",
+                block.hash()?,
+                height,
+                view
+            );
+            return Ok((block, justify));
         }
+        Ok((self.propose_block().await?, None))
+    }
 
-        if !qc_builder.has_quorum() {
-            return Err(ConsensusError::InsufficientSignatures {
-                have: qc_builder.signature_count(),
-                need: self.config.threshold,
-            });
+    /// Take a block through the prepare, pre-commit and commit phases,
+    /// returning it with its commit QC once a quorum voted in each
+    pub async fn run_committee_round(&self) -> Result<Block, ConsensusError> {
+        if !self.is_current_leader().await {
+            return Err(ConsensusError::NotLeader);
         }
+        let (mut block, justify) = self.next_proposal().await?;
+        let block_hash = block.hash()?;
+        let validators = self.validator_set().await;
 
-        block.qc = Some(qc_builder.build()?);
+        // Prepare: validators check the block and the safety rules
+        let signature = {
+            let state = self.state.read().await;
+            self.validator
+                .vote_prepare(&block, justify.as_ref(), &state, &validators)?
+        };
+        let request = ProposeRequest {
+            block: block.clone(),
+            timeout_cert: self.pacemaker.timeout_cert(block.header.height),
+            justify,
+        };
+        let mut qc = self
+            .collect_votes(&block, &validators, VotePhase::Prepare, signature, "propose", &request)
+            .await?;
 
+        // Pre-commit, then commit, on the QC of the phase before
+        while let Some(phase) = qc.phase.next() {
+            let (_, signature) = self.validator.vote(&qc, &validators)?;
+            let request = VoteRequest { qc };
+            qc = self
+                .collect_votes(&block, &validators, phase, signature, "vote", &request)
+                .await?;
+        }
+
+        info!(
+            "Block {} at height {} committed in view {}",
+            block_hash, block.header.height, block.header.view
+        );
+        block.qc = Some(qc);
         Ok(block)
     }
 
@@ -200,6 +298,7 @@ impl<S: Storage + Send + Sync + Clone + 'static> Proposer<S> {
             .filter_map(|tx| tx.hash().ok())
             .collect();
         self.mempool.remove_committed(&tx_hashes).await;
+        self.mempool.evict_expired(height + 1).await;
 
         info!("Applied block {} at height {}", block_hash, height);
 
@@ -210,10 +309,13 @@ impl<S: Storage + Send + Sync + Clone + 'static> Proposer<S> {
         Ok(())
     }
 
-    /// Run the proposer loop (single node mode)
-    pub async fn run_single_node(self: Arc<Self>) {
-        let round_duration = Duration::from_millis(self.config.round_time_ms);
-        let mut round_interval = interval(round_duration);
+    /// Run the proposer loop, leading rounds in the views this node is
+    /// leader of: the three-phase committee protocol when other validators
+    /// are reachable, otherwise the single-validator fast path. The mode is
+    /// chosen each round since the validator set can change at an epoch
+    /// boundary.
+    pub async fn run(self: Arc<Self>) {
+        let mut round_interval = interval(Duration::from_millis(self.config.round_time_ms));
 
         info!(
             "Starting proposer loop with round time {}ms",
@@ -223,56 +325,72 @@ impl<S: Storage + Send + Sync + Clone + 'static> Proposer<S> {
         loop {
             round_interval.tick().await;
 
+            self.check_timeout().await;
             if !self.is_current_leader().await {
                 continue;
             }
 
-            // Propose block
-            match self.propose_block().await {
+            let result = if self.is_committee().await {
+                self.run_committee_round().await
+            } else {
+                self.run_single_node_round().await
+            };
+            match result {
                 Ok(block) => {
-                    // In single-node mode, immediately finalize and apply
-                    match self.finalize_block(block).await {
-                        Ok(finalized_block) => {
-                            let commit_block = finalized_block.clone();
-                            if let Err(e) = self.apply_block(finalized_block).await {
-                                error!("Failed to apply block: {}", e);
-                            } else if !self.validator_endpoints.is_empty() {
-                                self.broadcast_commit(&commit_block).await;
-                            }
-                        }
-                        Err(e) => {
-                            error!("Failed to finalize block: {}", e);
-                        }
-                    }
-                }
-                Err(e) => {
-                    if !matches!(e, ConsensusError::NotLeader) {
-                        warn!("Failed to propose block: {}", e);
+                    let commit_block = block.clone();
+                    if let Err(e) = self.apply_block(block).await {
+                        error!("Failed to apply block: {}", e);
+                    } else {
+                        self.broadcast_commit(&commit_block).await;
                     }
                 }
+                Err(ConsensusError::NotLeader) => {}
+                Err(e) => warn!("Consensus round failed: {}", e),
             }
         }
     }
 
-    async fn collect_signatures(
+    /// Propose a block and finalize it with this node's own vote (single
+    /// node mode)
+    async fn run_single_node_round(&self) -> Result<Block, ConsensusError> {
+        let block = self.propose_block().await?;
+        self.finalize_block(block).await
+    }
+
+    /// Send `request` to `/consensus/{route}` on the other validators and
+    /// collect their `phase` votes for `block` into a QC, starting with our
+    /// own vote
+    async fn collect_votes<T: serde::Serialize>(
         &self,
         block: &Block,
-        qc_builder: &mut QcBuilder,
-    ) {
+        validators: &ValidatorSet,
+        phase: VotePhase,
+        our_sig: Sig,
+        route: &str,
+        request: &T,
+    ) -> Result<QuorumCertificate, ConsensusError> {
+        let mut qc_builder = QcBuilder::new(block.header.chain_id, block.hash()?, validators)
+            .with_view(block.header.view)
+            .with_phase(phase);
+        qc_builder.add_signature(self.public_key, our_sig)?;
+
         let client = reqwest::Client::new();
 
         for endpoint in &self.validator_endpoints {
+            if qc_builder.has_quorum() {
+                break;
+            }
             if endpoint.pubkey == self.public_key {
                 continue;
             }
 
             let url = format!(
-                "{}/consensus/propose",
-                endpoint.address.trim_end_matches('/')
+                "{}/consensus/{}",
+                endpoint.address.trim_end_matches('/'),
+                route
             );
-            let request = ProposeRequest { block: block.clone() };
 
-            let response = client.post(&url).json(&request).send().await;
+            let response = client.post(&url).json(request).send().await;
             let response = match response {
                 Ok(resp) => resp,
                 Err(e) => {
@@ -283,28 +401,85 @@ impl<S: Storage + Send + Sync + Clone + 'static> Proposer<S> {
 
             if !response.status().is_success() {
                 warn!(
-                    "Validator {} rejected propose: {}",
+                    "Validator {} rejected {:?} vote: {}",
                     endpoint.pubkey,
+                    phase,
                     response.status()
                 );
                 continue;
             }
 
-            let body: ProposeResponse = match response.json().await {
+            let body: VoteResponse = match response.json().await {
                 Ok(body) => body,
                 Err(e) => {
-                    warn!("Invalid propose response from {}: {}", endpoint.pubkey, e);
+                    warn!("Invalid vote response from {}: {}", endpoint.pubkey, e);
                     continue;
                 }
             };
+            if body.phase != phase {
+                warn!(
+                    "Validator {} voted {:?}, expected {:?}",
+                    body.validator_pubkey, body.phase, phase
+                );
+                continue;
+            }
 
             if let Err(e) = qc_builder.add_signature(body.validator_pubkey, body.signature) {
                 warn!("Invalid signature from {}: {}", body.validator_pubkey, e);
+            }
+        }
+
+        // Another leader may have committed a different block at this height
+        // while we collected votes; whoever voted for both equivocated
+        let committed = self.state.read().await.get_block(block.header.height)?;
+        if let Some(committed) = committed {
+            for evidence in qc_builder.equivocations(&block.header, &committed) {
+                self.evidence.add(evidence);
+            }
+            return Err(ConsensusError::HeightMismatch {
+                expected: committed.header.height + 1,
+                got: block.header.height,
+            });
+        }
+
+        qc_builder.build()
+    }
+
+    /// Vote to time out the current view if its leader has not produced a
+    /// block in time, and send the vote to the other validators
+    async fn check_timeout(&self) {
+        let (chain_id, height) = {
+            let state = self.state.read().await;
+            (state.chain_id, state.current_height() + 1)
+        };
+        let Some(view) = self.pacemaker.take_timeout(height) else {
+            return;
+        };
+
+        warn!("No block from the leader of view {} at height {}; voting to time out", view, height);
+        let vote = TimeoutVote {
+            height,
+            view,
+            validator_pubkey: self.public_key,
+            signature: sign(&self.secret_key, &timeout_message(chain_id, height, view)),
+        };
+        let validators = self.validator_set().await;
+        if let Err(e) = self.pacemaker.add_timeout(chain_id, height, &vote, &validators) {
+            error!("Failed to record own timeout vote: {}", e);
+        }
+
+        let client = reqwest::Client::new();
+        for endpoint in &self.validator_endpoints {
+            if endpoint.pubkey == self.public_key {
                 continue;
             }
 
-            if qc_builder.has_quorum() {
-                break;
+            let url = format!(
+                "{}/consensus/timeout",
+                endpoint.address.trim_end_matches('/')
+            );
+            if let Err(e) = client.post(&url).json(&vote).send().await {
+                warn!("Failed to send timeout to {}: {}", endpoint.pubkey, e);
             }
         }
     }
@@ -338,7 +513,7 @@ impl<S: Storage + Send + Sync + Clone + 'static> Proposer<S> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use seloria_core::{GenesisConfig, KeyPair};
+    use seloria_core::{GenesisConfig, KeyPair, StateKey, StateProof};
     use seloria_mempool::MempoolConfig;
     use seloria_state::MemoryStorage;
 
@@ -354,6 +529,7 @@ mod tests {
             initial_balances: vec![],
             trusted_issuers: vec![],
             validators: vec![validator.public],
+            validator_stake: 0,
         };
         state.write().await.init_genesis(&config).unwrap();
 
@@ -363,7 +539,6 @@ mod tests {
             validator.secret,
             state,
             mempool,
-            vec![validator.public],
         );
 
         assert!(proposer.is_current_leader().await);
@@ -381,21 +556,16 @@ mod tests {
             initial_balances: vec![],
             trusted_issuers: vec![],
             validators: vec![validator.public],
+            validator_stake: 0,
         };
         state.write().await.init_genesis(&config).unwrap();
 
-        let proposer_config = ProposerConfig {
-            threshold: 1, // Single validator
-            ..Default::default()
-        };
-
         let proposer = Proposer::new(
-            proposer_config,
+            ProposerConfig::default(),
             validator.public,
             validator.secret,
             state.clone(),
             mempool,
-            vec![validator.public],
         );
 
         // Propose block
@@ -409,5 +579,19 @@ mod tests {
         // Check height updated
         let state_read = state.read().await;
         assert_eq!(state_read.current_height(), 1);
+
+        // The persisted state is provable against the certified head block
+        let head = state_read.head_block.clone().unwrap();
+        let key = StateKey::Account(validator.public);
+        let (value, proof) = state_read.prove(&key).unwrap();
+        let state_proof = StateProof {
+            value,
+            proof,
+            header: head.header,
+            qc: head.qc.unwrap(),
+        };
+        state_proof
+            .verify(&key, 1, &ValidatorSet::equal(&[validator.public]))
+            .unwrap();
     }
 }
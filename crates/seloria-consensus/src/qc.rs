@@ -1,28 +1,49 @@
-use seloria_core::{verify, Hash, PublicKey, QuorumCertificate, Sig, ValidatorSignature};
-use std::collections::HashSet;
+use seloria_core::{
+    vote_message, verify, Block, BlockHeader, Evidence, Hash, PublicKey, QuorumCertificate, Sig,
+    ValidatorSet, ValidatorSignature, VotePhase,
+};
+use seloria_state::{ChainState, Storage};
 use tracing::debug;
 
 use crate::error::ConsensusError;
+use crate::evidence::conflicting_votes;
 
 /// Quorum certificate builder/collector
 pub struct QcBuilder {
+    chain_id: u64,
+    view: u64,
+    phase: VotePhase,
     block_hash: Hash,
     signatures: Vec<ValidatorSignature>,
-    validators: HashSet<PublicKey>,
-    threshold: usize,
+    validators: ValidatorSet,
 }
 
 impl QcBuilder {
-    /// Create a new QC builder for a block
-    pub fn new(block_hash: Hash, validators: &[PublicKey], threshold: usize) -> Self {
+    /// Create a new QC builder for a block on the given chain, collecting
+    /// votes from `validators`
+    pub fn new(chain_id: u64, block_hash: Hash, validators: &ValidatorSet) -> Self {
         QcBuilder {
+            chain_id,
+            view: 0,
+            phase: VotePhase::Commit,
             block_hash,
             signatures: Vec::new(),
-            validators: validators.iter().copied().collect(),
-            threshold,
+            validators: validators.clone(),
         }
     }
 
+    /// Set the view the block was proposed in
+    pub fn with_view(mut self, view: u64) -> Self {
+        self.view = view;
+        self
+    }
+
+    /// Set the phase the votes are cast in (commit votes by default)
+    pub fn with_phase(mut self, phase: VotePhase) -> Self {
+        self.phase = phase;
+        self
+    }
+
     /// Add a validator signature
     pub fn add_signature(
         &mut self,
@@ -34,8 +55,9 @@ impl QcBuilder {
             return Err(ConsensusError::ValidatorNotFound(validator.to_hex()));
         }
 
-        // Verify signature
-        verify(&validator, self.block_hash.as_bytes(), &signature)?;
+        // Verify signature over the domain-separated vote message
+        let message = vote_message(self.chain_id, self.phase, &self.block_hash);
+        verify(&validator, &message, &signature)?;
 
         // Check for duplicate
         if self
@@ -53,18 +75,25 @@ impl QcBuilder {
         });
 
         debug!(
-            "Added signature from {}, total: {}/{}",
+            "Added signature from {}, voting power: {}/{}",
             validator,
-            self.signatures.len(),
-            self.threshold
+            self.voting_power(),
+            self.validators.quorum_power()
         );
 
         Ok(self.has_quorum())
     }
 
-    /// Check if we have enough signatures
+    /// Check if the signers hold a quorum of the voting power
     pub fn has_quorum(&self) -> bool {
-        self.signatures.len() >= self.threshold
+        self.validators
+            .has_quorum(self.signatures.iter().map(|vs| &vs.validator_pubkey))
+    }
+
+    /// Combined voting power of the validators that signed so far
+    pub fn voting_power(&self) -> u64 {
+        self.validators
+            .signed_power(self.signatures.iter().map(|vs| &vs.validator_pubkey))
     }
 
     /// Get current signature count
@@ -72,72 +101,130 @@ impl QcBuilder {
         self.signatures.len()
     }
 
+    /// Evidence against the validators whose votes collected so far for
+    /// `header` (the block being certified) are also in `other`'s QC for
+    /// the same phase, when `other` is a different block at the same height
+    /// and view
+    pub fn equivocations(&self, header: &BlockHeader, other: &Block) -> Vec<Evidence> {
+        match &other.qc {
+            Some(qc) if qc.phase == self.phase => conflicting_votes(
+                self.phase,
+                header,
+                &self.signatures,
+                &other.header,
+                &qc.signatures,
+            ),
+            _ => Vec::new(),
+        }
+    }
+
     /// Build the quorum certificate (only if we have quorum)
     pub fn build(self) -> Result<QuorumCertificate, ConsensusError> {
         if !self.has_quorum() {
-            return Err(ConsensusError::InsufficientSignatures {
-                have: self.signatures.len(),
-                need: self.threshold,
+            return Err(ConsensusError::InsufficientVotingPower {
+                have: self.voting_power(),
+                need: self.validators.quorum_power(),
             });
         }
 
         Ok(QuorumCertificate {
+            chain_id: self.chain_id,
+            view: self.view,
+            phase: self.phase,
             block_hash: self.block_hash,
             signatures: self.signatures,
         })
     }
 }
 
-/// Verify a quorum certificate
-pub fn verify_qc(
-    qc: &QuorumCertificate,
-    validators: &[PublicKey],
-    threshold: usize,
-) -> Result<(), ConsensusError> {
-    let validator_set: HashSet<_> = validators.iter().collect();
-
-    // Check we have enough signatures
-    if qc.signatures.len() < threshold {
-        return Err(ConsensusError::InsufficientSignatures {
-            have: qc.signatures.len(),
-            need: threshold,
-        });
-    }
-
+/// Verify a quorum certificate: every signer is a member of `validators`
+/// and the signers hold a quorum of its voting power
+pub fn verify_qc(qc: &QuorumCertificate, validators: &ValidatorSet) -> Result<(), ConsensusError> {
     // Verify each signature
+    let message = vote_message(qc.chain_id, qc.phase, &qc.block_hash);
     for vs in &qc.signatures {
-        if !validator_set.contains(&vs.validator_pubkey) {
+        if !validators.contains(&vs.validator_pubkey) {
             return Err(ConsensusError::ValidatorNotFound(
                 vs.validator_pubkey.to_hex(),
             ));
         }
 
-        verify(&vs.validator_pubkey, qc.block_hash.as_bytes(), &vs.signature)?;
+        verify(&vs.validator_pubkey, &message, &vs.signature)?;
+    }
+
+    // Check the signers hold enough voting power
+    let signers = || qc.signatures.iter().map(|vs| &vs.validator_pubkey);
+    if !validators.has_quorum(signers()) {
+        return Err(ConsensusError::InsufficientVotingPower {
+            have: validators.signed_power(signers()),
+            need: validators.quorum_power(),
+        });
     }
 
     Ok(())
 }
 
+/// Verify that `block` carries a quorum certificate for itself
+pub fn verify_block_qc(block: &Block, validators: &ValidatorSet) -> Result<(), ConsensusError> {
+    let qc = block
+        .qc
+        .as_ref()
+        .ok_or_else(|| ConsensusError::InvalidQc("Missing quorum certificate".to_string()))?;
+    if qc.phase != VotePhase::Commit {
+        return Err(ConsensusError::InvalidQc(format!(
+            "Expected a commit QC, got {:?}",
+            qc.phase
+        )));
+    }
+    if qc.chain_id != block.header.chain_id
+        || qc.view != block.header.view
+        || qc.block_hash != block.hash()?
+    {
+        return Err(ConsensusError::InvalidQc(
+            "QC is not for this block".to_string(),
+        ));
+    }
+    verify_qc(qc, validators)
+}
+
+/// Verify that `block` carries a quorum certificate from the validator set
+/// that was active at its height in `state`. Blocks past the head are
+/// checked against the current set.
+pub fn verify_block_qc_at<S: Storage>(
+    block: &Block,
+    state: &ChainState<S>,
+) -> Result<(), ConsensusError> {
+    verify_block_qc(block, &state.validator_set_at(block.header.height))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use seloria_core::{hash_blake3, sign, KeyPair};
+    use seloria_core::testing;
+    use seloria_core::{
+        block_vote_message, hash_blake3, sign, KeyPair, ValidatorInfo, EPOCH_LENGTH_BLOCKS,
+    };
 
     fn create_validators(n: usize) -> Vec<KeyPair> {
         (0..n).map(|_| KeyPair::generate()).collect()
     }
 
+    fn set_of(validators: &[KeyPair]) -> ValidatorSet {
+        let pubkeys: Vec<_> = validators.iter().map(|v| v.public).collect();
+        ValidatorSet::equal(&pubkeys)
+    }
+
     #[test]
     fn test_qc_builder_basic() {
         let validators = create_validators(4);
-        let validator_pubkeys: Vec<_> = validators.iter().map(|v| v.public).collect();
+        let validator_set = set_of(&validators);
         let block_hash = hash_blake3(b"test block");
 
-        let mut builder = QcBuilder::new(block_hash, &validator_pubkeys, 3);
+        let mut builder = QcBuilder::new(1, block_hash, &validator_set);
 
         // Add 3 signatures
         for validator in &validators[..3] {
-            let sig = sign(&validator.secret, block_hash.as_bytes());
+            let sig = sign(&validator.secret, &block_vote_message(1, &block_hash));
             let has_quorum = builder.add_signature(validator.public, sig).unwrap();
             if validators.iter().position(|v| v.public == validator.public).unwrap() < 2 {
                 assert!(!has_quorum);
@@ -153,13 +240,13 @@ mod tests {
     #[test]
     fn test_qc_builder_invalid_validator() {
         let validators = create_validators(4);
-        let validator_pubkeys: Vec<_> = validators.iter().map(|v| v.public).collect();
+        let validator_set = set_of(&validators);
         let block_hash = hash_blake3(b"test block");
 
-        let mut builder = QcBuilder::new(block_hash, &validator_pubkeys, 3);
+        let mut builder = QcBuilder::new(1, block_hash, &validator_set);
 
         let outsider = KeyPair::generate();
-        let sig = sign(&outsider.secret, block_hash.as_bytes());
+        let sig = sign(&outsider.secret, &block_vote_message(1, &block_hash));
         let result = builder.add_signature(outsider.public, sig);
 
         assert!(matches!(result, Err(ConsensusError::ValidatorNotFound(_))));
@@ -168,10 +255,10 @@ mod tests {
     #[test]
     fn test_qc_builder_invalid_signature() {
         let validators = create_validators(4);
-        let validator_pubkeys: Vec<_> = validators.iter().map(|v| v.public).collect();
+        let validator_set = set_of(&validators);
         let block_hash = hash_blake3(b"test block");
 
-        let mut builder = QcBuilder::new(block_hash, &validator_pubkeys, 3);
+        let mut builder = QcBuilder::new(1, block_hash, &validator_set);
 
         // Sign wrong message
         let wrong_sig = sign(&validators[0].secret, b"wrong message");
@@ -183,20 +270,102 @@ mod tests {
     #[test]
     fn test_verify_qc() {
         let validators = create_validators(4);
-        let validator_pubkeys: Vec<_> = validators.iter().map(|v| v.public).collect();
+        let validator_set = set_of(&validators);
         let block_hash = hash_blake3(b"test block");
 
         let qc = QuorumCertificate {
+            chain_id: 1,
+            view: 0,
+            phase: VotePhase::Commit,
             block_hash,
             signatures: validators[..3]
                 .iter()
                 .map(|v| ValidatorSignature {
                     validator_pubkey: v.public,
-                    signature: sign(&v.secret, block_hash.as_bytes()),
+                    signature: sign(&v.secret, &block_vote_message(1, &block_hash)),
                 })
                 .collect(),
         };
 
-        verify_qc(&qc, &validator_pubkeys, 3).unwrap();
+        verify_qc(&qc, &validator_set).unwrap();
+
+        // Votes cast on another chain must not verify
+        let mut replayed = qc.clone();
+        replayed.chain_id = 2;
+        assert!(verify_qc(&replayed, &validator_set).is_err());
+
+        // ...nor commit votes relabeled as another phase
+        let relabeled = qc.clone().with_phase(VotePhase::Prepare);
+        assert!(verify_qc(&relabeled, &validator_set).is_err());
+    }
+
+    #[test]
+    fn test_quorum_is_stake_weighted() {
+        let validators = create_validators(4);
+        let block_hash = hash_blake3(b"test block");
+        // The first validator holds 70 of 100 power
+        let weighted = ValidatorSet::new(
+            validators
+                .iter()
+                .zip([70, 10, 10, 10])
+                .map(|(v, power)| ValidatorInfo {
+                    pubkey: v.public,
+                    power,
+                })
+                .collect(),
+        );
+
+        // Three of the four validators are not a quorum without the first
+        let mut builder = QcBuilder::new(1, block_hash, &weighted);
+        for validator in &validators[1..] {
+            let sig = sign(&validator.secret, &block_vote_message(1, &block_hash));
+            assert!(!builder.add_signature(validator.public, sig).unwrap());
+        }
+        assert_eq!(builder.voting_power(), 30);
+        assert!(matches!(
+            builder.build(),
+            Err(ConsensusError::InsufficientVotingPower { have: 30, need: 67 })
+        ));
+
+        let mut builder = QcBuilder::new(1, block_hash, &weighted);
+        for validator in &validators[..2] {
+            let sig = sign(&validator.secret, &block_vote_message(1, &block_hash));
+            builder.add_signature(validator.public, sig).unwrap();
+        }
+        let qc = builder.build().unwrap();
+        verify_qc(&qc, &weighted).unwrap();
+        // The same two signers are not a quorum when power is equal
+        assert!(verify_qc(&qc, &set_of(&validators)).is_err());
+    }
+
+    fn certified_block(height: u64, signers: &[KeyPair]) -> Block {
+        testing::certified_block(testing::header(1, height), signers)
+    }
+
+    #[test]
+    fn test_block_qc_checked_against_its_epoch() {
+        let old = create_validators(3);
+        let new = create_validators(3);
+        let mut state = ChainState::new(seloria_state::MemoryStorage::new());
+        state
+            .init_genesis(&seloria_core::GenesisConfig {
+                chain_id: 1,
+                timestamp: 0,
+                initial_balances: vec![],
+                trusted_issuers: vec![],
+                validators: set_of(&old).pubkeys(),
+                validator_stake: 0,
+            })
+            .unwrap();
+        state.schedule_validator_set(1, set_of(&new));
+        state.end_block(EPOCH_LENGTH_BLOCKS);
+
+        // A block from before the change still verifies with its own set
+        verify_block_qc_at(&certified_block(5, &old), &state).unwrap();
+        assert!(verify_block_qc_at(&certified_block(5, &new), &state).is_err());
+
+        let next_epoch = EPOCH_LENGTH_BLOCKS + 1;
+        verify_block_qc_at(&certified_block(next_epoch, &new), &state).unwrap();
+        assert!(verify_block_qc_at(&certified_block(next_epoch, &old), &state).is_err());
     }
 }
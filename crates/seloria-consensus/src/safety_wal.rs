@@ -0,0 +1,186 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use seloria_core::{hash_blake3, serialize, Hash, QuorumCertificate, VotePhase};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::error::ConsensusError;
+
+/// Record header: payload length (u32 LE) followed by a payload checksum
+const RECORD_HEADER_LEN: usize = 4 + CHECKSUM_LEN;
+const CHECKSUM_LEN: usize = 8;
+
+/// A vote this validator signed, with the QCs it raised its high QC or
+/// locked on to when casting it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedVote {
+    pub height: u64,
+    pub view: u64,
+    pub phase: VotePhase,
+    pub block_hash: Hash,
+    /// Prepare QC that became the high QC (pre-commit votes only)
+    pub high_qc: Option<QuorumCertificate>,
+    /// Pre-commit QC the vote locked on (commit votes only)
+    pub locked_qc: Option<QuorumCertificate>,
+}
+
+/// Append-only record of the votes a validator signed at the height being
+/// decided.
+///
+/// Every vote is appended and fsynced before its signature is released, so
+/// a validator that crashes and restarts still refuses to sign a block that
+/// conflicts with one it already voted for. Records are checksummed; a torn
+/// write at the end of the file is discarded on open, which is safe because
+/// its signature never left the node.
+pub struct SafetyWal {
+    path: PathBuf,
+    file: File,
+}
+
+impl SafetyWal {
+    /// Open (or create) the WAL at `path`, returning it with the votes it
+    /// holds in the order they were signed
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<(Self, Vec<SignedVote>), ConsensusError> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(wal_err)?;
+        }
+        let bytes = if path.exists() {
+            fs::read(&path).map_err(wal_err)?
+        } else {
+            Vec::new()
+        };
+
+        let mut votes = Vec::new();
+        let mut offset = 0usize;
+        while let Some((vote, len)) = decode_record(&bytes[offset..]) {
+            votes.push(vote);
+            offset += len;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(wal_err)?;
+        if offset < bytes.len() {
+            warn!(
+                "Discarding {} bytes after the last complete vote in {}",
+                bytes.len() - offset,
+                path.display()
+            );
+            file.set_len(offset as u64).map_err(wal_err)?;
+            file.sync_all().map_err(wal_err)?;
+        }
+
+        debug!(
+            "Opened safety WAL at {} ({} votes)",
+            path.display(),
+            votes.len()
+        );
+        Ok((SafetyWal { path, file }, votes))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Durably record a vote. Must return before the vote is signed.
+    pub fn append(&mut self, vote: &SignedVote) -> Result<(), ConsensusError> {
+        let payload = serialize::to_bytes(vote)?;
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&checksum(&payload));
+        record.extend_from_slice(&payload);
+
+        self.file.write_all(&record).map_err(wal_err)?;
+        self.file.sync_data().map_err(wal_err)
+    }
+
+    /// Drop the votes of earlier heights once the chain has moved past them
+    pub fn reset(&mut self) -> Result<(), ConsensusError> {
+        self.file.set_len(0).map_err(wal_err)?;
+        self.file.sync_all().map_err(wal_err)
+    }
+}
+
+fn wal_err(e: std::io::Error) -> ConsensusError {
+    ConsensusError::SafetyWal(e.to_string())
+}
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let mut out = [0u8; CHECKSUM_LEN];
+    out.copy_from_slice(&hash_blake3(payload).as_bytes()[..CHECKSUM_LEN]);
+    out
+}
+
+/// Decode the vote record at the start of `bytes`, returning the vote and
+/// the record length, or `None` if the record is incomplete or corrupt
+fn decode_record(bytes: &[u8]) -> Option<(SignedVote, usize)> {
+    if bytes.len() < RECORD_HEADER_LEN {
+        return None;
+    }
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&bytes[..4]);
+    let end = RECORD_HEADER_LEN.checked_add(u32::from_le_bytes(len) as usize)?;
+    if end > bytes.len() {
+        return None;
+    }
+    let payload = &bytes[RECORD_HEADER_LEN..end];
+    if checksum(payload) != bytes[4..RECORD_HEADER_LEN] {
+        return None;
+    }
+    let vote = serialize::from_bytes(payload).ok()?;
+    Some((vote, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(height: u64, view: u64, phase: VotePhase) -> SignedVote {
+        SignedVote {
+            height,
+            view,
+            phase,
+            block_hash: hash_blake3(&height.to_le_bytes()),
+            high_qc: None,
+            locked_qc: None,
+        }
+    }
+
+    #[test]
+    fn test_votes_survive_reopen_and_torn_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("safety.wal");
+
+        let (mut wal, votes) = SafetyWal::open(&path).unwrap();
+        assert!(votes.is_empty());
+        wal.append(&vote(1, 0, VotePhase::Prepare)).unwrap();
+        wal.reset().unwrap();
+        wal.append(&vote(2, 0, VotePhase::Prepare)).unwrap();
+        wal.append(&vote(2, 0, VotePhase::PreCommit)).unwrap();
+        drop(wal);
+
+        // A torn record after the last vote is dropped
+        let mut bytes = fs::read(&path).unwrap();
+        let good_len = bytes.len();
+        bytes.extend_from_slice(&[40, 0, 0, 0, 1, 2]);
+        fs::write(&path, &bytes).unwrap();
+
+        let (mut wal, votes) = SafetyWal::open(&path).unwrap();
+        let recorded: Vec<_> = votes.iter().map(|v| (v.height, v.phase)).collect();
+        assert_eq!(
+            recorded,
+            vec![(2, VotePhase::Prepare), (2, VotePhase::PreCommit)]
+        );
+        assert_eq!(fs::metadata(&path).unwrap().len(), good_len as u64);
+
+        // Appends continue after the recovered votes
+        wal.append(&vote(2, 0, VotePhase::Commit)).unwrap();
+        let (_, votes) = SafetyWal::open(&path).unwrap();
+        assert_eq!(votes.len(), 3);
+    }
+}
@@ -1,9 +1,18 @@
-use seloria_core::{sign, Block, PublicKey, SecretKey, Sig};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+use seloria_core::{
+    sign, vote_message, Block, Hash, PublicKey, QuorumCertificate, SecretKey, Sig, ValidatorSet,
+    VotePhase,
+};
 use seloria_state::{ChainState, Storage};
 use tracing::{debug, info};
 
 use crate::block_builder::BlockBuilder;
 use crate::error::ConsensusError;
+use crate::pacemaker::leader_for;
+use crate::safety_wal::{SafetyWal, SignedVote};
 
 /// A validator node that signs blocks
 pub struct Validator {
@@ -12,6 +21,136 @@ pub struct Validator {
     secret_key: SecretKey,
     /// Block builder for validation
     block_builder: BlockBuilder,
+    /// Votes and certificates at the height being decided
+    safety: Mutex<SafetyState>,
+}
+
+/// What this validator has voted for at the height being decided, which
+/// the safety rules check new votes against
+#[derive(Default)]
+struct SafetyState {
+    height: u64,
+    /// Blocks prepared at `height`, by hash
+    proposals: HashMap<Hash, Block>,
+    /// View and block of the last vote in each phase
+    last_votes: HashMap<VotePhase, (u64, Hash)>,
+    /// Highest prepare QC seen at `height`
+    high_qc: Option<QuorumCertificate>,
+    /// Pre-commit QC this validator is locked on at `height`
+    locked_qc: Option<QuorumCertificate>,
+    /// Durable record of the votes, if this validator keeps one
+    wal: Option<SafetyWal>,
+}
+
+impl SafetyState {
+    /// Start over once the chain has moved on to `height`
+    fn enter_height(&mut self, height: u64) -> Result<(), ConsensusError> {
+        if height > self.height {
+            let mut wal = self.wal.take();
+            if let Some(wal) = &mut wal {
+                wal.reset()?;
+            }
+            *self = SafetyState {
+                height,
+                wal,
+                ..Default::default()
+            };
+        } else if height < self.height {
+            return Err(ConsensusError::SafetyViolation(format!(
+                "Height {} is already decided",
+                height
+            )));
+        }
+        Ok(())
+    }
+
+    /// Refuse to vote for two blocks in the same view, or to go back to an
+    /// earlier view, in `phase`. Repeating the last vote is allowed.
+    fn check_vote(&self, phase: VotePhase, view: u64, block_hash: Hash) -> Result<(), ConsensusError> {
+        match self.last_votes.get(&phase) {
+            Some(&(last_view, last_hash))
+                if view < last_view || (view == last_view && block_hash != last_hash) =>
+            {
+                Err(ConsensusError::SafetyViolation(format!(
+                    "Already voted {:?} for {} in view {}",
+                    phase, last_hash, last_view
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Record a vote, and the high QC or lock it moves to, in the WAL and
+    /// then in memory. Called before the vote is signed.
+    fn record_vote(
+        &mut self,
+        phase: VotePhase,
+        view: u64,
+        block_hash: Hash,
+        high: Option<QuorumCertificate>,
+        lock: Option<QuorumCertificate>,
+    ) -> Result<(), ConsensusError> {
+        if let Some(wal) = &mut self.wal {
+            wal.append(&SignedVote {
+                height: self.height,
+                view,
+                phase,
+                block_hash,
+                high_qc: high.clone(),
+                locked_qc: lock.clone(),
+            })?;
+        }
+        self.last_votes.insert(phase, (view, block_hash));
+        if high.is_some() {
+            self.high_qc = high;
+        }
+        if lock.is_some() {
+            self.locked_qc = lock;
+        }
+        Ok(())
+    }
+
+    /// Restore the votes and certificates recorded before a restart
+    fn replay(&mut self, votes: Vec<SignedVote>) {
+        for vote in votes {
+            if vote.height > self.height {
+                self.height = vote.height;
+                self.last_votes.clear();
+                self.high_qc = None;
+                self.locked_qc = None;
+            }
+            self.last_votes
+                .insert(vote.phase, (vote.view, vote.block_hash));
+            if vote.high_qc.is_some() {
+                self.high_qc = vote.high_qc;
+            }
+            if vote.locked_qc.is_some() {
+                self.locked_qc = vote.locked_qc;
+            }
+        }
+    }
+}
+
+/// Hash of `block` as proposed in `view` by `proposer`. A block is
+/// re-proposed in a later view by changing only these two header fields.
+fn hash_as_proposed(block: &Block, view: u64, proposer: PublicKey) -> Result<Hash, ConsensusError> {
+    let mut header = block.header.clone();
+    header.view = view;
+    header.proposer_pubkey = proposer;
+    Ok(header.hash()?)
+}
+
+/// Whether `block` is the block certified by `qc`, re-proposed in a later
+/// view by its leader
+fn reproposes(
+    block: &Block,
+    qc: &QuorumCertificate,
+    validators: &ValidatorSet,
+) -> Result<bool, ConsensusError> {
+    let Some(leader) = leader_for(&validators.pubkeys(), block.header.height, qc.view) else {
+        return Ok(false);
+    };
+    Ok(hash_as_proposed(block, qc.view, leader)? == qc.block_hash)
 }
 
 impl Validator {
@@ -21,57 +160,201 @@ impl Validator {
             public_key,
             secret_key,
             block_builder,
+            safety: Mutex::new(SafetyState::default()),
+        }
+    }
+
+    /// Keep a durable record of every vote in the safety WAL at `path`,
+    /// restoring the votes recorded there before a restart. Votes are only
+    /// signed once recorded, so conflicting votes are refused across crashes.
+    pub fn with_safety_wal(self, path: impl Into<PathBuf>) -> Result<Self, ConsensusError> {
+        let (wal, votes) = SafetyWal::open(path)?;
+        {
+            let mut safety = self.lock();
+            if let Some(last) = votes.last() {
+                info!(
+                    "Restored {} votes at height {} from {}",
+                    votes.len(),
+                    last.height,
+                    wal.path().display()
+                );
+            }
+            safety.replay(votes);
+            safety.wal = Some(wal);
         }
+        Ok(self)
     }
 
-    /// Validate a proposed block and sign it if valid
-    pub fn validate_and_sign<S: Storage + Clone>(
+    /// Validate a proposed block and cast a prepare vote for it.
+    ///
+    /// `justify` is the leader's highest prepare QC when it re-proposes a
+    /// block prepared in an earlier view. A validator locked on a block only
+    /// votes for that block, or for one justified by a prepare QC from a
+    /// later view than its lock.
+    pub fn vote_prepare<S: Storage>(
         &self,
         block: &Block,
+        justify: Option<&QuorumCertificate>,
         state: &ChainState<S>,
+        validators: &ValidatorSet,
     ) -> Result<Sig, ConsensusError> {
+        let block_hash = block.hash()?;
         info!(
-            "Validating block {} at height {}",
-            block.hash()?,
-            block.header.height
+            "Validating block {} at height {} view {}",
+            block_hash, block.header.height, block.header.view
         );
 
+        if let Some(qc) = justify {
+            if qc.phase != VotePhase::Prepare
+                || qc.view >= block.header.view
+                || !reproposes(block, qc, validators)?
+            {
+                return Err(ConsensusError::InvalidQc(
+                    "Justify QC does not certify the proposed block".to_string(),
+                ));
+            }
+            verify_phase_qc(qc, validators)?;
+        }
+
         // Basic validation
         self.block_builder.validate_block(block, state)?;
 
-        // Re-execute and verify on a clone of state
+        // Re-execute and verify on an overlay of state
         self.block_builder.verify_execution(block, state)?;
 
-        // Sign the block
-        let block_hash = block.hash()?;
-        let signature = sign(&self.secret_key, block_hash.as_bytes());
+        let mut safety = self.lock();
+        safety.enter_height(block.header.height)?;
+        safety.check_vote(VotePhase::Prepare, block.header.view, block_hash)?;
+        if let Some(locked) = &safety.locked_qc {
+            let extends_lock = reproposes(block, locked, validators)?;
+            let newer_justify = justify.is_some_and(|qc| qc.view > locked.view);
+            if !extends_lock && !newer_justify {
+                return Err(ConsensusError::SafetyViolation(format!(
+                    "Locked on {} from view {}",
+                    locked.block_hash, locked.view
+                )));
+            }
+        }
+
+        safety.record_vote(VotePhase::Prepare, block.header.view, block_hash, None, None)?;
+        safety.proposals.insert(block_hash, block.clone());
+        let signature = self.sign_vote(block.header.chain_id, VotePhase::Prepare, &block_hash);
 
         debug!(
-            "Validator {} signed block {}",
+            "Validator {} prepared block {}",
             self.public_key, block_hash
         );
 
         Ok(signature)
     }
 
-    /// Check if this validator is the leader for a given height
-    pub fn is_leader(&self, height: u64, validators: &[PublicKey]) -> bool {
-        if validators.is_empty() {
-            return false;
+    /// Cast the vote that follows a certificate for a block this validator
+    /// prepared: a pre-commit vote on a prepare QC, which becomes the high
+    /// QC, or a commit vote on a pre-commit QC, which this validator locks on.
+    /// Returns the phase voted in and the signature.
+    pub fn vote(
+        &self,
+        qc: &QuorumCertificate,
+        validators: &ValidatorSet,
+    ) -> Result<(VotePhase, Sig), ConsensusError> {
+        let phase = qc.phase.next().ok_or_else(|| {
+            ConsensusError::InvalidQc("Block is already committed".to_string())
+        })?;
+        verify_phase_qc(qc, validators)?;
+
+        let mut safety = self.lock();
+        if !safety.proposals.contains_key(&qc.block_hash) {
+            return Err(ConsensusError::InvalidQc(format!(
+                "Block {} was not prepared at height {}",
+                qc.block_hash, safety.height
+            )));
         }
-        let leader_index = (height as usize) % validators.len();
-        validators.get(leader_index) == Some(&self.public_key)
+        safety.check_vote(phase, qc.view, qc.block_hash)?;
+
+        let slot = match qc.phase {
+            VotePhase::Prepare => &safety.high_qc,
+            _ => &safety.locked_qc,
+        };
+        let newer = slot.as_ref().is_none_or(|current| qc.view > current.view);
+        let high = (newer && qc.phase == VotePhase::Prepare).then(|| qc.clone());
+        let lock = (newer && qc.phase == VotePhase::PreCommit).then(|| qc.clone());
+        safety.record_vote(phase, qc.view, qc.block_hash, high, lock)?;
+
+        debug!(
+            "Validator {} voted {:?} for block {}",
+            self.public_key, phase, qc.block_hash
+        );
+
+        Ok((phase, self.sign_vote(qc.chain_id, phase, &qc.block_hash)))
     }
+
+    /// Cast a commit vote for a block this node built and executed itself
+    /// (single-validator fast path, where no other votes are needed)
+    pub fn commit_own_block(&self, block: &Block) -> Result<Sig, ConsensusError> {
+        let block_hash = block.hash()?;
+        let mut safety = self.lock();
+        safety.enter_height(block.header.height)?;
+        safety.check_vote(VotePhase::Commit, block.header.view, block_hash)?;
+        safety.record_vote(VotePhase::Commit, block.header.view, block_hash, None, None)?;
+        Ok(self.sign_vote(block.header.chain_id, VotePhase::Commit, &block_hash))
+    }
+
+    /// Block the leader of `view` at `height` has to propose, if any, with
+    /// the QC justifying it: the block this validator already prepared in
+    /// `view`, or else the block of its highest prepare QC. Proposing
+    /// anything else would not get past validators locked on that block.
+    pub fn reproposal(
+        &self,
+        height: u64,
+        view: u64,
+    ) -> Option<(Block, Option<QuorumCertificate>)> {
+        let safety = self.lock();
+        if safety.height != height {
+            return None;
+        }
+        let high_qc = safety.high_qc.clone();
+
+        if let Some(&(last_view, last_hash)) = safety.last_votes.get(&VotePhase::Prepare) {
+            if last_view == view {
+                let block = safety.proposals.get(&last_hash)?.clone();
+                let justify = high_qc.filter(|qc| qc.view < view && qc.block_hash != last_hash);
+                return Some((block, justify));
+            }
+        }
+
+        let high_qc = high_qc?;
+        let mut block = safety.proposals.get(&high_qc.block_hash)?.clone();
+        block.header.view = view;
+        block.header.proposer_pubkey = self.public_key;
+        block.qc = None;
+        Some((block, Some(high_qc)))
+    }
+
+    /// Check if this validator is the leader for a given height and view
+    pub fn is_leader(&self, height: u64, view: u64, validators: &[PublicKey]) -> bool {
+        leader_for(validators, height, view) == Some(self.public_key)
+    }
+
+    fn sign_vote(&self, chain_id: u64, phase: VotePhase, block_hash: &Hash) -> Sig {
+        sign(&self.secret_key, &vote_message(chain_id, phase, block_hash))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SafetyState> {
+        self.safety.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Check a prepare or pre-commit QC carries a quorum of votes
+fn verify_phase_qc(qc: &QuorumCertificate, validators: &ValidatorSet) -> Result<(), ConsensusError> {
+    qc.verify_phase_quorum(validators)
+        .map_err(|e| ConsensusError::InvalidQc(e.to_string()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::block_builder::BlockBuilderConfig;
-    use seloria_core::{
-        verify, AgentCertificate, Capability, GenesisConfig, Hash, KeyPair,
-        SignedAgentCertificate, hash_blake3,
-    };
+    use seloria_core::{verify, GenesisConfig, KeyPair};
     use seloria_mempool::{Mempool, MempoolConfig};
     use seloria_state::MemoryStorage;
 
@@ -87,6 +370,7 @@ mod tests {
             initial_balances: vec![],
             trusted_issuers: vec![issuer.public],
             validators: vec![validator_kp.public],
+            validator_stake: 0,
         };
         state.init_genesis(&config).unwrap();
 
@@ -106,11 +390,116 @@ mod tests {
         state.head_block = Some(config.create_genesis_block());
 
         let validator = Validator::new(validator_kp.public, validator_kp.secret.clone(), block_builder);
-        let signature = validator.validate_and_sign(&block, &state).unwrap();
+        let signature = validator
+            .vote_prepare(&block, None, &state, &ValidatorSet::equal(&[validator_kp.public]))
+            .unwrap();
 
         // Verify signature
         let block_hash = block.hash().unwrap();
-        verify(&validator_kp.public, block_hash.as_bytes(), &signature).unwrap();
+        let message = vote_message(block.header.chain_id, VotePhase::Prepare, &block_hash);
+        verify(&validator_kp.public, &message, &signature).unwrap();
+    }
+
+    /// QC over `block_hash` in `phase` signed by all of `signers`
+    fn phase_qc(
+        signers: &[KeyPair],
+        phase: VotePhase,
+        view: u64,
+        block_hash: Hash,
+    ) -> QuorumCertificate {
+        let mut qc = QuorumCertificate::new(1, block_hash)
+            .with_view(view)
+            .with_phase(phase);
+        for signer in signers {
+            qc.add_signature(signer.public, sign(&signer.secret, &vote_message(1, phase, &block_hash)));
+        }
+        qc
+    }
+
+    /// `block` as proposed by the leader of `view`
+    fn in_view(block: &Block, view: u64, validators: &[PublicKey]) -> Block {
+        let mut block = block.clone();
+        block.header.view = view;
+        block.header.proposer_pubkey = leader_for(validators, block.header.height, view).unwrap();
+        block
+    }
+
+    #[tokio::test]
+    async fn test_safety_rules_lock_on_precommit_qc() {
+        let keys: Vec<_> = (0..4).map(|_| KeyPair::generate()).collect();
+        let validators: Vec<_> = keys.iter().map(|k| k.public).collect();
+        let set = ValidatorSet::equal(&validators);
+        let mut state = ChainState::new(MemoryStorage::new());
+        state
+            .init_genesis(&GenesisConfig {
+                chain_id: 1,
+                timestamp: 0,
+                initial_balances: vec![],
+                trusted_issuers: vec![],
+                validators: validators.clone(),
+                validator_stake: 0,
+            })
+            .unwrap();
+
+        let builder = || BlockBuilder::new(BlockBuilderConfig::default());
+        let mempool = Mempool::new(MempoolConfig::default());
+        let base = builder()
+            .build_block(&state, &mempool, validators[0], 1000)
+            .await
+            .unwrap();
+        let other = builder()
+            .build_block(&state, &mempool, validators[0], 2000)
+            .await
+            .unwrap();
+        let validator = Validator::new(keys[3].public, keys[3].secret.clone(), builder());
+
+        // Prepare a block in view 0, and refuse a conflicting one in the same view
+        let block = in_view(&base, 0, &validators);
+        let block_hash = block.hash().unwrap();
+        validator
+            .vote_prepare(&block, None, &state, &set)
+            .unwrap();
+        let conflicting = in_view(&other, 0, &validators);
+        assert!(matches!(
+            validator.vote_prepare(&conflicting, None, &state, &set),
+            Err(ConsensusError::SafetyViolation(_))
+        ));
+
+        // Pre-commit on its prepare QC and lock on its pre-commit QC
+        let prepare_qc = phase_qc(&keys[..3], VotePhase::Prepare, 0, block_hash);
+        let (phase, _) = validator.vote(&prepare_qc, &set).unwrap();
+        assert_eq!(phase, VotePhase::PreCommit);
+        let precommit_qc = phase_qc(&keys[..3], VotePhase::PreCommit, 0, block_hash);
+        let (phase, signature) = validator.vote(&precommit_qc, &set).unwrap();
+        assert_eq!(phase, VotePhase::Commit);
+        verify(&keys[3].public, &vote_message(1, VotePhase::Commit, &block_hash), &signature).unwrap();
+
+        // A QC short of quorum is not voted on
+        let weak_qc = phase_qc(&keys[..2], VotePhase::Prepare, 0, block_hash);
+        assert!(validator.vote(&weak_qc, &set).is_err());
+
+        // Once locked, a new block in a later view is refused...
+        let fresh = in_view(&other, 1, &validators);
+        assert!(matches!(
+            validator.vote_prepare(&fresh, None, &state, &set),
+            Err(ConsensusError::SafetyViolation(_))
+        ));
+        // ...even with a justify QC for a different block
+        assert!(validator
+            .vote_prepare(&fresh, Some(&prepare_qc), &state, &set)
+            .is_err());
+
+        // ...while the locked block re-proposed by the next leader is accepted
+        let reproposed = in_view(&block, 1, &validators);
+        validator
+            .vote_prepare(&reproposed, Some(&prepare_qc), &state, &set)
+            .unwrap();
+
+        // A later leader re-proposes the block of its high QC
+        let (next, justify) = validator.reproposal(1, 2).unwrap();
+        assert_eq!(next.header.view, 2);
+        assert_eq!(next.header.tx_root, block.header.tx_root);
+        assert_eq!(justify.unwrap().block_hash, block_hash);
     }
 
     #[test]
@@ -128,11 +517,111 @@ mod tests {
             );
 
             // At height i, validator i should be leader
-            assert!(validator.is_leader(i as u64, &validator_pubkeys));
+            assert!(validator.is_leader(i as u64, 0, &validator_pubkeys));
             // At height i+4, validator i should be leader again
-            assert!(validator.is_leader((i + 4) as u64, &validator_pubkeys));
+            assert!(validator.is_leader((i + 4) as u64, 0, &validator_pubkeys));
             // At height i+1, validator i should not be leader
-            assert!(!validator.is_leader((i + 1) as u64 % 4, &validator_pubkeys));
+            assert!(!validator.is_leader((i + 1) as u64 % 4, 0, &validator_pubkeys));
+            // ...unless the leader of height i+1 timed out, passing the turn on
+            assert!(validator.is_leader((i + 3) as u64, 1, &validator_pubkeys));
         }
     }
+
+    #[tokio::test]
+    async fn test_votes_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal_path = dir.path().join("safety.wal");
+        let key = KeyPair::generate();
+        let mut state = ChainState::new(MemoryStorage::new());
+        state
+            .init_genesis(&GenesisConfig {
+                chain_id: 1,
+                timestamp: 0,
+                initial_balances: vec![],
+                trusted_issuers: vec![],
+                validators: vec![key.public],
+                validator_stake: 0,
+            })
+            .unwrap();
+
+        let builder = || BlockBuilder::new(BlockBuilderConfig::default());
+        let mempool = Mempool::new(MempoolConfig::default());
+        let block = builder()
+            .build_block(&state, &mempool, key.public, 1000)
+            .await
+            .unwrap();
+        let conflicting = builder()
+            .build_block(&state, &mempool, key.public, 2000)
+            .await
+            .unwrap();
+
+        let validator = Validator::new(key.public, key.secret.clone(), builder())
+            .with_safety_wal(&wal_path)
+            .unwrap();
+        validator.commit_own_block(&block).unwrap();
+        drop(validator);
+
+        // After a restart the vote is still on record: the same block may be
+        // signed again, a conflicting one in the same view may not
+        let restarted = Validator::new(key.public, key.secret.clone(), builder())
+            .with_safety_wal(&wal_path)
+            .unwrap();
+        restarted.commit_own_block(&block).unwrap();
+        assert!(matches!(
+            restarted.commit_own_block(&conflicting),
+            Err(ConsensusError::SafetyViolation(_))
+        ));
+
+        // A later view is a fresh vote
+        let mut next_view = conflicting.clone();
+        next_view.header.view = 1;
+        restarted.commit_own_block(&next_view).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_high_qc_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal_path = dir.path().join("safety.wal");
+        let keys: Vec<_> = (0..4).map(|_| KeyPair::generate()).collect();
+        let validators: Vec<_> = keys.iter().map(|k| k.public).collect();
+        let set = ValidatorSet::equal(&validators);
+        let mut state = ChainState::new(MemoryStorage::new());
+        state
+            .init_genesis(&GenesisConfig {
+                chain_id: 1,
+                timestamp: 0,
+                initial_balances: vec![],
+                trusted_issuers: vec![],
+                validators: validators.clone(),
+                validator_stake: 0,
+            })
+            .unwrap();
+
+        let builder = || BlockBuilder::new(BlockBuilderConfig::default());
+        let mempool = Mempool::new(MempoolConfig::default());
+        let base = builder()
+            .build_block(&state, &mempool, validators[0], 1000)
+            .await
+            .unwrap();
+        let block = in_view(&base, 0, &validators);
+        let block_hash = block.hash().unwrap();
+
+        let validator = Validator::new(keys[3].public, keys[3].secret.clone(), builder())
+            .with_safety_wal(&wal_path)
+            .unwrap();
+        validator.vote_prepare(&block, None, &state, &set).unwrap();
+        let prepare_qc = phase_qc(&keys[..3], VotePhase::Prepare, 0, block_hash);
+        validator.vote(&prepare_qc, &set).unwrap();
+        drop(validator);
+
+        let restarted = Validator::new(keys[3].public, keys[3].secret.clone(), builder())
+            .with_safety_wal(&wal_path)
+            .unwrap();
+        let safety = restarted.lock();
+        let high_qc = safety.high_qc.as_ref().expect("high QC restored");
+        assert_eq!(high_qc.phase, VotePhase::Prepare);
+        assert_eq!((high_qc.view, high_qc.block_hash), (0, block_hash));
+        assert_eq!(high_qc.signatures.len(), 3);
+        assert!(safety.locked_qc.is_none());
+    }
 }
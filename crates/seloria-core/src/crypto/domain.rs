@@ -0,0 +1,45 @@
+//! Domain separation for signed payloads
+//!
+//! Every signature covers a domain tag naming the kind of payload and its
+//! format version, followed by the chain ID. A signature made for one chain
+//! or one kind of payload can therefore never verify as another.
+
+/// Transaction signing domain
+pub const TX_V1: &[u8] = b"seloria/tx/v1";
+/// Agent certificate signing domain (certificate version 2)
+pub const AGENT_CERT_V2: &[u8] = b"seloria/agent-cert/v2";
+/// Validator block vote signing domain
+pub const BLOCK_VOTE_V1: &[u8] = b"seloria/block-vote/v1";
+/// Validator prepare-phase vote signing domain
+pub const PREPARE_VOTE_V1: &[u8] = b"seloria/prepare-vote/v1";
+/// Validator pre-commit-phase vote signing domain
+pub const PRECOMMIT_VOTE_V1: &[u8] = b"seloria/precommit-vote/v1";
+/// Validator view timeout signing domain
+pub const TIMEOUT_V1: &[u8] = b"seloria/timeout/v1";
+/// Validator set update signing domain
+pub const VALIDATOR_SET_V1: &[u8] = b"seloria/validator-set/v1";
+
+/// Build the message to sign: `len(domain) || domain || chain_id || payload`
+pub fn signing_message(domain: &[u8], chain_id: u64, payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(1 + domain.len() + 8 + payload.len());
+    message.push(domain.len() as u8);
+    message.extend_from_slice(domain);
+    message.extend_from_slice(&chain_id.to_le_bytes());
+    message.extend_from_slice(payload);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signing_message_separates_domains_and_chains() {
+        let payload = b"payload";
+        let base = signing_message(TX_V1, 1, payload);
+
+        assert_ne!(base, signing_message(BLOCK_VOTE_V1, 1, payload));
+        assert_ne!(base, signing_message(TX_V1, 2, payload));
+        assert_eq!(base, signing_message(TX_V1, 1, payload));
+    }
+}
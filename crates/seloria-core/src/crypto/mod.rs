@@ -1,7 +1,10 @@
+pub mod domain;
 pub mod hash;
 pub mod keys;
 pub mod signature;
+pub mod smt;
 
 pub use hash::{hash_blake3, merkle_root, Hash};
 pub use keys::{KeyPair, PublicKey, SecretKey};
 pub use signature::{sign, verify, Sig};
+pub use smt::SmtProof;
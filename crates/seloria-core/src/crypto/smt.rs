@@ -0,0 +1,127 @@
+//! Sparse Merkle tree hashing and proof verification
+//!
+//! The state tree maps `blake3(storage_key)` to `blake3(value)` in a binary
+//! tree of depth 256, where the bits of the key hash choose the path from the
+//! root. Empty subtrees hash to `Hash::ZERO`, and a subtree holding a single
+//! leaf is replaced by that leaf, so a leaf sits at the shallowest depth that
+//! separates it from every other key.
+
+use serde::{Deserialize, Serialize};
+
+use super::hash::{hash_blake3, Hash};
+
+const LEAF_TAG: u8 = 0x00;
+const NODE_TAG: u8 = 0x01;
+
+/// Path of a storage key in the tree
+pub fn smt_key(storage_key: &[u8]) -> Hash {
+    hash_blake3(storage_key)
+}
+
+/// Hash of a leaf holding `value_hash` at `key_hash`
+pub fn smt_leaf_hash(key_hash: &Hash, value_hash: &Hash) -> Hash {
+    let mut data = [0u8; 65];
+    data[0] = LEAF_TAG;
+    data[1..33].copy_from_slice(key_hash.as_bytes());
+    data[33..].copy_from_slice(value_hash.as_bytes());
+    hash_blake3(&data)
+}
+
+/// Hash of an internal node
+pub fn smt_node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut data = [0u8; 65];
+    data[0] = NODE_TAG;
+    data[1..33].copy_from_slice(left.as_bytes());
+    data[33..].copy_from_slice(right.as_bytes());
+    hash_blake3(&data)
+}
+
+/// Bit of `key_hash` that picks the child at `depth` (0 = left, 1 = right)
+pub fn smt_bit(key_hash: &Hash, depth: usize) -> bool {
+    (key_hash.0[depth / 8] >> (7 - depth % 8)) & 1 == 1
+}
+
+/// Proof that a key holds a value, or holds nothing, under a state root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmtProof {
+    /// Leaf (key hash, value hash) where the key's path ends: the key's own
+    /// leaf, another key's leaf, or `None` for an empty subtree
+    pub leaf: Option<(Hash, Hash)>,
+    /// Sibling hashes along the path, from the root down
+    pub siblings: Vec<Hash>,
+}
+
+impl SmtProof {
+    /// Check the proof against `root`. `value` is the encoded value the key
+    /// is claimed to hold, or `None` to check that the key is absent.
+    pub fn verify(&self, root: &Hash, storage_key: &[u8], value: Option<&[u8]>) -> bool {
+        if self.siblings.len() > 256 {
+            return false;
+        }
+        let key_hash = smt_key(storage_key);
+        let depth = self.siblings.len();
+
+        let mut current = match (value, self.leaf) {
+            (Some(value), Some((leaf_key, value_hash))) => {
+                if leaf_key != key_hash || value_hash != hash_blake3(value) {
+                    return false;
+                }
+                smt_leaf_hash(&leaf_key, &value_hash)
+            }
+            (None, Some((leaf_key, value_hash))) => {
+                // Another key's leaf must sit where this key's path ends
+                if leaf_key == key_hash
+                    || (0..depth).any(|d| smt_bit(&leaf_key, d) != smt_bit(&key_hash, d))
+                {
+                    return false;
+                }
+                smt_leaf_hash(&leaf_key, &value_hash)
+            }
+            (None, None) => Hash::ZERO,
+            (Some(_), None) => return false,
+        };
+
+        for (d, sibling) in self.siblings.iter().enumerate().rev() {
+            current = if smt_bit(&key_hash, d) {
+                smt_node_hash(sibling, &current)
+            } else {
+                smt_node_hash(&current, sibling)
+            };
+        }
+        current == *root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_leaf_proofs() {
+        let key_hash = smt_key(b"acc:alice");
+        let value_hash = hash_blake3(b"balance");
+        let root = smt_leaf_hash(&key_hash, &value_hash);
+
+        let inclusion = SmtProof {
+            leaf: Some((key_hash, value_hash)),
+            siblings: vec![],
+        };
+        assert!(inclusion.verify(&root, b"acc:alice", Some(b"balance")));
+        assert!(!inclusion.verify(&root, b"acc:alice", Some(b"other")));
+        assert!(!inclusion.verify(&root, b"acc:alice", None));
+
+        // The same leaf proves any other key absent
+        assert!(inclusion.verify(&root, b"acc:bob", None));
+        assert!(!inclusion.verify(&root, b"acc:bob", Some(b"balance")));
+    }
+
+    #[test]
+    fn test_empty_tree_excludes_everything() {
+        let proof = SmtProof {
+            leaf: None,
+            siblings: vec![],
+        };
+        assert!(proof.verify(&Hash::ZERO, b"acc:alice", None));
+        assert!(!proof.verify(&hash_blake3(b"root"), b"acc:alice", None));
+    }
+}
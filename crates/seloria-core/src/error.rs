@@ -17,6 +17,12 @@ pub enum CoreError {
     #[error("Invalid hash length")]
     InvalidHashLength,
 
+    #[error("Invalid proof: {0}")]
+    InvalidProof(String),
+
+    #[error("Invalid evidence: {0}")]
+    InvalidEvidence(String),
+
     #[error("Hex decode error: {0}")]
     HexDecode(#[from] hex::FromHexError),
 }
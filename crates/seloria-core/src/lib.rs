@@ -8,6 +8,11 @@ pub mod error;
 pub mod serialize;
 pub mod types;
 
-pub use crypto::{hash_blake3, merkle_root, sign, verify, Hash, KeyPair, PublicKey, SecretKey, Sig};
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+
+pub use crypto::{
+    hash_blake3, merkle_root, sign, verify, Hash, KeyPair, PublicKey, SecretKey, Sig, SmtProof,
+};
 pub use error::CoreError;
 pub use types::*;
@@ -0,0 +1,57 @@
+//! Block and certificate builders shared by the tests of the workspace
+//! crates (enabled in dependent crates with the `test-utils` feature)
+
+use crate::crypto::{sign, Hash, KeyPair, PublicKey};
+use crate::types::block::{
+    vote_message, Block, BlockHeader, QuorumCertificate, ValidatorSignature, VotePhase,
+};
+
+/// Header of a block at `height` on chain `chain_id` with zero roots,
+/// timestamp and view. Override fields with struct update syntax.
+pub fn header(chain_id: u64, height: u64) -> BlockHeader {
+    BlockHeader {
+        chain_id,
+        height,
+        view: 0,
+        prev_hash: Hash::ZERO,
+        timestamp: 0,
+        tx_root: Hash::ZERO,
+        state_root: Hash::ZERO,
+        receipts_root: Hash::ZERO,
+        proposer_pubkey: PublicKey::default(),
+    }
+}
+
+/// `signer`'s vote in `phase` for the block with `header`
+pub fn vote(signer: &KeyPair, phase: VotePhase, header: &BlockHeader) -> ValidatorSignature {
+    let message = vote_message(header.chain_id, phase, &header.hash().unwrap());
+    ValidatorSignature {
+        validator_pubkey: signer.public,
+        signature: sign(&signer.secret, &message),
+    }
+}
+
+/// Commit QC on `header` signed by `signers`
+pub fn commit_qc<'a>(
+    header: &BlockHeader,
+    signers: impl IntoIterator<Item = &'a KeyPair>,
+) -> QuorumCertificate {
+    let mut qc =
+        QuorumCertificate::new(header.chain_id, header.hash().unwrap()).with_view(header.view);
+    for signer in signers {
+        let vote = vote(signer, VotePhase::Commit, header);
+        qc.add_signature(vote.validator_pubkey, vote.signature);
+    }
+    qc
+}
+
+/// Block without transactions carrying a commit QC from `signers`
+pub fn certified_block<'a>(
+    header: BlockHeader,
+    signers: impl IntoIterator<Item = &'a KeyPair>,
+) -> Block {
+    let qc = commit_qc(&header, signers);
+    let mut block = Block::new(header, Vec::new());
+    block.qc = Some(qc);
+    block
+}
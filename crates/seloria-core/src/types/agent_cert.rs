@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 
+use crate::crypto::domain::{self, signing_message};
 use crate::crypto::{hash_blake3, verify, Hash, PublicKey, SecretKey, Sig, sign};
 use crate::error::CoreError;
 use crate::serialize;
@@ -39,7 +40,9 @@ pub struct AgentCertificate {
 }
 
 impl AgentCertificate {
-    pub const CURRENT_VERSION: u8 = 1;
+    pub const CURRENT_VERSION: u8 = 2;
+    /// Certificates issued before signatures were bound to a chain
+    pub const LEGACY_VERSION: u8 = 1;
 
     /// Create a new agent certificate
     pub fn new(
@@ -91,8 +94,17 @@ impl AgentCertificate {
     }
 
     /// Get bytes for signing
-    pub fn signing_bytes(&self) -> Result<Vec<u8>, CoreError> {
-        serialize::to_bytes(self)
+    ///
+    /// Version 2 certificates are signed under a domain tag and the chain ID.
+    /// Legacy version 1 certificates signed the bare encoding; they still
+    /// verify so already-issued certificates keep working, but they are not
+    /// bound to a chain and should be re-issued.
+    pub fn signing_bytes(&self, chain_id: u64) -> Result<Vec<u8>, CoreError> {
+        let payload = serialize::to_bytes(self)?;
+        if self.version <= Self::LEGACY_VERSION {
+            return Ok(payload);
+        }
+        Ok(signing_message(domain::AGENT_CERT_V2, chain_id, &payload))
     }
 }
 
@@ -104,9 +116,13 @@ pub struct SignedAgentCertificate {
 }
 
 impl SignedAgentCertificate {
-    /// Create and sign a new agent certificate
-    pub fn new(cert: AgentCertificate, issuer_secret: &SecretKey) -> Result<Self, CoreError> {
-        let signing_bytes = cert.signing_bytes()?;
+    /// Create and sign a new agent certificate for the given chain
+    pub fn new(
+        cert: AgentCertificate,
+        chain_id: u64,
+        issuer_secret: &SecretKey,
+    ) -> Result<Self, CoreError> {
+        let signing_bytes = cert.signing_bytes(chain_id)?;
         let signature = sign(issuer_secret, &signing_bytes);
         Ok(SignedAgentCertificate {
             cert,
@@ -114,9 +130,9 @@ impl SignedAgentCertificate {
         })
     }
 
-    /// Verify the issuer signature
-    pub fn verify_signature(&self, issuer_pubkey: &PublicKey) -> Result<(), CoreError> {
-        let signing_bytes = self.cert.signing_bytes()?;
+    /// Verify the issuer signature for the given chain
+    pub fn verify_signature(&self, issuer_pubkey: &PublicKey, chain_id: u64) -> Result<(), CoreError> {
+        let signing_bytes = self.cert.signing_bytes(chain_id)?;
         verify(issuer_pubkey, &signing_bytes, &self.issuer_signature)
     }
 
@@ -149,7 +165,7 @@ mod tests {
             vec![Capability::TxSubmit, Capability::Claim],
             Hash::ZERO,
         );
-        SignedAgentCertificate::new(cert, &issuer.secret).unwrap()
+        SignedAgentCertificate::new(cert, 1, &issuer.secret).unwrap()
     }
 
     #[test]
@@ -170,7 +186,33 @@ mod tests {
         let agent = KeyPair::generate();
         let signed = create_test_cert(&issuer, &agent);
 
-        assert!(signed.verify_signature(&issuer.public).is_ok());
+        assert!(signed.verify_signature(&issuer.public, 1).is_ok());
+        assert!(signed.verify_signature(&issuer.public, 2).is_err());
+    }
+
+    #[test]
+    fn test_legacy_certificate_still_verifies() {
+        let issuer = KeyPair::generate();
+        let agent = KeyPair::generate();
+
+        let mut cert = AgentCertificate::new(
+            hash_blake3(issuer.public.as_bytes()),
+            agent.public,
+            1000,
+            2000,
+            vec![Capability::TxSubmit],
+            Hash::ZERO,
+        );
+        cert.version = AgentCertificate::LEGACY_VERSION;
+        cert.agent_id = cert.compute_agent_id();
+        let legacy_bytes = serialize::to_bytes(&cert).unwrap();
+        let signed = SignedAgentCertificate {
+            cert,
+            issuer_signature: sign(&issuer.secret, &legacy_bytes),
+        };
+
+        assert!(signed.verify_signature(&issuer.public, 1).is_ok());
+        assert!(signed.verify_signature(&issuer.public, 2).is_ok());
     }
 
     #[test]
@@ -180,7 +222,7 @@ mod tests {
         let agent = KeyPair::generate();
         let signed = create_test_cert(&issuer, &agent);
 
-        assert!(signed.verify_signature(&wrong_issuer.public).is_err());
+        assert!(signed.verify_signature(&wrong_issuer.public, 1).is_err());
     }
 
     #[test]
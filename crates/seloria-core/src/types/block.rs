@@ -1,8 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+use crate::crypto::domain::{self, signing_message};
 use crate::crypto::{hash_blake3, merkle_root, sign, verify, Hash, PublicKey, SecretKey, Sig};
 use crate::error::CoreError;
 use crate::serialize;
+use crate::types::staking::{ValidatorInfo, ValidatorSet};
 use crate::types::transaction::Transaction;
 
 /// Block header containing metadata
@@ -12,6 +14,9 @@ pub struct BlockHeader {
     pub chain_id: u64,
     /// Block height (0 for genesis)
     pub height: u64,
+    /// View the block was proposed in: 0, plus one for each leader at this
+    /// height that timed out
+    pub view: u64,
     /// Hash of the previous block (zeros for genesis)
     pub prev_hash: Hash,
     /// Unix timestamp
@@ -20,6 +25,8 @@ pub struct BlockHeader {
     pub tx_root: Hash,
     /// State root after applying transactions
     pub state_root: Hash,
+    /// Merkle root of transaction receipts
+    pub receipts_root: Hash,
     /// Proposer's public key
     pub proposer_pubkey: PublicKey,
 }
@@ -39,9 +46,84 @@ pub struct ValidatorSignature {
     pub signature: Sig,
 }
 
+/// Message a validator signs to vote for a block
+pub fn block_vote_message(chain_id: u64, block_hash: &Hash) -> Vec<u8> {
+    signing_message(domain::BLOCK_VOTE_V1, chain_id, block_hash.as_bytes())
+}
+
+/// Consensus phase a validator vote is cast in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum VotePhase {
+    /// The block is valid and safe to vote for
+    Prepare,
+    /// A quorum prepared the block
+    PreCommit,
+    /// A quorum pre-committed the block; a quorum of commit votes finalizes it
+    Commit,
+}
+
+impl VotePhase {
+    /// Phase voted in once a quorum certificate for this phase exists
+    pub fn next(self) -> Option<VotePhase> {
+        match self {
+            VotePhase::Prepare => Some(VotePhase::PreCommit),
+            VotePhase::PreCommit => Some(VotePhase::Commit),
+            VotePhase::Commit => None,
+        }
+    }
+}
+
+/// Message a validator signs to vote for a block in `phase`. Commit votes
+/// are the block votes that certify committed blocks.
+pub fn vote_message(chain_id: u64, phase: VotePhase, block_hash: &Hash) -> Vec<u8> {
+    let domain = match phase {
+        VotePhase::Prepare => domain::PREPARE_VOTE_V1,
+        VotePhase::PreCommit => domain::PRECOMMIT_VOTE_V1,
+        VotePhase::Commit => domain::BLOCK_VOTE_V1,
+    };
+    signing_message(domain, chain_id, block_hash.as_bytes())
+}
+
+/// Message a validator signs to give up on the leader of `view` at `height`
+pub fn timeout_message(chain_id: u64, height: u64, view: u64) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(16);
+    payload.extend_from_slice(&height.to_le_bytes());
+    payload.extend_from_slice(&view.to_le_bytes());
+    signing_message(domain::TIMEOUT_V1, chain_id, &payload)
+}
+
+/// Check that members of `validators` holding a quorum of the voting power
+/// signed `message`. Signatures from others or that do not verify are not
+/// counted.
+pub(crate) fn verify_signers(
+    signatures: &[ValidatorSignature],
+    message: &[u8],
+    validators: &ValidatorSet,
+) -> Result<(), CoreError> {
+    let signers: Vec<_> = signatures
+        .iter()
+        .filter(|vs| verify(&vs.validator_pubkey, message, &vs.signature).is_ok())
+        .map(|vs| &vs.validator_pubkey)
+        .collect();
+    if !validators.has_quorum(signers.iter().copied()) {
+        return Err(CoreError::InvalidProof(format!(
+            "Certificate signers hold {} voting power, need {}",
+            validators.signed_power(signers),
+            validators.quorum_power()
+        )));
+    }
+    Ok(())
+}
+
 /// Quorum certificate proving validator consensus
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QuorumCertificate {
+    /// Chain the votes were cast on
+    pub chain_id: u64,
+    /// View the certified block was proposed in
+    pub view: u64,
+    /// Phase the votes were cast in (`Commit` for committed blocks)
+    pub phase: VotePhase,
     /// Hash of the block being certified
     pub block_hash: Hash,
     /// Validator signatures
@@ -49,14 +131,29 @@ pub struct QuorumCertificate {
 }
 
 impl QuorumCertificate {
-    /// Create a new empty QC
-    pub fn new(block_hash: Hash) -> Self {
+    /// Create a new empty commit QC for a block proposed in view 0
+    pub fn new(chain_id: u64, block_hash: Hash) -> Self {
         QuorumCertificate {
+            chain_id,
+            view: 0,
+            phase: VotePhase::Commit,
             block_hash,
             signatures: Vec::new(),
         }
     }
 
+    /// Set the view of the certified block
+    pub fn with_view(mut self, view: u64) -> Self {
+        self.view = view;
+        self
+    }
+
+    /// Set the phase the votes are cast in
+    pub fn with_phase(mut self, phase: VotePhase) -> Self {
+        self.phase = phase;
+        self
+    }
+
     /// Add a validator signature
     pub fn add_signature(&mut self, validator_pubkey: PublicKey, signature: Sig) {
         self.signatures.push(ValidatorSignature {
@@ -67,12 +164,33 @@ impl QuorumCertificate {
 
     /// Verify all signatures in the QC
     pub fn verify_signatures(&self) -> Result<(), CoreError> {
+        let message = vote_message(self.chain_id, self.phase, &self.block_hash);
         for vs in &self.signatures {
-            verify(&vs.validator_pubkey, self.block_hash.as_bytes(), &vs.signature)?;
+            verify(&vs.validator_pubkey, &message, &vs.signature)?;
         }
         Ok(())
     }
 
+    /// Check that this certifies the block as committed: members of
+    /// `validators` holding a quorum of the voting power cast commit votes.
+    /// Signatures from others or that do not verify are not counted.
+    pub fn verify_quorum(&self, validators: &ValidatorSet) -> Result<(), CoreError> {
+        if self.phase != VotePhase::Commit {
+            return Err(CoreError::InvalidProof(format!(
+                "Certificate is for the {:?} phase, not a commit",
+                self.phase
+            )));
+        }
+        self.verify_phase_quorum(validators)
+    }
+
+    /// Check that members of `validators` holding a quorum of the voting
+    /// power voted for the block in the certificate's phase
+    pub fn verify_phase_quorum(&self, validators: &ValidatorSet) -> Result<(), CoreError> {
+        let message = vote_message(self.chain_id, self.phase, &self.block_hash);
+        verify_signers(&self.signatures, &message, validators)
+    }
+
     /// Check if quorum is reached (requires threshold signatures)
     pub fn has_quorum(&self, threshold: usize) -> bool {
         self.signatures.len() >= threshold
@@ -84,6 +202,45 @@ impl QuorumCertificate {
     }
 }
 
+/// Timeout certificate: a quorum of validators gave up on the leader of
+/// `view` at `height`, so the leader of the next view may propose
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeoutCertificate {
+    pub chain_id: u64,
+    pub height: u64,
+    /// View that timed out
+    pub view: u64,
+    /// Validator signatures on the timeout message
+    pub signatures: Vec<ValidatorSignature>,
+}
+
+impl TimeoutCertificate {
+    /// Create a new empty TC
+    pub fn new(chain_id: u64, height: u64, view: u64) -> Self {
+        TimeoutCertificate {
+            chain_id,
+            height,
+            view,
+            signatures: Vec::new(),
+        }
+    }
+
+    /// Add a validator signature
+    pub fn add_signature(&mut self, validator_pubkey: PublicKey, signature: Sig) {
+        self.signatures.push(ValidatorSignature {
+            validator_pubkey,
+            signature,
+        });
+    }
+
+    /// Check that members of `validators` holding a quorum of the voting
+    /// power signed the timeout
+    pub fn verify_quorum(&self, validators: &ValidatorSet) -> Result<(), CoreError> {
+        let message = timeout_message(self.chain_id, self.height, self.view);
+        verify_signers(&self.signatures, &message, validators)
+    }
+}
+
 /// A complete block
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
@@ -119,10 +276,11 @@ impl Block {
         Ok(computed == self.header.tx_root)
     }
 
-    /// Sign the block as a validator
+    /// Sign the block as a validator (a vote bound to the header's chain ID)
     pub fn sign_as_validator(&self, secret_key: &SecretKey) -> Result<Sig, CoreError> {
         let block_hash = self.hash()?;
-        Ok(sign(secret_key, block_hash.as_bytes()))
+        let message = block_vote_message(self.header.chain_id, &block_hash);
+        Ok(sign(secret_key, &message))
     }
 
     /// Add a validator signature to the QC
@@ -134,7 +292,10 @@ impl Block {
         let block_hash = self.hash()?;
 
         if self.qc.is_none() {
-            self.qc = Some(QuorumCertificate::new(block_hash));
+            self.qc = Some(
+                QuorumCertificate::new(self.header.chain_id, block_hash)
+                    .with_view(self.header.view),
+            );
         }
 
         if let Some(ref mut qc) = self.qc {
@@ -153,18 +314,38 @@ pub struct GenesisConfig {
     pub initial_balances: Vec<(PublicKey, u64)>,
     pub trusted_issuers: Vec<PublicKey>,
     pub validators: Vec<PublicKey>,
+    /// Stake bonded for each genesis validator, which is also its voting
+    /// power. With no stake every genesis validator has a power of 1.
+    #[serde(default)]
+    pub validator_stake: u64,
 }
 
 impl GenesisConfig {
+    /// Validator set of the first epoch: the genesis validators with their
+    /// stake as voting power, or equal power without stake
+    pub fn validator_set(&self) -> ValidatorSet {
+        ValidatorSet::new(
+            self.validators
+                .iter()
+                .map(|pubkey| ValidatorInfo {
+                    pubkey: *pubkey,
+                    power: self.validator_stake.max(1),
+                })
+                .collect(),
+        )
+    }
+
     /// Create a genesis block from this config
     pub fn create_genesis_block(&self) -> Block {
         let header = BlockHeader {
             chain_id: self.chain_id,
             height: 0,
+            view: 0,
             prev_hash: Hash::ZERO,
             timestamp: self.timestamp,
             tx_root: Hash::ZERO, // No transactions in genesis
             state_root: Hash::ZERO, // Will be computed after applying initial state
+            receipts_root: Hash::ZERO,
             proposer_pubkey: PublicKey::default(),
         };
 
@@ -176,6 +357,7 @@ impl GenesisConfig {
 mod tests {
     use super::*;
     use crate::crypto::KeyPair;
+    use crate::testing;
     use crate::types::transaction::Op;
 
     fn create_test_block() -> Block {
@@ -184,6 +366,7 @@ mod tests {
         let receiver = KeyPair::generate();
 
         let tx = Transaction::new_signed(
+            1,
             sender.public,
             1,
             100,
@@ -198,13 +381,10 @@ mod tests {
         let tx_root = merkle_root(&[tx.hash().unwrap()]);
 
         let header = BlockHeader {
-            chain_id: 1,
-            height: 1,
-            prev_hash: Hash::ZERO,
             timestamp: 1000,
             tx_root,
-            state_root: Hash::ZERO,
             proposer_pubkey: proposer.public,
+            ..testing::header(1, 1)
         };
 
         Block::new(header, vec![tx])
@@ -236,6 +416,11 @@ mod tests {
         let qc = block.qc.as_ref().unwrap();
         assert_eq!(qc.signature_count(), 1);
         assert!(qc.verify_signatures().is_ok());
+
+        // A vote is not valid for the same block hash on another chain
+        let mut other_chain = qc.clone();
+        other_chain.chain_id = 2;
+        assert!(other_chain.verify_signatures().is_err());
     }
 
     #[test]
@@ -253,6 +438,29 @@ mod tests {
         assert!(!qc.has_quorum(4));
     }
 
+    #[test]
+    fn test_vote_phases_are_separated() {
+        let block = create_test_block();
+        let block_hash = block.hash().unwrap();
+        let validators: Vec<_> = (0..3).map(|_| KeyPair::generate()).collect();
+        let pubkeys: Vec<_> = validators.iter().map(|v| v.public).collect();
+
+        let mut qc = QuorumCertificate::new(1, block_hash).with_phase(VotePhase::PreCommit);
+        for validator in &validators {
+            let message = vote_message(1, VotePhase::PreCommit, &block_hash);
+            qc.add_signature(validator.public, sign(&validator.secret, &message));
+        }
+        let set = ValidatorSet::equal(&pubkeys);
+        qc.verify_phase_quorum(&set).unwrap();
+        // A pre-commit certificate does not certify the block as committed
+        assert!(qc.verify_quorum(&set).is_err());
+        // ...nor do its votes count for another phase
+        let mut relabeled = qc.clone().with_phase(VotePhase::Commit);
+        assert!(relabeled.verify_quorum(&set).is_err());
+        relabeled.phase = VotePhase::Prepare;
+        assert!(relabeled.verify_phase_quorum(&set).is_err());
+    }
+
     #[test]
     fn test_genesis_config() {
         let issuer = KeyPair::generate();
@@ -265,6 +473,7 @@ mod tests {
             initial_balances: vec![(account.public, 1_000_000)],
             trusted_issuers: vec![issuer.public],
             validators: vec![validator.public],
+            validator_stake: 0,
         };
 
         let genesis = config.create_genesis_block();
@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{hash_blake3, verify, Hash, PublicKey};
+use crate::error::CoreError;
+use crate::serialize;
+use crate::types::block::{vote_message, BlockHeader, ValidatorSignature, VotePhase};
+
+/// Percentage of an equivocating validator's bonded and unbonding stake
+/// that is slashed
+pub const EQUIVOCATION_SLASH_PERCENTAGE: u64 = 20;
+
+/// Percentage of the slashed stake paid to whoever submits the evidence.
+/// The rest is burned, so an offender reporting itself still loses stake.
+pub const EVIDENCE_REWARD_PERCENTAGE: u64 = 10;
+
+/// Proof that a validator equivocated: it voted in the same phase for two
+/// different blocks proposed at the same height and view.
+///
+/// Honest validators vote for a new block at a height only after the view
+/// changes, and a re-proposed block is in a new view, so two such votes can
+/// only come from a validator breaking the safety rules.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Evidence {
+    /// Phase both votes were cast in
+    pub phase: VotePhase,
+    pub header_a: BlockHeader,
+    pub vote_a: ValidatorSignature,
+    pub header_b: BlockHeader,
+    pub vote_b: ValidatorSignature,
+}
+
+impl Evidence {
+    /// Create evidence from two votes. The pair is ordered by block hash so
+    /// the same equivocation always produces the same evidence.
+    pub fn new(
+        phase: VotePhase,
+        header_a: BlockHeader,
+        vote_a: ValidatorSignature,
+        header_b: BlockHeader,
+        vote_b: ValidatorSignature,
+    ) -> Result<Self, CoreError> {
+        let (a, b) = if header_a.hash()? <= header_b.hash()? {
+            ((header_a, vote_a), (header_b, vote_b))
+        } else {
+            ((header_b, vote_b), (header_a, vote_a))
+        };
+        Ok(Evidence {
+            phase,
+            header_a: a.0,
+            vote_a: a.1,
+            header_b: b.0,
+            vote_b: b.1,
+        })
+    }
+
+    /// Validator the evidence is against
+    pub fn offender(&self) -> PublicKey {
+        self.vote_a.validator_pubkey
+    }
+
+    /// Height the validator equivocated at
+    pub fn height(&self) -> u64 {
+        self.header_a.height
+    }
+
+    /// Identifier of this evidence
+    pub fn id(&self) -> Result<Hash, CoreError> {
+        Ok(hash_blake3(&serialize::to_bytes(self)?))
+    }
+
+    /// Check that the evidence proves an equivocation on `chain_id`: both
+    /// votes are by one validator, in the same phase, for different blocks
+    /// at the same height and view
+    pub fn verify(&self, chain_id: u64) -> Result<(), CoreError> {
+        let (a, b) = (&self.header_a, &self.header_b);
+        if a.chain_id != chain_id || b.chain_id != chain_id {
+            return Err(CoreError::InvalidEvidence(format!(
+                "Headers are not for chain {}",
+                chain_id
+            )));
+        }
+        if a.height != b.height || a.view != b.view {
+            return Err(CoreError::InvalidEvidence(format!(
+                "Votes are at height {} view {} and height {} view {}",
+                a.height, a.view, b.height, b.view
+            )));
+        }
+        if self.vote_a.validator_pubkey != self.vote_b.validator_pubkey {
+            return Err(CoreError::InvalidEvidence(
+                "Votes are from different validators".to_string(),
+            ));
+        }
+
+        let hash_a = a.hash()?;
+        let hash_b = b.hash()?;
+        if hash_a == hash_b {
+            return Err(CoreError::InvalidEvidence(
+                "Votes are for the same block".to_string(),
+            ));
+        }
+
+        for (hash, vote) in [(hash_a, &self.vote_a), (hash_b, &self.vote_b)] {
+            let message = vote_message(chain_id, self.phase, &hash);
+            verify(&vote.validator_pubkey, &message, &vote.signature)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use crate::testing::{self, vote};
+
+    fn header(height: u64, view: u64, timestamp: u64) -> BlockHeader {
+        BlockHeader {
+            view,
+            timestamp,
+            ..testing::header(1, height)
+        }
+    }
+
+    fn evidence(
+        validator: &KeyPair,
+        phase: VotePhase,
+        a: BlockHeader,
+        b: BlockHeader,
+    ) -> Evidence {
+        let vote_a = vote(validator, phase, &a);
+        let vote_b = vote(validator, phase, &b);
+        Evidence::new(phase, a, vote_a, b, vote_b).unwrap()
+    }
+
+    #[test]
+    fn test_evidence_verification() {
+        let validator = KeyPair::generate();
+        let phase = VotePhase::Commit;
+
+        let valid = evidence(&validator, phase, header(5, 0, 1), header(5, 0, 2));
+        valid.verify(1).unwrap();
+        assert_eq!(valid.offender(), validator.public);
+        assert_eq!(valid.height(), 5);
+        assert!(valid.verify(2).is_err());
+
+        // The same pair in either order is the same evidence
+        let swapped = evidence(&validator, phase, header(5, 0, 2), header(5, 0, 1));
+        assert_eq!(valid.id().unwrap(), swapped.id().unwrap());
+
+        // Voting again in a later view or at another height is not evidence
+        let later_view = evidence(&validator, phase, header(5, 0, 1), header(5, 1, 2));
+        assert!(later_view.verify(1).is_err());
+        let other_height = evidence(&validator, phase, header(5, 0, 1), header(6, 0, 2));
+        assert!(other_height.verify(1).is_err());
+
+        // Nor is one vote cited twice
+        let same_block = evidence(&validator, phase, header(5, 0, 1), header(5, 0, 1));
+        assert!(same_block.verify(1).is_err());
+
+        // Votes must be in the claimed phase and by the same validator
+        let mut relabeled = valid.clone();
+        relabeled.phase = VotePhase::Prepare;
+        assert!(relabeled.verify(1).is_err());
+        let mut mixed = valid.clone();
+        mixed.vote_b = vote(&KeyPair::generate(), phase, &mixed.header_b);
+        assert!(mixed.verify(1).is_err());
+    }
+}
@@ -4,7 +4,12 @@ pub mod amm;
 pub mod app;
 pub mod block;
 pub mod claim;
+pub mod evidence;
 pub mod namespace;
+pub mod proof;
+pub mod receipt;
+pub mod snapshot;
+pub mod staking;
 pub mod token;
 pub mod transaction;
 
@@ -12,8 +17,20 @@ pub use account::{Account, LockId};
 pub use agent_cert::{AgentCertificate, Capability, SignedAgentCertificate};
 pub use amm::{canonical_pair, compute_pool_id, AmmPool, AMM_FEE_BPS, BPS_DENOM};
 pub use app::AppMeta;
-pub use block::{Block, BlockHeader, GenesisConfig, QuorumCertificate, ValidatorSignature};
+pub use block::{
+    block_vote_message, timeout_message, vote_message, Block, BlockHeader, GenesisConfig,
+    QuorumCertificate, TimeoutCertificate, ValidatorSignature, VotePhase,
+};
 pub use claim::{calculate_settlement, Attestation, Claim, ClaimStatus, Vote, SLASH_PERCENTAGE};
+pub use evidence::{Evidence, EQUIVOCATION_SLASH_PERCENTAGE, EVIDENCE_REWARD_PERCENTAGE};
 pub use namespace::{KvData, KvValue, NamespaceMeta, NamespacePolicy};
+pub use proof::{StateKey, StateProof};
+pub use receipt::{compute_receipts_root, ExecutionEvent, ReceiptRecord, TxReceipt};
+pub use snapshot::{SnapshotChunk, SnapshotManifest, SNAPSHOT_FORMAT_VERSION};
+pub use staking::{
+    epoch_of, epoch_start, validator_bond_lock, validator_unbonding_lock, ValidatorInfo,
+    ValidatorSet, ValidatorSetUpdate, EPOCH_LENGTH_BLOCKS, MAX_VALIDATORS, MIN_VALIDATOR_BOND,
+    UNBONDING_DELAY_BLOCKS,
+};
 pub use token::{compute_token_id, TokenMeta, NATIVE_TOKEN_ID};
 pub use transaction::{Op, Transaction};
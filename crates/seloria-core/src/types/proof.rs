@@ -0,0 +1,197 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{Hash, PublicKey, SmtProof};
+use crate::error::CoreError;
+use crate::serialize;
+use crate::types::block::{BlockHeader, QuorumCertificate};
+use crate::types::staking::ValidatorSet;
+
+/// Storage key prefixes of the entries covered by the state root
+pub mod prefix {
+    pub const ACCOUNT: &[u8] = b"acc:";
+    pub const AGENT: &[u8] = b"agt:";
+    pub const CLAIM: &[u8] = b"clm:";
+    pub const NAMESPACE: &[u8] = b"ns:";
+    pub const KV: &[u8] = b"kv:";
+    pub const APP: &[u8] = b"app:";
+    pub const TOKEN: &[u8] = b"tok:";
+    pub const POOL: &[u8] = b"pool:";
+    pub const LP: &[u8] = b"lp:";
+
+    /// Chain metadata that decides who certifies blocks, committed so that
+    /// snapshots cannot carry a forged validator set
+    pub const ISSUER: &[u8] = b"iss:";
+    pub const VALIDATORS: &[u8] = b"chain:validators";
+    pub const STAKING: &[u8] = b"chain:staking";
+    pub const EPOCHS: &[u8] = b"chain:epochs";
+    /// Current validators with their voting power
+    pub const VALIDATOR_SET: &[u8] = b"chain:validator_set";
+
+    /// Every prefix covered by the state root
+    pub const ALL: &[&[u8]] = &[
+        ACCOUNT, AGENT, CLAIM, NAMESPACE, KV, APP, TOKEN, POOL, LP, ISSUER, VALIDATORS, STAKING,
+        EPOCHS, VALIDATOR_SET,
+    ];
+}
+
+/// A state entry covered by the state root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StateKey {
+    Account(PublicKey),
+    Agent(PublicKey),
+    Claim(Hash),
+    Namespace(Hash),
+    Kv(Hash, String),
+    App(Hash),
+    Token(Hash),
+    Pool(Hash),
+    Lp(Hash, PublicKey),
+    /// The [`ValidatorSet`] certifying the next block
+    ValidatorSet,
+}
+
+impl StateKey {
+    /// Key of the entry in storage and in the state tree
+    pub fn storage_key(&self) -> Vec<u8> {
+        match self {
+            StateKey::Account(pubkey) => [prefix::ACCOUNT, pubkey.as_bytes()].concat(),
+            StateKey::Agent(pubkey) => [prefix::AGENT, pubkey.as_bytes()].concat(),
+            StateKey::Claim(id) => [prefix::CLAIM, id.as_bytes()].concat(),
+            StateKey::Namespace(id) => [prefix::NAMESPACE, id.as_bytes()].concat(),
+            StateKey::Kv(ns_id, key) => {
+                [prefix::KV, ns_id.as_bytes(), b":", key.as_bytes()].concat()
+            }
+            StateKey::App(id) => [prefix::APP, id.as_bytes()].concat(),
+            StateKey::Token(id) => [prefix::TOKEN, id.as_bytes()].concat(),
+            StateKey::Pool(id) => [prefix::POOL, id.as_bytes()].concat(),
+            StateKey::Lp(pool_id, owner) => {
+                [prefix::LP, pool_id.as_bytes(), owner.as_bytes()].concat()
+            }
+            StateKey::ValidatorSet => prefix::VALIDATOR_SET.to_vec(),
+        }
+    }
+}
+
+/// A state entry together with a proof that it is committed by a block the
+/// validators certified, for clients that do not trust the serving node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateProof {
+    /// Encoded entry, `None` if the entry does not exist
+    pub value: Option<Vec<u8>>,
+    /// Proof of the entry under `header.state_root`
+    pub proof: SmtProof,
+    /// Header of the block the proof is against
+    pub header: BlockHeader,
+    /// Validator signatures on `header`
+    pub qc: QuorumCertificate,
+}
+
+impl StateProof {
+    /// Verify that `key` holds `value` in the state committed by a block that
+    /// a quorum of `validators` signed on chain `chain_id`
+    pub fn verify(
+        &self,
+        key: &StateKey,
+        chain_id: u64,
+        validators: &ValidatorSet,
+    ) -> Result<(), CoreError> {
+        if self.header.chain_id != chain_id || self.qc.chain_id != chain_id {
+            return Err(CoreError::InvalidProof("Wrong chain ID".to_string()));
+        }
+        if self.qc.block_hash != self.header.hash()? {
+            return Err(CoreError::InvalidProof(
+                "Certificate is not for this header".to_string(),
+            ));
+        }
+        self.qc.verify_quorum(validators)?;
+
+        if !self.proof.verify(
+            &self.header.state_root,
+            &key.storage_key(),
+            self.value.as_deref(),
+        ) {
+            return Err(CoreError::InvalidProof(
+                "Value does not match the state root".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Decode the proven value
+    pub fn decode_value<T: DeserializeOwned>(&self) -> Result<Option<T>, CoreError> {
+        self.value.as_deref().map(serialize::from_bytes).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::smt::{smt_key, smt_leaf_hash};
+    use crate::crypto::{hash_blake3, KeyPair};
+    use crate::testing;
+    use crate::types::staking::ValidatorInfo;
+
+    /// A proof of `key = value` in a single-entry state, signed by `signers`
+    fn single_entry_proof(key: &StateKey, value: &[u8], signers: &[&KeyPair]) -> StateProof {
+        let key_hash = smt_key(&key.storage_key());
+        let value_hash = hash_blake3(value);
+        let header = BlockHeader {
+            state_root: smt_leaf_hash(&key_hash, &value_hash),
+            ..testing::header(1, 1)
+        };
+        let qc = testing::commit_qc(&header, signers.iter().copied());
+        StateProof {
+            value: Some(value.to_vec()),
+            proof: SmtProof {
+                leaf: Some((key_hash, value_hash)),
+                siblings: vec![],
+            },
+            header,
+            qc,
+        }
+    }
+
+    #[test]
+    fn test_verify_state_proof() {
+        let v1 = KeyPair::generate();
+        let v2 = KeyPair::generate();
+        let validators = ValidatorSet::equal(&[v1.public, v2.public]);
+        let key = StateKey::Claim(hash_blake3(b"claim"));
+
+        let proof = single_entry_proof(&key, b"value", &[&v1, &v2]);
+        proof.verify(&key, 1, &validators).unwrap();
+        assert!(proof.verify(&key, 2, &validators).is_err());
+        assert!(proof
+            .verify(&StateKey::Claim(hash_blake3(b"other")), 1, &validators)
+            .is_err());
+
+        let mut tampered = proof.clone();
+        tampered.value = Some(b"forged".to_vec());
+        assert!(tampered.verify(&key, 1, &validators).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_signatures_do_not_reach_quorum() {
+        let v1 = KeyPair::generate();
+        let v2 = KeyPair::generate();
+        let validators = ValidatorSet::equal(&[v1.public, v2.public]);
+        let key = StateKey::Account(v1.public);
+
+        let proof = single_entry_proof(&key, b"value", &[&v1, &v1]);
+        assert!(proof.verify(&key, 1, &validators).is_err());
+
+        // v1 alone holds a quorum when it has most of the voting power
+        let weighted = ValidatorSet::new(vec![
+            ValidatorInfo {
+                pubkey: v1.public,
+                power: 3,
+            },
+            ValidatorInfo {
+                pubkey: v2.public,
+                power: 1,
+            },
+        ]);
+        proof.verify(&key, 1, &weighted).unwrap();
+    }
+}
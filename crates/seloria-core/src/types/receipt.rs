@@ -0,0 +1,194 @@
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{hash_blake3, merkle_root, Hash, PublicKey};
+use crate::error::CoreError;
+use crate::serialize;
+use crate::types::claim::{ClaimStatus, Vote};
+
+/// Events emitted during transaction execution
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ExecutionEvent {
+    Transfer {
+        from: PublicKey,
+        to: PublicKey,
+        amount: u64,
+    },
+    AgentRegistered {
+        agent_pubkey: PublicKey,
+    },
+    ClaimCreated {
+        claim_id: Hash,
+        claim_type: String,
+        creator: PublicKey,
+        stake: u64,
+    },
+    AttestationAdded {
+        claim_id: Hash,
+        attester: PublicKey,
+        vote: Vote,
+        stake: u64,
+    },
+    ClaimFinalized {
+        claim_id: Hash,
+        status: ClaimStatus,
+        yes_stake: u64,
+        no_stake: u64,
+    },
+    NamespaceCreated {
+        ns_id: Hash,
+        owner: PublicKey,
+    },
+    KvUpdated {
+        ns_id: Hash,
+        key: String,
+    },
+    KvDeleted {
+        ns_id: Hash,
+        key: String,
+    },
+    TokenCreated {
+        token_id: Hash,
+        symbol: String,
+        total_supply: u64,
+        creator: PublicKey,
+    },
+    TokenTransfer {
+        token_id: Hash,
+        from: PublicKey,
+        to: PublicKey,
+        amount: u64,
+    },
+    PoolCreated {
+        pool_id: Hash,
+        token_a: Hash,
+        token_b: Hash,
+    },
+    PoolLiquidityAdded {
+        pool_id: Hash,
+        provider: PublicKey,
+        lp_minted: u64,
+    },
+    PoolLiquidityRemoved {
+        pool_id: Hash,
+        provider: PublicKey,
+        amount_a: u64,
+        amount_b: u64,
+    },
+    SwapExecuted {
+        pool_id: Hash,
+        trader: PublicKey,
+        token_in: Hash,
+        amount_in: u64,
+        token_out: Hash,
+        amount_out: u64,
+    },
+    AppRegistered {
+        app_id: Hash,
+    },
+    ValidatorSlashed {
+        validator: PublicKey,
+        reporter: PublicKey,
+        height: u64,
+        slashed: u64,
+        /// Part of `slashed` paid to the reporter; the rest is burned
+        reward: u64,
+    },
+    ValidatorBonded {
+        validator: PublicKey,
+        amount: u64,
+    },
+    ValidatorUnbonding {
+        validator: PublicKey,
+        amount: u64,
+        release_height: u64,
+    },
+    ValidatorSetScheduled {
+        epoch: u64,
+        validators: usize,
+    },
+}
+
+/// Outcome of a transaction included in a block
+///
+/// Receipts are committed to by `BlockHeader::receipts_root`, so their
+/// contents must be deterministic across nodes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxReceipt {
+    /// Transaction hash
+    pub tx_hash: Hash,
+    /// Whether all operations were applied
+    pub success: bool,
+    /// Stable machine-readable error code if the transaction failed
+    pub error_code: Option<String>,
+    /// Error message if the transaction failed
+    pub error: Option<String>,
+    /// Fee charged to the sender
+    pub fee_used: u64,
+    /// Events emitted by the transaction (empty if it failed)
+    pub events: Vec<ExecutionEvent>,
+}
+
+impl TxReceipt {
+    /// Compute the hash of this receipt
+    pub fn hash(&self) -> Result<Hash, CoreError> {
+        let bytes = serialize::to_bytes(self)?;
+        Ok(hash_blake3(&bytes))
+    }
+}
+
+/// A receipt together with the position of its transaction in the chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceiptRecord {
+    /// Height of the block containing the transaction
+    pub block_height: u64,
+    /// Index of the transaction within the block
+    pub index: u32,
+    /// The transaction receipt
+    pub receipt: TxReceipt,
+}
+
+/// Compute the merkle root of a block's receipts
+pub fn compute_receipts_root(receipts: &[TxReceipt]) -> Result<Hash, CoreError> {
+    let hashes: Result<Vec<Hash>, _> = receipts.iter().map(|r| r.hash()).collect();
+    Ok(merkle_root(&hashes?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+
+    fn receipt(success: bool) -> TxReceipt {
+        let from = KeyPair::generate();
+        let to = KeyPair::generate();
+        TxReceipt {
+            tx_hash: hash_blake3(b"tx"),
+            success,
+            error_code: (!success).then(|| "insufficient_balance".to_string()),
+            error: None,
+            fee_used: 100,
+            events: vec![ExecutionEvent::Transfer {
+                from: from.public,
+                to: to.public,
+                amount: 10,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_receipts_root_commits_to_status() {
+        let ok = receipt(true);
+        let mut failed = ok.clone();
+        failed.success = false;
+
+        let root_ok = compute_receipts_root(&[ok]).unwrap();
+        let root_failed = compute_receipts_root(&[failed]).unwrap();
+        assert_ne!(root_ok, root_failed);
+    }
+
+    #[test]
+    fn test_empty_receipts_root() {
+        assert_eq!(compute_receipts_root(&[]).unwrap(), Hash::ZERO);
+        assert_ne!(compute_receipts_root(&[receipt(false)]).unwrap(), Hash::ZERO);
+    }
+}
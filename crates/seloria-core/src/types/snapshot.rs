@@ -0,0 +1,238 @@
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{hash_blake3, Hash};
+use crate::error::CoreError;
+use crate::serialize;
+use crate::types::block::QuorumCertificate;
+use crate::types::proof::{StateKey, StateProof};
+use crate::types::staking::ValidatorSet;
+
+/// Version of the snapshot format described by [`SnapshotManifest`]
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 3;
+
+/// Describes a state snapshot: the certified block it was taken at and the
+/// chunks holding its state entries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    /// Snapshot format version
+    pub version: u32,
+    pub chain_id: u64,
+    /// Height of the block the snapshot was taken at
+    pub height: u64,
+    /// Hash of that block's header
+    pub block_hash: Hash,
+    /// State root in that block's header
+    pub state_root: Hash,
+    /// Validator signatures on the block (`None` for genesis)
+    pub qc: Option<QuorumCertificate>,
+    /// Blake3 hash of each chunk, in order
+    pub chunk_hashes: Vec<Hash>,
+    /// Every validator set change up to the snapshot block, oldest first:
+    /// a proof of the new [`StateKey::ValidatorSet`] at the block that made
+    /// the change, certified by the set it replaced
+    pub handoffs: Vec<StateProof>,
+}
+
+impl SnapshotManifest {
+    /// Check that the manifest is in a supported format for chain `chain_id`
+    /// and that its block was certified by the validator set reached from
+    /// `genesis` through the certified hand-offs
+    pub fn verify(&self, chain_id: u64, genesis: &ValidatorSet) -> Result<(), CoreError> {
+        if self.version != SNAPSHOT_FORMAT_VERSION {
+            return Err(CoreError::InvalidProof(format!(
+                "Unsupported snapshot format version {}",
+                self.version
+            )));
+        }
+        if self.chain_id != chain_id {
+            return Err(CoreError::InvalidProof("Wrong chain ID".to_string()));
+        }
+        let validators = self.validator_set(chain_id, genesis)?;
+        match &self.qc {
+            Some(qc) => {
+                if qc.chain_id != chain_id || qc.block_hash != self.block_hash {
+                    return Err(CoreError::InvalidProof(
+                        "Certificate is not for the snapshot block".to_string(),
+                    ));
+                }
+                qc.verify_quorum(&validators)
+            }
+            None if self.height == 0 => Ok(()),
+            None => Err(CoreError::InvalidProof(
+                "Snapshot block has no certificate".to_string(),
+            )),
+        }
+    }
+
+    /// Follow the hand-offs from `genesis` to the set that certified the
+    /// snapshot block. A hand-off at the snapshot block itself names the set
+    /// for the blocks after it and is checked, but not followed.
+    fn validator_set(
+        &self,
+        chain_id: u64,
+        genesis: &ValidatorSet,
+    ) -> Result<ValidatorSet, CoreError> {
+        let mut validators = genesis.clone();
+        let mut last_height = 0;
+        for handoff in &self.handoffs {
+            let height = handoff.header.height;
+            if height <= last_height || height > self.height {
+                return Err(CoreError::InvalidProof(format!(
+                    "Unexpected validator set hand-off at height {}",
+                    height
+                )));
+            }
+            handoff.verify(&StateKey::ValidatorSet, chain_id, &validators)?;
+            let next: ValidatorSet = handoff.decode_value()?.ok_or_else(|| {
+                CoreError::InvalidProof(format!("Hand-off at height {} has no set", height))
+            })?;
+            if height < self.height {
+                validators = next;
+            }
+            last_height = height;
+        }
+        Ok(validators)
+    }
+
+    /// Decode chunk `index`, checking its bytes against the manifest
+    pub fn verify_chunk(&self, index: usize, bytes: &[u8]) -> Result<SnapshotChunk, CoreError> {
+        let expected = self.chunk_hashes.get(index).ok_or_else(|| {
+            CoreError::InvalidProof(format!("Snapshot has no chunk {}", index))
+        })?;
+        if hash_blake3(bytes) != *expected {
+            return Err(CoreError::InvalidProof(format!(
+                "Chunk {} does not match its hash",
+                index
+            )));
+        }
+        serialize::from_bytes(bytes)
+    }
+}
+
+/// Part of a snapshot: storage entries in key order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotChunk {
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl SnapshotChunk {
+    /// Encode the chunk, returning the bytes and their hash
+    pub fn encode(&self) -> Result<(Vec<u8>, Hash), CoreError> {
+        let bytes = serialize::to_bytes(self)?;
+        let hash = hash_blake3(&bytes);
+        Ok((bytes, hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::smt::{smt_key, smt_leaf_hash};
+    use crate::crypto::{KeyPair, SmtProof};
+    use crate::testing;
+    use crate::types::block::BlockHeader;
+
+    /// Header at `height` with `state_root`, and a QC on it from `signers`
+    fn certified(
+        height: u64,
+        state_root: Hash,
+        signers: &[&KeyPair],
+    ) -> (BlockHeader, QuorumCertificate) {
+        let header = BlockHeader {
+            state_root,
+            ..testing::header(1, height)
+        };
+        let qc = testing::commit_qc(&header, signers.iter().copied());
+        (header, qc)
+    }
+
+    /// Hand-off to `next` at `height`, in a state holding only the set
+    fn handoff(height: u64, next: &ValidatorSet, signers: &[&KeyPair]) -> StateProof {
+        let value = serialize::to_bytes(next).unwrap();
+        let key_hash = smt_key(&StateKey::ValidatorSet.storage_key());
+        let value_hash = hash_blake3(&value);
+        let (header, qc) = certified(height, smt_leaf_hash(&key_hash, &value_hash), signers);
+        StateProof {
+            value: Some(value),
+            proof: SmtProof {
+                leaf: Some((key_hash, value_hash)),
+                siblings: vec![],
+            },
+            header,
+            qc,
+        }
+    }
+
+    #[test]
+    fn test_verify_manifest_and_chunks() {
+        let validator = KeyPair::generate();
+        let (header, qc) = certified(5, Hash::ZERO, &[&validator]);
+
+        let chunk = SnapshotChunk {
+            entries: vec![(b"acc:a".to_vec(), b"1".to_vec())],
+        };
+        let (bytes, hash) = chunk.encode().unwrap();
+        let manifest = SnapshotManifest {
+            version: SNAPSHOT_FORMAT_VERSION,
+            chain_id: 1,
+            height: 5,
+            block_hash: header.hash().unwrap(),
+            state_root: Hash::ZERO,
+            qc: Some(qc),
+            chunk_hashes: vec![hash],
+            handoffs: vec![],
+        };
+
+        let validators = ValidatorSet::equal(&[validator.public]);
+        manifest.verify(1, &validators).unwrap();
+        assert!(manifest.verify(2, &validators).is_err());
+        let others = ValidatorSet::equal(&[KeyPair::generate().public]);
+        assert!(manifest.verify(1, &others).is_err());
+        assert_eq!(manifest.verify_chunk(0, &bytes).unwrap(), chunk);
+
+        let mut tampered = bytes.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(manifest.verify_chunk(0, &tampered).is_err());
+        assert!(manifest.verify_chunk(1, &bytes).is_err());
+
+        let mut uncertified = manifest.clone();
+        uncertified.qc = None;
+        assert!(uncertified.verify(1, &validators).is_err());
+    }
+
+    #[test]
+    fn test_manifest_follows_validator_set_handoffs() {
+        let genesis = KeyPair::generate();
+        let successor = KeyPair::generate();
+        let genesis_set = ValidatorSet::equal(&[genesis.public]);
+        let next = ValidatorSet::equal(&[successor.public]);
+
+        let (header, qc) = certified(5, Hash::ZERO, &[&successor]);
+        let mut manifest = SnapshotManifest {
+            version: SNAPSHOT_FORMAT_VERSION,
+            chain_id: 1,
+            height: 5,
+            block_hash: header.hash().unwrap(),
+            state_root: Hash::ZERO,
+            qc: Some(qc),
+            chunk_hashes: vec![],
+            handoffs: vec![],
+        };
+        // Without the hand-off the snapshot block is not certified by genesis
+        assert!(manifest.verify(1, &genesis_set).is_err());
+
+        manifest.handoffs = vec![handoff(3, &next, &[&genesis])];
+        manifest.verify(1, &genesis_set).unwrap();
+
+        // A hand-off must be certified by the set it replaces
+        manifest.handoffs = vec![handoff(3, &next, &[&successor])];
+        assert!(manifest.verify(1, &genesis_set).is_err());
+
+        // Hand-offs past the snapshot block are rejected
+        manifest.handoffs = vec![
+            handoff(3, &next, &[&genesis]),
+            handoff(6, &next, &[&successor]),
+        ];
+        assert!(manifest.verify(1, &genesis_set).is_err());
+    }
+}
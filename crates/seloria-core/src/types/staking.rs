@@ -0,0 +1,314 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::domain::{self, signing_message};
+use crate::crypto::{hash_blake3, PublicKey};
+use crate::error::CoreError;
+use crate::serialize;
+use crate::types::account::LockId;
+use crate::types::block::{verify_signers, ValidatorSignature};
+
+/// Smallest bond that makes an account a validator candidate
+pub const MIN_VALIDATOR_BOND: u64 = 1_000_000;
+
+/// Number of blocks unbonded stake stays locked (and slashable) before it
+/// is returned to the validator's balance
+pub const UNBONDING_DELAY_BLOCKS: u64 = 1_000;
+
+/// Number of blocks in an epoch. The validator set is chosen from the
+/// bonded candidates at the end of every block whose height is a multiple
+/// of this.
+pub const EPOCH_LENGTH_BLOCKS: u64 = 100;
+
+/// Maximum number of validators chosen for an epoch
+pub const MAX_VALIDATORS: usize = 100;
+
+/// Epoch the block at `height` belongs to. Epoch `n` covers the blocks
+/// after height `n * EPOCH_LENGTH_BLOCKS` up to and including the next
+/// multiple; the genesis block is in epoch 0.
+pub fn epoch_of(height: u64) -> u64 {
+    height.saturating_sub(1) / EPOCH_LENGTH_BLOCKS
+}
+
+/// Height of the first block of `epoch`
+pub fn epoch_start(epoch: u64) -> u64 {
+    epoch * EPOCH_LENGTH_BLOCKS + 1
+}
+
+/// Lock holding a validator's bonded stake
+pub fn validator_bond_lock() -> LockId {
+    LockId::new(hash_blake3(b"seloria/validator-bond"))
+}
+
+/// Lock holding a validator's stake while it unbonds
+pub fn validator_unbonding_lock() -> LockId {
+    LockId::new(hash_blake3(b"seloria/validator-unbonding"))
+}
+
+/// A validator and its voting power
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidatorInfo {
+    pub pubkey: PublicKey,
+    pub power: u64,
+}
+
+/// Validators of an epoch with their voting power. A certificate needs
+/// votes from members holding more than two thirds of the total power.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidatorSet {
+    /// Members in leader rotation order
+    pub validators: Vec<ValidatorInfo>,
+}
+
+impl ValidatorSet {
+    pub fn new(validators: Vec<ValidatorInfo>) -> Self {
+        ValidatorSet { validators }
+    }
+
+    /// A set where every validator has a power of 1, so quorums are counted
+    /// by validators
+    pub fn equal(pubkeys: &[PublicKey]) -> Self {
+        ValidatorSet::new(
+            pubkeys
+                .iter()
+                .map(|pubkey| ValidatorInfo {
+                    pubkey: *pubkey,
+                    power: 1,
+                })
+                .collect(),
+        )
+    }
+
+    /// Member public keys in leader rotation order
+    pub fn pubkeys(&self) -> Vec<PublicKey> {
+        self.validators.iter().map(|v| v.pubkey).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    pub fn contains(&self, pubkey: &PublicKey) -> bool {
+        self.validators.iter().any(|v| v.pubkey == *pubkey)
+    }
+
+    /// Voting power of `pubkey` (0 for non-members)
+    pub fn power_of(&self, pubkey: &PublicKey) -> u64 {
+        self.validators
+            .iter()
+            .find(|v| v.pubkey == *pubkey)
+            .map_or(0, |v| v.power)
+    }
+
+    /// Total voting power, saturating at `u64::MAX`
+    pub fn total_power(&self) -> u64 {
+        saturate(self.total_power_wide())
+    }
+
+    /// Total voting power, or `None` if it does not fit in a `u64`
+    pub fn checked_total_power(&self) -> Option<u64> {
+        u64::try_from(self.total_power_wide()).ok()
+    }
+
+    /// Power a certificate needs: more than two thirds of the total
+    pub fn quorum_power(&self) -> u64 {
+        saturate(self.quorum_power_wide())
+    }
+
+    /// Combined power of the distinct members among `signers`, saturating
+    /// at `u64::MAX`
+    pub fn signed_power<'a>(&self, signers: impl IntoIterator<Item = &'a PublicKey>) -> u64 {
+        saturate(self.signed_power_wide(signers))
+    }
+
+    /// Whether `signers` hold a quorum of the voting power
+    pub fn has_quorum<'a>(&self, signers: impl IntoIterator<Item = &'a PublicKey>) -> bool {
+        self.signed_power_wide(signers) >= self.quorum_power_wide()
+    }
+
+    // Sums are taken in u128 so that large powers cannot overflow
+
+    fn total_power_wide(&self) -> u128 {
+        self.validators.iter().map(|v| v.power as u128).sum()
+    }
+
+    fn quorum_power_wide(&self) -> u128 {
+        self.total_power_wide() * 2 / 3 + 1
+    }
+
+    fn signed_power_wide<'a>(&self, signers: impl IntoIterator<Item = &'a PublicKey>) -> u128 {
+        let mut seen = HashSet::new();
+        signers
+            .into_iter()
+            .filter(|signer| seen.insert(**signer))
+            .map(|signer| self.power_of(signer) as u128)
+            .sum()
+    }
+}
+
+/// Validator set the current validators adopted for a coming epoch. It
+/// takes effect when the epoch begins, in place of the set chosen by stake.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidatorSetUpdate {
+    /// Epoch the set takes effect in
+    pub epoch: u64,
+    pub validators: ValidatorSet,
+    /// Signatures of current validators over [`ValidatorSetUpdate::message`]
+    pub signatures: Vec<ValidatorSignature>,
+}
+
+impl ValidatorSetUpdate {
+    /// Message validators sign to approve the update on `chain_id`
+    pub fn message(&self, chain_id: u64) -> Result<Vec<u8>, CoreError> {
+        let payload = serialize::to_bytes(&(self.epoch, &self.validators))?;
+        Ok(signing_message(
+            domain::VALIDATOR_SET_V1,
+            chain_id,
+            hash_blake3(&payload).as_bytes(),
+        ))
+    }
+
+    /// Check that the new set is well formed and that members of `current`
+    /// holding a quorum of its voting power approved it
+    pub fn verify(&self, chain_id: u64, current: &ValidatorSet) -> Result<(), CoreError> {
+        let members = &self.validators.validators;
+        if members.is_empty() || members.len() > MAX_VALIDATORS {
+            return Err(CoreError::InvalidProof(format!(
+                "Validator set must have 1 to {} members, got {}",
+                MAX_VALIDATORS,
+                members.len()
+            )));
+        }
+        let mut seen = HashSet::new();
+        if members.iter().any(|v| v.power == 0 || !seen.insert(v.pubkey)) {
+            return Err(CoreError::InvalidProof(
+                "Validator set has a duplicate member or one without power".to_string(),
+            ));
+        }
+        if self.validators.checked_total_power().is_none() {
+            return Err(CoreError::InvalidProof(
+                "Validator set's total power overflows".to_string(),
+            ));
+        }
+        verify_signers(&self.signatures, &self.message(chain_id)?, current)
+    }
+}
+
+fn saturate(power: u128) -> u64 {
+    u64::try_from(power).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+
+    #[test]
+    fn test_quorum_is_weighted_by_power() {
+        let keys: Vec<_> = (0..4).map(|_| KeyPair::generate().public).collect();
+        let set = ValidatorSet::new(
+            keys.iter()
+                .zip([60, 20, 10, 10])
+                .map(|(pubkey, power)| ValidatorInfo {
+                    pubkey: *pubkey,
+                    power,
+                })
+                .collect(),
+        );
+
+        assert_eq!(set.total_power(), 100);
+        assert_eq!(set.quorum_power(), 67);
+        // One heavy validator is not enough alone...
+        assert!(!set.has_quorum([&keys[0]]));
+        // ...nor are the three light ones, even counted twice
+        assert!(!set.has_quorum([&keys[1], &keys[2], &keys[3], &keys[1]]));
+        assert!(set.has_quorum([&keys[0], &keys[1]]));
+        // Non-members carry no power
+        let outsider = KeyPair::generate().public;
+        assert_eq!(set.signed_power([&outsider]), 0);
+
+        // Equal power reduces to the count-based 2/3 + 1 threshold
+        let equal = ValidatorSet::equal(&keys);
+        assert_eq!(equal.quorum_power(), 3);
+        assert_eq!(equal.pubkeys(), keys);
+    }
+
+    #[test]
+    fn test_epoch_boundaries() {
+        assert_eq!(epoch_of(0), 0);
+        assert_eq!(epoch_of(EPOCH_LENGTH_BLOCKS), 0);
+        assert_eq!(epoch_of(EPOCH_LENGTH_BLOCKS + 1), 1);
+        assert_eq!(epoch_start(1), EPOCH_LENGTH_BLOCKS + 1);
+        assert_eq!(epoch_of(epoch_start(7)), 7);
+    }
+
+    #[test]
+    fn test_validator_set_update_needs_quorum() {
+        let current: Vec<_> = (0..4).map(|_| KeyPair::generate()).collect();
+        let set = ValidatorSet::equal(&current.iter().map(|k| k.public).collect::<Vec<_>>());
+        let mut update = ValidatorSetUpdate {
+            epoch: 3,
+            validators: ValidatorSet::equal(&[KeyPair::generate().public]),
+            signatures: Vec::new(),
+        };
+        let message = update.message(1).unwrap();
+        let signature = |key: &KeyPair| ValidatorSignature {
+            validator_pubkey: key.public,
+            signature: crate::crypto::sign(&key.secret, &message),
+        };
+
+        update.signatures = current[..2].iter().map(signature).collect();
+        assert!(update.verify(1, &set).is_err());
+        update.signatures = current[..3].iter().map(signature).collect();
+        update.verify(1, &set).unwrap();
+        // Signed for another chain or epoch
+        assert!(update.verify(2, &set).is_err());
+        let mut later = update.clone();
+        later.epoch = 4;
+        assert!(later.verify(1, &set).is_err());
+
+        // The new set must not be empty
+        let mut empty = update.clone();
+        empty.validators = ValidatorSet::default();
+        assert!(empty.verify(1, &set).is_err());
+    }
+
+    #[test]
+    fn test_validator_set_update_rejects_overflowing_power() {
+        let current: Vec<_> = (0..3).map(|_| KeyPair::generate()).collect();
+        let set = ValidatorSet::equal(&current.iter().map(|k| k.public).collect::<Vec<_>>());
+        let heavy = ValidatorSet::new(
+            (0..2)
+                .map(|_| ValidatorInfo {
+                    pubkey: KeyPair::generate().public,
+                    power: u64::MAX,
+                })
+                .collect(),
+        );
+        let mut update = ValidatorSetUpdate {
+            epoch: 3,
+            validators: heavy.clone(),
+            signatures: Vec::new(),
+        };
+        let message = update.message(1).unwrap();
+        update.signatures = current
+            .iter()
+            .map(|key| ValidatorSignature {
+                validator_pubkey: key.public,
+                signature: crate::crypto::sign(&key.secret, &message),
+            })
+            .collect();
+        assert!(update.verify(1, &set).is_err());
+
+        // Sums over such a set still count without wrapping around
+        assert_eq!(heavy.checked_total_power(), None);
+        assert_eq!(heavy.total_power(), u64::MAX);
+        assert!(!heavy.has_quorum([&heavy.validators[0].pubkey]));
+        assert!(heavy.has_quorum(heavy.validators.iter().map(|v| &v.pubkey)));
+    }
+}
@@ -1,12 +1,15 @@
 use serde::{Deserialize, Serialize};
 
+use crate::crypto::domain::{self, signing_message};
 use crate::crypto::{hash_blake3, sign, verify, Hash, PublicKey, SecretKey, Sig};
 use crate::error::CoreError;
 use crate::serialize;
 use crate::types::agent_cert::SignedAgentCertificate;
 use crate::types::app::AppMeta;
 use crate::types::claim::Vote;
+use crate::types::evidence::Evidence;
 use crate::types::namespace::KvValue;
+use crate::types::staking::ValidatorSetUpdate;
 use crate::types::token::NATIVE_TOKEN_ID;
 
 /// Operations that can be included in a transaction
@@ -102,17 +105,46 @@ pub enum Op {
         allowlist: Vec<PublicKey>,
         min_write_stake: u64,
     },
+    /// Submit proof that a validator equivocated, slashing its bond and
+    /// removing it from the validator set
+    SubmitEvidence {
+        evidence: Box<Evidence>,
+    },
+    /// Bond native tokens as validator stake, becoming a candidate for the
+    /// validator set once the total bond reaches the minimum
+    ValidatorBond {
+        amount: u64,
+    },
+    /// Start unbonding validator stake. It stays locked and slashable for
+    /// the unbonding delay before it is returned.
+    ValidatorUnbond {
+        amount: u64,
+    },
+    /// Schedule a validator set approved by the current validators for a
+    /// coming epoch
+    UpdateValidatorSet {
+        update: Box<ValidatorSetUpdate>,
+    },
 }
 
 /// A transaction containing one or more operations
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
+    /// Chain the transaction is valid on (covered by the signature)
+    #[serde(default)]
+    pub chain_id: u64,
     /// Sender's public key
     pub sender_pubkey: PublicKey,
     /// Transaction nonce (must equal account nonce + 1)
     pub nonce: u64,
     /// Fee to pay for transaction processing
     pub fee: u64,
+    /// Last block height the transaction may be included at (inclusive)
+    #[serde(default)]
+    pub valid_until_height: Option<u64>,
+    /// Last block timestamp the transaction may be included at (inclusive)
+    #[serde(default)]
+    pub valid_until_time: Option<u64>,
     /// List of operations to execute
     pub ops: Vec<Op>,
     /// Signature over the transaction (excluding this field)
@@ -122,37 +154,71 @@ pub struct Transaction {
 /// Transaction data for signing (excludes signature field)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct TransactionSigningData {
+    chain_id: u64,
     sender_pubkey: PublicKey,
     nonce: u64,
     fee: u64,
+    valid_until_height: Option<u64>,
+    valid_until_time: Option<u64>,
     ops: Vec<Op>,
 }
 
 impl Transaction {
     /// Create a new unsigned transaction
-    pub fn new(sender_pubkey: PublicKey, nonce: u64, fee: u64, ops: Vec<Op>) -> Self {
+    pub fn new(
+        chain_id: u64,
+        sender_pubkey: PublicKey,
+        nonce: u64,
+        fee: u64,
+        ops: Vec<Op>,
+    ) -> Self {
         Transaction {
+            chain_id,
             sender_pubkey,
             nonce,
             fee,
+            valid_until_height: None,
+            valid_until_time: None,
             ops,
             signature: Sig::default(),
         }
     }
 
+    /// Set an expiry (by block height and/or block time); must be called before signing
+    pub fn with_expiry(
+        mut self,
+        valid_until_height: Option<u64>,
+        valid_until_time: Option<u64>,
+    ) -> Self {
+        self.valid_until_height = valid_until_height;
+        self.valid_until_time = valid_until_time;
+        self
+    }
+
+    /// Check whether the transaction can no longer be included in a block
+    /// at `height` with timestamp `time`
+    pub fn is_expired(&self, height: u64, time: u64) -> bool {
+        self.valid_until_height.is_some_and(|h| height > h)
+            || self.valid_until_time.is_some_and(|t| time > t)
+    }
+
     /// Get the data to be signed
     fn signing_data(&self) -> TransactionSigningData {
         TransactionSigningData {
+            chain_id: self.chain_id,
             sender_pubkey: self.sender_pubkey,
             nonce: self.nonce,
             fee: self.fee,
+            valid_until_height: self.valid_until_height,
+            valid_until_time: self.valid_until_time,
             ops: self.ops.clone(),
         }
     }
 
-    /// Get bytes for signing
+    /// Get bytes for signing (domain-separated and bound to the chain ID)
     pub fn signing_bytes(&self) -> Result<Vec<u8>, CoreError> {
-        serialize::to_bytes(&self.signing_data())
+        let payload = serialize::to_bytes(&self.signing_data())?;
+        Ok(signing_message(domain::TX_V1, self.chain_id, &payload))
     }
 
     /// Sign the transaction
@@ -164,13 +230,14 @@ impl Transaction {
 
     /// Create a signed transaction
     pub fn new_signed(
+        chain_id: u64,
         sender_pubkey: PublicKey,
         nonce: u64,
         fee: u64,
         ops: Vec<Op>,
         secret_key: &SecretKey,
     ) -> Result<Self, CoreError> {
-        let mut tx = Self::new(sender_pubkey, nonce, fee, ops);
+        let mut tx = Self::new(chain_id, sender_pubkey, nonce, fee, ops);
         tx.sign(secret_key)?;
         Ok(tx)
     }
@@ -196,6 +263,7 @@ impl Transaction {
                 Op::Transfer { amount, .. } => cost += amount,
                 Op::ClaimCreate { stake, .. } => cost += stake,
                 Op::Attest { stake, .. } => cost += stake,
+                Op::ValidatorBond { amount } => cost += amount,
                 Op::PoolCreate {
                     token_a,
                     token_b,
@@ -238,6 +306,7 @@ mod tests {
         let receiver = KeyPair::generate();
 
         let tx = Transaction::new_signed(
+            1,
             sender.public,
             1,
             100,
@@ -259,6 +328,7 @@ mod tests {
         let receiver = KeyPair::generate();
 
         let tx = Transaction::new_signed(
+            1,
             sender.public, // Sender pubkey
             1,
             100,
@@ -273,11 +343,52 @@ mod tests {
         assert!(tx.verify_signature().is_err());
     }
 
+    #[test]
+    fn test_transaction_signature_bound_to_chain() {
+        let sender = KeyPair::generate();
+
+        let mut tx = Transaction::new_signed(
+            1,
+            sender.public,
+            1,
+            100,
+            vec![Op::Transfer {
+                to: sender.public,
+                amount: 500,
+            }],
+            &sender.secret,
+        )
+        .unwrap();
+        assert!(tx.verify_signature().is_ok());
+
+        // Replaying the same signature on another chain fails
+        tx.chain_id = 2;
+        assert!(tx.verify_signature().is_err());
+    }
+
+    #[test]
+    fn test_transaction_expiry() {
+        let sender = KeyPair::generate();
+
+        let mut tx = Transaction::new(1, sender.public, 1, 100, vec![])
+            .with_expiry(Some(10), Some(5_000));
+        tx.sign(&sender.secret).unwrap();
+
+        assert!(!tx.is_expired(10, 5_000));
+        assert!(tx.is_expired(11, 5_000));
+        assert!(tx.is_expired(10, 5_001));
+
+        // The expiry is covered by the signature
+        tx.valid_until_height = Some(100);
+        assert!(tx.verify_signature().is_err());
+    }
+
     #[test]
     fn test_transaction_hash_deterministic() {
         let sender = KeyPair::generate();
 
         let tx = Transaction::new_signed(
+            1,
             sender.public,
             1,
             100,
@@ -300,6 +411,7 @@ mod tests {
         let receiver = KeyPair::generate();
 
         let tx = Transaction::new(
+            1,
             sender.public,
             1,
             100, // fee
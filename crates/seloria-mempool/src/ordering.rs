@@ -70,6 +70,11 @@ fn estimate_op_size(op: &seloria_core::Op) -> usize {
         Op::PoolAdd { .. } => 96,    // pool_id + amounts + min_lp
         Op::PoolRemove { .. } => 96, // pool_id + lp + mins
         Op::Swap { .. } => 96,       // pool_id + token + amounts
+        Op::SubmitEvidence { .. } => 640, // two headers + two votes
+        Op::ValidatorBond { .. } | Op::ValidatorUnbond { .. } => 16, // amount
+        Op::UpdateValidatorSet { update } => {
+            16 + update.validators.len() * 40 + update.signatures.len() * 96
+        }
     }
 }
 
@@ -1,4 +1,5 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use seloria_core::{Hash, PublicKey, Transaction};
@@ -49,6 +50,8 @@ pub struct Mempool {
     by_sender: RwLock<HashMap<PublicKey, HashSet<Hash>>>,
     /// Transaction hashes ordered by priority (for fee-rate ordering)
     by_priority: RwLock<BTreeMap<(TxPriority, Hash), Hash>>,
+    /// Height of the next block, used to reject transactions past `valid_until_height`
+    next_height: AtomicU64,
 }
 
 impl Mempool {
@@ -58,6 +61,7 @@ impl Mempool {
             by_hash: RwLock::new(HashMap::new()),
             by_sender: RwLock::new(HashMap::new()),
             by_priority: RwLock::new(BTreeMap::new()),
+            next_height: AtomicU64::new(0),
         }
     }
 
@@ -73,6 +77,9 @@ impl Mempool {
     pub async fn add(&self, tx: Transaction) -> Result<Hash, MempoolError> {
         let hash = tx.hash().map_err(|_| MempoolError::InvalidTransaction)?;
         let timestamp = Self::current_timestamp();
+        if tx.is_expired(self.next_height.load(Ordering::Relaxed), timestamp) {
+            return Err(MempoolError::Expired);
+        }
         let priority = TxPriority::from_transaction(&tx, timestamp);
 
         let mut by_hash = self.by_hash.write().await;
@@ -212,6 +219,14 @@ impl Mempool {
 
     /// Remove expired transactions
     pub async fn remove_expired(&self) {
+        self.evict_expired(self.next_height.load(Ordering::Relaxed)).await;
+    }
+
+    /// Record the next block height and evict every transaction that can no
+    /// longer be included: past its `valid_until_height`/`valid_until_time`,
+    /// or older than `expiry_seconds`. Returns the number of evicted transactions.
+    pub async fn evict_expired(&self, next_height: u64) -> usize {
+        self.next_height.store(next_height, Ordering::Relaxed);
         let now = Self::current_timestamp();
         let expiry_threshold = now.saturating_sub(self.config.expiry_seconds);
 
@@ -221,14 +236,16 @@ impl Mempool {
 
         let expired: Vec<Hash> = by_hash
             .iter()
-            .filter(|(_, p)| p.added_at < expiry_threshold)
+            .filter(|(_, p)| p.added_at < expiry_threshold || p.tx.is_expired(next_height, now))
             .map(|(h, _)| *h)
             .collect();
 
-        for hash in expired {
-            self.remove_internal(&hash, &mut by_hash, &mut by_sender, &mut by_priority);
+        for hash in &expired {
+            self.remove_internal(hash, &mut by_hash, &mut by_sender, &mut by_priority);
             warn!("Removed expired transaction {}", hash);
         }
+
+        expired.len()
     }
 
     /// Get current pool size
@@ -275,6 +292,9 @@ pub enum MempoolError {
 
     #[error("Invalid transaction")]
     InvalidTransaction,
+
+    #[error("Transaction expired")]
+    Expired,
 }
 
 #[cfg(test)]
@@ -284,6 +304,7 @@ mod tests {
 
     fn create_test_tx(sender: &KeyPair, nonce: u64, fee: u64) -> Transaction {
         Transaction::new_signed(
+            1,
             sender.public,
             nonce,
             fee,
@@ -389,4 +410,32 @@ mod tests {
         assert_eq!(txs[0].nonce, 1);
         assert_eq!(txs[1].nonce, 2);
     }
+
+    #[tokio::test]
+    async fn test_expired_transactions() {
+        let mempool = Mempool::new(MempoolConfig::default());
+        let sender = KeyPair::generate();
+
+        let expiring = |nonce: u64, valid_until_height: u64| {
+            let mut tx = create_test_tx(&sender, nonce, 100)
+                .with_expiry(Some(valid_until_height), None);
+            tx.sign(&sender.secret).unwrap();
+            tx
+        };
+
+        let short = expiring(1, 5);
+        let short_hash = short.hash().unwrap();
+        mempool.add(short).await.unwrap();
+        mempool.add(expiring(2, 10)).await.unwrap();
+        mempool.add(create_test_tx(&sender, 3, 100)).await.unwrap();
+
+        // Block 5 is committed: height-5 transactions can no longer land
+        assert_eq!(mempool.evict_expired(6).await, 1);
+        assert!(!mempool.contains(&short_hash).await);
+        assert_eq!(mempool.size().await, 2);
+
+        // Already-expired transactions are rejected on add
+        let result = mempool.add(expiring(4, 5)).await;
+        assert!(matches!(result, Err(MempoolError::Expired)));
+    }
 }
@@ -59,10 +59,46 @@ pub enum Commands {
 
     /// Generate signed transactions for testing
     Txgen {
+        /// Chain ID the generated transactions and certificates are bound to
+        #[arg(long, default_value = "1")]
+        chain_id: u64,
+
         #[command(subcommand)]
         command: TxGenCommands,
     },
 
+    /// Revert the node's state by undoing its last blocks (node must be stopped)
+    Revert {
+        /// Path to configuration file
+        #[arg(short, long, default_value = "config.json")]
+        config: PathBuf,
+
+        /// Number of blocks to undo
+        #[arg(short, long)]
+        blocks: u64,
+    },
+
+    /// Rebuild the node's state by replaying its stored blocks from genesis
+    /// into a new data directory (node must be stopped)
+    RebuildState {
+        /// Path to configuration file
+        #[arg(short, long, default_value = "config.json")]
+        config: PathBuf,
+
+        /// Data directory to write the rebuilt state and blocks to
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+
+    /// Sync the node's state from its peers: restore the newest verified
+    /// snapshot into an empty data directory, then fetch and apply the
+    /// blocks after it (node must be stopped)
+    Sync {
+        /// Path to configuration file
+        #[arg(short, long, default_value = "config.json")]
+        config: PathBuf,
+    },
+
     /// Snapshot utilities
     Snapshot {
         #[command(subcommand)]
@@ -72,14 +108,18 @@ pub enum Commands {
 
 #[derive(Subcommand)]
 pub enum SnapshotCommands {
-    /// Download a snapshot from a node
+    /// Download a snapshot from a node, verify it and restore it into an
+    /// empty data directory
     Pull {
         /// RPC endpoint
         #[arg(short, long, default_value = "http://127.0.0.1:8080")]
         endpoint: String,
-        /// Output file path
-        #[arg(short, long, default_value = "seloria-data/state.bin")]
-        out: PathBuf,
+        /// Configuration file giving the chain ID and validators to verify against
+        #[arg(short, long, default_value = "config.json")]
+        config: PathBuf,
+        /// Data directory to restore into (defaults to the config's data_dir)
+        #[arg(short, long)]
+        out: Option<PathBuf>,
     },
 }
 
@@ -3,6 +3,7 @@ use std::path::PathBuf;
 
 use anyhow::Result;
 use seloria_core::{GenesisConfig, KeyPair, PublicKey};
+use seloria_state::PruningMode;
 use serde::{Deserialize, Serialize};
 
 /// Node configuration
@@ -26,6 +27,25 @@ pub struct NodeConfig {
     /// Block builder max transactions
     pub max_block_txs: usize,
 
+    /// Include transactions that fail execution in blocks (fee charged, failed receipt)
+    #[serde(default = "default_include_failed_txs")]
+    pub include_failed_txs: bool,
+
+    /// Number of past blocks whose state can be queried with `at_height`
+    /// (`null` keeps all history)
+    #[serde(default = "default_history_retention_blocks")]
+    pub history_retention_blocks: Option<u64>,
+
+    /// Block and transaction retention: `"archive"` keeps everything,
+    /// `{"pruned": {"keep_blocks": N}}` keeps only the last N block bodies
+    #[serde(default)]
+    pub pruning: PruningMode,
+
+    /// Time to wait for a block from the leader of a view before voting to
+    /// time it out and move to the next validator
+    #[serde(default = "default_view_timeout_ms")]
+    pub view_timeout_ms: u64,
+
     /// Mempool max size
     pub mempool_max_size: usize,
 
@@ -46,6 +66,11 @@ pub struct NodeConfig {
 
     /// Optional validator endpoints for committee mode
     pub validator_endpoints: Vec<ValidatorEndpointConfig>,
+
+    /// RPC endpoints `seloria sync` fetches snapshots and blocks from
+    /// (defaults to the validator endpoints)
+    #[serde(default)]
+    pub sync_peers: Vec<String>,
 }
 
 /// Genesis configuration for file
@@ -55,6 +80,9 @@ pub struct GenesisConfigFile {
     pub initial_balances: Vec<BalanceEntry>,
     pub trusted_issuers: Vec<String>,
     pub validators: Vec<String>,
+    /// Stake bonded for each genesis validator (0 gives equal voting power)
+    #[serde(default)]
+    pub validator_stake: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -69,6 +97,18 @@ pub struct ValidatorEndpointConfig {
     pub address: String,
 }
 
+fn default_include_failed_txs() -> bool {
+    true
+}
+
+fn default_history_retention_blocks() -> Option<u64> {
+    Some(10_000)
+}
+
+fn default_view_timeout_ms() -> u64 {
+    seloria_consensus::DEFAULT_VIEW_TIMEOUT_MS
+}
+
 impl Default for NodeConfig {
     fn default() -> Self {
         NodeConfig {
@@ -78,6 +118,10 @@ impl Default for NodeConfig {
             enable_ws: true,
             round_time_ms: 2000,
             max_block_txs: 1000,
+            include_failed_txs: true,
+            history_retention_blocks: default_history_retention_blocks(),
+            pruning: PruningMode::Archive,
+            view_timeout_ms: default_view_timeout_ms(),
             mempool_max_size: 10_000,
             mempool_max_per_sender: 100,
             genesis: GenesisConfigFile::default(),
@@ -85,6 +129,7 @@ impl Default for NodeConfig {
             issuer_key: None,
             faucet_secret: None,
             validator_endpoints: Vec::new(),
+            sync_peers: Vec::new(),
         }
     }
 }
@@ -96,11 +141,24 @@ impl Default for GenesisConfigFile {
             initial_balances: vec![],
             trusted_issuers: vec![],
             validators: vec![],
+            validator_stake: 0,
         }
     }
 }
 
 impl NodeConfig {
+    /// Endpoints to sync from: `sync_peers`, or the validator endpoints
+    /// if none are configured
+    pub fn sync_endpoints(&self) -> Vec<String> {
+        if !self.sync_peers.is_empty() {
+            return self.sync_peers.clone();
+        }
+        self.validator_endpoints
+            .iter()
+            .map(|endpoint| endpoint.address.clone())
+            .collect()
+    }
+
     /// Load config from file
     pub fn load(path: &PathBuf) -> Result<Self> {
         let content = std::fs::read_to_string(path)?;
@@ -148,6 +206,7 @@ impl NodeConfig {
             initial_balances: initial_balances?,
             trusted_issuers: trusted_issuers?,
             validators: validators?,
+            validator_stake: self.genesis.validator_stake,
         })
     }
 }
@@ -166,6 +225,10 @@ pub fn generate_sample_config() -> NodeConfig {
         enable_ws: true,
         round_time_ms: 2000,
         max_block_txs: 1000,
+        include_failed_txs: true,
+        history_retention_blocks: default_history_retention_blocks(),
+        pruning: PruningMode::Archive,
+        view_timeout_ms: default_view_timeout_ms(),
         mempool_max_size: 10_000,
         mempool_max_per_sender: 100,
         genesis: GenesisConfigFile {
@@ -179,11 +242,13 @@ pub fn generate_sample_config() -> NodeConfig {
             }],
             trusted_issuers: vec![issuer.public.to_hex()],
             validators: vec![validator.public.to_hex()],
+            validator_stake: 0,
         },
         validator_key: Some(validator.secret.to_hex()),
         issuer_key: Some(issuer.secret.to_hex()),
         faucet_secret: None,
         validator_endpoints: Vec::new(),
+        sync_peers: Vec::new(),
     }
 }
 
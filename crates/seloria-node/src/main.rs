@@ -10,10 +10,13 @@ mod config;
 mod node;
 mod txgen;
 mod snapshot;
+mod sync;
 
 use cli::{Cli, Commands, SnapshotCommands};
 use config::{generate_sample_config, NodeConfig};
 use node::Node;
+use seloria_consensus::{BlockBuilder, BlockBuilderConfig};
+use seloria_state::{BlockStore, ChainState, FileBlockStore, LogStorage};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -42,8 +45,18 @@ async fn main() -> Result<()> {
         Commands::Tx { endpoint, file } => {
             submit_transaction(&endpoint, file).await?;
         }
-        Commands::Txgen { command } => {
-            txgen::handle_txgen(command)?;
+        Commands::Txgen { chain_id, command } => {
+            txgen::handle_txgen(chain_id, command)?;
+        }
+        Commands::Revert { config, blocks } => {
+            revert_blocks(config, blocks)?;
+        }
+        Commands::RebuildState { config, out } => {
+            rebuild_state(config, out)?;
+        }
+        Commands::Sync { config } => {
+            let config = NodeConfig::load(&config)?;
+            sync::sync(&config).await?;
         }
         Commands::Snapshot { command } => {
             handle_snapshot(command).await?;
@@ -154,11 +167,68 @@ async fn submit_transaction(endpoint: &str, file: PathBuf) -> Result<()> {
     Ok(())
 }
 
+/// Undo the last blocks of a stopped node's state
+fn revert_blocks(config_path: PathBuf, blocks: u64) -> Result<()> {
+    let config = NodeConfig::load(&config_path)?;
+    let storage = LogStorage::open(config.data_dir.join("state.bin"))?;
+    let mut state = ChainState::new(storage);
+    state.set_block_store(FileBlockStore::open(config.data_dir.join("blocks"))?);
+    state.load_from_storage()?;
+
+    let from = state.height;
+    state.revert_blocks(blocks)?;
+    state.compact_storage()?;
+    info!("Reverted state from height {} to {}", from, state.height);
+
+    Ok(())
+}
+
+/// Replay a stopped node's blocks from genesis into a new data directory
+fn rebuild_state(config_path: PathBuf, out: PathBuf) -> Result<()> {
+    let config = NodeConfig::load(&config_path)?;
+    let blocks = FileBlockStore::open(config.data_dir.join("blocks"))?;
+    if blocks.pruned_below() > 1 {
+        return Err(anyhow::anyhow!(
+            "Blocks below height {} have been pruned; rebuilding needs an archive node's blocks",
+            blocks.pruned_below()
+        ));
+    }
+    if out.join("state.bin").exists() {
+        return Err(anyhow::anyhow!("{} already holds a state", out.display()));
+    }
+
+    std::fs::create_dir_all(&out)?;
+    let mut state = ChainState::new(LogStorage::open(out.join("state.bin"))?);
+    state.set_block_store(FileBlockStore::open(out.join("blocks"))?);
+    state.init_genesis(&config.to_genesis_config()?)?;
+
+    let builder = BlockBuilder::new(BlockBuilderConfig {
+        chain_id: config.chain_id,
+        ..Default::default()
+    });
+    builder.replay_blocks(&mut state, &blocks)?;
+    state.compact_storage()?;
+    info!(
+        "Rebuilt state at height {} with root {} in {:?}",
+        state.height,
+        state.compute_state_root()?,
+        out
+    );
+
+    Ok(())
+}
+
 /// Handle snapshot commands
 async fn handle_snapshot(command: SnapshotCommands) -> Result<()> {
     match command {
-        SnapshotCommands::Pull { endpoint, out } => {
-            snapshot::download_snapshot(&endpoint, out).await?;
+        SnapshotCommands::Pull {
+            endpoint,
+            config,
+            out,
+        } => {
+            let config = NodeConfig::load(&config)?;
+            let data_dir = out.unwrap_or_else(|| config.data_dir.clone());
+            snapshot::download_snapshot(&endpoint, &config, &data_dir).await?;
         }
     }
 
@@ -1,13 +1,17 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
-use seloria_consensus::{BlockEventSink, Proposer, ProposerConfig, ValidatorEndpoint};
+use seloria_consensus::{
+    BlockBuilder, BlockBuilderConfig, BlockEventSink, EvidencePool, Pacemaker, Proposer,
+    ProposerConfig, Validator, ValidatorEndpoint,
+};
 use seloria_core::{Block, KeyPair, SecretKey};
 use seloria_core::{AgentCertificate, Hash, SignedAgentCertificate, Transaction, Op};
 use seloria_mempool::{Mempool, MempoolConfig};
 use seloria_rpc::{RpcConfig, RpcServer, WsEvent};
 use seloria_rpc::ws::EventBroadcaster;
-use seloria_state::{ChainState, FileStorage};
+use seloria_state::{ChainState, FileBlockStore, LogStorage};
 use tokio::sync::{Mutex, RwLock};
 use tracing::{error, info};
 
@@ -16,7 +20,7 @@ use crate::config::NodeConfig;
 /// The Seloria node
 pub struct Node {
     config: NodeConfig,
-    state: Arc<RwLock<ChainState<FileStorage>>>,
+    state: Arc<RwLock<ChainState<LogStorage>>>,
     mempool: Arc<Mempool>,
     broadcaster: Arc<EventBroadcaster>,
     validator_keypair: Option<Arc<Mutex<KeyPair>>>,
@@ -90,8 +94,12 @@ impl Node {
 
         // Create state
         let storage_path = config.data_dir.join("state.bin");
-        let storage = FileStorage::new(storage_path)?;
-        let state = Arc::new(RwLock::new(ChainState::new(storage)));
+        let storage = LogStorage::open(storage_path)?;
+        let mut chain_state = ChainState::new(storage);
+        chain_state.set_block_store(FileBlockStore::open(config.data_dir.join("blocks"))?);
+        chain_state.set_history_retention(config.history_retention_blocks);
+        chain_state.set_pruning_mode(config.pruning);
+        let state = Arc::new(RwLock::new(chain_state));
 
         // Create mempool
         let mempool_config = MempoolConfig {
@@ -155,10 +163,33 @@ impl Node {
             }
         }
 
-        // Get validators
-        let validators = {
-            let state = self.state.read().await;
-            state.validators.clone()
+        // Views at the current height, shared by the proposer and the
+        // timeout route
+        let pacemaker = Arc::new(Pacemaker::new(Duration::from_millis(
+            self.config.view_timeout_ms,
+        )));
+
+        // Equivocations detected by the proposer or the commit route
+        let evidence = Arc::new(EvidencePool::new());
+
+        // Safety state for this validator's votes, shared by the proposer and
+        // the consensus routes
+        let validator = match &self.validator_keypair {
+            Some(keypair) => {
+                let keypair = keypair.lock().await.clone();
+                let validator = Validator::new(
+                    keypair.public,
+                    keypair.secret,
+                    BlockBuilder::new(BlockBuilderConfig {
+                        chain_id: self.config.chain_id,
+                        max_transactions: self.config.max_block_txs,
+                        include_failed_txs: self.config.include_failed_txs,
+                    }),
+                )
+                .with_safety_wal(self.config.data_dir.join("safety.wal"))?;
+                Some(Arc::new(validator))
+            }
+            None => None,
         };
 
         // Start RPC server
@@ -167,7 +198,7 @@ impl Node {
             enable_ws: self.config.enable_ws,
         };
 
-        let rpc_server = RpcServer::new(
+        let mut rpc_server = RpcServer::new(
             rpc_config,
             Arc::clone(&self.state),
             Arc::clone(&self.mempool),
@@ -177,7 +208,13 @@ impl Node {
             Some(self.config.data_dir.join("state.bin")),
             self.validator_endpoints.clone(),
             self.faucet_keypair.clone(),
-        );
+        )
+        .with_snapshot_dir(self.config.data_dir.join("snapshot"))
+        .with_pacemaker(Arc::clone(&pacemaker))
+        .with_evidence_pool(Arc::clone(&evidence));
+        if let Some(validator) = &validator {
+            rpc_server = rpc_server.with_validator(Arc::clone(validator));
+        }
 
         let rpc_router = rpc_server.router();
         let rpc_addr = self.config.rpc_addr;
@@ -187,10 +224,10 @@ impl Node {
             let keypair = keypair.lock().await.clone();
             let proposer_config = ProposerConfig {
                 round_time_ms: self.config.round_time_ms,
-                num_validators: validators.len(),
-                threshold: (validators.len() * 2 / 3) + 1,
                 chain_id: self.config.chain_id,
                 max_block_txs: self.config.max_block_txs,
+                include_failed_txs: self.config.include_failed_txs,
+                view_timeout_ms: self.config.view_timeout_ms,
             };
 
             let mut proposer = Proposer::new(
@@ -199,12 +236,16 @@ impl Node {
                 keypair.secret.clone(),
                 Arc::clone(&self.state),
                 Arc::clone(&self.mempool),
-                validators,
             );
 
             if !self.validator_endpoints.is_empty() {
                 proposer.set_validator_endpoints(self.validator_endpoints.clone());
             }
+            proposer.set_pacemaker(pacemaker);
+            proposer.set_evidence_pool(evidence);
+            if let Some(validator) = validator {
+                proposer.set_validator(validator);
+            }
 
             proposer.set_event_sink(Arc::new(RpcEventSink {
                 broadcaster: Arc::clone(&self.broadcaster),
@@ -215,7 +256,7 @@ impl Node {
             info!("Starting as validator: {}", keypair.public);
 
             Some(tokio::spawn(async move {
-                proposer.run_single_node().await;
+                proposer.run().await;
             }))
         } else {
             info!("Starting as non-validator node");
@@ -285,7 +326,7 @@ impl Node {
             ],
             Hash::ZERO,
         );
-        let signed_cert = SignedAgentCertificate::new(cert, &issuer_keypair.secret)?;
+        let signed_cert = SignedAgentCertificate::new(cert, self.config.chain_id, &issuer_keypair.secret)?;
 
         let nonce = {
             let state = self.state.read().await;
@@ -296,6 +337,7 @@ impl Node {
         };
 
         let mut tx = Transaction::new(
+            self.config.chain_id,
             faucet_keypair.public,
             nonce,
             0,
@@ -323,7 +365,9 @@ mod tests {
 
     #[tokio::test]
     async fn test_genesis_init() {
-        let config = generate_sample_config();
+        let data_dir = tempfile::tempdir().unwrap();
+        let mut config = generate_sample_config();
+        config.data_dir = data_dir.path().to_path_buf();
         let node = Node::new(config).unwrap();
 
         node.init_genesis().await.unwrap();
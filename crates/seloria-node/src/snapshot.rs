@@ -1,26 +1,90 @@
-use std::path::PathBuf;
+use std::path::Path;
 
 use anyhow::Result;
-use tokio::io::AsyncWriteExt;
+use seloria_core::SnapshotManifest;
+use seloria_state::{ChainState, FileBlockStore, LogStorage};
 
-pub async fn download_snapshot(endpoint: &str, out: PathBuf) -> Result<()> {
-    let url = format!("{}/snapshot", endpoint);
-    let response = reqwest::get(&url).await?;
+use crate::config::NodeConfig;
 
+/// Download a snapshot, verify it from the genesis validators in `config`
+/// and restore it into the empty data directory `data_dir`
+pub async fn download_snapshot(endpoint: &str, config: &NodeConfig, data_dir: &Path) -> Result<()> {
+    let state_path = data_dir.join("state.bin");
+    if state_path.exists() {
+        anyhow::bail!("{} already holds a state", data_dir.display());
+    }
+
+    let client = reqwest::Client::new();
+    let manifest = fetch_manifest(&client, endpoint).await?;
+
+    let validators = config.to_genesis_config()?.validator_set();
+    manifest.verify(config.chain_id, &validators)?;
+    println!(
+        "Downloading snapshot at height {} ({} chunks)",
+        manifest.height,
+        manifest.chunk_hashes.len()
+    );
+
+    let mut chunks = Vec::with_capacity(manifest.chunk_hashes.len());
+    for index in 0..manifest.chunk_hashes.len() {
+        chunks.push(fetch_chunk(&client, endpoint, &manifest, index).await?);
+    }
+
+    // Logs left over from a previous state must not be replayed on top of it
+    std::fs::create_dir_all(data_dir)?;
+    LogStorage::discard_logs(&state_path)?;
+    let mut state = ChainState::new(LogStorage::open(&state_path)?);
+    state.set_block_store(FileBlockStore::open(data_dir.join("blocks"))?);
+    state.restore_snapshot(&manifest, chunks)?;
+    state.compact_storage()?;
+
+    println!(
+        "Snapshot at height {} restored to {}",
+        manifest.height,
+        data_dir.display()
+    );
+    Ok(())
+}
+
+/// Fetch the manifest of the snapshot a node serves. The manifest is not
+/// verified.
+pub async fn fetch_manifest(client: &reqwest::Client, endpoint: &str) -> Result<SnapshotManifest> {
+    let response = client
+        .get(format!("{}/snapshot/manifest", endpoint))
+        .send()
+        .await?;
     if !response.status().is_success() {
         let status = response.status();
         let body = response.text().await.unwrap_or_default();
         anyhow::bail!("Snapshot download failed: {} {}", status, body);
     }
+    Ok(response.json().await?)
+}
 
-    if let Some(parent) = out.parent() {
-        tokio::fs::create_dir_all(parent).await?;
+/// Fetch chunk `index` of `manifest` from a node, checking it against the
+/// manifest
+pub async fn fetch_chunk(
+    client: &reqwest::Client,
+    endpoint: &str,
+    manifest: &SnapshotManifest,
+    index: usize,
+) -> Result<Vec<u8>> {
+    let Some(hash) = manifest.chunk_hashes.get(index) else {
+        anyhow::bail!("Snapshot has no chunk {}", index);
+    };
+    let response = client
+        .get(format!("{}/snapshot/chunk/{}", endpoint, hash))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        // The node moved on to a newer snapshot; start over
+        anyhow::bail!(
+            "Snapshot chunk {} download failed: {}",
+            index,
+            response.status()
+        );
     }
-
-    let bytes = response.bytes().await?;
-    let mut file = tokio::fs::File::create(&out).await?;
-    file.write_all(&bytes).await?;
-
-    println!("Snapshot saved to {}", out.display());
-    Ok(())
+    let bytes = response.bytes().await?.to_vec();
+    manifest.verify_chunk(index, &bytes)?;
+    Ok(bytes)
 }
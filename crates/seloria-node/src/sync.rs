@@ -0,0 +1,267 @@
+use anyhow::Result;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use reqwest::StatusCode;
+use seloria_consensus::{verify_block_qc, BlockBuilder, BlockBuilderConfig};
+use seloria_core::{Block, SnapshotManifest, ValidatorSet};
+use seloria_state::{ChainState, FileBlockStore, LogStorage};
+use tracing::{info, warn};
+
+use crate::config::NodeConfig;
+use crate::snapshot::{fetch_chunk, fetch_manifest};
+
+/// Number of snapshot chunks downloaded at once
+const PARALLEL_CHUNK_DOWNLOADS: usize = 8;
+
+/// Bring the node's data directory up to date with its sync peers (node
+/// must be stopped).
+///
+/// An empty data directory is first restored from the newest snapshot a
+/// peer serves that verifies from the genesis validators, downloading
+/// its chunks in parallel from every peer serving it; without one the node
+/// starts from genesis. The blocks above the local head are then fetched
+/// one at a time, their QCs checked and the blocks applied, until no peer
+/// has the next block.
+pub async fn sync(config: &NodeConfig) -> Result<()> {
+    let peers = config.sync_endpoints();
+    if peers.is_empty() {
+        anyhow::bail!("No peers to sync from; set sync_peers or validator_endpoints");
+    }
+    let genesis = config.to_genesis_config()?;
+    let client = reqwest::Client::new();
+
+    std::fs::create_dir_all(&config.data_dir)?;
+    let state_path = config.data_dir.join("state.bin");
+    if !state_path.exists() {
+        // Logs left over from a previous state must not be replayed
+        LogStorage::discard_logs(&state_path)?;
+    }
+    let mut state = ChainState::new(LogStorage::open(&state_path)?);
+    state.set_block_store(FileBlockStore::open(config.data_dir.join("blocks"))?);
+    state.set_history_retention(config.history_retention_blocks);
+    state.set_pruning_mode(config.pruning);
+    state.load_from_storage()?;
+
+    if state.head_block.is_none() {
+        match best_snapshot(&client, &peers, config.chain_id, &genesis.validator_set()).await {
+            Some((manifest, sources)) => {
+                info!(
+                    "Downloading snapshot at height {} ({} chunks) from {} peers",
+                    manifest.height,
+                    manifest.chunk_hashes.len(),
+                    sources.len()
+                );
+                let chunks = download_chunks(&client, &manifest, &sources).await?;
+                state.restore_snapshot(&manifest, chunks)?;
+                info!("Restored snapshot at height {}", manifest.height);
+            }
+            None => {
+                warn!("No peer serves a valid snapshot; syncing from genesis");
+                state.init_genesis(&genesis)?;
+            }
+        }
+    }
+
+    let start = state.current_height();
+    catch_up(&client, &peers, config.chain_id, &mut state).await?;
+    state.compact_storage()?;
+    info!(
+        "Synced {} blocks up to height {}",
+        state.current_height() - start,
+        state.current_height()
+    );
+
+    Ok(())
+}
+
+/// Find the highest snapshot served by any peer that verifies, returning
+/// its manifest and the peers serving it
+async fn best_snapshot(
+    client: &reqwest::Client,
+    peers: &[String],
+    chain_id: u64,
+    genesis: &ValidatorSet,
+) -> Option<(SnapshotManifest, Vec<String>)> {
+    let manifests: Vec<_> = stream::iter(peers)
+        .map(|peer| async move { (peer, fetch_manifest(client, peer).await) })
+        .buffer_unordered(peers.len())
+        .collect()
+        .await;
+
+    let mut verified = Vec::new();
+    for (peer, manifest) in manifests {
+        let checked = manifest.and_then(|manifest| {
+            manifest.verify(chain_id, genesis)?;
+            Ok(manifest)
+        });
+        match checked {
+            Ok(manifest) => verified.push((peer.clone(), manifest)),
+            Err(e) => warn!("Ignoring snapshot from {}: {}", peer, e),
+        }
+    }
+
+    let best = verified
+        .iter()
+        .map(|(_, manifest)| manifest)
+        .max_by_key(|manifest| manifest.height)?
+        .clone();
+    let sources = verified
+        .into_iter()
+        .filter(|(_, manifest)| {
+            manifest.block_hash == best.block_hash && manifest.chunk_hashes == best.chunk_hashes
+        })
+        .map(|(peer, _)| peer)
+        .collect();
+    Some((best, sources))
+}
+
+/// Download and verify every chunk of `manifest`, spreading the requests
+/// over `sources` and retrying a failed chunk on the other sources
+async fn download_chunks(
+    client: &reqwest::Client,
+    manifest: &SnapshotManifest,
+    sources: &[String],
+) -> Result<Vec<Vec<u8>>> {
+    stream::iter(0..manifest.chunk_hashes.len())
+        .map(|index| async move {
+            let mut last_error = None;
+            for offset in 0..sources.len() {
+                let source = &sources[(index + offset) % sources.len()];
+                match fetch_chunk(client, source, manifest, index).await {
+                    Ok(bytes) => return Ok(bytes),
+                    Err(e) => {
+                        warn!("Chunk {} from {} failed: {}", index, source, e);
+                        last_error = Some(e);
+                    }
+                }
+            }
+            Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No peer serves chunk {}", index)))
+        })
+        .buffered(PARALLEL_CHUNK_DOWNLOADS)
+        .try_collect()
+        .await
+}
+
+/// Fetch, verify and apply blocks above the current head until no peer
+/// has the next one
+async fn catch_up(
+    client: &reqwest::Client,
+    peers: &[String],
+    chain_id: u64,
+    state: &mut ChainState<LogStorage>,
+) -> Result<()> {
+    let builder = BlockBuilder::new(BlockBuilderConfig {
+        chain_id,
+        ..Default::default()
+    });
+
+    loop {
+        let height = state.current_height() + 1;
+        // The set of the state the block applies to, which follows the
+        // validator set changes of the blocks synced so far
+        let validators = state.validator_set_at(height);
+        let Some(block) =
+            fetch_certified_block(client, peers, height, chain_id, &validators).await
+        else {
+            return Ok(());
+        };
+
+        builder.apply_block(state, &block)?;
+        state.persist_state()?;
+        if height.is_multiple_of(100) {
+            info!("Synced block {}", height);
+        }
+    }
+}
+
+/// Fetch the block at `height` from the first peer that has it with a
+/// valid certificate
+async fn fetch_certified_block(
+    client: &reqwest::Client,
+    peers: &[String],
+    height: u64,
+    chain_id: u64,
+    validators: &ValidatorSet,
+) -> Option<Block> {
+    for peer in peers {
+        let block = match fetch_block(client, peer, height).await {
+            Ok(Some(block)) => block,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Fetching block {} from {} failed: {}", height, peer, e);
+                continue;
+            }
+        };
+        match verify_block(&block, height, chain_id, validators) {
+            Ok(()) => return Some(block),
+            Err(e) => warn!("Ignoring block {} from {}: {}", height, peer, e),
+        }
+    }
+    None
+}
+
+/// Fetch the full block at `height` from a node, or `None` if it does not
+/// have it yet
+async fn fetch_block(client: &reqwest::Client, endpoint: &str, height: u64) -> Result<Option<Block>> {
+    let response = client
+        .get(format!("{}/block/{}/full", endpoint, height))
+        .send()
+        .await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        anyhow::bail!("{}", response.status());
+    }
+    Ok(Some(response.json().await?))
+}
+
+/// Check that `block` is the block at `height` and is certified by a
+/// quorum of `validators`
+fn verify_block(
+    block: &Block,
+    height: u64,
+    chain_id: u64,
+    validators: &ValidatorSet,
+) -> Result<()> {
+    if block.header.height != height || block.header.chain_id != chain_id {
+        anyhow::bail!("Not block {} of chain {}", height, chain_id);
+    }
+    verify_block_qc(block, validators)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seloria_core::{testing, BlockHeader, KeyPair};
+
+    fn certified_block(height: u64, signer: &KeyPair) -> Block {
+        let header = BlockHeader {
+            proposer_pubkey: signer.public,
+            ..testing::header(1, height)
+        };
+        testing::certified_block(header, [signer])
+    }
+
+    #[test]
+    fn test_verify_block() {
+        let validator = KeyPair::generate();
+        let block = certified_block(3, &validator);
+        let validators = ValidatorSet::equal(&[validator.public]);
+        verify_block(&block, 3, 1, &validators).unwrap();
+
+        assert!(verify_block(&block, 4, 1, &validators).is_err());
+        assert!(verify_block(&block, 3, 2, &validators).is_err());
+        let outsider = certified_block(3, &KeyPair::generate());
+        assert!(verify_block(&outsider, 3, 1, &validators).is_err());
+
+        let mut uncertified = block.clone();
+        uncertified.qc = None;
+        assert!(verify_block(&uncertified, 3, 1, &validators).is_err());
+
+        // A certificate copied onto a different block is rejected
+        let mut forged = certified_block(3, &KeyPair::generate());
+        forged.qc = block.qc.clone();
+        assert!(verify_block(&forged, 3, 1, &validators).is_err());
+    }
+}
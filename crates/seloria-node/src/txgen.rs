@@ -9,7 +9,7 @@ use seloria_core::{
 
 use crate::cli::TxGenCommands;
 
-pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
+pub fn handle_txgen(chain_id: u64, command: TxGenCommands) -> Result<()> {
     match command {
         TxGenCommands::AgentCert {
             issuer_secret,
@@ -43,9 +43,10 @@ pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
                 caps,
                 metadata_hash,
             );
-            let signed = SignedAgentCertificate::new(cert, &issuer_secret)?;
+            let signed = SignedAgentCertificate::new(cert, chain_id, &issuer_secret)?;
 
             let tx = Transaction::new_signed(
+                chain_id,
                 agent_pub,
                 nonce,
                 fee,
@@ -69,6 +70,7 @@ pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
             let to_pub = parse_pubkey(&to_pubkey)?;
 
             let tx = Transaction::new_signed(
+                chain_id,
                 sender_pub,
                 nonce,
                 fee,
@@ -100,6 +102,7 @@ pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
             );
 
             let tx = Transaction::new_signed(
+                chain_id,
                 sender_pub,
                 nonce,
                 fee,
@@ -130,6 +133,7 @@ pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
             let to_pub = parse_pubkey(&to_pubkey)?;
 
             let tx = Transaction::new_signed(
+                chain_id,
                 sender_pub,
                 nonce,
                 fee,
@@ -159,6 +163,7 @@ pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
             let payload_hash = hash_blake3(&payload_bytes);
 
             let tx = Transaction::new_signed(
+                chain_id,
                 sender_pub,
                 nonce,
                 fee,
@@ -187,6 +192,7 @@ pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
             let vote = parse_vote(&vote)?;
 
             let tx = Transaction::new_signed(
+                chain_id,
                 sender_pub,
                 nonce,
                 fee,
@@ -217,6 +223,7 @@ pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
             let allowlist = parse_pubkey_list(&allowlist)?;
 
             let tx = Transaction::new_signed(
+                chain_id,
                 sender_pub,
                 nonce,
                 fee,
@@ -249,6 +256,7 @@ pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
             let kv_value = KvValue::inline(&codec, value_bytes);
 
             let tx = Transaction::new_signed(
+                chain_id,
                 sender_pub,
                 nonce,
                 fee,
@@ -275,6 +283,7 @@ pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
             let ns_id = parse_hash(&ns_id)?;
 
             let tx = Transaction::new_signed(
+                chain_id,
                 sender_pub,
                 nonce,
                 fee,
@@ -302,6 +311,7 @@ pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
             let kv_value = KvValue::inline(&codec, value_bytes);
 
             let tx = Transaction::new_signed(
+                chain_id,
                 sender_pub,
                 nonce,
                 fee,
@@ -333,6 +343,7 @@ pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
             let pool_id = compute_pool_id(token_a, token_b);
 
             let tx = Transaction::new_signed(
+                chain_id,
                 sender_pub,
                 nonce,
                 fee,
@@ -363,6 +374,7 @@ pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
             let pool_id = parse_hash(&pool_id)?;
 
             let tx = Transaction::new_signed(
+                chain_id,
                 sender_pub,
                 nonce,
                 fee,
@@ -392,6 +404,7 @@ pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
             let pool_id = parse_hash(&pool_id)?;
 
             let tx = Transaction::new_signed(
+                chain_id,
                 sender_pub,
                 nonce,
                 fee,
@@ -422,6 +435,7 @@ pub fn handle_txgen(command: TxGenCommands) -> Result<()> {
             let token_in = parse_hash(&token_in)?;
 
             let tx = Transaction::new_signed(
+                chain_id,
                 sender_pub,
                 nonce,
                 fee,
@@ -1,5 +1,6 @@
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
+use seloria_state::StateError;
 use serde_json::json;
 use thiserror::Error;
 
@@ -24,7 +25,7 @@ pub enum RpcError {
     Core(#[from] seloria_core::CoreError),
 
     #[error("State error: {0}")]
-    State(#[from] seloria_state::StateError),
+    State(#[from] StateError),
 
     #[error("Serialization error: {0}")]
     Serialization(String),
@@ -39,6 +40,10 @@ impl IntoResponse for RpcError {
             RpcError::Mempool(e) => (StatusCode::BAD_REQUEST, e.to_string()),
             RpcError::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
             RpcError::Core(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            RpcError::State(e @ StateError::HistoryUnavailable { .. }) => {
+                (StatusCode::BAD_REQUEST, e.to_string())
+            }
+            RpcError::State(e @ StateError::Pruned { .. }) => (StatusCode::GONE, e.to_string()),
             RpcError::State(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
             RpcError::Serialization(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
         };
@@ -1,25 +1,30 @@
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::body::Body;
 use axum::http::{header, HeaderMap, StatusCode};
 use axum::response::Response;
 use axum::Json;
 use async_compression::tokio::bufread::GzipEncoder;
 use seloria_consensus::{
-    verify_qc, BlockBuilder, BlockBuilderConfig, CommitRequest, CommitResponse, ProposeRequest,
-    ProposeResponse, Validator, ValidatorEndpoint,
+    find_equivocations, leader_for, verify_block_qc_at, BlockBuilder, BlockBuilderConfig,
+    BlockRangeResponse, BlockSyncer, CommitRequest, CommitResponse, EvidencePool, Pacemaker, ProposeRequest, TimeoutResponse,
+    TimeoutVote, Validator, ValidatorEndpoint, VoteRequest, VoteResponse,
+    MAX_BLOCKS_PER_REQUEST,
+};
+use seloria_core::{
+    Account, Block, Claim, Evidence, ExecutionEvent, Hash, KeyPair, KvValue, PublicKey,
+    SnapshotManifest, StateKey, StateProof, Transaction, VotePhase, EPOCH_LENGTH_BLOCKS,
 };
-use seloria_core::{Account, Block, Claim, Hash, KeyPair, KvValue, PublicKey, Transaction};
 use seloria_mempool::Mempool;
-use seloria_state::{ChainState, Storage};
-use seloria_vm::validate_transaction;
+use seloria_state::{ChainState, StateError, Storage};
+use seloria_vm::{validate_transaction, ExecutionResult};
 use serde::{Deserialize, Serialize};
 use tokio::io::BufReader;
 use tokio::sync::RwLock;
 use tokio_util::io::ReaderStream;
-use tracing::info;
+use tracing::{info, warn};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::error::RpcError;
@@ -31,13 +36,27 @@ pub struct AppState<S: Storage> {
     pub mempool: Arc<Mempool>,
     pub broadcaster: Arc<EventBroadcaster>,
     pub validator_keypair: Option<Arc<tokio::sync::Mutex<KeyPair>>>,
+    /// Votes on proposals under the consensus safety rules (validators only)
+    pub validator: Option<Arc<Validator>>,
     pub issuer_keypair: Option<Arc<tokio::sync::Mutex<KeyPair>>>,
     pub snapshot_path: Option<PathBuf>,
+    /// Directory holding the chunked snapshot served to syncing nodes
+    pub snapshot_dir: Option<PathBuf>,
+    pub snapshot_lock: Arc<tokio::sync::Mutex<()>>,
+    /// Views and timeout votes at the height being decided
+    pub pacemaker: Arc<Pacemaker>,
+    /// Equivocations detected by this node, awaiting submission on-chain
+    pub evidence: Arc<EvidencePool>,
     pub validator_endpoints: Vec<ValidatorEndpoint>,
+    /// Fetches blocks this node missed from the validator endpoints
+    pub block_syncer: Arc<BlockSyncer>,
     pub faucet_keypair: Option<Arc<tokio::sync::Mutex<KeyPair>>>,
     pub faucet_lock: Arc<tokio::sync::Mutex<()>>,
 }
 
+/// Target size of the chunks of served snapshots
+const SNAPSHOT_CHUNK_BYTES: usize = 4 * 1024 * 1024;
+
 // Response types
 
 #[derive(Debug, Serialize)]
@@ -48,6 +67,22 @@ pub struct StatusResponse {
     pub mempool_size: usize,
 }
 
+#[derive(Debug, Serialize)]
+pub struct ValidatorResponse {
+    pub pubkey: String,
+    pub power: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValidatorSetResponse {
+    pub epoch: u64,
+    /// Height of the first block certified by this set
+    pub start_height: u64,
+    pub total_power: u64,
+    pub quorum_power: u64,
+    pub validators: Vec<ValidatorResponse>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct TxSubmitResponse {
     pub hash: String,
@@ -72,6 +107,33 @@ pub struct BlockResponse {
     pub proposer: String,
     pub tx_root: String,
     pub state_root: String,
+    pub receipts_root: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockDiffResponse {
+    pub height: u64,
+    pub changes: Vec<EntryChangeResponse>,
+}
+
+/// Change of one state entry; values are hex-encoded bincode, `null` if absent
+#[derive(Debug, Serialize)]
+pub struct EntryChangeResponse {
+    pub key: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReceiptResponse {
+    pub tx_hash: String,
+    pub block_height: u64,
+    pub index: u32,
+    pub success: bool,
+    pub error_code: Option<String>,
+    pub error: Option<String>,
+    pub fee_used: u64,
+    pub events: Vec<ExecutionEvent>,
 }
 
 #[derive(Debug, Serialize)]
@@ -88,6 +150,17 @@ pub struct ClaimResponse {
     pub attestation_count: usize,
 }
 
+#[derive(Debug, Serialize)]
+pub struct PoolResponse {
+    pub pool_id: String,
+    pub token_a: String,
+    pub token_b: String,
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+    pub lp_supply: u64,
+    pub fee_bps: u64,
+}
+
 #[derive(Debug, Serialize)]
 pub struct KvResponse {
     pub ns_id: String,
@@ -100,6 +173,8 @@ pub struct KvResponse {
 pub struct KvKeysResponse {
     pub ns_id: String,
     pub keys: Vec<String>,
+    /// Cursor for the next page, if there may be more keys
+    pub next_start_after: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -117,6 +192,30 @@ pub struct SnapshotPublishResponse {
 
 // Request types
 
+/// Query parameter selecting the block height to read state at
+/// (defaults to the head)
+#[derive(Debug, Deserialize)]
+pub struct AtHeightQuery {
+    pub at_height: Option<u64>,
+}
+
+/// Maximum (and default) number of keys returned by one KV listing
+pub const MAX_KV_KEYS_PAGE: usize = 1000;
+
+/// Page of a KV key listing: keys after `start_after`, at most `limit`
+#[derive(Debug, Deserialize)]
+pub struct KvKeysQuery {
+    pub start_after: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Range of blocks to fetch; `to` defaults to the end of a full page
+#[derive(Debug, Deserialize)]
+pub struct BlockRangeQuery {
+    pub from: u64,
+    pub to: Option<u64>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct TxSubmitRequest {
     pub transaction: Transaction,
@@ -173,7 +272,16 @@ pub async fn get_status<S: Storage + Send + Sync>(
     }))
 }
 
-/// GET /snapshot/meta - Get snapshot metadata
+/// Fold incrementally persisted state into the snapshot file so it is
+/// complete on its own before it is published
+async fn compact_snapshot<S: Storage + Send + Sync>(state: &AppState<S>) -> Result<(), RpcError> {
+    state.chain_state.write().await.compact_storage()?;
+    Ok(())
+}
+
+/// GET /snapshot/meta - Get snapshot metadata. Read-only: the reported
+/// size is that of the snapshot file as of its last compaction, which
+/// happens as the storage rolls its log and on publish.
 pub async fn get_snapshot_meta<S: Storage + Send + Sync>(
     State(state): State<Arc<AppState<S>>>,
 ) -> Result<Json<SnapshotMetaResponse>, RpcError> {
@@ -202,18 +310,86 @@ pub async fn get_snapshot_meta<S: Storage + Send + Sync>(
     }))
 }
 
-/// GET /snapshot - Download latest snapshot file
-pub async fn get_snapshot<S: Storage + Send + Sync>(
+/// Write a snapshot of the head state to `dir`, unless the snapshot there is
+/// already at the head, and return its manifest. Chunks of older snapshots
+/// are removed.
+async fn current_snapshot<S: Storage + Send + Sync>(
+    state: &AppState<S>,
+    dir: &std::path::Path,
+) -> Result<SnapshotManifest, RpcError> {
+    let _guard = state.snapshot_lock.lock().await;
+    let chain_state = state.chain_state.read().await;
+
+    let manifest_path = dir.join("manifest.json");
+    if let Ok(bytes) = tokio::fs::read(&manifest_path).await {
+        if let Ok(manifest) = serde_json::from_slice::<SnapshotManifest>(&bytes) {
+            if manifest.height == chain_state.height {
+                return Ok(manifest);
+            }
+        }
+    }
+
+    let io_err = |e: std::io::Error| RpcError::Internal(format!("Snapshot write failed: {}", e));
+    std::fs::create_dir_all(dir).map_err(io_err)?;
+    let manifest = chain_state.create_snapshot(SNAPSHOT_CHUNK_BYTES, |hash, bytes| {
+        std::fs::write(dir.join(format!("{}.chunk", hash)), bytes)
+            .map_err(|e| StateError::Storage(e.to_string()))
+    })?;
+    let json = serde_json::to_vec(&manifest)
+        .map_err(|e| RpcError::Internal(format!("Snapshot write failed: {}", e)))?;
+    let tmp_path = dir.join("manifest.json.tmp");
+    std::fs::write(&tmp_path, json).map_err(io_err)?;
+    std::fs::rename(&tmp_path, &manifest_path).map_err(io_err)?;
+
+    let current: Vec<String> = manifest
+        .chunk_hashes
+        .iter()
+        .map(|hash| format!("{}.chunk", hash))
+        .collect();
+    for entry in std::fs::read_dir(dir).map_err(io_err)?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.ends_with(".chunk") && !current.contains(&name) {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+
+    info!(
+        "Created snapshot at height {} with {} chunks",
+        manifest.height,
+        manifest.chunk_hashes.len()
+    );
+    Ok(manifest)
+}
+
+fn snapshot_dir<S: Storage>(state: &AppState<S>) -> Result<&PathBuf, RpcError> {
+    state
+        .snapshot_dir
+        .as_ref()
+        .ok_or_else(|| RpcError::NotFound("Snapshot not available on this node".to_string()))
+}
+
+/// GET /snapshot/manifest - Get the manifest of a snapshot at the head block
+pub async fn get_snapshot_manifest<S: Storage + Send + Sync>(
+    State(state): State<Arc<AppState<S>>>,
+) -> Result<Json<SnapshotManifest>, RpcError> {
+    let dir = snapshot_dir(&state)?;
+    Ok(Json(current_snapshot(&state, dir).await?))
+}
+
+/// GET /snapshot/chunk/:hash - Download a snapshot chunk by its hash
+pub async fn get_snapshot_chunk<S: Storage + Send + Sync>(
     State(state): State<Arc<AppState<S>>>,
+    Path(hash_hex): Path<String>,
     headers: HeaderMap,
 ) -> Result<Response, RpcError> {
-    let path = state.snapshot_path.as_ref().ok_or_else(|| {
-        RpcError::NotFound("Snapshot not available on this node".to_string())
-    })?;
+    let dir = snapshot_dir(&state)?;
+    let hash = Hash::from_hex(&hash_hex)
+        .map_err(|_| RpcError::BadRequest("Invalid chunk hash".to_string()))?;
 
-    let file = tokio::fs::File::open(path)
+    // Chunks of a replaced snapshot are gone; fetch the manifest again
+    let file = tokio::fs::File::open(dir.join(format!("{}.chunk", hash)))
         .await
-        .map_err(|_| RpcError::NotFound("Snapshot file not found".to_string()))?;
+        .map_err(|_| RpcError::NotFound("Snapshot chunk not found".to_string()))?;
 
     let accept_encoding = headers
         .get(header::ACCEPT_ENCODING)
@@ -256,6 +432,7 @@ pub async fn publish_snapshot<S: Storage + Send + Sync>(
     let path = state.snapshot_path.as_ref().ok_or_else(|| {
         RpcError::NotFound("Snapshot not available on this node".to_string())
     })?;
+    compact_snapshot(&state).await?;
 
     let file = tokio::fs::File::open(path)
         .await
@@ -402,20 +579,22 @@ pub async fn faucet<S: Storage + Send + Sync>(
         .unwrap()
         .as_secs();
 
-    let nonce = {
+    let (chain_id, nonce) = {
         let chain_state = state.chain_state.read().await;
         if !chain_state.is_certified_agent(&faucet_keypair.public, current_time) {
             return Err(RpcError::BadRequest(
                 "Faucet is not registered as a certified agent".to_string(),
             ));
         }
-        chain_state
+        let nonce = chain_state
             .get_account(&faucet_keypair.public)
             .map(|a| a.nonce + 1)
-            .unwrap_or(1)
+            .unwrap_or(1);
+        (chain_state.chain_id, nonce)
     };
 
     let mut tx = Transaction::new(
+        chain_id,
         faucet_keypair.public,
         nonce,
         0,
@@ -463,26 +642,80 @@ pub async fn get_tx<S: Storage + Send + Sync>(
     // Check committed transactions
     let chain_state = state.chain_state.read().await;
     if let Some(tx) = chain_state.get_transaction(&hash) {
-        return Ok(Json(tx.clone()));
+        return Ok(Json(tx));
     }
 
-    Err(RpcError::NotFound(format!(
-        "Transaction {} not found",
-        hash_hex
-    )))
+    Err(not_found_in_pruned(
+        format!("Transaction {} not found", hash_hex),
+        chain_state.pruned_below(),
+    ))
+}
+
+/// GET /tx/:hash/receipt - Get the receipt of a committed transaction
+pub async fn get_tx_receipt<S: Storage + Send + Sync>(
+    State(state): State<Arc<AppState<S>>>,
+    Path(hash_hex): Path<String>,
+) -> Result<Json<ReceiptResponse>, RpcError> {
+    let hash = Hash::from_hex(&hash_hex).map_err(|_| RpcError::BadRequest("Invalid hash".to_string()))?;
+
+    let chain_state = state.chain_state.read().await;
+    if let Some(record) = chain_state.get_receipt(&hash) {
+        let receipt = &record.receipt;
+        return Ok(Json(ReceiptResponse {
+            tx_hash: receipt.tx_hash.to_hex(),
+            block_height: record.block_height,
+            index: record.index,
+            success: receipt.success,
+            error_code: receipt.error_code.clone(),
+            error: receipt.error.clone(),
+            fee_used: receipt.fee_used,
+            events: receipt.events.clone(),
+        }));
+    }
+    let pruned_below = chain_state.pruned_below();
+    drop(chain_state);
+
+    if state.mempool.contains(&hash).await {
+        return Err(RpcError::NotFound(format!(
+            "Transaction {} is pending",
+            hash_hex
+        )));
+    }
+
+    Err(not_found_in_pruned(
+        format!("Receipt for transaction {} not found", hash_hex),
+        pruned_below,
+    ))
 }
 
-/// GET /account/:pubkey - Get account by public key
+/// Not-found error for a transaction lookup, noting when older transactions
+/// have been pruned and may have existed
+fn not_found_in_pruned(message: String, pruned_below: u64) -> RpcError {
+    if pruned_below == 0 {
+        return RpcError::NotFound(message);
+    }
+    RpcError::NotFound(format!(
+        "{} (this node has pruned transactions of blocks below height {})",
+        message, pruned_below
+    ))
+}
+
+/// GET /account/:pubkey?at_height= - Get account by public key
 pub async fn get_account<S: Storage + Send + Sync>(
     State(state): State<Arc<AppState<S>>>,
     Path(pubkey_hex): Path<String>,
+    Query(query): Query<AtHeightQuery>,
 ) -> Result<Json<AccountResponse>, RpcError> {
     let pubkey = PublicKey::from_hex(&pubkey_hex)
         .map_err(|_| RpcError::BadRequest("Invalid public key".to_string()))?;
 
     let chain_state = state.chain_state.read().await;
 
-    let account = chain_state.get_account(&pubkey).cloned().unwrap_or_default();
+    let account = match query.at_height {
+        Some(height) => chain_state.get_account_at(&pubkey, height)?,
+        None => chain_state.get_account(&pubkey),
+    }
+    .unwrap_or_default();
 
     Ok(Json(AccountResponse {
         pubkey: pubkey_hex,
@@ -499,7 +732,7 @@ pub async fn get_block<S: Storage + Send + Sync>(
 ) -> Result<Json<BlockResponse>, RpcError> {
     let chain_state = state.chain_state.read().await;
 
-    if let Some(block) = chain_state.get_block(height) {
+    if let Some(block) = chain_state.get_block(height)? {
         let hash = block.hash().map_err(|e| RpcError::Internal(e.to_string()))?;
         return Ok(Json(BlockResponse {
             height: block.header.height,
@@ -510,6 +743,7 @@ pub async fn get_block<S: Storage + Send + Sync>(
             proposer: block.header.proposer_pubkey.to_hex(),
             tx_root: block.header.tx_root.to_hex(),
             state_root: block.header.state_root.to_hex(),
+            receipts_root: block.header.receipts_root.to_hex(),
         }));
     }
 
@@ -519,19 +753,114 @@ pub async fn get_block<S: Storage + Send + Sync>(
     )))
 }
 
-/// GET /claim/:id - Get claim by ID
+/// GET /validators/:height - Get the validator set that certified the block
+/// at a height, for checking QCs from before a validator set change
+pub async fn get_validators<S: Storage + Send + Sync>(
+    State(state): State<Arc<AppState<S>>>,
+    Path(height): Path<u64>,
+) -> Result<Json<ValidatorSetResponse>, RpcError> {
+    let chain_state = state.chain_state.read().await;
+    let epoch = chain_state
+        .epoch_at(height)
+        .ok_or_else(|| RpcError::NotFound("No validator set recorded".to_string()))?;
+    let set = epoch.validators;
+
+    Ok(Json(ValidatorSetResponse {
+        epoch: epoch.number,
+        start_height: epoch.start_height,
+        total_power: set.total_power(),
+        quorum_power: set.quorum_power(),
+        validators: set
+            .validators
+            .iter()
+            .map(|v| ValidatorResponse {
+                pubkey: v.pubkey.to_hex(),
+                power: v.power,
+            })
+            .collect(),
+    }))
+}
+
+/// GET /block/:height/full - Get a block with its transactions and QC, for
+/// nodes catching up
+pub async fn get_block_full<S: Storage + Send + Sync>(
+    State(state): State<Arc<AppState<S>>>,
+    Path(height): Path<u64>,
+) -> Result<Json<Block>, RpcError> {
+    let chain_state = state.chain_state.read().await;
+
+    chain_state.get_block(height)?.map(Json).ok_or_else(|| {
+        RpcError::NotFound(format!("Block at height {} not found", height))
+    })
+}
+
+/// GET /blocks?from=&to= - Get committed blocks with their QCs, at most
+/// `MAX_BLOCKS_PER_REQUEST`, stopping at the head
+pub async fn get_blocks<S: Storage + Send + Sync>(
+    State(state): State<Arc<AppState<S>>>,
+    Query(query): Query<BlockRangeQuery>,
+) -> Result<Json<BlockRangeResponse>, RpcError> {
+    let last = query.from.saturating_add(MAX_BLOCKS_PER_REQUEST - 1);
+    let to = query.to.unwrap_or(last).min(last);
+    if to < query.from {
+        return Err(RpcError::BadRequest(
+            "`to` must not be below `from`".to_string(),
+        ));
+    }
+
+    let chain_state = state.chain_state.read().await;
+    let mut blocks = Vec::new();
+    for height in query.from..=to.min(chain_state.current_height()) {
+        match chain_state.get_block(height)? {
+            Some(block) => blocks.push(block),
+            None => break,
+        }
+    }
+
+    Ok(Json(BlockRangeResponse { blocks }))
+}
+
+/// GET /block/:height/diff - Get the state entries changed by a block
+pub async fn get_block_diff<S: Storage + Send + Sync>(
+    State(state): State<Arc<AppState<S>>>,
+    Path(height): Path<u64>,
+) -> Result<Json<BlockDiffResponse>, RpcError> {
+    let chain_state = state.chain_state.read().await;
+
+    let changeset = chain_state.get_changeset(height)?.ok_or_else(|| {
+        RpcError::NotFound(format!("No changeset retained for block {}", height))
+    })?;
+
+    Ok(Json(BlockDiffResponse {
+        height,
+        changes: changeset
+            .changes
+            .into_iter()
+            .map(|change| EntryChangeResponse {
+                key: hex::encode(&change.key),
+                old: change.old.map(hex::encode),
+                new: change.new.map(hex::encode),
+            })
+            .collect(),
+    }))
+}
+
+/// GET /claim/:id?at_height= - Get claim by ID
 pub async fn get_claim<S: Storage + Send + Sync>(
     State(state): State<Arc<AppState<S>>>,
     Path(claim_id_hex): Path<String>,
+    Query(query): Query<AtHeightQuery>,
 ) -> Result<Json<ClaimResponse>, RpcError> {
     let claim_id = Hash::from_hex(&claim_id_hex)
         .map_err(|_| RpcError::BadRequest("Invalid claim ID".to_string()))?;
 
     let chain_state = state.chain_state.read().await;
 
-    let claim = chain_state
-        .get_claim(&claim_id)
-        .ok_or_else(|| RpcError::NotFound(format!("Claim {} not found", claim_id_hex)))?;
+    let claim = match query.at_height {
+        Some(height) => chain_state.get_claim_at(&claim_id, height)?,
+        None => chain_state.get_claim(&claim_id),
+    }
+    .ok_or_else(|| RpcError::NotFound(format!("Claim {} not found", claim_id_hex)))?;
 
     let status = match claim.status {
         seloria_core::ClaimStatus::Pending => "pending",
@@ -553,19 +882,22 @@ pub async fn get_claim<S: Storage + Send + Sync>(
     }))
 }
 
-/// GET /kv/:ns_id/:key - Get KV entry
+/// GET /kv/:ns_id/:key?at_height= - Get KV entry
 pub async fn get_kv<S: Storage + Send + Sync>(
     State(state): State<Arc<AppState<S>>>,
     Path((ns_id_hex, key)): Path<(String, String)>,
+    Query(query): Query<AtHeightQuery>,
 ) -> Result<Json<KvResponse>, RpcError> {
     let ns_id = Hash::from_hex(&ns_id_hex)
         .map_err(|_| RpcError::BadRequest("Invalid namespace ID".to_string()))?;
 
     let chain_state = state.chain_state.read().await;
 
-    let value = chain_state
-        .kv_get(&ns_id, &key)
-        .ok_or_else(|| RpcError::NotFound(format!("Key '{}' not found", key)))?;
+    let value = match query.at_height {
+        Some(height) => chain_state.kv_get_at(&ns_id, &key, height)?,
+        None => chain_state.kv_get(&ns_id, &key),
+    }
+    .ok_or_else(|| RpcError::NotFound(format!("Key '{}' not found", key)))?;
 
     let data = match &value.data {
         seloria_core::KvData::Inline(bytes) => {
@@ -595,24 +927,123 @@ pub async fn get_kv<S: Storage + Send + Sync>(
     }))
 }
 
-/// GET /kv/:ns_id - List keys in namespace
+/// GET /pool/:id?at_height= - Get AMM pool by ID
+pub async fn get_pool<S: Storage + Send + Sync>(
+    State(state): State<Arc<AppState<S>>>,
+    Path(pool_id_hex): Path<String>,
+    Query(query): Query<AtHeightQuery>,
+) -> Result<Json<PoolResponse>, RpcError> {
+    let pool_id = Hash::from_hex(&pool_id_hex)
+        .map_err(|_| RpcError::BadRequest("Invalid pool ID".to_string()))?;
+
+    let chain_state = state.chain_state.read().await;
+
+    let pool = match query.at_height {
+        Some(height) => chain_state.get_pool_at(&pool_id, height)?,
+        None => chain_state.get_pool(&pool_id),
+    }
+    .ok_or_else(|| RpcError::NotFound(format!("Pool {} not found", pool_id_hex)))?;
+
+    Ok(Json(PoolResponse {
+        pool_id: pool_id_hex,
+        token_a: pool.token_a.to_hex(),
+        token_b: pool.token_b.to_hex(),
+        reserve_a: pool.reserve_a,
+        reserve_b: pool.reserve_b,
+        lp_supply: pool.lp_supply,
+        fee_bps: pool.fee_bps,
+    }))
+}
+
+/// GET /kv/:ns_id - List keys in namespace, a page at a time
 pub async fn list_kv_keys<S: Storage + Send + Sync>(
     State(state): State<Arc<AppState<S>>>,
     Path(ns_id_hex): Path<String>,
+    Query(query): Query<KvKeysQuery>,
 ) -> Result<Json<KvKeysResponse>, RpcError> {
     let ns_id = Hash::from_hex(&ns_id_hex)
         .map_err(|_| RpcError::BadRequest("Invalid namespace ID".to_string()))?;
+    let limit = query.limit.unwrap_or(MAX_KV_KEYS_PAGE).clamp(1, MAX_KV_KEYS_PAGE);
 
     let chain_state = state.chain_state.read().await;
 
-    let keys = chain_state.kv_keys(&ns_id);
+    let keys = chain_state.kv_keys_page(&ns_id, query.start_after.as_deref(), limit);
+    let next_start_after = if keys.len() == limit {
+        keys.last().cloned()
+    } else {
+        None
+    };
 
     Ok(Json(KvKeysResponse {
         ns_id: ns_id_hex,
         keys,
+        next_start_after,
     }))
 }
 
+/// GET /proof/account/:pubkey - Get an account with a proof against the head block
+pub async fn get_account_proof<S: Storage + Send + Sync>(
+    State(state): State<Arc<AppState<S>>>,
+    Path(pubkey_hex): Path<String>,
+) -> Result<Json<StateProof>, RpcError> {
+    let pubkey = PublicKey::from_hex(&pubkey_hex)
+        .map_err(|_| RpcError::BadRequest("Invalid public key".to_string()))?;
+
+    let chain_state = state.chain_state.read().await;
+    Ok(Json(prove_entry(&chain_state, &StateKey::Account(pubkey))?))
+}
+
+/// GET /proof/claim/:id - Get a claim with a proof against the head block
+pub async fn get_claim_proof<S: Storage + Send + Sync>(
+    State(state): State<Arc<AppState<S>>>,
+    Path(claim_id_hex): Path<String>,
+) -> Result<Json<StateProof>, RpcError> {
+    let claim_id = Hash::from_hex(&claim_id_hex)
+        .map_err(|_| RpcError::BadRequest("Invalid claim ID".to_string()))?;
+
+    let chain_state = state.chain_state.read().await;
+    Ok(Json(prove_entry(&chain_state, &StateKey::Claim(claim_id))?))
+}
+
+/// GET /proof/kv/:ns_id/:key - Get a KV entry with a proof against the head block
+pub async fn get_kv_proof<S: Storage + Send + Sync>(
+    State(state): State<Arc<AppState<S>>>,
+    Path((ns_id_hex, key)): Path<(String, String)>,
+) -> Result<Json<StateProof>, RpcError> {
+    let ns_id = Hash::from_hex(&ns_id_hex)
+        .map_err(|_| RpcError::BadRequest("Invalid namespace ID".to_string()))?;
+
+    let chain_state = state.chain_state.read().await;
+    Ok(Json(prove_entry(&chain_state, &StateKey::Kv(ns_id, key))?))
+}
+
+/// Prove a state entry (or its absence) against the certified head block
+fn prove_entry<S: Storage>(
+    chain_state: &ChainState<S>,
+    key: &StateKey,
+) -> Result<StateProof, RpcError> {
+    let head = chain_state
+        .head_block
+        .as_ref()
+        .ok_or_else(|| RpcError::NotFound("No blocks committed yet".to_string()))?;
+    let qc = head.qc.clone().ok_or_else(|| {
+        RpcError::NotFound("Head block has no quorum certificate".to_string())
+    })?;
+    if chain_state.committed_state_root() != head.header.state_root {
+        return Err(RpcError::Internal(
+            "Head block state is not persisted yet".to_string(),
+        ));
+    }
+
+    let (value, proof) = chain_state.prove(key)?;
+    Ok(StateProof {
+        value,
+        proof,
+        header: head.header.clone(),
+        qc,
+    })
+}
+
 /// POST /cert/issue - Issue a signed agent certificate (dev-only)
 pub async fn issue_certificate<S: Storage + Send + Sync>(
     State(state): State<Arc<AppState<S>>>,
@@ -642,49 +1073,133 @@ pub async fn issue_certificate<S: Storage + Send + Sync>(
         request.capabilities.clone(),
         metadata_hash,
     );
-    let signed = seloria_core::SignedAgentCertificate::new(cert, &issuer.secret)?;
+    let chain_id = state.chain_state.read().await.chain_id;
+    let signed = seloria_core::SignedAgentCertificate::new(cert, chain_id, &issuer.secret)?;
 
     Ok(Json(IssueCertResponse { cert: signed }))
 }
 
-/// POST /consensus/propose - Validate and sign a proposed block
+/// POST /consensus/propose - Validate a proposed block and cast a prepare vote
 pub async fn consensus_propose<S: Storage + Send + Sync + Clone>(
     State(state): State<Arc<AppState<S>>>,
     Json(request): Json<ProposeRequest>,
-) -> Result<Json<ProposeResponse>, RpcError> {
-    let keypair_lock = state
-        .validator_keypair
+) -> Result<Json<VoteResponse>, RpcError> {
+    let validator = state
+        .validator
         .as_ref()
         .ok_or_else(|| RpcError::BadRequest("Node is not a validator".to_string()))?;
-    let keypair = keypair_lock.lock().await.clone();
 
-    let chain_state = state.chain_state.read().await;
-    if chain_state.validators.is_empty() {
-        return Err(RpcError::BadRequest("No validators configured".to_string()));
+    let header = &request.block.header;
+    {
+        let chain_state = state.chain_state.read().await;
+        if chain_state.validators.is_empty() {
+            return Err(RpcError::BadRequest("No validators configured".to_string()));
+        }
+
+        // A proposal in a later view carries the certificate that moved the
+        // height there. Without one, only the leader of the proposed view
+        // may make this node fetch the blocks below it.
+        if let Some(tc) = &request.timeout_cert {
+            if tc.chain_id != chain_state.chain_id
+                || tc.height != header.height
+                || tc.view + 1 != header.view
+            {
+                return Err(RpcError::BadRequest(
+                    "Timeout certificate does not match proposal".to_string(),
+                ));
+            }
+            state
+                .pacemaker
+                .advance(tc, &chain_state.validator_set())
+                .map_err(|e| RpcError::BadRequest(e.to_string()))?;
+        } else if leader_for(&chain_state.validators, header.height, header.view)
+            != Some(header.proposer_pubkey)
+        {
+            return Err(RpcError::BadRequest(format!(
+                "Unexpected proposer for height {} view {}",
+                header.height, header.view
+            )));
+        }
     }
 
-    // Check leader is correct for this height
-    let leader_index = (request.block.header.height as usize) % chain_state.validators.len();
-    let expected_leader = chain_state.validators[leader_index];
-    if request.block.header.proposer_pubkey != expected_leader {
+    catch_up_before(&state, header.height).await?;
+
+    let chain_state = state.chain_state.read().await;
+    let validators = chain_state.validator_set();
+
+    let view = state.pacemaker.view(header.height);
+    if header.view != view {
+        return Err(RpcError::BadRequest(format!(
+            "Proposal for view {} at height {}, current view is {}",
+            header.view, header.height, view
+        )));
+    }
+    if state.pacemaker.has_timed_out(header.height, view) {
         return Err(RpcError::BadRequest(format!(
-            "Unexpected proposer for height {}",
-            request.block.header.height
+            "View {} at height {} has timed out",
+            view, header.height
         )));
     }
 
-    let block_builder = BlockBuilder::new(BlockBuilderConfig {
-        chain_id: chain_state.chain_id,
-        ..Default::default()
-    });
-    let validator = Validator::new(keypair.public, keypair.secret.clone(), block_builder);
+    // Check leader is correct for this height and view
+    if leader_for(&chain_state.validators, header.height, view) != Some(header.proposer_pubkey) {
+        return Err(RpcError::BadRequest(format!(
+            "Unexpected proposer for height {} view {}",
+            header.height, view
+        )));
+    }
 
     let signature = validator
-        .validate_and_sign(&request.block, &chain_state)
+        .vote_prepare(
+            &request.block,
+            request.justify.as_ref(),
+            &chain_state,
+            &validators,
+        )
         .map_err(|e| RpcError::BadRequest(e.to_string()))?;
 
-    Ok(Json(ProposeResponse {
-        validator_pubkey: keypair.public,
+    Ok(Json(VoteResponse {
+        validator_pubkey: validator.public_key,
+        phase: VotePhase::Prepare,
+        signature,
+    }))
+}
+
+/// POST /consensus/vote - Vote in the phase after the one a QC certifies
+pub async fn consensus_vote<S: Storage + Send + Sync + Clone>(
+    State(state): State<Arc<AppState<S>>>,
+    Json(request): Json<VoteRequest>,
+) -> Result<Json<VoteResponse>, RpcError> {
+    let validator = state
+        .validator
+        .as_ref()
+        .ok_or_else(|| RpcError::BadRequest("Node is not a validator".to_string()))?;
+
+    let chain_state = state.chain_state.read().await;
+    if request.qc.chain_id != chain_state.chain_id {
+        return Err(RpcError::BadRequest(format!(
+            "QC chain ID {} does not match chain {}",
+            request.qc.chain_id, chain_state.chain_id
+        )));
+    }
+    if chain_state.validators.is_empty() {
+        return Err(RpcError::BadRequest("No validators configured".to_string()));
+    }
+    let height = chain_state.current_height() + 1;
+    if state.pacemaker.has_timed_out(height, request.qc.view) {
+        return Err(RpcError::BadRequest(format!(
+            "View {} at height {} has timed out",
+            request.qc.view, height
+        )));
+    }
+
+    let (phase, signature) = validator
+        .vote(&request.qc, &chain_state.validator_set())
+        .map_err(|e| RpcError::BadRequest(e.to_string()))?;
+
+    Ok(Json(VoteResponse {
+        validator_pubkey: validator.public_key,
+        phase,
         signature,
     }))
 }
@@ -710,16 +1225,51 @@ pub async fn consensus_commit<S: Storage + Send + Sync + Clone>(
         ));
     }
 
-    let mut chain_state = state.chain_state.write().await;
-    let validators = chain_state.validators.clone();
-    if validators.is_empty() {
-        return Err(RpcError::BadRequest("No validators configured".to_string()));
+    {
+        let chain_state = state.chain_state.read().await;
+        if qc.chain_id != chain_state.chain_id {
+            return Err(RpcError::BadRequest(format!(
+                "QC chain ID {} does not match chain {}",
+                qc.chain_id, chain_state.chain_id
+            )));
+        }
+        if chain_state.validators.is_empty() {
+            return Err(RpcError::BadRequest("No validators configured".to_string()));
+        }
+        // Only a block certified by the validators this node knows may make
+        // it fetch the blocks below
+        verify_block_qc_at(&block, &chain_state)
+            .map_err(|e| RpcError::BadRequest(e.to_string()))?;
     }
-    let threshold = (validators.len() * 2 / 3) + 1;
 
-    verify_qc(qc, &validators, threshold)
+    catch_up_before(&state, block.header.height).await?;
+
+    let mut chain_state = state.chain_state.write().await;
+
+    // Blocks at committed heights are checked against the set of their
+    // epoch, which may have changed since
+    verify_block_qc_at(&block, &chain_state)
         .map_err(|e| RpcError::BadRequest(e.to_string()))?;
 
+    // A certified block at a height this node already committed conflicts
+    // with the local block; whoever signed both equivocated
+    if block.header.height <= chain_state.current_height() {
+        let local = chain_state
+            .get_block(block.header.height)
+            .map_err(|e| RpcError::Internal(e.to_string()))?;
+        if let Some(local) = local.filter(|local| local.hash().ok() != Some(block_hash)) {
+            let found = find_equivocations(&local, &block);
+            let count = found.len();
+            for evidence in found {
+                state.evidence.add(evidence);
+            }
+            return Err(RpcError::BadRequest(format!(
+                "Block conflicts with committed block at height {} ({} validators equivocated)",
+                block.header.height, count
+            )));
+        }
+    }
+
     let block_builder = BlockBuilder::new(BlockBuilderConfig {
         chain_id: chain_state.chain_id,
         ..Default::default()
@@ -733,6 +1283,92 @@ pub async fn consensus_commit<S: Storage + Send + Sync + Clone>(
         .map_err(|e| RpcError::Internal(e.to_string()))?;
     drop(chain_state);
 
+    publish_committed(&state, &block, block_hash, &results).await;
+
+    Ok(Json(CommitResponse {
+        status: "committed".to_string(),
+        height: block.header.height,
+        hash: block_hash.to_hex(),
+    }))
+}
+
+/// POST /consensus/timeout - Record a validator's vote to time out a view
+pub async fn consensus_timeout<S: Storage + Send + Sync + Clone>(
+    State(state): State<Arc<AppState<S>>>,
+    Json(vote): Json<TimeoutVote>,
+) -> Result<Json<TimeoutResponse>, RpcError> {
+    let chain_state = state.chain_state.read().await;
+    if chain_state.validators.is_empty() {
+        return Err(RpcError::BadRequest("No validators configured".to_string()));
+    }
+    let height = chain_state.current_height() + 1;
+
+    state
+        .pacemaker
+        .add_timeout(chain_state.chain_id, height, &vote, &chain_state.validator_set())
+        .map_err(|e| RpcError::BadRequest(e.to_string()))?;
+
+    Ok(Json(TimeoutResponse {
+        height,
+        view: state.pacemaker.view(height),
+    }))
+}
+
+/// GET /consensus/evidence - Equivocation evidence detected by this node
+/// against validators that can still be slashed, ready to submit with a
+/// `SubmitEvidence` op
+pub async fn get_evidence<S: Storage + Send + Sync + Clone>(
+    State(state): State<Arc<AppState<S>>>,
+) -> Result<Json<Vec<Evidence>>, RpcError> {
+    let slashable = state.chain_state.read().await.slashable_validators();
+    Ok(Json(state.evidence.pending(&slashable)))
+}
+
+/// Furthest past the head a proposed or committed block may be to make this
+/// node catch up. A node further behind than an epoch cannot check the block
+/// against its own validator set; it restores from a snapshot instead.
+pub const MAX_CATCH_UP_BLOCKS: u64 = EPOCH_LENGTH_BLOCKS;
+
+/// Fetch and apply the committed blocks below a proposed or committed block
+/// at `height` when this node has fallen behind, e.g. after missing a commit.
+/// Callers check the block's proposer or QC first. Blocks more than
+/// `MAX_CATCH_UP_BLOCKS` past the head are refused.
+async fn catch_up_before<S: Storage + Send + Sync + Clone>(
+    state: &AppState<S>,
+    height: u64,
+) -> Result<(), RpcError> {
+    let local_height = state.chain_state.read().await.current_height();
+    if height <= local_height + 1 {
+        return Ok(());
+    }
+    if height - local_height > MAX_CATCH_UP_BLOCKS {
+        return Err(RpcError::BadRequest(format!(
+            "Block {} is more than {} blocks past local height {}",
+            height, MAX_CATCH_UP_BLOCKS, local_height
+        )));
+    }
+
+    info!(
+        "Received block {} at local height {}; catching up",
+        height, local_height
+    );
+    for (block, results) in state.block_syncer.catch_up(&state.chain_state, height - 1).await {
+        match block.hash() {
+            Ok(block_hash) => publish_committed(state, &block, block_hash, &results).await,
+            Err(e) => warn!("Failed to hash caught-up block: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Remove a committed block's transactions from the mempool and broadcast
+/// its events
+async fn publish_committed<S: Storage>(
+    state: &AppState<S>,
+    block: &Block,
+    block_hash: Hash,
+    results: &[ExecutionResult],
+) {
     // Remove committed transactions from mempool
     let tx_hashes: Vec<Hash> = block
         .txs
@@ -740,6 +1376,7 @@ pub async fn consensus_commit<S: Storage + Send + Sync + Clone>(
         .filter_map(|tx| tx.hash().ok())
         .collect();
     state.mempool.remove_committed(&tx_hashes).await;
+    state.mempool.evict_expired(block.header.height + 1).await;
 
     // Broadcast events
     state.broadcaster.broadcast(crate::ws::WsEvent::block_committed(
@@ -762,10 +1399,4 @@ pub async fn consensus_commit<S: Storage + Send + Sync + Clone>(
             }
         }
     }
-
-    Ok(Json(CommitResponse {
-        status: "committed".to_string(),
-        height: block.header.height,
-        hash: block_hash.to_hex(),
-    }))
 }
@@ -7,9 +7,11 @@ use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 
 use super::handlers::{
-    consensus_commit, consensus_propose, get_account, get_block, get_claim, get_kv,
-    faucet, get_snapshot, get_snapshot_meta, get_status, get_tx, issue_certificate,
-    list_kv_keys, publish_snapshot, submit_tx, AppState,
+    consensus_commit, consensus_propose, consensus_timeout, consensus_vote, get_account, get_account_proof, get_block,
+    get_block_diff, get_block_full, get_blocks, get_claim, get_evidence,
+    get_claim_proof, get_kv, get_kv_proof, get_pool, faucet, get_snapshot_chunk, get_snapshot_manifest, get_snapshot_meta, get_status,
+    get_tx, get_tx_receipt, get_validators, issue_certificate, list_kv_keys, publish_snapshot, submit_tx,
+    AppState,
 };
 
 /// Create the HTTP router
@@ -24,19 +26,32 @@ pub fn create_router<S: Storage + Send + Sync + Clone + 'static>(
     Router::new()
         .route("/status", get(get_status::<S>))
         .route("/snapshot/meta", get(get_snapshot_meta::<S>))
-        .route("/snapshot", get(get_snapshot::<S>))
+        .route("/snapshot/manifest", get(get_snapshot_manifest::<S>))
+        .route("/snapshot/chunk/{hash}", get(get_snapshot_chunk::<S>))
         .route("/snapshot/publish", post(publish_snapshot::<S>))
         .route("/tx", post(submit_tx::<S>))
         .route("/faucet", post(faucet::<S>))
         .route("/tx/{hash}", get(get_tx::<S>))
+        .route("/tx/{hash}/receipt", get(get_tx_receipt::<S>))
         .route("/account/{pubkey}", get(get_account::<S>))
+        .route("/blocks", get(get_blocks::<S>))
         .route("/block/{height}", get(get_block::<S>))
+        .route("/block/{height}/diff", get(get_block_diff::<S>))
+        .route("/block/{height}/full", get(get_block_full::<S>))
+        .route("/validators/{height}", get(get_validators::<S>))
         .route("/claim/{id}", get(get_claim::<S>))
+        .route("/pool/{id}", get(get_pool::<S>))
         .route("/kv/{ns_id}", get(list_kv_keys::<S>))
         .route("/kv/{ns_id}/{key}", get(get_kv::<S>))
+        .route("/proof/account/{pubkey}", get(get_account_proof::<S>))
+        .route("/proof/claim/{id}", get(get_claim_proof::<S>))
+        .route("/proof/kv/{ns_id}/{key}", get(get_kv_proof::<S>))
         .route("/cert/issue", post(issue_certificate::<S>))
         .route("/consensus/propose", post(consensus_propose::<S>))
+        .route("/consensus/vote", post(consensus_vote::<S>))
         .route("/consensus/commit", post(consensus_commit::<S>))
+        .route("/consensus/timeout", post(consensus_timeout::<S>))
+        .route("/consensus/evidence", get(get_evidence::<S>))
         .layer(TraceLayer::new_for_http())
         .layer(cors)
         .with_state(state)
@@ -64,8 +64,16 @@ impl<S: Storage + Send + Sync + Clone + 'static> RpcServer<S> {
             mempool,
             broadcaster,
             validator_keypair,
+            validator: None,
             issuer_keypair,
             snapshot_path,
+            snapshot_dir: None,
+            snapshot_lock: Arc::new(tokio::sync::Mutex::new(())),
+            pacemaker: Arc::new(seloria_consensus::Pacemaker::default()),
+            evidence: Arc::new(seloria_consensus::EvidencePool::new()),
+            block_syncer: Arc::new(seloria_consensus::BlockSyncer::new(
+                validator_endpoints.clone(),
+            )),
             validator_endpoints,
             faucet_keypair,
             faucet_lock: Arc::new(tokio::sync::Mutex::new(())),
@@ -74,6 +82,38 @@ impl<S: Storage + Send + Sync + Clone + 'static> RpcServer<S> {
         RpcServer { config, app_state }
     }
 
+    /// Write chunked snapshots to `dir` and serve them
+    pub fn with_snapshot_dir(mut self, dir: std::path::PathBuf) -> Self {
+        Arc::get_mut(&mut self.app_state)
+            .expect("app state is only shared once the router is built")
+            .snapshot_dir = Some(dir);
+        self
+    }
+
+    /// Track views and timeout votes with the proposer's pacemaker
+    pub fn with_pacemaker(mut self, pacemaker: Arc<seloria_consensus::Pacemaker>) -> Self {
+        Arc::get_mut(&mut self.app_state)
+            .expect("app state is only shared once the router is built")
+            .pacemaker = pacemaker;
+        self
+    }
+
+    /// Collect detected equivocations in `evidence`, shared with the proposer
+    pub fn with_evidence_pool(mut self, evidence: Arc<seloria_consensus::EvidencePool>) -> Self {
+        Arc::get_mut(&mut self.app_state)
+            .expect("app state is only shared once the router is built")
+            .evidence = evidence;
+        self
+    }
+
+    /// Vote on proposals with `validator`, shared with this node's proposer
+    pub fn with_validator(mut self, validator: Arc<seloria_consensus::Validator>) -> Self {
+        Arc::get_mut(&mut self.app_state)
+            .expect("app state is only shared once the router is built")
+            .validator = Some(validator);
+        self
+    }
+
     /// Get the event broadcaster
     pub fn broadcaster(&self) -> Arc<EventBroadcaster> {
         Arc::clone(&self.app_state.broadcaster)
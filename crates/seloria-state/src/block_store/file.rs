@@ -0,0 +1,372 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use seloria_core::{serialize, Hash};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use super::{BlockIndex, BlockStore, CertifiedHeader, IndexEntry, StoredBlock};
+use crate::error::StateError;
+use crate::storage::log::{frame_record, read_record, remove_synced, storage_err, write_atomic};
+
+/// Default number of block heights per segment file
+pub const DEFAULT_BLOCKS_PER_SEGMENT: u64 = 1024;
+
+/// Location of a block record in its segment file
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct Location {
+    offset: u64,
+    len: u32,
+}
+
+/// Change to the block index, appended to the index log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum IndexRecord {
+    Block {
+        height: u64,
+        entry: Box<IndexEntry<Location>>,
+    },
+    Truncate {
+        height: u64,
+    },
+    Prune {
+        below: u64,
+    },
+}
+
+/// Append-only block store on disk.
+///
+/// Files, all in one directory:
+/// - `NNNNNNNN.seg` — checksummed block records for the heights
+///   `N * blocks_per_segment` up to the next segment, in append order
+/// - `index.log` — checksummed index records giving each block's header,
+///   hash, transaction hashes and segment offset
+///
+/// The index is held in memory, so a block is found by height or hash, and
+/// a transaction by hash, with one read. Every write is fsynced, block
+/// records before the index record that points to them; a torn index record
+/// at the end of the log is discarded on open.
+#[derive(Debug)]
+pub struct FileBlockStore {
+    dir: PathBuf,
+    blocks_per_segment: u64,
+    index: BlockIndex<Location>,
+}
+
+impl FileBlockStore {
+    /// Open (or create) a block store in `dir`
+    pub fn open<P: Into<PathBuf>>(dir: P) -> Result<Self, StateError> {
+        Self::open_with_segment_size(dir, DEFAULT_BLOCKS_PER_SEGMENT)
+    }
+
+    /// Open (or create) a block store with `blocks_per_segment` heights per
+    /// segment file
+    pub fn open_with_segment_size<P: Into<PathBuf>>(
+        dir: P,
+        blocks_per_segment: u64,
+    ) -> Result<Self, StateError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(storage_err)?;
+        let mut store = FileBlockStore {
+            dir,
+            blocks_per_segment: blocks_per_segment.max(1),
+            index: BlockIndex::default(),
+        };
+        store.recover_index()?;
+        debug!(
+            "Opened block store at {} (tip {:?})",
+            store.dir.display(),
+            store.index.tip()
+        );
+        Ok(store)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Replay the index log, truncating anything after its last complete
+    /// record
+    fn recover_index(&mut self) -> Result<(), StateError> {
+        let path = self.index_path();
+        if !path.exists() {
+            return Ok(());
+        }
+        let bytes = fs::read(&path).map_err(storage_err)?;
+
+        let mut offset = 0usize;
+        while let Some((payload, len)) = read_record(&bytes[offset..]) {
+            let Ok(record) = serialize::from_bytes::<IndexRecord>(payload) else {
+                break;
+            };
+            self.apply(record);
+            offset += len;
+        }
+
+        if offset < bytes.len() {
+            warn!(
+                "Discarding {} bytes after the last complete record in {}",
+                bytes.len() - offset,
+                path.display()
+            );
+            let file = OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .map_err(storage_err)?;
+            file.set_len(offset as u64).map_err(storage_err)?;
+            file.sync_all().map_err(storage_err)?;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, record: IndexRecord) {
+        match record {
+            IndexRecord::Block { height, entry } => {
+                self.index.truncate(height);
+                self.index.insert(height, *entry);
+            }
+            IndexRecord::Truncate { height } => self.index.truncate(height),
+            IndexRecord::Prune { below } => self.index.prune_below(below),
+        }
+    }
+
+    fn append_index(&self, record: &IndexRecord) -> Result<(), StateError> {
+        let payload = encode(record)?;
+        append_synced(&self.index_path(), &frame_record(&payload))?;
+        Ok(())
+    }
+
+    /// Rewrite the index log with one record per block, dropping truncated
+    /// and replaced entries
+    fn compact_index(&self) -> Result<(), StateError> {
+        let mut bytes = Vec::new();
+        for (height, entry) in &self.index.entries {
+            let record = IndexRecord::Block {
+                height: *height,
+                entry: Box::new(entry.clone()),
+            };
+            bytes.extend(frame_record(&encode(&record)?));
+        }
+        let prune = IndexRecord::Prune {
+            below: self.index.pruned_below,
+        };
+        bytes.extend(frame_record(&encode(&prune)?));
+        write_atomic(&self.index_path(), &bytes)
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join("index.log")
+    }
+
+    fn segment_path(&self, segment: u64) -> PathBuf {
+        self.dir.join(format!("{:08}.seg", segment))
+    }
+
+    fn segment_of(&self, height: u64) -> u64 {
+        height / self.blocks_per_segment
+    }
+}
+
+impl BlockStore for FileBlockStore {
+    fn put_block(&mut self, block: &StoredBlock) -> Result<(), StateError> {
+        let height = block.block.header.height;
+        if self.index.tip().is_some_and(|tip| tip >= height) {
+            self.truncate(height)?;
+        }
+
+        let record = frame_record(&encode(block)?);
+        let offset = append_synced(&self.segment_path(self.segment_of(height)), &record)?;
+        let entry = IndexEntry::new(
+            block,
+            Location {
+                offset,
+                len: record.len() as u32,
+            },
+        )?;
+        self.append_index(&IndexRecord::Block {
+            height,
+            entry: Box::new(entry.clone()),
+        })?;
+        self.index.insert(height, entry);
+        Ok(())
+    }
+
+    fn get_block(&self, height: u64) -> Result<Option<StoredBlock>, StateError> {
+        let Some(location) = self.index.body(height)? else {
+            return Ok(None);
+        };
+        let path = self.segment_path(self.segment_of(height));
+        let mut file = File::open(&path).map_err(storage_err)?;
+        file.seek(SeekFrom::Start(location.offset))
+            .map_err(storage_err)?;
+        let mut bytes = vec![0u8; location.len as usize];
+        file.read_exact(&mut bytes).map_err(storage_err)?;
+
+        let (payload, _) = read_record(&bytes).ok_or_else(|| {
+            StateError::Storage(format!("Corrupt block record at height {}", height))
+        })?;
+        Ok(Some(
+            serialize::from_bytes(payload).map_err(|e| StateError::Serialization(e.to_string()))?,
+        ))
+    }
+
+    fn get_header(&self, height: u64) -> Option<CertifiedHeader> {
+        self.index.header(height)
+    }
+
+    fn height_of(&self, block_hash: &Hash) -> Option<u64> {
+        self.index.height_of(block_hash)
+    }
+
+    fn locate_tx(&self, tx_hash: &Hash) -> Option<(u64, u32)> {
+        self.index.locate_tx(tx_hash)
+    }
+
+    fn tip(&self) -> Option<u64> {
+        self.index.tip()
+    }
+
+    fn truncate(&mut self, height: u64) -> Result<(), StateError> {
+        if self.index.tip().is_none_or(|tip| tip < height) {
+            return Ok(());
+        }
+        self.append_index(&IndexRecord::Truncate { height })?;
+        self.index.truncate(height);
+        Ok(())
+    }
+
+    fn prune_below(&mut self, height: u64) -> Result<(), StateError> {
+        let previous = self.index.pruned_below;
+        if height <= previous {
+            return Ok(());
+        }
+        self.append_index(&IndexRecord::Prune { below: height })?;
+        self.index.prune_below(height);
+
+        // Segments below the first kept height hold only pruned blocks
+        let first_kept = self.segment_of(height);
+        let first_pruned = self.segment_of(previous);
+        if first_pruned < first_kept {
+            self.compact_index()?;
+            for segment in first_pruned..first_kept {
+                remove_synced(&self.segment_path(segment))?;
+            }
+            info!("Pruned block bodies below height {}", height);
+        }
+        Ok(())
+    }
+
+    fn pruned_below(&self) -> u64 {
+        self.index.pruned_below
+    }
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, StateError> {
+    serialize::to_bytes(value).map_err(|e| StateError::Serialization(e.to_string()))
+}
+
+/// Append `bytes` to the file at `path` and fsync it, returning the offset
+/// they were written at
+fn append_synced(path: &Path, bytes: &[u8]) -> Result<u64, StateError> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(storage_err)?;
+    let offset = file.metadata().map_err(storage_err)?.len();
+    file.write_all(bytes).map_err(storage_err)?;
+    file.sync_data().map_err(storage_err)?;
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seloria_core::{testing, Block, BlockHeader, KeyPair, Op, Transaction, TxReceipt};
+
+    fn stored_block(height: u64, sender: &KeyPair) -> StoredBlock {
+        let tx = Transaction::new_signed(
+            1,
+            sender.public,
+            height,
+            10,
+            vec![Op::Transfer {
+                to: sender.public,
+                amount: height,
+            }],
+            &sender.secret,
+        )
+        .unwrap();
+        let receipt = TxReceipt {
+            tx_hash: tx.hash().unwrap(),
+            success: true,
+            error_code: None,
+            error: None,
+            fee_used: 10,
+            events: vec![],
+        };
+        let header = BlockHeader {
+            timestamp: height,
+            proposer_pubkey: sender.public,
+            ..testing::header(1, height)
+        };
+        StoredBlock {
+            block: Block::new(header, vec![tx]),
+            receipts: vec![receipt],
+        }
+    }
+
+    #[test]
+    fn test_blocks_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let sender = KeyPair::generate();
+        let blocks: Vec<StoredBlock> = (0..5).map(|h| stored_block(h, &sender)).collect();
+
+        let mut store = FileBlockStore::open_with_segment_size(dir.path(), 2).unwrap();
+        for block in &blocks {
+            store.put_block(block).unwrap();
+        }
+        // Replacing height 3 drops height 4
+        let replacement = stored_block(3, &KeyPair::generate());
+        store.put_block(&replacement).unwrap();
+
+        let store = FileBlockStore::open_with_segment_size(dir.path(), 2).unwrap();
+        assert_eq!(store.tip(), Some(3));
+        let block = store.get_block(1).unwrap().unwrap();
+        assert_eq!(block.block.hash().unwrap(), blocks[1].block.hash().unwrap());
+        let hash = replacement.block.hash().unwrap();
+        assert_eq!(store.height_of(&hash), Some(3));
+        assert!(store.height_of(&blocks[4].block.hash().unwrap()).is_none());
+
+        let tx_hash = blocks[2].block.txs[0].hash().unwrap();
+        assert!(store.get_transaction(&tx_hash).unwrap().is_some());
+        let record = store.get_receipt(&tx_hash).unwrap().unwrap();
+        assert_eq!((record.block_height, record.index), (2, 0));
+    }
+
+    #[test]
+    fn test_prune_drops_segments_and_keeps_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let sender = KeyPair::generate();
+        let mut store = FileBlockStore::open_with_segment_size(dir.path(), 2).unwrap();
+        for height in 0..6 {
+            store.put_block(&stored_block(height, &sender)).unwrap();
+        }
+        let pruned_tx = store.get_block(2).unwrap().unwrap().block.txs[0].hash().unwrap();
+
+        store.prune_below(3).unwrap();
+        assert!(!store.segment_path(0).exists());
+        assert!(store.segment_path(1).exists());
+
+        let store = FileBlockStore::open_with_segment_size(dir.path(), 2).unwrap();
+        assert_eq!(store.pruned_below(), 3);
+        assert!(matches!(
+            store.get_block(2),
+            Err(StateError::Pruned { height: 2, pruned_below: 3 })
+        ));
+        assert_eq!(store.get_header(0).unwrap().header.height, 0);
+        assert!(store.get_transaction(&pruned_tx).unwrap().is_none());
+        assert!(store.get_block(3).unwrap().is_some());
+    }
+}
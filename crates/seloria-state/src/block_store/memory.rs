@@ -0,0 +1,60 @@
+use seloria_core::Hash;
+
+use super::{BlockIndex, BlockStore, CertifiedHeader, IndexEntry, StoredBlock};
+use crate::error::StateError;
+
+/// In-memory block store for testing and development
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBlockStore {
+    index: BlockIndex<StoredBlock>,
+}
+
+impl MemoryBlockStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BlockStore for MemoryBlockStore {
+    fn put_block(&mut self, block: &StoredBlock) -> Result<(), StateError> {
+        let height = block.block.header.height;
+        let entry = IndexEntry::new(block, block.clone())?;
+        self.index.truncate(height);
+        self.index.insert(height, entry);
+        Ok(())
+    }
+
+    fn get_block(&self, height: u64) -> Result<Option<StoredBlock>, StateError> {
+        Ok(self.index.body(height)?.cloned())
+    }
+
+    fn get_header(&self, height: u64) -> Option<CertifiedHeader> {
+        self.index.header(height)
+    }
+
+    fn height_of(&self, block_hash: &Hash) -> Option<u64> {
+        self.index.height_of(block_hash)
+    }
+
+    fn locate_tx(&self, tx_hash: &Hash) -> Option<(u64, u32)> {
+        self.index.locate_tx(tx_hash)
+    }
+
+    fn tip(&self) -> Option<u64> {
+        self.index.tip()
+    }
+
+    fn truncate(&mut self, height: u64) -> Result<(), StateError> {
+        self.index.truncate(height);
+        Ok(())
+    }
+
+    fn prune_below(&mut self, height: u64) -> Result<(), StateError> {
+        self.index.prune_below(height);
+        Ok(())
+    }
+
+    fn pruned_below(&self) -> u64 {
+        self.index.pruned_below
+    }
+}
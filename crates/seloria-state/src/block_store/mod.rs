@@ -0,0 +1,223 @@
+//! Block storage, kept apart from the state entries
+//!
+//! Blocks, their transactions and receipts are append-only and only looked
+//! up by height or hash, so they live in a [`BlockStore`] rather than in the
+//! state [`Storage`](crate::Storage). State snapshots then stay small, and
+//! state can be rebuilt by replaying the stored blocks.
+
+pub mod file;
+pub mod memory;
+
+use std::collections::{BTreeMap, HashMap};
+
+use seloria_core::{
+    Block, BlockHeader, Hash, QuorumCertificate, ReceiptRecord, Transaction, TxReceipt,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::StateError;
+
+pub use file::FileBlockStore;
+pub use memory::MemoryBlockStore;
+
+/// A block together with the receipts of its transactions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredBlock {
+    pub block: Block,
+    /// One receipt per transaction, in block order (empty for blocks written
+    /// before receipts were recorded and for blocks restored from a snapshot)
+    pub receipts: Vec<TxReceipt>,
+}
+
+impl StoredBlock {
+    /// Receipt of the transaction at `index`
+    pub fn receipt_record(&self, index: u32) -> Option<ReceiptRecord> {
+        let receipt = self.receipts.get(index as usize)?;
+        Some(ReceiptRecord {
+            block_height: self.block.header.height,
+            index,
+            receipt: receipt.clone(),
+        })
+    }
+}
+
+/// Header and quorum certificate of a block, kept after its body is pruned
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CertifiedHeader {
+    pub header: BlockHeader,
+    pub qc: Option<QuorumCertificate>,
+}
+
+/// Height-indexed store of committed blocks, their transactions and receipts
+pub trait BlockStore: Send + Sync {
+    /// Store a block at its height, replacing any stored block at that
+    /// height or above
+    fn put_block(&mut self, block: &StoredBlock) -> Result<(), StateError>;
+
+    /// Get the block at `height`. Fails with `StateError::Pruned` if its
+    /// body has been pruned.
+    fn get_block(&self, height: u64) -> Result<Option<StoredBlock>, StateError>;
+
+    /// Header and QC of the block at `height`, including pruned blocks
+    fn get_header(&self, height: u64) -> Option<CertifiedHeader>;
+
+    /// Height of the block with the given hash
+    fn height_of(&self, block_hash: &Hash) -> Option<u64>;
+
+    /// Height and index of a transaction whose block has not been pruned
+    fn locate_tx(&self, tx_hash: &Hash) -> Option<(u64, u32)>;
+
+    /// Height of the highest stored block
+    fn tip(&self) -> Option<u64>;
+
+    /// Remove the blocks at `height` and above
+    fn truncate(&mut self, height: u64) -> Result<(), StateError>;
+
+    /// Drop the bodies, transactions and receipts of blocks below `height`,
+    /// keeping their headers and QCs
+    fn prune_below(&mut self, height: u64) -> Result<(), StateError>;
+
+    /// Lowest height whose block body is kept
+    fn pruned_below(&self) -> u64;
+
+    /// Get a block by hash
+    fn get_block_by_hash(&self, block_hash: &Hash) -> Result<Option<StoredBlock>, StateError> {
+        match self.height_of(block_hash) {
+            Some(height) => self.get_block(height),
+            None => Ok(None),
+        }
+    }
+
+    /// Get a committed transaction
+    fn get_transaction(&self, tx_hash: &Hash) -> Result<Option<Transaction>, StateError> {
+        let Some((height, index)) = self.locate_tx(tx_hash) else {
+            return Ok(None);
+        };
+        Ok(self
+            .get_block(height)?
+            .and_then(|stored| stored.block.txs.into_iter().nth(index as usize)))
+    }
+
+    /// Get the receipt of a committed transaction
+    fn get_receipt(&self, tx_hash: &Hash) -> Result<Option<ReceiptRecord>, StateError> {
+        let Some((height, index)) = self.locate_tx(tx_hash) else {
+            return Ok(None);
+        };
+        Ok(self
+            .get_block(height)?
+            .and_then(|stored| stored.receipt_record(index)))
+    }
+}
+
+/// Index entry of a stored block; `body` locates the block unless pruned
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct IndexEntry<L> {
+    pub header: CertifiedHeader,
+    pub hash: Hash,
+    pub tx_hashes: Vec<Hash>,
+    pub body: Option<L>,
+}
+
+impl<L> IndexEntry<L> {
+    pub fn new(stored: &StoredBlock, body: L) -> Result<Self, StateError> {
+        let block = &stored.block;
+        Ok(IndexEntry {
+            header: CertifiedHeader {
+                header: block.header.clone(),
+                qc: block.qc.clone(),
+            },
+            hash: block.hash()?,
+            tx_hashes: block
+                .txs
+                .iter()
+                .map(|tx| tx.hash())
+                .collect::<Result<_, _>>()?,
+            body: Some(body),
+        })
+    }
+}
+
+/// Lookup tables shared by the block store implementations
+#[derive(Debug, Clone)]
+pub(crate) struct BlockIndex<L> {
+    pub entries: BTreeMap<u64, IndexEntry<L>>,
+    by_hash: HashMap<Hash, u64>,
+    txs: HashMap<Hash, (u64, u32)>,
+    pub pruned_below: u64,
+}
+
+impl<L> Default for BlockIndex<L> {
+    fn default() -> Self {
+        BlockIndex {
+            entries: BTreeMap::new(),
+            by_hash: HashMap::new(),
+            txs: HashMap::new(),
+            pruned_below: 0,
+        }
+    }
+}
+
+impl<L> BlockIndex<L> {
+    /// Add a block above the current tip
+    pub fn insert(&mut self, height: u64, entry: IndexEntry<L>) {
+        self.by_hash.insert(entry.hash, height);
+        if entry.body.is_some() {
+            for (index, tx_hash) in entry.tx_hashes.iter().enumerate() {
+                self.txs.insert(*tx_hash, (height, index as u32));
+            }
+        }
+        self.entries.insert(height, entry);
+    }
+
+    pub fn truncate(&mut self, height: u64) {
+        for (_, entry) in self.entries.split_off(&height) {
+            self.by_hash.remove(&entry.hash);
+            for tx_hash in &entry.tx_hashes {
+                self.txs.remove(tx_hash);
+            }
+        }
+    }
+
+    pub fn prune_below(&mut self, height: u64) {
+        if height <= self.pruned_below {
+            return;
+        }
+        let entries = self.entries.range_mut(self.pruned_below..height);
+        for entry in entries.map(|(_, entry)| entry) {
+            if entry.body.take().is_some() {
+                for tx_hash in std::mem::take(&mut entry.tx_hashes) {
+                    self.txs.remove(&tx_hash);
+                }
+            }
+        }
+        self.pruned_below = height;
+    }
+
+    /// Body location of the block at `height`
+    pub fn body(&self, height: u64) -> Result<Option<&L>, StateError> {
+        match self.entries.get(&height) {
+            Some(IndexEntry { body: Some(body), .. }) => Ok(Some(body)),
+            Some(_) => Err(StateError::Pruned {
+                height,
+                pruned_below: self.pruned_below,
+            }),
+            None => Ok(None),
+        }
+    }
+
+    pub fn header(&self, height: u64) -> Option<CertifiedHeader> {
+        self.entries.get(&height).map(|entry| entry.header.clone())
+    }
+
+    pub fn height_of(&self, block_hash: &Hash) -> Option<u64> {
+        self.by_hash.get(block_hash).copied()
+    }
+
+    pub fn locate_tx(&self, tx_hash: &Hash) -> Option<(u64, u32)> {
+        self.txs.get(tx_hash).copied()
+    }
+
+    pub fn tip(&self) -> Option<u64> {
+        self.entries.keys().next_back().copied()
+    }
+}
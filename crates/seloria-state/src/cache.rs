@@ -0,0 +1,118 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// Bounded least-recently-used cache
+#[derive(Debug, Clone)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    /// Entries with the tick of their last use
+    entries: HashMap<K, (V, u64)>,
+    /// Keys ordered by last use (oldest first)
+    order: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Get a value, marking it as most recently used
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let tick = self.next_tick();
+        let (value, last_used) = self.entries.get_mut(key)?;
+        self.order.remove(last_used);
+        *last_used = tick;
+        self.order.insert(tick, key.clone());
+        Some(value.clone())
+    }
+
+    /// Insert a value, evicting the least recently used entry if full
+    pub fn put(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let tick = self.next_tick();
+        if let Some((_, last_used)) = self.entries.insert(key.clone(), (value, tick)) {
+            self.order.remove(&last_used);
+        }
+        self.order.insert(tick, key);
+
+        while self.entries.len() > self.capacity {
+            match self.order.pop_first() {
+                Some((_, oldest)) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Drop a value
+    pub fn remove(&mut self, key: &K) {
+        if let Some((_, last_used)) = self.entries.remove(key) {
+            self.order.remove(&last_used);
+        }
+    }
+
+    /// Drop every value
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = LruCache::new(2);
+        cache.put(1, "a");
+        cache.put(2, "b");
+
+        // Touch 1 so that 2 becomes the eviction candidate
+        assert_eq!(cache.get(&1), Some("a"));
+        cache.put(3, "c");
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some("a"));
+        assert_eq!(cache.get(&3), Some("c"));
+    }
+
+    #[test]
+    fn test_overwrite_and_remove() {
+        let mut cache = LruCache::new(2);
+        cache.put(1, "a");
+        cache.put(1, "b");
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(&1), Some("b"));
+
+        cache.remove(&1);
+        assert!(cache.is_empty());
+    }
+}
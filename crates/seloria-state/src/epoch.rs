@@ -0,0 +1,63 @@
+//! History of the validator sets that certified the chain
+
+use seloria_core::{epoch_start, ValidatorSet};
+use serde::{Deserialize, Serialize};
+
+/// A validator set and the first block it certifies
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Epoch {
+    /// Epoch the set took effect in
+    pub number: u64,
+    /// Height of the first block certified by the set
+    pub start_height: u64,
+    pub validators: ValidatorSet,
+}
+
+/// Every validator set the chain has had, oldest first, and the set
+/// scheduled by governance for a coming epoch.
+///
+/// Sets change at epoch boundaries; removing a slashed validator also
+/// starts a new entry within the epoch.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EpochHistory {
+    pub epochs: Vec<Epoch>,
+    /// Set approved by the validators, and the epoch it takes effect in
+    pub scheduled: Option<(u64, ValidatorSet)>,
+}
+
+impl EpochHistory {
+    /// History starting with `validators` in epoch 0
+    pub fn new(validators: ValidatorSet) -> Self {
+        EpochHistory {
+            epochs: vec![Epoch {
+                number: 0,
+                start_height: epoch_start(0),
+                validators,
+            }],
+            scheduled: None,
+        }
+    }
+
+    /// The latest entry
+    pub fn current(&self) -> Option<&Epoch> {
+        self.epochs.last()
+    }
+
+    /// Entry whose set certifies the block at `height`. Heights before the
+    /// first entry belong to it; heights past the head to the latest.
+    pub fn at(&self, height: u64) -> Option<&Epoch> {
+        let index = self.epochs.partition_point(|e| e.start_height <= height);
+        self.epochs.get(index.saturating_sub(1))
+    }
+
+    /// Record that `validators` certify blocks from `start_height` on, in
+    /// `number`. An entry starting at the same height is replaced.
+    pub fn begin(&mut self, number: u64, start_height: u64, validators: ValidatorSet) {
+        self.epochs.retain(|e| e.start_height < start_height);
+        self.epochs.push(Epoch {
+            number,
+            start_height,
+            validators,
+        });
+    }
+}
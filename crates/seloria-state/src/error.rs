@@ -35,6 +35,18 @@ pub enum StateError {
     #[error("Invalid state root")]
     InvalidStateRoot,
 
+    #[error("Block has {txs} transactions but {receipts} receipts")]
+    ReceiptCountMismatch { txs: usize, receipts: usize },
+
+    #[error("State at height {height} is not available (history covers {oldest} to {latest})")]
+    HistoryUnavailable { height: u64, oldest: u64, latest: u64 },
+
+    #[error("Block {height} has been pruned (this node keeps blocks from height {pruned_below})")]
+    Pruned { height: u64, pruned_below: u64 },
+
+    #[error("Invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+
     #[error("Core error: {0}")]
     Core(#[from] seloria_core::CoreError),
 }
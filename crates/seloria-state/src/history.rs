@@ -0,0 +1,245 @@
+//! Per-block changesets and per-key history of state entries.
+//!
+//! Each persisted block stores a [`Changeset`] of the entries it changed
+//! (`chg:` + height), and for every changed entry a record of the value it
+//! held before the block (`hist:` + key length + key + height). The value of
+//! an entry at height `H` is the prior value in its first record above `H`,
+//! or its current value if it has not changed since. Changesets list the
+//! records of each height, so old history is pruned without scanning every
+//! key, and they let blocks be undone.
+
+use seloria_core::serialize;
+use serde::{Deserialize, Serialize};
+
+use crate::error::StateError;
+use crate::storage::{Storage, WriteBatch};
+
+const RECORD_PREFIX: &[u8] = b"hist:";
+const CHANGESET_PREFIX: &[u8] = b"chg:";
+/// Oldest height whose state can be read
+const OLDEST_KEY: &[u8] = b"chain:history_oldest";
+
+/// Change of one state entry (`None` means absent)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryChange {
+    /// Storage key of the entry
+    pub key: Vec<u8>,
+    /// Encoded value before the block
+    pub old: Option<Vec<u8>>,
+    /// Encoded value after the block
+    pub new: Option<Vec<u8>>,
+}
+
+/// State entries changed by a block, in storage key order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Changeset {
+    pub height: u64,
+    pub changes: Vec<EntryChange>,
+}
+
+/// Record the entries changed by the block at `height`. Must be called
+/// before the changes are written to `storage`.
+pub(crate) fn record_changes<S: Storage>(
+    storage: &mut S,
+    height: u64,
+    updates: &[(Vec<u8>, Option<Vec<u8>>)],
+) -> Result<(), StateError> {
+    if storage.get(OLDEST_KEY).is_none() {
+        // History starts here: the state before this block is still readable
+        // from the records about to be written
+        storage.put(OLDEST_KEY, &height.saturating_sub(1).to_be_bytes());
+    }
+    if updates.is_empty() {
+        return Ok(());
+    }
+
+    // A second persist at the same height extends the block's changeset and
+    // keeps the values from before the block
+    let mut changeset = changeset(storage, height)?.unwrap_or(Changeset {
+        height,
+        changes: Vec::new(),
+    });
+    for (key, new) in updates {
+        match changeset.changes.iter_mut().find(|change| &change.key == key) {
+            Some(change) => change.new = new.clone(),
+            None => {
+                let old = storage.get(key);
+                storage.put(&record_key(key, height), &encode(&old)?);
+                changeset.changes.push(EntryChange {
+                    key: key.clone(),
+                    old,
+                    new: new.clone(),
+                });
+            }
+        }
+    }
+    changeset.changes.sort_by(|a, b| a.key.cmp(&b.key));
+    storage.put(&changeset_key(height), &encode(&changeset)?);
+    Ok(())
+}
+
+/// Changeset of the block at `height`, if it is still retained
+pub(crate) fn changeset<S: Storage>(
+    storage: &S,
+    height: u64,
+) -> Result<Option<Changeset>, StateError> {
+    storage
+        .get(&changeset_key(height))
+        .map(|bytes| decode(&bytes))
+        .transpose()
+}
+
+/// Remove the changeset and history records of the block at `height`,
+/// returning the changeset
+pub(crate) fn remove_changeset<S: Storage>(
+    storage: &mut S,
+    height: u64,
+) -> Result<Option<Changeset>, StateError> {
+    let mut batch = WriteBatch::new();
+    let changeset = delete_changeset(storage, height, &mut batch)?;
+    batch.stage(storage);
+    Ok(changeset)
+}
+
+/// Add the removal of the changeset and history records of the block at
+/// `height` to `batch`, returning the changeset
+pub(crate) fn delete_changeset<S: Storage>(
+    storage: &S,
+    height: u64,
+    batch: &mut WriteBatch,
+) -> Result<Option<Changeset>, StateError> {
+    let Some(changeset) = changeset(storage, height)? else {
+        return Ok(None);
+    };
+    for change in &changeset.changes {
+        batch.delete(&record_key(&change.key, height));
+    }
+    batch.delete(&changeset_key(height));
+    Ok(Some(changeset))
+}
+
+/// Drop the history needed only to read state below `cutoff`
+pub(crate) fn prune<S: Storage>(storage: &mut S, cutoff: u64) -> Result<(), StateError> {
+    let oldest = oldest_height(storage).unwrap_or(0);
+    if cutoff <= oldest {
+        return Ok(());
+    }
+    for height in oldest..=cutoff {
+        remove_changeset(storage, height)?;
+    }
+    storage.put(OLDEST_KEY, &cutoff.to_be_bytes());
+    Ok(())
+}
+
+/// Oldest height whose state can be read, if history has been recorded
+pub(crate) fn oldest_height<S: Storage>(storage: &S) -> Option<u64> {
+    let bytes = storage.get(OLDEST_KEY)?;
+    Some(u64::from_be_bytes(bytes.as_slice().try_into().ok()?))
+}
+
+/// Encoded value of `storage_key` at the end of block `height`, or `None` if
+/// it has not changed since and the current value applies
+pub(crate) fn value_at<S: Storage>(
+    storage: &S,
+    storage_key: &[u8],
+    height: u64,
+) -> Result<Option<Option<Vec<u8>>>, StateError> {
+    // Records sort by height, so the first one after `height` is the next
+    // change
+    let prefix = record_prefix(storage_key);
+    let cursor = record_key(storage_key, height);
+    match storage.iter_prefix(&prefix, Some(&cursor)).next() {
+        Some((_, bytes)) => Ok(Some(decode(&bytes)?)),
+        None => Ok(None),
+    }
+}
+
+fn record_prefix(storage_key: &[u8]) -> Vec<u8> {
+    let mut prefix = RECORD_PREFIX.to_vec();
+    prefix.extend_from_slice(&(storage_key.len() as u32).to_be_bytes());
+    prefix.extend_from_slice(storage_key);
+    prefix
+}
+
+fn record_key(storage_key: &[u8], height: u64) -> Vec<u8> {
+    let mut key = record_prefix(storage_key);
+    key.extend_from_slice(&height.to_be_bytes());
+    key
+}
+
+fn changeset_key(height: u64) -> Vec<u8> {
+    [CHANGESET_PREFIX, &height.to_be_bytes()].concat()
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, StateError> {
+    serialize::to_bytes(value).map_err(|e| StateError::Serialization(e.to_string()))
+}
+
+fn decode<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, StateError> {
+    serialize::from_bytes(bytes).map_err(|e| StateError::Serialization(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    /// Persist a block at `height` that sets `key` to `value`
+    fn write(storage: &mut MemoryStorage, height: u64, key: &[u8], value: Option<&[u8]>) {
+        let value = value.map(<[u8]>::to_vec);
+        record_changes(storage, height, &[(key.to_vec(), value.clone())]).unwrap();
+        match value {
+            Some(value) => storage.put(key, &value),
+            None => storage.delete(key),
+        }
+        storage.commit().unwrap();
+    }
+
+    #[test]
+    fn test_value_at_and_prune() {
+        let mut storage = MemoryStorage::new();
+        write(&mut storage, 0, b"acc:a", Some(b"0"));
+        write(&mut storage, 2, b"acc:a", Some(b"2"));
+        write(&mut storage, 5, b"acc:a", None);
+
+        assert_eq!(value_at(&storage, b"acc:a", 0).unwrap(), Some(Some(b"0".to_vec())));
+        assert_eq!(value_at(&storage, b"acc:a", 1).unwrap(), Some(Some(b"0".to_vec())));
+        assert_eq!(value_at(&storage, b"acc:a", 4).unwrap(), Some(Some(b"2".to_vec())));
+        // Unchanged since: the current value applies
+        assert_eq!(value_at(&storage, b"acc:a", 5).unwrap(), None);
+        assert_eq!(value_at(&storage, b"acc:b", 0).unwrap(), None);
+
+        prune(&mut storage, 3).unwrap();
+        storage.commit().unwrap();
+        assert_eq!(oldest_height(&storage), Some(3));
+        assert_eq!(value_at(&storage, b"acc:a", 3).unwrap(), Some(Some(b"2".to_vec())));
+        assert_eq!(storage.keys_with_prefix(RECORD_PREFIX).len(), 1);
+        assert!(changeset(&storage, 2).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_changeset_merges_persists_at_same_height() {
+        let mut storage = MemoryStorage::new();
+        write(&mut storage, 1, b"acc:a", Some(b"1"));
+        write(&mut storage, 1, b"acc:a", Some(b"2"));
+        write(&mut storage, 1, b"acc:b", Some(b"3"));
+
+        let changeset = changeset(&storage, 1).unwrap().unwrap();
+        assert_eq!(
+            changeset.changes,
+            vec![
+                EntryChange {
+                    key: b"acc:a".to_vec(),
+                    old: None,
+                    new: Some(b"2".to_vec()),
+                },
+                EntryChange {
+                    key: b"acc:b".to_vec(),
+                    old: None,
+                    new: Some(b"3".to_vec()),
+                },
+            ]
+        );
+        assert_eq!(value_at(&storage, b"acc:a", 0).unwrap(), Some(None));
+    }
+}
@@ -3,12 +3,29 @@
 //! This crate provides chain state management, storage abstractions,
 //! and merkle tree support.
 
+pub mod block_store;
+pub mod cache;
+pub mod epoch;
 pub mod error;
+mod history;
 pub mod merkle;
+pub mod smt;
+pub mod staking;
 pub mod state;
 pub mod storage;
 
+pub use block_store::{BlockStore, CertifiedHeader, FileBlockStore, MemoryBlockStore, StoredBlock};
+pub use epoch::{Epoch, EpochHistory};
 pub use error::StateError;
+pub use history::{Changeset, EntryChange};
 pub use merkle::compute_state_root;
-pub use state::ChainState;
-pub use storage::{FileStorage, MemoryStorage, Storage};
+pub use smt::SparseMerkleTree;
+pub use staking::{Staking, Unbonding};
+pub use state::{
+    ChainState, Checkpoint, PruningMode, StateDiff, StateOverlay,
+    DEFAULT_CACHE_CAPACITY,
+};
+pub use storage::{
+    FileStorage, LogStorage, LogStorageConfig, MemoryStorage, OverlayStorage, Storage,
+    StorageIter, WriteBatch,
+};
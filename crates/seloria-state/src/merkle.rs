@@ -1,68 +1,21 @@
-use seloria_core::{hash_blake3, Hash};
+use seloria_core::Hash;
 
-/// Compute state root from key-value pairs
-/// Uses sorted keys for determinism
+use crate::smt::SparseMerkleTree;
+use crate::storage::MemoryStorage;
+
+/// Compute the sparse Merkle root of a set of key-value pairs from scratch.
+/// `ChainState` maintains the same root incrementally.
 pub fn compute_state_root<'a, I>(entries: I) -> Hash
 where
     I: IntoIterator<Item = (&'a [u8], &'a [u8])>,
 {
-    let mut sorted: Vec<_> = entries.into_iter().collect();
-    sorted.sort_by(|a, b| a.0.cmp(b.0));
-
-    if sorted.is_empty() {
-        return Hash::ZERO;
-    }
-
-    // Hash each key-value pair
-    let leaf_hashes: Vec<Hash> = sorted
-        .iter()
-        .map(|(k, v)| {
-            let mut data = Vec::with_capacity(k.len() + v.len());
-            data.extend_from_slice(k);
-            data.extend_from_slice(v);
-            hash_blake3(&data)
-        })
-        .collect();
-
-    // Build merkle tree
-    merkle_root_from_hashes(&leaf_hashes)
-}
-
-/// Compute merkle root from a list of hashes
-fn merkle_root_from_hashes(hashes: &[Hash]) -> Hash {
-    if hashes.is_empty() {
-        return Hash::ZERO;
+    let storage = MemoryStorage::new();
+    let mut tree = SparseMerkleTree::new(&storage, Hash::ZERO);
+    for (key, value) in entries {
+        tree.update(key, Some(value))
+            .expect("in-memory tree nodes are always present");
     }
-
-    if hashes.len() == 1 {
-        return hashes[0];
-    }
-
-    let mut current_level = hashes.to_vec();
-
-    while current_level.len() > 1 {
-        let mut next_level = Vec::new();
-
-        for chunk in current_level.chunks(2) {
-            let combined = if chunk.len() == 2 {
-                let mut data = [0u8; 64];
-                data[..32].copy_from_slice(chunk[0].as_bytes());
-                data[32..].copy_from_slice(chunk[1].as_bytes());
-                hash_blake3(&data)
-            } else {
-                // Odd number: duplicate last
-                let mut data = [0u8; 64];
-                data[..32].copy_from_slice(chunk[0].as_bytes());
-                data[32..].copy_from_slice(chunk[0].as_bytes());
-                hash_blake3(&data)
-            };
-            next_level.push(combined);
-        }
-
-        current_level = next_level;
-    }
-
-    current_level[0]
+    tree.root()
 }
 
 #[cfg(test)]
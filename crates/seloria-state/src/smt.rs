@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+
+use seloria_core::crypto::smt::{smt_bit, smt_key, smt_leaf_hash, smt_node_hash};
+use seloria_core::{hash_blake3, serialize, Hash, SmtProof};
+use serde::{Deserialize, Serialize};
+
+use crate::error::StateError;
+use crate::storage::Storage;
+
+/// Storage key prefix of tree nodes (followed by the node hash)
+pub(crate) const NODE_PREFIX: &[u8] = b"smt:n:";
+/// Storage key of the current root
+pub(crate) const ROOT_KEY: &[u8] = b"smt:root";
+
+/// Encoded nodes keyed by their storage key
+pub(crate) type NodeWrites = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// A stored tree node. Empty subtrees are `Hash::ZERO` and not stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Node {
+    Internal { left: Hash, right: Hash },
+    Leaf { key_hash: Hash, value_hash: Hash },
+}
+
+impl Node {
+    fn hash(&self) -> Hash {
+        match self {
+            Node::Internal { left, right } => smt_node_hash(left, right),
+            Node::Leaf {
+                key_hash,
+                value_hash,
+            } => smt_leaf_hash(key_hash, value_hash),
+        }
+    }
+}
+
+/// Sparse Merkle tree over state entries, stored node by node.
+///
+/// Updates never modify stored nodes; they create new ones in memory that
+/// `into_new_nodes` hands back for writing, so older roots stay provable.
+pub struct SparseMerkleTree<'a, S: Storage> {
+    storage: &'a S,
+    root: Hash,
+    /// Nodes created by updates and not yet written to storage
+    new_nodes: HashMap<Hash, Node>,
+}
+
+impl<'a, S: Storage> SparseMerkleTree<'a, S> {
+    /// Open the tree at `root`
+    pub fn new(storage: &'a S, root: Hash) -> Self {
+        SparseMerkleTree {
+            storage,
+            root,
+            new_nodes: HashMap::new(),
+        }
+    }
+
+    /// Open the tree at the root last written to storage
+    pub fn open(storage: &'a S) -> Self {
+        let root = storage
+            .get(ROOT_KEY)
+            .and_then(|bytes| Hash::from_slice(&bytes))
+            .unwrap_or(Hash::ZERO);
+        Self::new(storage, root)
+    }
+
+    pub fn root(&self) -> Hash {
+        self.root
+    }
+
+    /// Set `storage_key` to `value`, or remove it if `None`
+    pub fn update(&mut self, storage_key: &[u8], value: Option<&[u8]>) -> Result<(), StateError> {
+        let key_hash = smt_key(storage_key);
+        let value_hash = value.map(hash_blake3);
+        self.root = self.update_at(self.root, 0, &key_hash, value_hash)?;
+        Ok(())
+    }
+
+    /// Prove the value of `storage_key` (or its absence) under the current root
+    pub fn prove(&self, storage_key: &[u8]) -> Result<SmtProof, StateError> {
+        let key_hash = smt_key(storage_key);
+        let mut siblings = Vec::new();
+        let mut current = self.root;
+
+        loop {
+            match self.node(&current)? {
+                None => {
+                    return Ok(SmtProof {
+                        leaf: None,
+                        siblings,
+                    })
+                }
+                Some(Node::Leaf {
+                    key_hash: leaf_key,
+                    value_hash,
+                }) => {
+                    return Ok(SmtProof {
+                        leaf: Some((leaf_key, value_hash)),
+                        siblings,
+                    })
+                }
+                Some(Node::Internal { left, right }) => {
+                    if smt_bit(&key_hash, siblings.len()) {
+                        siblings.push(left);
+                        current = right;
+                    } else {
+                        siblings.push(right);
+                        current = left;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Nodes reachable from the current root that are not yet in storage,
+    /// keyed by their storage key
+    pub(crate) fn into_new_nodes(mut self) -> Result<NodeWrites, StateError> {
+        let mut out = Vec::new();
+        let mut stack = vec![self.root];
+        while let Some(hash) = stack.pop() {
+            let Some(node) = self.new_nodes.remove(&hash) else {
+                continue;
+            };
+            if let Node::Internal { left, right } = &node {
+                stack.push(*left);
+                stack.push(*right);
+            }
+            let bytes = serialize::to_bytes(&node)
+                .map_err(|e| StateError::Serialization(e.to_string()))?;
+            out.push((node_key(&hash), bytes));
+        }
+        Ok(out)
+    }
+
+    fn node(&self, hash: &Hash) -> Result<Option<Node>, StateError> {
+        if *hash == Hash::ZERO {
+            return Ok(None);
+        }
+        if let Some(node) = self.new_nodes.get(hash) {
+            return Ok(Some(node.clone()));
+        }
+        let bytes = self
+            .storage
+            .get(&node_key(hash))
+            .ok_or_else(|| StateError::Storage(format!("Missing state tree node {}", hash)))?;
+        let node = serialize::from_bytes(&bytes)
+            .map_err(|e| StateError::Serialization(e.to_string()))?;
+        Ok(Some(node))
+    }
+
+    fn insert_node(&mut self, node: Node) -> Hash {
+        let hash = node.hash();
+        self.new_nodes.insert(hash, node);
+        hash
+    }
+
+    fn update_at(
+        &mut self,
+        current: Hash,
+        depth: usize,
+        key_hash: &Hash,
+        value_hash: Option<Hash>,
+    ) -> Result<Hash, StateError> {
+        match self.node(&current)? {
+            None => Ok(match value_hash {
+                Some(value_hash) => self.insert_node(Node::Leaf {
+                    key_hash: *key_hash,
+                    value_hash,
+                }),
+                None => Hash::ZERO,
+            }),
+            Some(Node::Leaf {
+                key_hash: leaf_key, ..
+            }) if leaf_key == *key_hash => Ok(match value_hash {
+                Some(value_hash) => self.insert_node(Node::Leaf {
+                    key_hash: *key_hash,
+                    value_hash,
+                }),
+                None => Hash::ZERO,
+            }),
+            Some(Node::Leaf {
+                key_hash: leaf_key, ..
+            }) => match value_hash {
+                // Removing a key that is not present
+                None => Ok(current),
+                Some(value_hash) => {
+                    let leaf = self.insert_node(Node::Leaf {
+                        key_hash: *key_hash,
+                        value_hash,
+                    });
+                    Ok(self.split(depth, (current, leaf_key), (leaf, *key_hash)))
+                }
+            },
+            Some(Node::Internal { left, right }) => {
+                let (left, right) = if smt_bit(key_hash, depth) {
+                    (left, self.update_at(right, depth + 1, key_hash, value_hash)?)
+                } else {
+                    (self.update_at(left, depth + 1, key_hash, value_hash)?, right)
+                };
+                self.join(left, right)
+            }
+        }
+    }
+
+    /// Build the subtree at `depth` holding two leaves with distinct keys
+    fn split(&mut self, depth: usize, a: (Hash, Hash), b: (Hash, Hash)) -> Hash {
+        let (a_bit, b_bit) = (smt_bit(&a.1, depth), smt_bit(&b.1, depth));
+        let (left, right) = if a_bit != b_bit {
+            if a_bit {
+                (b.0, a.0)
+            } else {
+                (a.0, b.0)
+            }
+        } else {
+            let child = self.split(depth + 1, a, b);
+            if a_bit {
+                (Hash::ZERO, child)
+            } else {
+                (child, Hash::ZERO)
+            }
+        };
+        self.insert_node(Node::Internal { left, right })
+    }
+
+    /// Node with the given children, lifting a lone leaf up a level
+    fn join(&mut self, left: Hash, right: Hash) -> Result<Hash, StateError> {
+        let lone = match (left == Hash::ZERO, right == Hash::ZERO) {
+            (true, true) => return Ok(Hash::ZERO),
+            (true, false) => Some(right),
+            (false, true) => Some(left),
+            (false, false) => None,
+        };
+        if let Some(child) = lone {
+            if let Some(Node::Leaf { .. }) = self.node(&child)? {
+                return Ok(child);
+            }
+        }
+        Ok(self.insert_node(Node::Internal { left, right }))
+    }
+}
+
+fn node_key(hash: &Hash) -> Vec<u8> {
+    [NODE_PREFIX, hash.as_bytes().as_slice()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    /// Apply updates to the stored tree and write the new nodes
+    fn commit(
+        storage: &mut MemoryStorage,
+        update: impl FnOnce(&mut SparseMerkleTree<'_, MemoryStorage>),
+    ) -> Hash {
+        let mut tree = SparseMerkleTree::open(storage);
+        update(&mut tree);
+        let root = tree.root();
+        for (key, value) in tree.into_new_nodes().unwrap() {
+            storage.put(&key, &value);
+        }
+        storage.put(ROOT_KEY, root.as_bytes());
+        storage.commit().unwrap();
+        root
+    }
+
+    #[test]
+    fn test_root_is_independent_of_update_order() {
+        let storage = MemoryStorage::new();
+        let keys: Vec<Vec<u8>> = (0..50u32).map(|i| format!("key:{}", i).into_bytes()).collect();
+
+        let mut forward = SparseMerkleTree::open(&storage);
+        for key in &keys {
+            forward.update(key, Some(b"v")).unwrap();
+        }
+        let mut backward = SparseMerkleTree::open(&storage);
+        for key in keys.iter().rev() {
+            backward.update(key, Some(b"v")).unwrap();
+        }
+        assert_eq!(forward.root(), backward.root());
+
+        // Removing everything yields the empty tree
+        for key in &keys {
+            forward.update(key, None).unwrap();
+        }
+        assert_eq!(forward.root(), Hash::ZERO);
+    }
+
+    #[test]
+    fn test_inclusion_and_exclusion_proofs() {
+        let mut storage = MemoryStorage::new();
+        let root = commit(&mut storage, |tree| {
+            for i in 0..20u32 {
+                tree.update(format!("key:{}", i).as_bytes(), Some(&i.to_le_bytes()))
+                    .unwrap();
+            }
+        });
+
+        // Incremental updates on the stored tree
+        let new_root = commit(&mut storage, |tree| {
+            tree.update(b"key:3", None).unwrap();
+            tree.update(b"key:4", Some(b"new")).unwrap();
+        });
+
+        let tree = SparseMerkleTree::open(&storage);
+        let proof = tree.prove(b"key:4").unwrap();
+        assert!(proof.verify(&new_root, b"key:4", Some(b"new")));
+        assert!(!proof.verify(&new_root, b"key:4", Some(&4u32.to_le_bytes())));
+        let proof = tree.prove(b"key:3").unwrap();
+        assert!(proof.verify(&new_root, b"key:3", None));
+        let proof = tree.prove(b"missing").unwrap();
+        assert!(proof.verify(&new_root, b"missing", None));
+
+        // The previous root stays provable
+        let old = SparseMerkleTree::new(&storage, root);
+        let proof = old.prove(b"key:3").unwrap();
+        assert!(proof.verify(&root, b"key:3", Some(&3u32.to_le_bytes())));
+    }
+}
@@ -0,0 +1,42 @@
+//! Validator staking records kept with the chain metadata
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use seloria_core::PublicKey;
+use serde::{Deserialize, Serialize};
+
+/// Stake leaving a validator's bond, held in its unbonding lock until
+/// `release_height`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unbonding {
+    pub validator: PublicKey,
+    pub amount: u64,
+    pub release_height: u64,
+}
+
+/// Bonding state of the validator set. The stake itself is held in account
+/// locks; this records who is bonded, the voting power of the current
+/// validators and the stake waiting to be released.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Staking {
+    /// Voting power of current validators, fixed when their epoch began.
+    /// Validators without an entry have a power of 1.
+    pub powers: BTreeMap<PublicKey, u64>,
+    /// Accounts with a validator bond
+    pub candidates: BTreeSet<PublicKey>,
+    /// Pending unbondings in the order they were started
+    pub unbonding: Vec<Unbonding>,
+    /// Height of the last equivocation each validator was slashed for
+    pub last_slashed: BTreeMap<PublicKey, u64>,
+}
+
+impl Staking {
+    /// Remove and return the unbondings released at or before `height`
+    pub fn take_matured(&mut self, height: u64) -> Vec<Unbonding> {
+        let (matured, pending) = std::mem::take(&mut self.unbonding)
+            .into_iter()
+            .partition(|u| u.release_height <= height);
+        self.unbonding = pending;
+        matured
+    }
+}
@@ -1,61 +1,156 @@
+use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::sync::{Arc, Mutex, RwLock};
 
 use seloria_core::{
-    serialize, Account, AmmPool, AppMeta, Block, Claim, GenesisConfig, Hash, KvValue, LockId,
-    NamespaceMeta, PublicKey, SignedAgentCertificate, TokenMeta, NATIVE_TOKEN_ID,
+    epoch_of, serialize, validator_bond_lock, validator_unbonding_lock, Account, AmmPool, AppMeta,
+    Block, Claim, GenesisConfig, Hash, KvValue, LockId, NamespaceMeta, PublicKey, ReceiptRecord,
+    SignedAgentCertificate, SmtProof, StateKey, StateProof, TokenMeta, Transaction, TxReceipt,
+    SnapshotChunk, SnapshotManifest, ValidatorInfo, ValidatorSet, EPOCH_LENGTH_BLOCKS,
+    MAX_VALIDATORS, MIN_VALIDATOR_BOND, NATIVE_TOKEN_ID, SNAPSHOT_FORMAT_VERSION,
 };
-use tracing::{debug, info};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
 
+use crate::block_store::{BlockStore, CertifiedHeader, MemoryBlockStore, StoredBlock};
+use crate::cache::LruCache;
+use crate::epoch::{Epoch, EpochHistory};
 use crate::error::StateError;
-use crate::merkle::compute_state_root;
-use crate::storage::Storage;
+use crate::history::{self, Changeset};
+use crate::smt::{self, SparseMerkleTree};
+use crate::staking::{Staking, Unbonding};
+use crate::storage::{MemoryStorage, OverlayStorage, Storage, WriteBatch};
 
 /// Key prefixes for storage
 mod keys {
-    pub const ACCOUNT: &[u8] = b"acc:";
-    pub const AGENT: &[u8] = b"agt:";
-    pub const ISSUER: &[u8] = b"iss:";
-    pub const CLAIM: &[u8] = b"clm:";
-    pub const NAMESPACE: &[u8] = b"ns:";
-    pub const KV: &[u8] = b"kv:";
-    pub const APP: &[u8] = b"app:";
-    pub const TOKEN: &[u8] = b"tok:";
-    pub const POOL: &[u8] = b"pool:";
-    pub const LP: &[u8] = b"lp:";
-    pub const BLOCK: &[u8] = b"blk:";
-    pub const TX: &[u8] = b"tx:";
+    pub use seloria_core::types::proof::prefix::{
+        ACCOUNT, AGENT, APP, CLAIM, EPOCHS, ISSUER, KV, LP, NAMESPACE, POOL, STAKING, TOKEN,
+        VALIDATORS, VALIDATOR_SET,
+    };
+
     pub const CHAIN_ID: &[u8] = b"chain:id";
-    pub const VALIDATORS: &[u8] = b"chain:validators";
     pub const HEAD: &[u8] = b"head";
+    /// Certified validator set changes, by big-endian height
+    pub const HANDOFF: &[u8] = b"handoff:";
+
+    /// Blocks, transactions and receipts stored with the state before they
+    /// moved to the block store
+    pub const LEGACY_BLOCK: &[u8] = b"blk:";
+    pub const LEGACY_TX: &[u8] = b"tx:";
+    pub const LEGACY_RECEIPT: &[u8] = b"rcpt:";
+    pub const LEGACY_BLOCK_HEADER: &[u8] = b"bhdr:";
+
+    /// Prefixes of the entries covered by the state root
+    pub const STATE_ROOT: &[&[u8]] = seloria_core::types::proof::prefix::ALL;
+
+    /// Chain metadata carried in snapshots along with the state entries.
+    /// Both are checked against the manifest on restore.
+    pub const SNAPSHOT_META: &[&[u8]] = &[CHAIN_ID, HEAD];
+}
+
+/// Encoded entry writes keyed by storage key (`None` deletes)
+type EntryWrites = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+
+/// Default number of hot accounts and pools kept decoded in memory
+pub const DEFAULT_CACHE_CAPACITY: usize = 10_000;
+
+/// Which block bodies and transaction index entries a node keeps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PruningMode {
+    /// Keep every block and transaction
+    #[default]
+    Archive,
+    /// Keep the bodies, transactions and receipts of the last `keep_blocks`
+    /// blocks; older blocks keep only their header and QC
+    Pruned { keep_blocks: u64 },
+}
+
+/// Prior value of a state entry, recorded while a checkpoint is open so the
+/// entry can be restored if the checkpoint is reverted
+#[derive(Debug, Clone)]
+enum JournalEntry {
+    Account(PublicKey, Option<Account>),
+    Agent(PublicKey, Option<SignedAgentCertificate>),
+    Claim(Hash, Option<Claim>),
+    Namespace(Hash, Option<NamespaceMeta>),
+    Kv((Hash, String), Option<KvValue>),
+    App(Hash, Option<AppMeta>),
+    Token(Hash, Option<TokenMeta>),
+    Pool(Hash, Option<AmmPool>),
+    Lp((Hash, PublicKey), Option<u64>),
+    Validators(Vec<PublicKey>),
+    Staking(Staking),
+    Epochs(EpochHistory),
+}
+
+/// Entries written since the last persist, by kind (`None` marks a deletion)
+#[derive(Debug, Clone, Default)]
+struct PendingWrites {
+    accounts: BTreeMap<PublicKey, Option<Account>>,
+    agents: BTreeMap<PublicKey, Option<SignedAgentCertificate>>,
+    claims: BTreeMap<Hash, Option<Claim>>,
+    namespaces: BTreeMap<Hash, Option<NamespaceMeta>>,
+    kv: BTreeMap<(Hash, String), Option<KvValue>>,
+    apps: BTreeMap<Hash, Option<AppMeta>>,
+    tokens: BTreeMap<Hash, Option<TokenMeta>>,
+    pools: BTreeMap<Hash, Option<AmmPool>>,
+    lp_balances: BTreeMap<(Hash, PublicKey), Option<u64>>,
+    blocks: BTreeMap<u64, StoredBlock>,
+}
+
+/// Handle to an open state checkpoint
+///
+/// Checkpoints nest: a checkpoint must be reverted or committed before any
+/// checkpoint opened earlier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[must_use = "a checkpoint must be reverted or committed"]
+pub struct Checkpoint {
+    depth: usize,
+}
+
+/// Speculative view of a [`ChainState`] for executing blocks. It reads
+/// through to the base state's storage and keeps its writes to itself.
+pub type StateOverlay<'a, S> = ChainState<OverlayStorage<'a, S>>;
+
+/// Writes made on a [`StateOverlay`], detached from the base state so they
+/// can be applied to it with [`ChainState::apply_diff`]
+#[derive(Debug, Clone)]
+pub struct StateDiff {
+    pending: PendingWrites,
+    trusted_issuers: BTreeSet<PublicKey>,
+    head_block: Option<Block>,
+    height: u64,
+    chain_id: u64,
+    validators: Vec<PublicKey>,
+    staking: Staking,
+    epochs: EpochHistory,
 }
 
 /// The main chain state manager
+///
+/// Entries are read through from storage on demand; only writes made since
+/// the last `persist_state` (plus a bounded cache of hot accounts and pools)
+/// are held in memory. Blocks, transactions and receipts are kept in a
+/// separate [`BlockStore`].
 pub struct ChainState<S: Storage> {
     storage: S,
-    /// In-memory account cache
-    pub accounts: BTreeMap<PublicKey, Account>,
-    /// Registered agent certificates
-    pub agent_registry: BTreeMap<PublicKey, SignedAgentCertificate>,
+    /// Committed blocks, shared with overlays and clones of this state
+    block_store: Arc<RwLock<dyn BlockStore>>,
+    /// Writes not yet persisted to storage
+    pending: PendingWrites,
+    /// Recently read accounts
+    account_cache: Mutex<LruCache<PublicKey, Account>>,
+    /// Recently read AMM pools
+    pool_cache: Mutex<LruCache<Hash, AmmPool>>,
+    /// Number of past blocks whose state stays readable (`None` keeps all)
+    history_retention: Option<u64>,
+    /// Which blocks and transactions are kept
+    pruning: PruningMode,
     /// Trusted certificate issuers
     pub trusted_issuers: BTreeSet<PublicKey>,
-    /// Active claims
-    pub claims: BTreeMap<Hash, Claim>,
-    /// Namespace metadata
-    pub namespaces: BTreeMap<Hash, NamespaceMeta>,
-    /// KV store entries
-    pub kv_store: BTreeMap<(Hash, String), KvValue>,
-    /// Registered applications
-    pub apps: BTreeMap<Hash, AppMeta>,
-    /// Token registry
-    pub tokens: BTreeMap<Hash, TokenMeta>,
-    /// AMM pools
-    pub pools: BTreeMap<Hash, AmmPool>,
-    /// LP balances (pool_id, owner) -> amount
-    pub lp_balances: BTreeMap<(Hash, PublicKey), u64>,
-    /// Block storage by height
-    pub blocks: BTreeMap<u64, Block>,
-    /// Transaction index by hash
-    pub tx_index: BTreeMap<Hash, seloria_core::Transaction>,
     /// Current head block
     pub head_block: Option<Block>,
     /// Current block height
@@ -64,28 +159,35 @@ pub struct ChainState<S: Storage> {
     pub chain_id: u64,
     /// Validator public keys
     pub validators: Vec<PublicKey>,
+    /// Validator bonds, voting power and pending unbondings
+    pub staking: Staking,
+    /// Validator sets by the heights they certified
+    pub epochs: EpochHistory,
+    /// Prior values of entries modified since the oldest open checkpoint
+    journal: Vec<JournalEntry>,
+    /// Journal length at each open checkpoint
+    checkpoints: Vec<usize>,
 }
 
 impl<S: Storage + Clone> Clone for ChainState<S> {
     fn clone(&self) -> Self {
         ChainState {
             storage: self.storage.clone(),
-            accounts: self.accounts.clone(),
-            agent_registry: self.agent_registry.clone(),
+            block_store: Arc::clone(&self.block_store),
+            pending: self.pending.clone(),
+            account_cache: Mutex::new(self.account_cache.lock().expect("account cache").clone()),
+            pool_cache: Mutex::new(self.pool_cache.lock().expect("pool cache").clone()),
+            history_retention: self.history_retention,
+            pruning: self.pruning,
             trusted_issuers: self.trusted_issuers.clone(),
-            claims: self.claims.clone(),
-            namespaces: self.namespaces.clone(),
-            kv_store: self.kv_store.clone(),
-            apps: self.apps.clone(),
-            tokens: self.tokens.clone(),
-            pools: self.pools.clone(),
-            lp_balances: self.lp_balances.clone(),
-            blocks: self.blocks.clone(),
-            tx_index: self.tx_index.clone(),
             head_block: self.head_block.clone(),
             height: self.height,
             chain_id: self.chain_id,
             validators: self.validators.clone(),
+            staking: self.staking.clone(),
+            epochs: self.epochs.clone(),
+            journal: self.journal.clone(),
+            checkpoints: self.checkpoints.clone(),
         }
     }
 }
@@ -93,27 +195,49 @@ impl<S: Storage + Clone> Clone for ChainState<S> {
 impl<S: Storage> ChainState<S> {
     /// Create a new chain state with given storage
     pub fn new(storage: S) -> Self {
+        Self::with_cache_capacity(storage, DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Create a new chain state caching up to `capacity` accounts and pools
+    pub fn with_cache_capacity(storage: S, capacity: usize) -> Self {
         ChainState {
             storage,
-            accounts: BTreeMap::new(),
-            agent_registry: BTreeMap::new(),
+            block_store: Arc::new(RwLock::new(MemoryBlockStore::new())),
+            pending: PendingWrites::default(),
+            account_cache: Mutex::new(LruCache::new(capacity)),
+            pool_cache: Mutex::new(LruCache::new(capacity)),
+            history_retention: None,
+            pruning: PruningMode::Archive,
             trusted_issuers: BTreeSet::new(),
-            claims: BTreeMap::new(),
-            namespaces: BTreeMap::new(),
-            kv_store: BTreeMap::new(),
-            apps: BTreeMap::new(),
-            tokens: BTreeMap::new(),
-            pools: BTreeMap::new(),
-            lp_balances: BTreeMap::new(),
-            blocks: BTreeMap::new(),
-            tx_index: BTreeMap::new(),
             head_block: None,
             height: 0,
             chain_id: 0,
             validators: Vec::new(),
+            staking: Staking::default(),
+            epochs: EpochHistory::default(),
+            journal: Vec::new(),
+            checkpoints: Vec::new(),
         }
     }
 
+    /// Keep the state of the last `blocks` blocks readable with the `*_at`
+    /// getters, pruning older history on persist. `None` keeps all history.
+    pub fn set_history_retention(&mut self, blocks: Option<u64>) {
+        self.history_retention = blocks;
+    }
+
+    /// Keep blocks, transactions and receipts in `store` instead of memory.
+    /// Must be set before the state is loaded or initialized.
+    pub fn set_block_store(&mut self, store: impl BlockStore + 'static) {
+        self.block_store = Arc::new(RwLock::new(store));
+    }
+
+    /// Set which blocks and transactions are kept. Blocks outside the window
+    /// are pruned on persist.
+    pub fn set_pruning_mode(&mut self, mode: PruningMode) {
+        self.pruning = mode;
+    }
+
     /// Initialize state from genesis configuration
     pub fn init_genesis(&mut self, config: &GenesisConfig) -> Result<(), StateError> {
         info!("Initializing genesis state");
@@ -125,14 +249,32 @@ impl<S: Storage> ChainState<S> {
         let mut native_supply: u64 = 0;
         for (pubkey, balance) in &config.initial_balances {
             let account = Account::new_native(*balance);
-            self.accounts.insert(*pubkey, account);
+            self.pending.accounts.insert(*pubkey, Some(account));
             native_supply = native_supply.saturating_add(*balance);
             debug!("Set initial balance for {}: {}", pubkey, balance);
         }
 
+        // Bond the genesis validators' stake
+        if config.validator_stake > 0 {
+            for validator in &config.validators {
+                let account = self
+                    .pending
+                    .accounts
+                    .entry(*validator)
+                    .or_insert_with(|| Some(Account::new_native(0)))
+                    .get_or_insert_with(|| Account::new_native(0));
+                *account.locked.entry(validator_bond_lock()).or_insert(0) += config.validator_stake;
+                native_supply = native_supply.saturating_add(config.validator_stake);
+                self.staking.powers.insert(*validator, config.validator_stake);
+                self.staking.candidates.insert(*validator);
+            }
+        }
+
+        self.epochs = EpochHistory::new(self.validator_set());
+
         // Register native token
         let native = TokenMeta::native("Seloria", "SEL", 6, native_supply);
-        self.tokens.insert(NATIVE_TOKEN_ID, native);
+        self.pending.tokens.insert(NATIVE_TOKEN_ID, Some(native));
 
         // Set trusted issuers
         for issuer in &config.trusted_issuers {
@@ -142,7 +284,13 @@ impl<S: Storage> ChainState<S> {
 
         // Create and store genesis block
         let genesis = config.create_genesis_block();
-        self.blocks.insert(0, genesis.clone());
+        self.pending.blocks.insert(
+            0,
+            StoredBlock {
+                block: genesis.clone(),
+                receipts: Vec::new(),
+            },
+        );
         self.head_block = Some(genesis);
         self.height = 0;
 
@@ -153,405 +301,628 @@ impl<S: Storage> ChainState<S> {
         Ok(())
     }
 
-    /// Persist current state to storage
+    /// Persist pending writes to storage. Only entries modified since the
+    /// last persist are written.
     pub fn persist_state(&mut self) -> Result<(), StateError> {
-        // Persist accounts
-        for (pubkey, account) in &self.accounts {
-            let key = [keys::ACCOUNT, pubkey.as_bytes()].concat();
-            let value =
-                serialize::to_bytes(account).map_err(|e| StateError::Serialization(e.to_string()))?;
-            self.storage.put(&key, &value);
-        }
-
-        // Persist agents
-        for (pubkey, cert) in &self.agent_registry {
-            let key = [keys::AGENT, pubkey.as_bytes()].concat();
-            let value =
-                serialize::to_bytes(cert).map_err(|e| StateError::Serialization(e.to_string()))?;
-            self.storage.put(&key, &value);
-        }
-
-        // Persist issuers
-        for issuer in &self.trusted_issuers {
-            let key = [keys::ISSUER, issuer.as_bytes()].concat();
-            self.storage.put(&key, &[1u8]);
+        // Blocks go first: blocks above the persisted head are dropped on load
+        {
+            let mut block_store = self.block_store.write().expect("block store");
+            for stored in self.pending.blocks.values() {
+                block_store.put_block(stored)?;
+            }
+            if let PruningMode::Pruned { keep_blocks } = self.pruning {
+                // Always keep the head block
+                block_store.prune_below((self.height + 1).saturating_sub(keep_blocks.max(1)))?;
+            }
         }
 
-        // Persist claims
-        for (claim_id, claim) in &self.claims {
-            let key = [keys::CLAIM, claim_id.as_bytes()].concat();
-            let value =
-                serialize::to_bytes(claim).map_err(|e| StateError::Serialization(e.to_string()))?;
-            self.storage.put(&key, &value);
-        }
+        let updates = self.entry_updates()?;
+        let (root, nodes, handoff) = {
+            let mut tree = SparseMerkleTree::open(&self.storage);
+            for (key, value) in &updates {
+                tree.update(key, value.as_deref())?;
+            }
+            let handoff = self.handoff(&tree)?;
+            (tree.root(), tree.into_new_nodes()?, handoff)
+        };
 
-        // Persist namespaces
-        for (ns_id, ns) in &self.namespaces {
-            let key = [keys::NAMESPACE, ns_id.as_bytes()].concat();
-            let value =
-                serialize::to_bytes(ns).map_err(|e| StateError::Serialization(e.to_string()))?;
-            self.storage.put(&key, &value);
+        // History records are staged as pending writes and committed in the
+        // same batch as the entries
+        history::record_changes(&mut self.storage, self.height, &updates)?;
+        if let Some(retention) = self.history_retention {
+            history::prune(&mut self.storage, self.height.saturating_sub(retention))?;
         }
 
-        // Persist KV entries
-        for ((ns_id, kv_key), value) in &self.kv_store {
-            let storage_key = format_kv_key(ns_id, kv_key);
-            let value =
-                serialize::to_bytes(value).map_err(|e| StateError::Serialization(e.to_string()))?;
-            self.storage.put(&storage_key, &value);
+        let mut batch = WriteBatch::new();
+        for (key, value) in &updates {
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
         }
-
-        // Persist apps
-        for (app_id, app) in &self.apps {
-            let key = [keys::APP, app_id.as_bytes()].concat();
-            let value =
-                serialize::to_bytes(app).map_err(|e| StateError::Serialization(e.to_string()))?;
-            self.storage.put(&key, &value);
+        for (key, node) in &nodes {
+            batch.put(key, node);
         }
-
-        // Persist tokens
-        for (token_id, token) in &self.tokens {
-            let key = [keys::TOKEN, token_id.as_bytes()].concat();
-            let value =
-                serialize::to_bytes(token).map_err(|e| StateError::Serialization(e.to_string()))?;
-            self.storage.put(&key, &value);
+        batch.put(smt::ROOT_KEY, root.as_bytes());
+        if let Some(handoff) = handoff {
+            batch.put(&handoff_key(self.height), &encode(&handoff)?);
         }
 
-        // Persist pools
-        for (pool_id, pool) in &self.pools {
-            let key = [keys::POOL, pool_id.as_bytes()].concat();
-            let value =
-                serialize::to_bytes(pool).map_err(|e| StateError::Serialization(e.to_string()))?;
-            self.storage.put(&key, &value);
+        // Persist head block and chain ID
+        if let Some(ref block) = self.head_block {
+            batch.put(keys::HEAD, &encode(block)?);
         }
+        batch.put(keys::CHAIN_ID, &self.chain_id.to_le_bytes());
 
-        // Persist LP balances
-        for ((pool_id, owner), amount) in &self.lp_balances {
-            let key = format_lp_key(pool_id, owner);
-            let value =
-                serialize::to_bytes(amount).map_err(|e| StateError::Serialization(e.to_string()))?;
-            self.storage.put(&key, &value);
-        }
+        self.storage.write_batch(batch)?;
 
-        // Persist blocks
-        for (height, block) in &self.blocks {
-            let key = format_block_key(*height);
-            let value =
-                serialize::to_bytes(block).map_err(|e| StateError::Serialization(e.to_string()))?;
-            self.storage.put(&key, &value);
+        // Written entries are now served from storage; keep the caches current
+        let pending = std::mem::take(&mut self.pending);
+        let mut account_cache = self.account_cache.lock().expect("account cache");
+        for (pubkey, account) in pending.accounts {
+            match account {
+                Some(account) => account_cache.put(pubkey, account),
+                None => account_cache.remove(&pubkey),
+            }
         }
-
-        // Persist transaction index
-        for (tx_hash, tx) in &self.tx_index {
-            let key = format_tx_key(tx_hash);
-            let value =
-                serialize::to_bytes(tx).map_err(|e| StateError::Serialization(e.to_string()))?;
-            self.storage.put(&key, &value);
+        let mut pool_cache = self.pool_cache.lock().expect("pool cache");
+        for (pool_id, pool) in pending.pools {
+            match pool {
+                Some(pool) => pool_cache.put(pool_id, pool),
+                None => pool_cache.remove(&pool_id),
+            }
         }
+        Ok(())
+    }
 
-        // Persist head block
-        if let Some(ref block) = self.head_block {
-            let key = keys::HEAD;
-            let value =
-                serialize::to_bytes(block).map_err(|e| StateError::Serialization(e.to_string()))?;
-            self.storage.put(key, &value);
+    /// Proof that the head block, certified by the set it replaces, made the
+    /// current validator set take over, if it did. `tree` holds the head's
+    /// state.
+    fn handoff(&self, tree: &SparseMerkleTree<'_, S>) -> Result<Option<StateProof>, StateError> {
+        let Some(head) = &self.head_block else {
+            return Ok(None);
+        };
+        let Some(qc) = &head.qc else {
+            return Ok(None);
+        };
+        if self.epochs.current().map(|e| e.start_height) != Some(head.header.height + 1) {
+            return Ok(None);
         }
+        Ok(Some(StateProof {
+            value: Some(encode(&self.validator_set())?),
+            proof: tree.prove(keys::VALIDATOR_SET)?,
+            header: head.header.clone(),
+            qc: qc.clone(),
+        }))
+    }
 
-        // Persist chain metadata
+    /// Certified validator set changes up to the persisted head, oldest
+    /// first
+    pub fn handoffs(&self) -> Result<Vec<StateProof>, StateError> {
         self.storage
-            .put(keys::CHAIN_ID, &self.chain_id.to_le_bytes());
-        let validators_bytes = serialize::to_bytes(&self.validators)
-            .map_err(|e| StateError::Serialization(e.to_string()))?;
-        self.storage.put(keys::VALIDATORS, &validators_bytes);
+            .iter_prefix(keys::HANDOFF, None)
+            .map(|(_, value)| {
+                serialize::from_bytes(&value).map_err(|e| StateError::Serialization(e.to_string()))
+            })
+            .collect()
+    }
 
-        self.storage.commit()?;
-        Ok(())
+    /// Fold incrementally persisted changes into the storage's compact image
+    pub fn compact_storage(&mut self) -> Result<(), StateError> {
+        self.storage.compact()
     }
 
-    /// Load state from storage into memory
+    /// Load chain metadata from storage. Entries are read on demand, so only
+    /// the head block, chain ID, validators, staking records, epoch history and
+    /// trusted issuers are loaded.
     pub fn load_from_storage(&mut self) -> Result<(), StateError> {
-        self.accounts.clear();
-        self.agent_registry.clear();
+        self.pending = PendingWrites::default();
+        self.account_cache.lock().expect("account cache").clear();
+        self.pool_cache.lock().expect("pool cache").clear();
         self.trusted_issuers.clear();
-        self.claims.clear();
-        self.namespaces.clear();
-        self.kv_store.clear();
-        self.apps.clear();
-        self.tokens.clear();
-        self.pools.clear();
-        self.lp_balances.clear();
-        self.blocks.clear();
-        self.tx_index.clear();
         self.head_block = None;
         self.height = 0;
 
-        for key in self.storage.keys_with_prefix(keys::ACCOUNT) {
-            if let Some(value) = self.storage.get(&key) {
-                let pk_bytes = &key[keys::ACCOUNT.len()..];
-                if let Some(pubkey) = PublicKey::from_slice(pk_bytes) {
-                    let account = serialize::from_bytes(&value)
-                        .map_err(|e| StateError::Serialization(e.to_string()))?;
-                    self.accounts.insert(pubkey, account);
-                }
+        for (key, _) in self.storage.iter_prefix(keys::ISSUER, None) {
+            let pk_bytes = &key[keys::ISSUER.len()..];
+            if let Some(pubkey) = PublicKey::from_slice(pk_bytes) {
+                self.trusted_issuers.insert(pubkey);
             }
         }
 
-        for key in self.storage.keys_with_prefix(keys::AGENT) {
-            if let Some(value) = self.storage.get(&key) {
-                let pk_bytes = &key[keys::AGENT.len()..];
-                if let Some(pubkey) = PublicKey::from_slice(pk_bytes) {
-                    let cert = serialize::from_bytes(&value)
-                        .map_err(|e| StateError::Serialization(e.to_string()))?;
-                    self.agent_registry.insert(pubkey, cert);
-                }
-            }
+        if let Some(value) = self.storage.get(keys::HEAD) {
+            let block: Block = serialize::from_bytes(&value)
+                .map_err(|e| StateError::Serialization(e.to_string()))?;
+            self.height = block.header.height;
+            self.head_block = Some(block);
         }
 
-        for key in self.storage.keys_with_prefix(keys::ISSUER) {
-            let pk_bytes = &key[keys::ISSUER.len()..];
-            if let Some(pubkey) = PublicKey::from_slice(pk_bytes) {
-                self.trusted_issuers.insert(pubkey);
+        if let Some(value) = self.storage.get(keys::CHAIN_ID) {
+            if value.len() == 8 {
+                let mut arr = [0u8; 8];
+                arr.copy_from_slice(&value);
+                self.chain_id = u64::from_le_bytes(arr);
             }
+        } else if let Some(ref head) = self.head_block {
+            self.chain_id = head.header.chain_id;
         }
 
-        for key in self.storage.keys_with_prefix(keys::CLAIM) {
-            if let Some(value) = self.storage.get(&key) {
-                let claim_bytes = &key[keys::CLAIM.len()..];
-                if let Some(claim_id) = Hash::from_slice(claim_bytes) {
-                    let claim = serialize::from_bytes(&value)
-                        .map_err(|e| StateError::Serialization(e.to_string()))?;
-                    self.claims.insert(claim_id, claim);
-                }
+        if let Some(value) = self.storage.get(keys::VALIDATORS) {
+            let validators = serialize::from_bytes(&value)
+                .map_err(|e| StateError::Serialization(e.to_string()))?;
+            self.validators = validators;
+        }
+        if let Some(value) = self.storage.get(keys::STAKING) {
+            let staking = serialize::from_bytes(&value)
+                .map_err(|e| StateError::Serialization(e.to_string()))?;
+            self.staking = staking;
+        }
+        match self.storage.get(keys::EPOCHS) {
+            Some(value) => {
+                self.epochs = serialize::from_bytes(&value)
+                    .map_err(|e| StateError::Serialization(e.to_string()))?;
             }
+            // Chains from before the history was kept have had one set
+            None => self.epochs = EpochHistory::new(self.validator_set()),
         }
 
-        for key in self.storage.keys_with_prefix(keys::NAMESPACE) {
-            if let Some(value) = self.storage.get(&key) {
-                let ns_bytes = &key[keys::NAMESPACE.len()..];
-                if let Some(ns_id) = Hash::from_slice(ns_bytes) {
-                    let ns = serialize::from_bytes(&value)
-                        .map_err(|e| StateError::Serialization(e.to_string()))?;
-                    self.namespaces.insert(ns_id, ns);
-                }
-            }
+        if self.head_block.is_some() && self.storage.get(smt::ROOT_KEY).is_none() {
+            self.rebuild_state_tree()?;
         }
 
-        for key in self.storage.keys_with_prefix(keys::KV) {
-            if let Some(value) = self.storage.get(&key) {
-                let rest = &key[keys::KV.len()..];
-                if rest.len() < 33 || rest[32] != b':' {
-                    continue;
-                }
-                let ns_bytes = &rest[..32];
-                let key_bytes = &rest[33..];
-                if let Some(ns_id) = Hash::from_slice(ns_bytes) {
-                    if let Ok(kv_key) = String::from_utf8(key_bytes.to_vec()) {
-                        let kv_value = serialize::from_bytes(&value)
-                            .map_err(|e| StateError::Serialization(e.to_string()))?;
-                        self.kv_store.insert((ns_id, kv_key), kv_value);
-                    }
-                }
-            }
+        if self.storage.iter_prefix(keys::LEGACY_BLOCK, None).next().is_some() {
+            self.migrate_legacy_blocks()?;
+        }
+        // Blocks stored by a persist that did not complete
+        let mut block_store = self.block_store.write().expect("block store");
+        if block_store.tip().is_some_and(|tip| tip > self.height) {
+            warn!("Dropping stored blocks above height {}", self.height);
+            block_store.truncate(self.height + 1)?;
         }
 
-        for key in self.storage.keys_with_prefix(keys::APP) {
-            if let Some(value) = self.storage.get(&key) {
-                let app_bytes = &key[keys::APP.len()..];
-                if let Some(app_id) = Hash::from_slice(app_bytes) {
-                    let app = serialize::from_bytes(&value)
-                        .map_err(|e| StateError::Serialization(e.to_string()))?;
-                    self.apps.insert(app_id, app);
-                }
-            }
+        Ok(())
+    }
+
+    /// Compute the current state root, including pending writes
+    pub fn compute_state_root(&self) -> Result<Hash, StateError> {
+        let mut tree = SparseMerkleTree::open(&self.storage);
+        for (key, value) in self.entry_updates()? {
+            tree.update(&key, value.as_deref())?;
         }
+        Ok(tree.root())
+    }
 
-        for key in self.storage.keys_with_prefix(keys::TOKEN) {
-            if let Some(value) = self.storage.get(&key) {
-                let token_bytes = &key[keys::TOKEN.len()..];
-                if let Some(token_id) = Hash::from_slice(token_bytes) {
-                    let token = serialize::from_bytes(&value)
-                        .map_err(|e| StateError::Serialization(e.to_string()))?;
-                    self.tokens.insert(token_id, token);
-                }
+    /// State root as of the last `persist_state`
+    pub fn committed_state_root(&self) -> Hash {
+        SparseMerkleTree::open(&self.storage).root()
+    }
+
+    /// Prove the value of a state entry, or its absence, as of the last
+    /// `persist_state`. Returns the encoded value and a proof against
+    /// `committed_state_root`.
+    pub fn prove(&self, key: &StateKey) -> Result<(Option<Vec<u8>>, SmtProof), StateError> {
+        let storage_key = key.storage_key();
+        let proof = SparseMerkleTree::open(&self.storage).prove(&storage_key)?;
+        Ok((self.storage.get(&storage_key), proof))
+    }
+
+    /// Encoded pending writes to entries covered by the state root,
+    /// including changed chain metadata
+    fn entry_updates(&self) -> Result<EntryWrites, StateError> {
+        let pending = &self.pending;
+        let mut updates = Vec::new();
+        encode_entries(&mut updates, &pending.accounts, |pk| {
+            StateKey::Account(*pk).storage_key()
+        })?;
+        encode_entries(&mut updates, &pending.agents, |pk| {
+            StateKey::Agent(*pk).storage_key()
+        })?;
+        encode_entries(&mut updates, &pending.claims, |id| {
+            StateKey::Claim(*id).storage_key()
+        })?;
+        encode_entries(&mut updates, &pending.namespaces, |id| {
+            StateKey::Namespace(*id).storage_key()
+        })?;
+        encode_entries(&mut updates, &pending.kv, |(ns_id, key)| format_kv_key(ns_id, key))?;
+        encode_entries(&mut updates, &pending.apps, |id| StateKey::App(*id).storage_key())?;
+        encode_entries(&mut updates, &pending.tokens, |id| {
+            StateKey::Token(*id).storage_key()
+        })?;
+        encode_entries(&mut updates, &pending.pools, |id| StateKey::Pool(*id).storage_key())?;
+        encode_entries(&mut updates, &pending.lp_balances, |(pool_id, owner)| {
+            format_lp_key(pool_id, owner)
+        })?;
+
+        // Chain metadata is held in memory; only what changed is written
+        let mut metadata = vec![
+            (keys::VALIDATORS.to_vec(), encode(&self.validators)?),
+            (keys::STAKING.to_vec(), encode(&self.staking)?),
+            (keys::EPOCHS.to_vec(), encode(&self.epochs)?),
+            (keys::VALIDATOR_SET.to_vec(), encode(&self.validator_set())?),
+        ];
+        for issuer in &self.trusted_issuers {
+            metadata.push(([keys::ISSUER, issuer.as_bytes()].concat(), vec![1u8]));
+        }
+        for (key, value) in metadata {
+            if self.storage.get(&key).as_ref() != Some(&value) {
+                updates.push((key, Some(value)));
             }
         }
+        Ok(updates)
+    }
 
-        for key in self.storage.keys_with_prefix(keys::POOL) {
-            if let Some(value) = self.storage.get(&key) {
-                let pool_bytes = &key[keys::POOL.len()..];
-                if let Some(pool_id) = Hash::from_slice(pool_bytes) {
-                    let pool = serialize::from_bytes(&value)
-                        .map_err(|e| StateError::Serialization(e.to_string()))?;
-                    self.pools.insert(pool_id, pool);
+    /// Move blocks, transactions and receipts stored with the state into the
+    /// block store. Headers of blocks pruned before the move are dropped.
+    fn migrate_legacy_blocks(&mut self) -> Result<(), StateError> {
+        let mut heights: Vec<u64> = self
+            .storage
+            .keys_with_prefix(keys::LEGACY_BLOCK)
+            .iter()
+            .filter_map(|key| {
+                let bytes = key[keys::LEGACY_BLOCK.len()..].try_into().ok()?;
+                Some(u64::from_le_bytes(bytes))
+            })
+            .collect();
+        heights.sort_unstable();
+
+        let mut block_store = self.block_store.write().expect("block store");
+        for height in &heights {
+            let key = [keys::LEGACY_BLOCK, &height.to_le_bytes()].concat();
+            let Some(block) = self.load::<Block>(&key) else {
+                continue;
+            };
+            let mut receipts = Vec::with_capacity(block.txs.len());
+            for tx in &block.txs {
+                let key = [keys::LEGACY_RECEIPT, tx.hash()?.as_bytes()].concat();
+                match self.load::<ReceiptRecord>(&key) {
+                    Some(record) => receipts.push(record.receipt),
+                    // Blocks from before receipts were recorded have none
+                    None => break,
                 }
             }
+            if receipts.len() != block.txs.len() {
+                receipts.clear();
+            }
+            block_store.put_block(&StoredBlock { block, receipts })?;
         }
-
-        for key in self.storage.keys_with_prefix(keys::LP) {
-            if let Some(value) = self.storage.get(&key) {
-                let rest = &key[keys::LP.len()..];
-                if rest.len() != 64 {
-                    continue;
-                }
-                let pool_bytes = &rest[..32];
-                let owner_bytes = &rest[32..];
-                if let (Some(pool_id), Some(owner)) =
-                    (Hash::from_slice(pool_bytes), PublicKey::from_slice(owner_bytes))
-                {
-                    let amount: u64 = serialize::from_bytes(&value)
-                        .map_err(|e| StateError::Serialization(e.to_string()))?;
-                    self.lp_balances.insert((pool_id, owner), amount);
-                }
+        drop(block_store);
+
+        let mut batch = WriteBatch::new();
+        for prefix in [
+            keys::LEGACY_BLOCK,
+            keys::LEGACY_TX,
+            keys::LEGACY_RECEIPT,
+            keys::LEGACY_BLOCK_HEADER,
+        ] {
+            for (key, _) in self.storage.iter_prefix(prefix, None) {
+                batch.delete(&key);
             }
         }
+        self.storage.write_batch(batch)?;
+        info!("Moved {} blocks into the block store", heights.len());
+        Ok(())
+    }
 
-        for key in self.storage.keys_with_prefix(keys::BLOCK) {
-            if let Some(value) = self.storage.get(&key) {
-                let height_bytes = &key[keys::BLOCK.len()..];
-                if height_bytes.len() == 8 {
-                    let mut arr = [0u8; 8];
-                    arr.copy_from_slice(height_bytes);
-                    let height = u64::from_le_bytes(arr);
-                    let block = serialize::from_bytes(&value)
-                        .map_err(|e| StateError::Serialization(e.to_string()))?;
-                    self.blocks.insert(height, block);
+    /// Build the state tree from every stored entry (for data written
+    /// before the tree existed)
+    fn rebuild_state_tree(&mut self) -> Result<(), StateError> {
+        let (root, nodes) = {
+            let mut tree = SparseMerkleTree::new(&self.storage, Hash::ZERO);
+            for prefix in keys::STATE_ROOT {
+                for (key, value) in self.storage.iter_prefix(prefix, None) {
+                    tree.update(&key, Some(&value))?;
                 }
             }
+            (tree.root(), tree.into_new_nodes()?)
+        };
+        for (key, node) in &nodes {
+            self.storage.put(key, node);
         }
+        self.storage.put(smt::ROOT_KEY, root.as_bytes());
+        self.storage.commit()?;
+        info!("Built state tree with root {}", root);
+        Ok(())
+    }
 
-        for key in self.storage.keys_with_prefix(keys::TX) {
-            if let Some(value) = self.storage.get(&key) {
-                let tx_bytes = &key[keys::TX.len()..];
-                if let Some(tx_hash) = Hash::from_slice(tx_bytes) {
-                    let tx = serialize::from_bytes(&value)
-                        .map_err(|e| StateError::Serialization(e.to_string()))?;
-                    self.tx_index.insert(tx_hash, tx);
+    // Snapshot operations
+
+    /// Write a snapshot of the persisted state, passing each encoded chunk
+    /// and its hash to `sink` in order. Chunks hold the state entries and
+    /// chain metadata in key order (not the state tree, history or blocks)
+    /// and are cut once they reach `chunk_bytes`.
+    pub fn create_snapshot<F>(
+        &self,
+        chunk_bytes: usize,
+        mut sink: F,
+    ) -> Result<SnapshotManifest, StateError>
+    where
+        F: FnMut(&Hash, Vec<u8>) -> Result<(), StateError>,
+    {
+        let head: Block = self
+            .load(keys::HEAD)
+            .ok_or_else(|| StateError::InvalidSnapshot("No persisted head block".to_string()))?;
+
+        let mut chunk_hashes = Vec::new();
+        let mut chunk = SnapshotChunk::default();
+        let mut size = 0;
+        for prefix in snapshot_prefixes() {
+            for (key, value) in self.storage.iter_prefix(prefix, None) {
+                size += key.len() + value.len();
+                chunk.entries.push((key, value));
+                if size >= chunk_bytes {
+                    let (bytes, hash) = std::mem::take(&mut chunk).encode()?;
+                    sink(&hash, bytes)?;
+                    chunk_hashes.push(hash);
+                    size = 0;
                 }
             }
         }
+        if !chunk.entries.is_empty() {
+            let (bytes, hash) = chunk.encode()?;
+            sink(&hash, bytes)?;
+            chunk_hashes.push(hash);
+        }
 
-        if let Some(value) = self.storage.get(keys::HEAD) {
-            let block: Block = serialize::from_bytes(&value)
-                .map_err(|e| StateError::Serialization(e.to_string()))?;
-            self.height = block.header.height;
-            self.head_block = Some(block);
+        Ok(SnapshotManifest {
+            version: SNAPSHOT_FORMAT_VERSION,
+            chain_id: head.header.chain_id,
+            height: head.header.height,
+            block_hash: head.hash()?,
+            state_root: head.header.state_root,
+            qc: head.qc,
+            chunk_hashes,
+            handoffs: self.handoffs()?,
+        })
+    }
+
+    /// Load a snapshot into empty storage. Every chunk is checked against
+    /// the manifest and the state root is recomputed from the entries before
+    /// anything is written. The manifest's certificate is checked separately
+    /// with [`SnapshotManifest::verify`].
+    pub fn restore_snapshot<I>(
+        &mut self,
+        manifest: &SnapshotManifest,
+        chunks: I,
+    ) -> Result<(), StateError>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        let invalid = |message: String| Err(StateError::InvalidSnapshot(message));
+        if manifest.version != SNAPSHOT_FORMAT_VERSION {
+            return invalid(format!("Unsupported format version {}", manifest.version));
+        }
+        if self.storage.get(keys::HEAD).is_some() {
+            return invalid("Storage already holds a state".to_string());
         }
 
-        if let Some(value) = self.storage.get(keys::CHAIN_ID) {
-            if value.len() == 8 {
-                let mut arr = [0u8; 8];
-                arr.copy_from_slice(&value);
-                self.chain_id = u64::from_le_bytes(arr);
+        let prefixes = snapshot_prefixes();
+        let nodes = MemoryStorage::new();
+        let mut tree = SparseMerkleTree::new(&nodes, Hash::ZERO);
+        let mut batch = WriteBatch::new();
+        let mut head_bytes = None;
+        let mut last_key: Option<Vec<u8>> = None;
+        let mut chunk_count = 0;
+        for (index, bytes) in chunks.into_iter().enumerate() {
+            let chunk = manifest
+                .verify_chunk(index, &bytes)
+                .map_err(|e| StateError::InvalidSnapshot(e.to_string()))?;
+            for (key, value) in chunk.entries {
+                if last_key.as_ref().is_some_and(|last| *last >= key) {
+                    return invalid(format!("Entries out of order in chunk {}", index));
+                }
+                if !prefixes.iter().any(|prefix| key.starts_with(prefix)) {
+                    return invalid(format!(
+                        "Unexpected key {} in chunk {}",
+                        String::from_utf8_lossy(&key),
+                        index
+                    ));
+                }
+                if keys::STATE_ROOT.iter().any(|prefix| key.starts_with(prefix)) {
+                    tree.update(&key, Some(&value))?;
+                }
+                if key == keys::HEAD {
+                    head_bytes = Some(value.clone());
+                }
+                if key == keys::CHAIN_ID && value != manifest.chain_id.to_le_bytes() {
+                    return invalid("Chain ID does not match the manifest".to_string());
+                }
+                batch.put(&key, &value);
+                last_key = Some(key);
             }
-        } else if let Some(ref head) = self.head_block {
-            self.chain_id = head.header.chain_id;
+            chunk_count += 1;
+        }
+        if chunk_count != manifest.chunk_hashes.len() {
+            return invalid(format!(
+                "Got {} of {} chunks",
+                chunk_count,
+                manifest.chunk_hashes.len()
+            ));
         }
 
-        if let Some(value) = self.storage.get(keys::VALIDATORS) {
-            let validators = serialize::from_bytes(&value)
-                .map_err(|e| StateError::Serialization(e.to_string()))?;
-            self.validators = validators;
+        let root = tree.root();
+        if root != manifest.state_root {
+            return invalid(format!(
+                "State root {} does not match the manifest's {}",
+                root, manifest.state_root
+            ));
+        }
+        let Some(head_bytes) = head_bytes else {
+            return invalid("No head block".to_string());
+        };
+        let head: Block = serialize::from_bytes(&head_bytes)
+            .map_err(|e| StateError::Serialization(e.to_string()))?;
+        if head.hash()? != manifest.block_hash
+            || head.header.height != manifest.height
+            || head.header.chain_id != manifest.chain_id
+            || head.header.state_root != root
+        {
+            return invalid("Head block does not match the manifest".to_string());
+        }
+
+        for (key, node) in tree.into_new_nodes()? {
+            batch.put(&key, &node);
+        }
+        batch.put(smt::ROOT_KEY, root.as_bytes());
+        // Kept so that snapshots taken from this node verify as well
+        for handoff in &manifest.handoffs {
+            batch.put(&handoff_key(handoff.header.height), &encode(handoff)?);
+        }
+        self.storage.write_batch(batch)?;
+
+        // Blocks below the snapshot are not available on this node
+        {
+            let mut block_store = self.block_store.write().expect("block store");
+            block_store.put_block(&StoredBlock {
+                block: head,
+                receipts: Vec::new(),
+            })?;
+            block_store.prune_below(manifest.height)?;
         }
 
+        self.load_from_storage()?;
+        info!(
+            "Restored snapshot at height {} with state root {}",
+            manifest.height, root
+        );
         Ok(())
     }
 
-    /// Compute the current state root
-    pub fn compute_state_root(&self) -> Result<Hash, StateError> {
-        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
-
-        // Add accounts
-        for (pubkey, account) in &self.accounts {
-            let key = [keys::ACCOUNT, pubkey.as_bytes()].concat();
-            let value =
-                serialize::to_bytes(account).map_err(|e| StateError::Serialization(e.to_string()))?;
-            entries.push((key, value));
-        }
+    // Checkpoint operations
 
-        // Add agents
-        for (pubkey, cert) in &self.agent_registry {
-            let key = [keys::AGENT, pubkey.as_bytes()].concat();
-            let value =
-                serialize::to_bytes(cert).map_err(|e| StateError::Serialization(e.to_string()))?;
-            entries.push((key, value));
+    /// Open a checkpoint. Every change made through the state methods until
+    /// the checkpoint is committed or reverted is journaled.
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        self.checkpoints.push(self.journal.len());
+        Checkpoint {
+            depth: self.checkpoints.len(),
         }
+    }
 
-        // Add claims
-        for (claim_id, claim) in &self.claims {
-            let key = [keys::CLAIM, claim_id.as_bytes()].concat();
-            let value =
-                serialize::to_bytes(claim).map_err(|e| StateError::Serialization(e.to_string()))?;
-            entries.push((key, value));
+    /// Undo every change made since the checkpoint was opened
+    pub fn revert_to_checkpoint(&mut self, checkpoint: Checkpoint) {
+        let start = self.close_checkpoint(checkpoint);
+        let pending = &mut self.pending;
+        while self.journal.len() > start {
+            match self.journal.pop().expect("journal entry") {
+                JournalEntry::Account(key, prior) => {
+                    pending.accounts.insert(key, prior);
+                }
+                JournalEntry::Agent(key, prior) => {
+                    pending.agents.insert(key, prior);
+                }
+                JournalEntry::Claim(key, prior) => {
+                    pending.claims.insert(key, prior);
+                }
+                JournalEntry::Namespace(key, prior) => {
+                    pending.namespaces.insert(key, prior);
+                }
+                JournalEntry::Kv(key, prior) => {
+                    pending.kv.insert(key, prior);
+                }
+                JournalEntry::App(key, prior) => {
+                    pending.apps.insert(key, prior);
+                }
+                JournalEntry::Token(key, prior) => {
+                    pending.tokens.insert(key, prior);
+                }
+                JournalEntry::Pool(key, prior) => {
+                    pending.pools.insert(key, prior);
+                }
+                JournalEntry::Lp(key, prior) => {
+                    pending.lp_balances.insert(key, prior);
+                }
+                JournalEntry::Validators(prior) => {
+                    self.validators = prior;
+                }
+                JournalEntry::Staking(prior) => {
+                    self.staking = prior;
+                }
+                JournalEntry::Epochs(prior) => {
+                    self.epochs = prior;
+                }
+            }
         }
+    }
 
-        // Add namespaces
-        for (ns_id, ns) in &self.namespaces {
-            let key = [keys::NAMESPACE, ns_id.as_bytes()].concat();
-            let value =
-                serialize::to_bytes(ns).map_err(|e| StateError::Serialization(e.to_string()))?;
-            entries.push((key, value));
+    /// Keep every change made since the checkpoint was opened. The changes
+    /// can still be undone by reverting an enclosing checkpoint.
+    pub fn commit_checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.close_checkpoint(checkpoint);
+        if self.checkpoints.is_empty() {
+            self.journal.clear();
         }
+    }
 
-        // Add KV entries
-        for ((ns_id, kv_key), value) in &self.kv_store {
-            let storage_key = format_kv_key(ns_id, kv_key);
-            let value =
-                serialize::to_bytes(value).map_err(|e| StateError::Serialization(e.to_string()))?;
-            entries.push((storage_key, value));
-        }
+    /// Pop the checkpoint and return the journal length it was opened at
+    fn close_checkpoint(&mut self, checkpoint: Checkpoint) -> usize {
+        assert_eq!(
+            checkpoint.depth,
+            self.checkpoints.len(),
+            "checkpoints must be closed in reverse order"
+        );
+        self.checkpoints.pop().expect("open checkpoint")
+    }
 
-        // Add apps
-        for (app_id, app) in &self.apps {
-            let key = [keys::APP, app_id.as_bytes()].concat();
-            let value =
-                serialize::to_bytes(app).map_err(|e| StateError::Serialization(e.to_string()))?;
-            entries.push((key, value));
+    /// Record the prior value of an entry if a checkpoint is open
+    fn record(&mut self, entry: impl FnOnce(&Self) -> JournalEntry) {
+        if !self.checkpoints.is_empty() {
+            let entry = entry(self);
+            self.journal.push(entry);
         }
+    }
 
-        // Add tokens
-        for (token_id, token) in &self.tokens {
-            let key = [keys::TOKEN, token_id.as_bytes()].concat();
-            let value =
-                serialize::to_bytes(token).map_err(|e| StateError::Serialization(e.to_string()))?;
-            entries.push((key, value));
-        }
+    // Storage reads
 
-        // Add pools
-        for (pool_id, pool) in &self.pools {
-            let key = [keys::POOL, pool_id.as_bytes()].concat();
-            let value =
-                serialize::to_bytes(pool).map_err(|e| StateError::Serialization(e.to_string()))?;
-            entries.push((key, value));
+    /// Decode an entry from storage
+    fn load<V: DeserializeOwned>(&self, storage_key: &[u8]) -> Option<V> {
+        let bytes = self.storage.get(storage_key)?;
+        match serialize::from_bytes(&bytes) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                error!("Failed to decode state entry: {}", e);
+                None
+            }
         }
+    }
 
-        // Add LP balances
-        for ((pool_id, owner), amount) in &self.lp_balances {
-            let key = format_lp_key(pool_id, owner);
-            let value =
-                serialize::to_bytes(amount).map_err(|e| StateError::Serialization(e.to_string()))?;
-            entries.push((key, value));
+    /// Read an entry from pending writes, falling back to storage
+    fn read<K: Ord, V: DeserializeOwned + Clone>(
+        &self,
+        pending: &BTreeMap<K, Option<V>>,
+        key: &K,
+        storage_key: impl FnOnce() -> Vec<u8>,
+    ) -> Option<V> {
+        match pending.get(key) {
+            Some(entry) => entry.clone(),
+            None => self.load(&storage_key()),
         }
-
-        let entry_refs: Vec<(&[u8], &[u8])> = entries
-            .iter()
-            .map(|(k, v)| (k.as_slice(), v.as_slice()))
-            .collect();
-
-        Ok(compute_state_root(entry_refs))
     }
 
     // Account operations
 
     /// Get account, creating default if not exists
     pub fn get_or_create_account(&mut self, pubkey: &PublicKey) -> &mut Account {
-        self.accounts
+        let current = self.get_account(pubkey);
+        self.record(|_| JournalEntry::Account(*pubkey, current.clone()));
+        self.pending
+            .accounts
             .entry(*pubkey)
-            .or_insert_with(Account::default)
+            .or_insert(None)
+            .get_or_insert_with(|| current.unwrap_or_default())
     }
 
-    /// Get account (read-only)
-    pub fn get_account(&self, pubkey: &PublicKey) -> Option<&Account> {
-        self.accounts.get(pubkey)
+    /// Get account
+    pub fn get_account(&self, pubkey: &PublicKey) -> Option<Account> {
+        if let Some(entry) = self.pending.accounts.get(pubkey) {
+            return entry.clone();
+        }
+        let mut cache = self.account_cache.lock().expect("account cache");
+        if let Some(account) = cache.get(pubkey) {
+            return Some(account);
+        }
+        let account: Account = self.load(&[keys::ACCOUNT, pubkey.as_bytes()].concat())?;
+        cache.put(*pubkey, account.clone());
+        Some(account)
     }
 
     /// Get account balance
@@ -561,8 +932,7 @@ impl<S: Storage> ChainState<S> {
 
     /// Get token balance
     pub fn get_token_balance(&self, pubkey: &PublicKey, token_id: &Hash) -> u64 {
-        self.accounts
-            .get(pubkey)
+        self.get_account(pubkey)
             .map_or(0, |a| a.balance(token_id))
     }
 
@@ -660,6 +1030,212 @@ impl<S: Storage> ChainState<S> {
         }
     }
 
+    /// Remove a validator from the set, returning whether it was a member
+    pub fn remove_validator(&mut self, pubkey: &PublicKey) -> bool {
+        if !self.validators.contains(pubkey) {
+            return false;
+        }
+        self.record(|s| JournalEntry::Validators(s.validators.clone()));
+        self.validators.retain(|v| v != pubkey);
+        self.record(|s| JournalEntry::Staking(s.staking.clone()));
+        self.staking.powers.remove(pubkey);
+        true
+    }
+
+    // Staking operations
+
+    /// Current validators with their voting power
+    pub fn validator_set(&self) -> ValidatorSet {
+        ValidatorSet::new(
+            self.validators
+                .iter()
+                .map(|pubkey| ValidatorInfo {
+                    pubkey: *pubkey,
+                    power: self.staking.powers.get(pubkey).copied().unwrap_or(1),
+                })
+                .collect(),
+        )
+    }
+
+    /// Validator set that certifies the block at `height`. Heights past the
+    /// head get the latest recorded set.
+    pub fn validator_set_at(&self, height: u64) -> ValidatorSet {
+        self.epochs
+            .at(height)
+            .map_or_else(|| self.validator_set(), |epoch| epoch.validators.clone())
+    }
+
+    /// Epoch whose set certifies the block at `height`
+    pub fn epoch_at(&self, height: u64) -> Option<Epoch> {
+        self.epochs.at(height).cloned()
+    }
+
+    /// Adopt `validators` as the validator set from the start of `epoch`,
+    /// replacing any set scheduled before
+    pub fn schedule_validator_set(&mut self, epoch: u64, validators: ValidatorSet) {
+        self.record(|s| JournalEntry::Epochs(s.epochs.clone()));
+        self.epochs.scheduled = Some((epoch, validators));
+    }
+
+    /// Validators that can be slashed: current members and accounts with
+    /// bonded or unbonding stake
+    pub fn slashable_validators(&self) -> Vec<PublicKey> {
+        let mut slashable: BTreeSet<PublicKey> = self.validators.iter().copied().collect();
+        slashable.extend(self.staking.candidates.iter().copied());
+        slashable.extend(self.staking.unbonding.iter().map(|u| u.validator));
+        slashable.into_iter().collect()
+    }
+
+    /// Stake `pubkey` has bonded as a validator
+    pub fn validator_bond(&self, pubkey: &PublicKey) -> u64 {
+        self.get_account(pubkey)
+            .map_or(0, |account| account.get_locked(&validator_bond_lock()))
+    }
+
+    /// Bond `amount` of `pubkey`'s native balance as validator stake,
+    /// making it a candidate for the next epoch's validator set. Returns the
+    /// total bond.
+    pub fn bond_validator(&mut self, pubkey: &PublicKey, amount: u64) -> Result<u64, StateError> {
+        self.lock_stake(pubkey, validator_bond_lock(), amount)?;
+        self.record(|s| JournalEntry::Staking(s.staking.clone()));
+        self.staking.candidates.insert(*pubkey);
+        Ok(self.validator_bond(pubkey))
+    }
+
+    /// Move `amount` of `pubkey`'s bond to its unbonding lock, to be
+    /// returned at `release_height`. An account whose bond reaches zero
+    /// stops being a candidate. Returns the remaining bond.
+    pub fn unbond_validator(
+        &mut self,
+        pubkey: &PublicKey,
+        amount: u64,
+        release_height: u64,
+    ) -> Result<u64, StateError> {
+        let bonded = self.validator_bond(pubkey);
+        if amount > bonded {
+            return Err(StateError::InsufficientBalance {
+                have: bonded,
+                need: amount,
+            });
+        }
+        let account = self.get_or_create_account(pubkey);
+        account.slash_locked(&validator_bond_lock(), amount);
+        *account.locked.entry(validator_unbonding_lock()).or_insert(0) += amount;
+
+        self.record(|s| JournalEntry::Staking(s.staking.clone()));
+        if amount > 0 {
+            self.staking.unbonding.push(Unbonding {
+                validator: *pubkey,
+                amount,
+                release_height,
+            });
+        }
+        let remaining = bonded - amount;
+        if remaining == 0 {
+            self.staking.candidates.remove(pubkey);
+        }
+        Ok(remaining)
+    }
+
+    /// Record that `pubkey` was slashed for an equivocation at `height`
+    pub fn record_slash(&mut self, pubkey: &PublicKey, height: u64) {
+        self.record(|s| JournalEntry::Staking(s.staking.clone()));
+        self.staking.last_slashed.insert(*pubkey, height);
+    }
+
+    /// Finish executing the block at `height`: return unbonded stake that
+    /// has matured and, at the end of an epoch, choose the next validator
+    /// set. A changed set is recorded in the epoch history as certifying
+    /// the blocks from `height + 1`. Must run before the block's state root
+    /// is computed.
+    pub fn end_block(&mut self, height: u64) {
+        if self.staking.unbonding.iter().any(|u| u.release_height <= height) {
+            self.record(|s| JournalEntry::Staking(s.staking.clone()));
+            for unbonding in self.staking.take_matured(height) {
+                // Slashing may have left less than was unbonded
+                let account = self.get_or_create_account(&unbonding.validator);
+                let released = account.slash_locked(&validator_unbonding_lock(), unbonding.amount);
+                account.credit(&NATIVE_TOKEN_ID, released);
+                debug!("Released {} unbonded from {}", released, unbonding.validator);
+            }
+        }
+        if height.is_multiple_of(EPOCH_LENGTH_BLOCKS) {
+            self.rotate_validators(height);
+        }
+
+        // Rotation or slashing changed the set
+        let validators = self.validator_set();
+        if self
+            .epochs
+            .current()
+            .is_none_or(|current| current.validators != validators)
+        {
+            self.record(|s| JournalEntry::Epochs(s.epochs.clone()));
+            self.epochs.begin(epoch_of(height + 1), height + 1, validators);
+        }
+    }
+
+    /// Replace the validator set with the set scheduled for the next epoch
+    /// or, without one, the candidates holding the largest bonds of at least
+    /// [`MIN_VALIDATOR_BOND`], weighted by their bond.
+    ///
+    /// The set chosen by stake is only adopted if members of the current set
+    /// holding a quorum of its voting power stay in it. Otherwise (say, for
+    /// genesis validators without stake, or when most of the set unbonds)
+    /// the set is kept until the validators schedule one.
+    fn rotate_validators(&mut self, height: u64) {
+        let epoch = epoch_of(height + 1);
+        if let Some((scheduled_epoch, _)) = self.epochs.scheduled {
+            if scheduled_epoch <= epoch {
+                self.record(|s| JournalEntry::Epochs(s.epochs.clone()));
+                let (_, scheduled) = self.epochs.scheduled.take().expect("scheduled set");
+                if scheduled_epoch == epoch {
+                    self.set_validators(epoch, scheduled);
+                    return;
+                }
+            }
+        }
+
+        let mut bonded: Vec<(PublicKey, u64)> = self
+            .staking
+            .candidates
+            .iter()
+            .map(|pubkey| (*pubkey, self.validator_bond(pubkey)))
+            .filter(|(_, bond)| *bond >= MIN_VALIDATOR_BOND)
+            .collect();
+        if bonded.is_empty() {
+            return;
+        }
+        bonded.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        bonded.truncate(MAX_VALIDATORS);
+
+        let current = self.validator_set();
+        if !current.has_quorum(bonded.iter().map(|(pubkey, _)| pubkey)) {
+            warn!(
+                "Keeping the validator set for epoch {}: too few validators are bonded",
+                epoch
+            );
+            return;
+        }
+        let validators = bonded
+            .into_iter()
+            .map(|(pubkey, power)| ValidatorInfo { pubkey, power })
+            .collect();
+        self.set_validators(epoch, ValidatorSet::new(validators));
+    }
+
+    /// Make `set` the current validator set
+    fn set_validators(&mut self, epoch: u64, set: ValidatorSet) {
+        if set == self.validator_set() {
+            return;
+        }
+        self.record(|s| JournalEntry::Validators(s.validators.clone()));
+        self.record(|s| JournalEntry::Staking(s.staking.clone()));
+        info!("Epoch {} begins with {} validators", epoch, set.len());
+        self.validators = set.pubkeys();
+        self.staking.powers = set.validators.iter().map(|v| (v.pubkey, v.power)).collect();
+    }
+
     /// Increment account nonce
     pub fn increment_nonce(&mut self, pubkey: &PublicKey) {
         self.get_or_create_account(pubkey).nonce += 1;
@@ -684,10 +1260,9 @@ impl<S: Storage> ChainState<S> {
 
     /// Unlock and return stake to account
     pub fn unlock_stake(&mut self, pubkey: &PublicKey, lock_id: &LockId) -> u64 {
-        if let Some(account) = self.accounts.get_mut(pubkey) {
-            account.unlock(lock_id)
-        } else {
-            0
+        match self.get_account(pubkey) {
+            Some(_) => self.get_or_create_account(pubkey).unlock(lock_id),
+            None => 0,
         }
     }
 
@@ -695,18 +1270,21 @@ impl<S: Storage> ChainState<S> {
 
     /// Register an agent certificate
     pub fn register_agent(&mut self, cert: SignedAgentCertificate) {
-        self.agent_registry.insert(cert.cert.agent_pubkey, cert);
+        let agent = cert.cert.agent_pubkey;
+        self.record(|s| JournalEntry::Agent(agent, s.get_agent(&agent)));
+        self.pending.agents.insert(agent, Some(cert));
     }
 
     /// Get agent certificate
-    pub fn get_agent(&self, pubkey: &PublicKey) -> Option<&SignedAgentCertificate> {
-        self.agent_registry.get(pubkey)
+    pub fn get_agent(&self, pubkey: &PublicKey) -> Option<SignedAgentCertificate> {
+        self.read(&self.pending.agents, pubkey, || {
+            [keys::AGENT, pubkey.as_bytes()].concat()
+        })
     }
 
     /// Check if pubkey is a certified agent (not expired)
     pub fn is_certified_agent(&self, pubkey: &PublicKey, current_time: u64) -> bool {
-        self.agent_registry
-            .get(pubkey)
+        self.get_agent(pubkey)
             .map_or(false, |cert| !cert.is_expired(current_time))
     }
 
@@ -719,109 +1297,183 @@ impl<S: Storage> ChainState<S> {
 
     /// Add a new claim
     pub fn add_claim(&mut self, claim: Claim) {
-        self.claims.insert(claim.id, claim);
+        self.record(|s| JournalEntry::Claim(claim.id, s.get_claim(&claim.id)));
+        self.pending.claims.insert(claim.id, Some(claim));
     }
 
     /// Get a claim by ID
-    pub fn get_claim(&self, claim_id: &Hash) -> Option<&Claim> {
-        self.claims.get(claim_id)
+    pub fn get_claim(&self, claim_id: &Hash) -> Option<Claim> {
+        self.read(&self.pending.claims, claim_id, || {
+            [keys::CLAIM, claim_id.as_bytes()].concat()
+        })
     }
 
     /// Get a mutable claim
     pub fn get_claim_mut(&mut self, claim_id: &Hash) -> Option<&mut Claim> {
-        self.claims.get_mut(claim_id)
+        let current = self.get_claim(claim_id)?;
+        self.record(|_| JournalEntry::Claim(*claim_id, Some(current.clone())));
+        let entry = self.pending.claims.entry(*claim_id).or_insert(None);
+        Some(entry.get_or_insert(current))
     }
 
     // Namespace operations
 
     /// Add a new namespace
     pub fn add_namespace(&mut self, ns: NamespaceMeta) {
-        self.namespaces.insert(ns.ns_id, ns);
+        self.record(|s| JournalEntry::Namespace(ns.ns_id, s.get_namespace(&ns.ns_id)));
+        self.pending.namespaces.insert(ns.ns_id, Some(ns));
     }
 
     /// Get namespace metadata
-    pub fn get_namespace(&self, ns_id: &Hash) -> Option<&NamespaceMeta> {
-        self.namespaces.get(ns_id)
+    pub fn get_namespace(&self, ns_id: &Hash) -> Option<NamespaceMeta> {
+        self.read(&self.pending.namespaces, ns_id, || {
+            [keys::NAMESPACE, ns_id.as_bytes()].concat()
+        })
     }
 
     // KV operations
 
     /// Put a KV entry
     pub fn kv_put(&mut self, ns_id: Hash, key: String, value: KvValue) {
-        self.kv_store.insert((ns_id, key), value);
+        self.record(|s| JournalEntry::Kv((ns_id, key.clone()), s.kv_get(&ns_id, &key)));
+        self.pending.kv.insert((ns_id, key), Some(value));
     }
 
     /// Get a KV entry
-    pub fn kv_get(&self, ns_id: &Hash, key: &str) -> Option<&KvValue> {
-        self.kv_store.get(&(*ns_id, key.to_string()))
+    pub fn kv_get(&self, ns_id: &Hash, key: &str) -> Option<KvValue> {
+        self.read(&self.pending.kv, &(*ns_id, key.to_string()), || {
+            format_kv_key(ns_id, key)
+        })
     }
 
     /// Delete a KV entry
     pub fn kv_delete(&mut self, ns_id: &Hash, key: &str) -> Option<KvValue> {
-        self.kv_store.remove(&(*ns_id, key.to_string()))
+        let prior = self.kv_get(ns_id, key);
+        self.record(|_| JournalEntry::Kv((*ns_id, key.to_string()), prior.clone()));
+        self.pending.kv.insert((*ns_id, key.to_string()), None);
+        prior
     }
 
     /// Get all keys in a namespace
     pub fn kv_keys(&self, ns_id: &Hash) -> Vec<String> {
-        self.kv_store
-            .keys()
-            .filter_map(|(nid, k)| {
-                if nid == ns_id {
-                    Some(k.clone())
-                } else {
-                    None
-                }
-            })
-            .collect()
+        self.kv_keys_page(ns_id, None, usize::MAX)
+    }
+
+    /// Get up to `limit` keys in a namespace in order, starting after the key
+    /// `start_after` if given. Only the returned keys are read from storage.
+    pub fn kv_keys_page(&self, ns_id: &Hash, start_after: Option<&str>, limit: usize) -> Vec<String> {
+        let prefix = format_kv_key(ns_id, "");
+        let cursor = start_after.map(|key| format_kv_key(ns_id, key));
+        let mut stored = self
+            .storage
+            .iter_prefix(&prefix, cursor.as_deref())
+            .filter_map(|(k, _)| String::from_utf8(k[prefix.len()..].to_vec()).ok())
+            .peekable();
+
+        let lower = match start_after {
+            Some(key) => Bound::Excluded((*ns_id, key.to_string())),
+            None => Bound::Included((*ns_id, String::new())),
+        };
+        let mut pending = self
+            .pending
+            .kv
+            .range((lower, Bound::Unbounded))
+            .take_while(|((nid, _), _)| nid == ns_id)
+            .map(|((_, key), value)| (key, value.is_some()))
+            .peekable();
+
+        let mut keys = Vec::new();
+        while keys.len() < limit {
+            let order = match (stored.peek(), pending.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(stored_key), Some((pending_key, _))) => stored_key.as_str().cmp(pending_key),
+            };
+            if order == Ordering::Less {
+                keys.extend(stored.next());
+                continue;
+            }
+            if order == Ordering::Equal {
+                stored.next();
+            }
+            if let Some((key, true)) = pending.next() {
+                keys.push(key.clone());
+            }
+        }
+        keys
     }
 
     // App operations
 
     /// Register an app
     pub fn register_app(&mut self, app: AppMeta) {
-        self.apps.insert(app.app_id, app);
+        self.record(|s| JournalEntry::App(app.app_id, s.get_app(&app.app_id)));
+        self.pending.apps.insert(app.app_id, Some(app));
     }
 
     /// Get an app by ID
-    pub fn get_app(&self, app_id: &Hash) -> Option<&AppMeta> {
-        self.apps.get(app_id)
+    pub fn get_app(&self, app_id: &Hash) -> Option<AppMeta> {
+        self.read(&self.pending.apps, app_id, || {
+            [keys::APP, app_id.as_bytes()].concat()
+        })
     }
 
     // Token operations
 
     /// Register a new token
     pub fn add_token(&mut self, token: TokenMeta) {
-        self.tokens.insert(token.token_id, token);
+        self.record(|s| JournalEntry::Token(token.token_id, s.get_token(&token.token_id)));
+        self.pending.tokens.insert(token.token_id, Some(token));
     }
 
     /// Get token metadata
-    pub fn get_token(&self, token_id: &Hash) -> Option<&TokenMeta> {
-        self.tokens.get(token_id)
+    pub fn get_token(&self, token_id: &Hash) -> Option<TokenMeta> {
+        self.read(&self.pending.tokens, token_id, || {
+            [keys::TOKEN, token_id.as_bytes()].concat()
+        })
     }
 
     // AMM pool operations
 
     /// Add a new pool
     pub fn add_pool(&mut self, pool: AmmPool) {
-        self.pools.insert(pool.pool_id, pool);
+        self.record(|s| JournalEntry::Pool(pool.pool_id, s.get_pool(&pool.pool_id)));
+        self.pending.pools.insert(pool.pool_id, Some(pool));
     }
 
     /// Get pool metadata
-    pub fn get_pool(&self, pool_id: &Hash) -> Option<&AmmPool> {
-        self.pools.get(pool_id)
+    pub fn get_pool(&self, pool_id: &Hash) -> Option<AmmPool> {
+        if let Some(entry) = self.pending.pools.get(pool_id) {
+            return entry.clone();
+        }
+        let mut cache = self.pool_cache.lock().expect("pool cache");
+        if let Some(pool) = cache.get(pool_id) {
+            return Some(pool);
+        }
+        let pool: AmmPool = self.load(&[keys::POOL, pool_id.as_bytes()].concat())?;
+        cache.put(*pool_id, pool.clone());
+        Some(pool)
     }
 
     /// Get mutable pool
     pub fn get_pool_mut(&mut self, pool_id: &Hash) -> Option<&mut AmmPool> {
-        self.pools.get_mut(pool_id)
+        let current = self.get_pool(pool_id)?;
+        self.record(|_| JournalEntry::Pool(*pool_id, Some(current.clone())));
+        let entry = self.pending.pools.entry(*pool_id).or_insert(None);
+        Some(entry.get_or_insert(current))
     }
 
     /// Get LP balance
     pub fn get_lp_balance(&self, pool_id: &Hash, owner: &PublicKey) -> u64 {
-        self.lp_balances
-            .get(&(*pool_id, *owner))
-            .copied()
-            .unwrap_or(0)
+        self.lp_entry(pool_id, owner).unwrap_or(0)
+    }
+
+    /// LP balance entry, `None` if the owner holds no LP tokens
+    fn lp_entry(&self, pool_id: &Hash, owner: &PublicKey) -> Option<u64> {
+        self.read(&self.pending.lp_balances, &(*pool_id, *owner), || {
+            format_lp_key(pool_id, owner)
+        })
     }
 
     /// Credit LP balance
@@ -829,10 +1481,11 @@ impl<S: Storage> ChainState<S> {
         if amount == 0 {
             return;
         }
-        *self
+        let prior = self.lp_entry(pool_id, owner);
+        self.record(|_| JournalEntry::Lp((*pool_id, *owner), prior));
+        self.pending
             .lp_balances
-            .entry((*pool_id, *owner))
-            .or_insert(0) += amount;
+            .insert((*pool_id, *owner), Some(prior.unwrap_or(0) + amount));
     }
 
     /// Debit LP balance
@@ -842,50 +1495,264 @@ impl<S: Storage> ChainState<S> {
         owner: &PublicKey,
         amount: u64,
     ) -> Result<(), StateError> {
-        let balance = self.get_lp_balance(pool_id, owner);
+        let prior = self.lp_entry(pool_id, owner);
+        let balance = prior.unwrap_or(0);
         if balance < amount {
             return Err(StateError::InsufficientBalance {
                 have: balance,
                 need: amount,
             });
         }
-        if let Some(balance_mut) = self.lp_balances.get_mut(&(*pool_id, *owner)) {
-            *balance_mut -= amount;
-            if *balance_mut == 0 {
-                self.lp_balances.remove(&(*pool_id, *owner));
-            }
+        if prior.is_none() {
+            return Ok(());
         }
+        self.record(|_| JournalEntry::Lp((*pool_id, *owner), prior));
+        let remaining = balance - amount;
+        self.pending
+            .lp_balances
+            .insert((*pool_id, *owner), (remaining > 0).then_some(remaining));
         Ok(())
     }
 
     // Block and transaction index operations
 
-    /// Get a block by height
-    pub fn get_block(&self, height: u64) -> Option<&Block> {
-        self.blocks.get(&height)
+    /// Get a block by height. Fails with `StateError::Pruned` if the block's
+    /// body has been pruned.
+    pub fn get_block(&self, height: u64) -> Result<Option<Block>, StateError> {
+        if let Some(stored) = self.pending.blocks.get(&height) {
+            return Ok(Some(stored.block.clone()));
+        }
+        let block_store = self.block_store.read().expect("block store");
+        Ok(block_store.get_block(height)?.map(|stored| stored.block))
     }
 
-    /// Get a transaction by hash
-    pub fn get_transaction(&self, tx_hash: &Hash) -> Option<&seloria_core::Transaction> {
-        self.tx_index.get(tx_hash)
-    }
+    /// Get a block by hash
+    pub fn get_block_by_hash(&self, block_hash: &Hash) -> Result<Option<Block>, StateError> {
+        for stored in self.pending.blocks.values() {
+            if stored.block.hash()? == *block_hash {
+                return Ok(Some(stored.block.clone()));
+            }
+        }
+        let block_store = self.block_store.read().expect("block store");
+        Ok(block_store
+            .get_block_by_hash(block_hash)?
+            .map(|stored| stored.block))
+    }
+
+    /// Get the header and QC of a block, including pruned blocks
+    pub fn get_block_header(&self, height: u64) -> Option<CertifiedHeader> {
+        if let Some(stored) = self.pending.blocks.get(&height) {
+            return Some(CertifiedHeader {
+                header: stored.block.header.clone(),
+                qc: stored.block.qc.clone(),
+            });
+        }
+        self.block_store.read().expect("block store").get_header(height)
+    }
+
+    /// Lowest height whose block body, transactions and receipts are kept
+    pub fn pruned_below(&self) -> u64 {
+        self.block_store.read().expect("block store").pruned_below()
+    }
+
+    /// Get a transaction by hash
+    pub fn get_transaction(&self, tx_hash: &Hash) -> Option<Transaction> {
+        if let Some((stored, index)) = self.find_pending_tx(tx_hash) {
+            return Some(stored.block.txs[index].clone());
+        }
+        let block_store = self.block_store.read().expect("block store");
+        block_store.get_transaction(tx_hash).unwrap_or_else(|e| {
+            error!("Failed to read transaction {}: {}", tx_hash, e);
+            None
+        })
+    }
+
+    /// Get the receipt of a committed transaction
+    pub fn get_receipt(&self, tx_hash: &Hash) -> Option<ReceiptRecord> {
+        if let Some((stored, index)) = self.find_pending_tx(tx_hash) {
+            return stored.receipt_record(index as u32);
+        }
+        let block_store = self.block_store.read().expect("block store");
+        block_store.get_receipt(tx_hash).unwrap_or_else(|e| {
+            error!("Failed to read receipt of {}: {}", tx_hash, e);
+            None
+        })
+    }
+
+    /// Block not yet persisted that includes a transaction, and its index
+    fn find_pending_tx(&self, tx_hash: &Hash) -> Option<(&StoredBlock, usize)> {
+        self.pending.blocks.values().find_map(|stored| {
+            let index = stored
+                .block
+                .txs
+                .iter()
+                .position(|tx| tx.hash().is_ok_and(|hash| hash == *tx_hash))?;
+            Some((stored, index))
+        })
+    }
+
+    // Historical reads
+
+    /// Get an account as of the end of block `height`
+    pub fn get_account_at(
+        &self,
+        pubkey: &PublicKey,
+        height: u64,
+    ) -> Result<Option<Account>, StateError> {
+        self.load_at(&StateKey::Account(*pubkey), height)
+    }
+
+    /// Get a claim as of the end of block `height`
+    pub fn get_claim_at(&self, claim_id: &Hash, height: u64) -> Result<Option<Claim>, StateError> {
+        self.load_at(&StateKey::Claim(*claim_id), height)
+    }
+
+    /// Get a pool as of the end of block `height`
+    pub fn get_pool_at(&self, pool_id: &Hash, height: u64) -> Result<Option<AmmPool>, StateError> {
+        self.load_at(&StateKey::Pool(*pool_id), height)
+    }
+
+    /// Get a KV entry as of the end of block `height`
+    pub fn kv_get_at(
+        &self,
+        ns_id: &Hash,
+        key: &str,
+        height: u64,
+    ) -> Result<Option<KvValue>, StateError> {
+        self.load_at(&StateKey::Kv(*ns_id, key.to_string()), height)
+    }
+
+    /// Decode a persisted entry as of the end of block `height`. Fails if
+    /// `height` is above the head or older than the retained history.
+    fn load_at<V: DeserializeOwned>(
+        &self,
+        key: &StateKey,
+        height: u64,
+    ) -> Result<Option<V>, StateError> {
+        let oldest = history::oldest_height(&self.storage).unwrap_or(self.height);
+        if height < oldest || height > self.height {
+            return Err(StateError::HistoryUnavailable {
+                height,
+                oldest,
+                latest: self.height,
+            });
+        }
+        let storage_key = key.storage_key();
+        let bytes = match history::value_at(&self.storage, &storage_key, height)? {
+            Some(prior) => prior,
+            None => self.storage.get(&storage_key),
+        };
+        bytes
+            .map(|bytes| serialize::from_bytes(&bytes))
+            .transpose()
+            .map_err(|e| StateError::Serialization(e.to_string()))
+    }
+
+    /// Entries changed by the block at `height`, if its changeset is still
+    /// retained
+    pub fn get_changeset(&self, height: u64) -> Result<Option<Changeset>, StateError> {
+        history::changeset(&self.storage, height)
+    }
+
+    /// Undo the last `blocks` persisted blocks using their changesets,
+    /// making the block `blocks` below the head the new head. Pending writes
+    /// are discarded. Trusted issuers, validators, staking records and the
+    /// epoch history are part of the changesets and are reverted with the
+    /// entries.
+    pub fn revert_blocks(&mut self, blocks: u64) -> Result<(), StateError> {
+        assert!(
+            self.checkpoints.is_empty(),
+            "cannot revert blocks while a checkpoint is open"
+        );
+        let oldest = history::oldest_height(&self.storage).unwrap_or(self.height);
+        let target = match self.height.checked_sub(blocks) {
+            Some(target) if target >= oldest => target,
+            _ => {
+                return Err(StateError::HistoryUnavailable {
+                    height: self.height.saturating_sub(blocks),
+                    oldest,
+                    latest: self.height,
+                })
+            }
+        };
+        let head = self
+            .get_block(target)?
+            .ok_or_else(|| StateError::Storage(format!("Missing block {}", target)))?;
+
+        self.pending = PendingWrites::default();
+        // Everything below is written in one batch, so a crash leaves either
+        // the old head or the new one
+        let mut batch = WriteBatch::new();
+        let mut restored = Vec::new();
+        for height in (target + 1..=self.height).rev() {
+            let changeset = history::delete_changeset(&self.storage, height, &mut batch)?;
+            if let Some(changeset) = changeset {
+                restored.extend(changeset.changes.into_iter().map(|c| (c.key, c.old)));
+            }
+        }
+
+        // Later blocks come first, so the oldest prior value of each entry is
+        // applied last
+        let (root, nodes) = {
+            let mut tree = SparseMerkleTree::open(&self.storage);
+            for (key, value) in &restored {
+                tree.update(key, value.as_deref())?;
+            }
+            (tree.root(), tree.into_new_nodes()?)
+        };
+        for (key, value) in &restored {
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
+        }
+        for (key, node) in &nodes {
+            batch.put(key, node);
+        }
+        batch.put(smt::ROOT_KEY, root.as_bytes());
+        for (key, _) in self
+            .storage
+            .iter_prefix(keys::HANDOFF, Some(&handoff_key(target)))
+        {
+            batch.delete(&key);
+        }
+        batch.put(keys::HEAD, &encode(&head)?);
+        self.storage.write_batch(batch)?;
+        self.block_store
+            .write()
+            .expect("block store")
+            .truncate(target + 1)?;
+
+        info!(
+            "Reverted {} blocks to height {} with state root {}",
+            blocks, target, root
+        );
+        // Reload the head and the chain metadata the changesets restored
+        self.load_from_storage()
+    }
 
     // Block operations
 
-    /// Apply a block to the state
-    pub fn apply_block(&mut self, block: Block) -> Result<(), StateError> {
+    /// Apply a block to the state, indexing its transactions and receipts.
+    /// `receipts` must hold one receipt per transaction, in block order.
+    pub fn apply_block(&mut self, block: Block, receipts: Vec<TxReceipt>) -> Result<(), StateError> {
         if block.header.height != self.height + 1 && self.height > 0 {
             return Err(StateError::BlockExists(block.header.height));
         }
-
-        // Index transactions
-        for tx in &block.txs {
-            if let Ok(hash) = tx.hash() {
-                self.tx_index.insert(hash, tx.clone());
-            }
+        if receipts.len() != block.txs.len() {
+            return Err(StateError::ReceiptCountMismatch {
+                txs: block.txs.len(),
+                receipts: receipts.len(),
+            });
         }
 
-        self.blocks.insert(block.header.height, block.clone());
+        self.pending.blocks.insert(
+            block.header.height,
+            StoredBlock {
+                block: block.clone(),
+                receipts,
+            },
+        );
         self.head_block = Some(block);
         self.height += 1;
 
@@ -901,6 +1768,100 @@ impl<S: Storage> ChainState<S> {
     pub fn rollback(&mut self) {
         self.storage.rollback();
     }
+
+    // Overlay operations
+
+    /// Open an overlay for speculative execution. Only the pending writes
+    /// are copied; committed entries are read from the shared storage.
+    pub fn overlay(&self) -> StateOverlay<'_, S> {
+        let capacity = self.account_cache.lock().expect("account cache").capacity();
+        ChainState {
+            storage: OverlayStorage::new(&self.storage),
+            block_store: Arc::clone(&self.block_store),
+            pending: self.pending.clone(),
+            account_cache: Mutex::new(LruCache::new(capacity)),
+            pool_cache: Mutex::new(LruCache::new(capacity)),
+            history_retention: self.history_retention,
+            pruning: self.pruning,
+            trusted_issuers: self.trusted_issuers.clone(),
+            head_block: self.head_block.clone(),
+            height: self.height,
+            chain_id: self.chain_id,
+            validators: self.validators.clone(),
+            staking: self.staking.clone(),
+            epochs: self.epochs.clone(),
+            journal: Vec::new(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    /// Adopt the writes of an overlay opened on this state. The state must
+    /// not have been modified since the overlay was opened.
+    pub fn apply_diff(&mut self, diff: StateDiff) {
+        assert!(
+            self.checkpoints.is_empty(),
+            "cannot apply a diff while a checkpoint is open"
+        );
+        self.pending = diff.pending;
+        self.trusted_issuers = diff.trusted_issuers;
+        self.head_block = diff.head_block;
+        self.height = diff.height;
+        self.chain_id = diff.chain_id;
+        self.validators = diff.validators;
+        self.staking = diff.staking;
+        self.epochs = diff.epochs;
+    }
+}
+
+impl<S: Storage> StateOverlay<'_, S> {
+    /// Detach the overlay's writes from the base state. Dropping the overlay
+    /// instead discards them.
+    pub fn into_diff(self) -> StateDiff {
+        StateDiff {
+            pending: self.pending,
+            trusted_issuers: self.trusted_issuers,
+            head_block: self.head_block,
+            height: self.height,
+            chain_id: self.chain_id,
+            validators: self.validators,
+            staking: self.staking,
+            epochs: self.epochs,
+        }
+    }
+}
+
+/// Serialize a value for storage
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, StateError> {
+    serialize::to_bytes(value).map_err(|e| StateError::Serialization(e.to_string()))
+}
+
+/// Encode pending entries of one kind as storage writes (`None` deletes)
+fn encode_entries<K, V: Serialize>(
+    updates: &mut EntryWrites,
+    pending: &BTreeMap<K, Option<V>>,
+    storage_key: impl Fn(&K) -> Vec<u8>,
+) -> Result<(), StateError> {
+    for (key, value) in pending {
+        let value = value.as_ref().map(encode).transpose()?;
+        updates.push((storage_key(key), value));
+    }
+    Ok(())
+}
+
+/// Key prefixes of the entries in a snapshot, in key order
+fn snapshot_prefixes() -> Vec<&'static [u8]> {
+    let mut prefixes: Vec<&[u8]> = keys::STATE_ROOT
+        .iter()
+        .chain(keys::SNAPSHOT_META)
+        .copied()
+        .collect();
+    prefixes.sort_unstable();
+    prefixes
+}
+
+/// Storage key of the validator set hand-off made by the block at `height`
+fn handoff_key(height: u64) -> Vec<u8> {
+    [keys::HANDOFF, &height.to_be_bytes()].concat()
 }
 
 /// Format KV storage key
@@ -920,30 +1881,25 @@ fn format_lp_key(pool_id: &Hash, owner: &PublicKey) -> Vec<u8> {
     key
 }
 
-/// Format block storage key
-fn format_block_key(height: u64) -> Vec<u8> {
-    let mut key = keys::BLOCK.to_vec();
-    key.extend_from_slice(&height.to_le_bytes());
-    key
-}
-
-/// Format transaction storage key
-fn format_tx_key(tx_hash: &Hash) -> Vec<u8> {
-    let mut key = keys::TX.to_vec();
-    key.extend_from_slice(tx_hash.as_bytes());
-    key
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::storage::MemoryStorage;
-    use seloria_core::{hash_blake3, KeyPair};
+    use seloria_core::{hash_blake3, testing, KeyPair};
 
     fn create_test_state() -> ChainState<MemoryStorage> {
         ChainState::new(MemoryStorage::new())
     }
 
+    /// Block at `height` proposed by `proposer`, with placeholder roots
+    fn block_at(height: u64, proposer: &KeyPair, txs: Vec<Transaction>) -> Block {
+        let header = seloria_core::BlockHeader {
+            proposer_pubkey: proposer.public,
+            ..testing::header(1, height)
+        };
+        Block::new(header, txs)
+    }
+
     #[test]
     fn test_genesis_initialization() {
         let mut state = create_test_state();
@@ -957,6 +1913,7 @@ mod tests {
             initial_balances: vec![(user.public, 1_000_000)],
             trusted_issuers: vec![issuer.public],
             validators: vec![validator.public],
+            validator_stake: 0,
         };
 
         state.init_genesis(&config).unwrap();
@@ -966,6 +1923,472 @@ mod tests {
         assert_eq!(state.height, 0);
     }
 
+    #[test]
+    fn test_reads_through_to_storage_after_reload() {
+        let mut state = create_test_state();
+        let alice = KeyPair::generate();
+        let bob = KeyPair::generate();
+        let ns_id = hash_blake3(b"ns");
+
+        let config = GenesisConfig {
+            chain_id: 1,
+            timestamp: 0,
+            initial_balances: vec![(alice.public, 1_000), (bob.public, 1_000)],
+            trusted_issuers: vec![],
+            validators: vec![],
+            validator_stake: 0,
+        };
+        state.init_genesis(&config).unwrap();
+
+        state.kv_put(ns_id, "a".to_string(), KvValue::inline("raw", b"1".to_vec()));
+        state.kv_put(ns_id, "b".to_string(), KvValue::inline("raw", b"2".to_vec()));
+        state.credit_token(&bob.public, &NATIVE_TOKEN_ID, 5);
+        state.persist_state().unwrap();
+        let root = state.compute_state_root().unwrap();
+
+        // Nothing but chain metadata is loaded; entries are read on demand
+        state.load_from_storage().unwrap();
+        assert_eq!(state.account_cache.lock().unwrap().len(), 0);
+        assert_eq!(state.get_balance(&bob.public), 1_005);
+        assert_eq!(state.account_cache.lock().unwrap().len(), 1);
+        assert_eq!(state.compute_state_root().unwrap(), root);
+
+        // Pending writes shadow storage until persisted
+        state.kv_delete(&ns_id, "a");
+        state.kv_put(ns_id, "c".to_string(), KvValue::inline("raw", b"3".to_vec()));
+        assert!(state.storage.get(&format_kv_key(&ns_id, "a")).is_some());
+        assert_eq!(state.kv_keys(&ns_id), vec!["b".to_string(), "c".to_string()]);
+        assert_eq!(state.kv_keys_page(&ns_id, None, 1), vec!["b".to_string()]);
+        assert_eq!(state.kv_keys_page(&ns_id, Some("b"), 5), vec!["c".to_string()]);
+        state.persist_state().unwrap();
+        assert!(state.storage.get(&format_kv_key(&ns_id, "a")).is_none());
+
+        state.load_from_storage().unwrap();
+        assert!(state.kv_get(&ns_id, "a").is_none());
+        assert_eq!(state.kv_keys(&ns_id), vec!["b".to_string(), "c".to_string()]);
+
+        // Pages merge stored keys with pending ones after the cursor
+        state.kv_put(ns_id, "bb".to_string(), KvValue::inline("raw", b"4".to_vec()));
+        state.kv_delete(&ns_id, "c");
+        state.kv_put(ns_id, "d".to_string(), KvValue::inline("raw", b"5".to_vec()));
+        assert_eq!(
+            state.kv_keys_page(&ns_id, Some("b"), 2),
+            vec!["bb".to_string(), "d".to_string()]
+        );
+        assert!(state.kv_keys_page(&ns_id, Some("d"), 2).is_empty());
+    }
+
+    #[test]
+    fn test_reads_at_past_heights() {
+        let mut state = create_test_state();
+        let alice = KeyPair::generate();
+        let ns_id = hash_blake3(b"ns");
+
+        let config = GenesisConfig {
+            chain_id: 1,
+            timestamp: 0,
+            initial_balances: vec![(alice.public, 1_000)],
+            trusted_issuers: vec![],
+            validators: vec![],
+            validator_stake: 0,
+        };
+        state.init_genesis(&config).unwrap();
+        state.set_history_retention(Some(2));
+
+        // Blocks 1 to 3 each credit alice; block 2 also writes a KV entry
+        for height in 1..=3 {
+            state.height = height;
+            state.credit_token(&alice.public, &NATIVE_TOKEN_ID, 10);
+            if height == 2 {
+                state.kv_put(ns_id, "k".to_string(), KvValue::inline("raw", b"v".to_vec()));
+            }
+            state.persist_state().unwrap();
+        }
+
+        let balance_at = |height| {
+            state
+                .get_account_at(&alice.public, height)
+                .unwrap()
+                .unwrap()
+                .balance(&NATIVE_TOKEN_ID)
+        };
+        assert_eq!(balance_at(1), 1_010);
+        assert_eq!(balance_at(2), 1_020);
+        assert_eq!(balance_at(3), 1_030);
+        assert!(state.kv_get_at(&ns_id, "k", 1).unwrap().is_none());
+        assert!(state.kv_get_at(&ns_id, "k", 3).unwrap().is_some());
+
+        // Heights outside the retention window or above the head are rejected
+        assert!(matches!(
+            state.get_account_at(&alice.public, 0),
+            Err(StateError::HistoryUnavailable { oldest: 1, latest: 3, .. })
+        ));
+        assert!(state.get_account_at(&alice.public, 4).is_err());
+    }
+
+    #[test]
+    fn test_revert_blocks_with_changesets() {
+        let mut state = create_test_state();
+        let alice = KeyPair::generate();
+        let ns_id = hash_blake3(b"ns");
+
+        let config = GenesisConfig {
+            chain_id: 1,
+            timestamp: 0,
+            initial_balances: vec![(alice.public, 1_000)],
+            trusted_issuers: vec![],
+            validators: vec![],
+            validator_stake: 0,
+        };
+        state.init_genesis(&config).unwrap();
+        let genesis_root = state.committed_state_root();
+
+        for height in 1..=2 {
+            state.credit_token(&alice.public, &NATIVE_TOKEN_ID, 10);
+            if height == 2 {
+                state.kv_put(ns_id, "k".to_string(), KvValue::inline("raw", b"v".to_vec()));
+            }
+            state.apply_block(block_at(height, &alice, vec![]), vec![]).unwrap();
+            state.persist_state().unwrap();
+        }
+
+        let changeset = state.get_changeset(2).unwrap().unwrap();
+        assert_eq!(changeset.changes.len(), 2);
+        let account_key = StateKey::Account(alice.public).storage_key();
+        let change = changeset.changes.iter().find(|c| c.key == account_key).unwrap();
+        let old: Account = serialize::from_bytes(change.old.as_ref().unwrap()).unwrap();
+        let new: Account = serialize::from_bytes(change.new.as_ref().unwrap()).unwrap();
+        assert_eq!((old.balance(&NATIVE_TOKEN_ID), new.balance(&NATIVE_TOKEN_ID)), (1_010, 1_020));
+
+        state.revert_blocks(2).unwrap();
+        assert_eq!(state.height, 0);
+        assert_eq!(state.get_balance(&alice.public), 1_000);
+        assert!(state.kv_get(&ns_id, "k").is_none());
+        assert_eq!(state.committed_state_root(), genesis_root);
+        assert!(state.get_block(1).unwrap().is_none());
+        assert!(state.get_changeset(1).unwrap().is_none());
+
+        state.load_from_storage().unwrap();
+        assert_eq!(state.height, 0);
+        assert!(state.revert_blocks(1).is_err());
+    }
+
+    #[test]
+    fn test_revert_blocks_restores_validators_and_staking() {
+        let mut state = create_test_state();
+        let validators: Vec<_> = (0..2).map(|_| KeyPair::generate()).collect();
+        let alice = KeyPair::generate();
+        let config = GenesisConfig {
+            chain_id: 1,
+            timestamp: 0,
+            initial_balances: vec![(alice.public, MIN_VALIDATOR_BOND)],
+            trusted_issuers: vec![],
+            validators: validators.iter().map(|v| v.public).collect(),
+            validator_stake: 0,
+        };
+        state.init_genesis(&config).unwrap();
+        let genesis_set = state.validator_set();
+        let genesis_epochs = state.epochs.clone();
+
+        state.bond_validator(&alice.public, MIN_VALIDATOR_BOND).unwrap();
+        state.end_block(1);
+        state.apply_block(block_at(1, &validators[0], vec![]), vec![]).unwrap();
+        state.persist_state().unwrap();
+        state.remove_validator(&validators[1].public);
+        state.end_block(2);
+        state.apply_block(block_at(2, &validators[0], vec![]), vec![]).unwrap();
+        state.persist_state().unwrap();
+        assert_eq!(state.epochs.epochs.len(), 2);
+
+        state.revert_blocks(2).unwrap();
+        assert_eq!(state.validator_set(), genesis_set);
+        assert_eq!(state.epochs, genesis_epochs);
+        assert!(state.staking.candidates.is_empty());
+        assert_eq!(state.validator_bond(&alice.public), 0);
+        assert_eq!(state.get_balance(&alice.public), MIN_VALIDATOR_BOND);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut state = create_test_state();
+        let users: Vec<KeyPair> = (0..8).map(|_| KeyPair::generate()).collect();
+        let config = GenesisConfig {
+            chain_id: 1,
+            timestamp: 0,
+            initial_balances: users.iter().map(|user| (user.public, 1_000)).collect(),
+            trusted_issuers: vec![users[0].public],
+            validators: vec![users[1].public],
+            validator_stake: 0,
+        };
+        state.init_genesis(&config).unwrap();
+        state.credit_token(&users[2].public, &NATIVE_TOKEN_ID, 5);
+        let mut block = block_at(1, &users[1], vec![]);
+        block.header.state_root = state.compute_state_root().unwrap();
+        state.apply_block(block, vec![]).unwrap();
+        state.persist_state().unwrap();
+
+        let mut chunks = Vec::new();
+        let manifest = state
+            .create_snapshot(256, |hash, bytes| {
+                assert_eq!(hash_blake3(&bytes), *hash);
+                chunks.push(bytes);
+                Ok(())
+            })
+            .unwrap();
+        assert!(chunks.len() > 1);
+        assert_eq!(manifest.height, 1);
+
+        let mut restored = create_test_state();
+        restored.restore_snapshot(&manifest, chunks.clone()).unwrap();
+        assert_eq!(restored.height, 1);
+        assert_eq!(restored.chain_id, 1);
+        assert_eq!(restored.validators, vec![users[1].public]);
+        assert!(restored.trusted_issuers.contains(&users[0].public));
+        assert_eq!(restored.get_balance(&users[2].public), 1_005);
+        assert_eq!(
+            restored.compute_state_root().unwrap(),
+            state.compute_state_root().unwrap()
+        );
+        assert!(restored.get_block(1).unwrap().is_some());
+        assert!(restored.get_block(0).unwrap().is_none());
+        assert_eq!(restored.pruned_below(), 1);
+        // Only empty storage can be restored into
+        assert!(restored.restore_snapshot(&manifest, chunks.clone()).is_err());
+
+        // A tampered chunk, a missing chunk or a wrong root writes nothing
+        let mut tampered = chunks.clone();
+        *tampered[0].last_mut().unwrap() ^= 1;
+        let mut missing = chunks.clone();
+        missing.pop();
+        let mut wrong_root = manifest.clone();
+        wrong_root.state_root = Hash::ZERO;
+        for (manifest, chunks) in [
+            (&manifest, tampered),
+            (&manifest, missing),
+            (&wrong_root, chunks),
+        ] {
+            let mut restored = create_test_state();
+            assert!(matches!(
+                restored.restore_snapshot(manifest, chunks),
+                Err(StateError::InvalidSnapshot(_))
+            ));
+            assert!(restored.storage.all_data().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_snapshot_carries_validator_set_handoffs() {
+        let mut state = create_test_state();
+        let validators: Vec<_> = (0..2).map(|_| KeyPair::generate()).collect();
+        let config = GenesisConfig {
+            chain_id: 1,
+            timestamp: 0,
+            initial_balances: vec![],
+            trusted_issuers: vec![],
+            validators: validators.iter().map(|v| v.public).collect(),
+            validator_stake: 0,
+        };
+        state.init_genesis(&config).unwrap();
+        let genesis_set = state.validator_set();
+
+        // Block 1 removes a validator, so block 2 is certified by the rest
+        for height in 1..=2 {
+            if height == 1 {
+                state.remove_validator(&validators[1].public);
+            }
+            state.end_block(height);
+            let mut block = block_at(height, &validators[0], vec![]);
+            block.header.state_root = state.compute_state_root().unwrap();
+            block.qc = Some(testing::commit_qc(&block.header, &validators[..3 - height as usize]));
+            state.apply_block(block, vec![]).unwrap();
+            state.persist_state().unwrap();
+        }
+        assert_eq!(state.handoffs().unwrap().len(), 1);
+
+        let mut chunks = Vec::new();
+        let manifest = state
+            .create_snapshot(1024, |_, bytes| {
+                chunks.push(bytes);
+                Ok(())
+            })
+            .unwrap();
+        manifest.verify(1, &genesis_set).unwrap();
+        let mut stripped = manifest.clone();
+        stripped.handoffs.clear();
+        assert!(stripped.verify(1, &genesis_set).is_err());
+
+        let mut restored = create_test_state();
+        restored.restore_snapshot(&manifest, chunks).unwrap();
+        assert_eq!(restored.handoffs().unwrap().len(), 1);
+
+        state.revert_blocks(1).unwrap();
+        assert_eq!(state.handoffs().unwrap().len(), 1);
+        state.revert_blocks(1).unwrap();
+        assert!(state.handoffs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_with_forged_validators_is_rejected() {
+        let mut state = create_test_state();
+        let validator = KeyPair::generate();
+        let config = GenesisConfig {
+            chain_id: 1,
+            timestamp: 0,
+            initial_balances: vec![(validator.public, 1_000)],
+            trusted_issuers: vec![],
+            validators: vec![validator.public],
+            validator_stake: 0,
+        };
+        state.init_genesis(&config).unwrap();
+        let mut block = block_at(1, &validator, vec![]);
+        block.header.state_root = state.compute_state_root().unwrap();
+        state.apply_block(block, vec![]).unwrap();
+        state.persist_state().unwrap();
+
+        let mut chunks = Vec::new();
+        let mut manifest = state
+            .create_snapshot(1 << 20, |_, bytes| {
+                chunks.push(bytes);
+                Ok(())
+            })
+            .unwrap();
+
+        // Swap in another validator set, with chunk hashes to match
+        let mut chunk: SnapshotChunk = serialize::from_bytes(&chunks[0]).unwrap();
+        let entry = chunk
+            .entries
+            .iter_mut()
+            .find(|(key, _)| key == keys::VALIDATORS)
+            .unwrap();
+        entry.1 = encode(&vec![KeyPair::generate().public]).unwrap();
+        let (bytes, hash) = chunk.encode().unwrap();
+        chunks[0] = bytes;
+        manifest.chunk_hashes[0] = hash;
+
+        let mut restored = create_test_state();
+        assert!(matches!(
+            restored.restore_snapshot(&manifest, chunks),
+            Err(StateError::InvalidSnapshot(_))
+        ));
+        assert!(restored.storage.all_data().is_empty());
+    }
+
+    #[test]
+    fn test_pruned_mode_keeps_headers_of_old_blocks() {
+        let mut state = create_test_state();
+        let alice = KeyPair::generate();
+        state.set_pruning_mode(PruningMode::Pruned { keep_blocks: 2 });
+
+        let mut tx_hashes = Vec::new();
+        for height in 1..=3 {
+            let tx = Transaction::new_signed(
+                1,
+                alice.public,
+                height,
+                10,
+                vec![seloria_core::Op::Transfer {
+                    to: alice.public,
+                    amount: 1,
+                }],
+                &alice.secret,
+            )
+            .unwrap();
+            let tx_hash = tx.hash().unwrap();
+            let receipt = TxReceipt {
+                tx_hash,
+                success: true,
+                error_code: None,
+                error: None,
+                fee_used: 10,
+                events: vec![],
+            };
+            state.apply_block(block_at(height, &alice, vec![tx]), vec![receipt]).unwrap();
+            state.persist_state().unwrap();
+            tx_hashes.push(tx_hash);
+        }
+
+        assert_eq!(state.pruned_below(), 2);
+        assert!(matches!(
+            state.get_block(1),
+            Err(StateError::Pruned { height: 1, pruned_below: 2 })
+        ));
+        assert_eq!(state.get_block_header(1).unwrap().header.height, 1);
+        assert!(state.get_transaction(&tx_hashes[0]).is_none());
+        assert!(state.get_receipt(&tx_hashes[0]).is_none());
+
+        assert!(state.get_block(2).unwrap().is_some());
+        assert!(state.get_transaction(&tx_hashes[1]).is_some());
+        assert!(state.get_block(4).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_overlay_diff_is_applied_or_discarded() {
+        let mut state = create_test_state();
+        let alice = KeyPair::generate();
+        let bob = KeyPair::generate();
+        state.credit_token(&alice.public, &NATIVE_TOKEN_ID, 1000);
+        state.persist_state().unwrap();
+        let root = state.compute_state_root().unwrap();
+
+        // Dropped overlays leave the base untouched
+        let mut overlay = state.overlay();
+        overlay.transfer(&alice.public, &bob.public, 300).unwrap();
+        assert_eq!(overlay.get_balance(&bob.public), 300);
+        drop(overlay);
+        assert_eq!(state.get_balance(&bob.public), 0);
+        assert_eq!(state.compute_state_root().unwrap(), root);
+
+        let mut overlay = state.overlay();
+        overlay.transfer(&alice.public, &bob.public, 300).unwrap();
+        let overlay_root = overlay.compute_state_root().unwrap();
+        let diff = overlay.into_diff();
+        state.apply_diff(diff);
+        assert_eq!(state.get_balance(&alice.public), 700);
+        assert_eq!(state.get_balance(&bob.public), 300);
+        assert_eq!(state.compute_state_root().unwrap(), overlay_root);
+
+        state.persist_state().unwrap();
+        state.load_from_storage().unwrap();
+        assert_eq!(state.get_balance(&bob.public), 300);
+    }
+
+    #[test]
+    fn test_account_proofs_against_committed_root() {
+        let mut state = create_test_state();
+        let alice = KeyPair::generate();
+        let bob = KeyPair::generate();
+        state.credit_token(&alice.public, &NATIVE_TOKEN_ID, 1000);
+        state.persist_state().unwrap();
+
+        let root = state.committed_state_root();
+        assert_eq!(root, state.compute_state_root().unwrap());
+
+        let key = StateKey::Account(alice.public);
+        let (value, proof) = state.prove(&key).unwrap();
+        let account: Account = serialize::from_bytes(value.as_ref().unwrap()).unwrap();
+        assert_eq!(account.native_balance(), 1000);
+        assert!(proof.verify(&root, &key.storage_key(), value.as_deref()));
+
+        let key = StateKey::Account(bob.public);
+        let (value, proof) = state.prove(&key).unwrap();
+        assert!(value.is_none());
+        assert!(proof.verify(&root, &key.storage_key(), None));
+
+        // The incremental root matches a rebuild from scratch
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = keys::STATE_ROOT
+            .iter()
+            .flat_map(|prefix| state.storage.keys_with_prefix(prefix))
+            .map(|k| {
+                let v = state.storage.get(&k).unwrap();
+                (k, v)
+            })
+            .collect();
+        let rebuilt = crate::merkle::compute_state_root(
+            entries.iter().map(|(k, v)| (k.as_slice(), v.as_slice())),
+        );
+        assert_eq!(rebuilt, root);
+    }
+
     #[test]
     fn test_transfer() {
         let mut state = create_test_state();
@@ -1021,6 +2444,230 @@ mod tests {
         assert_eq!(state.get_balance(&user.public), 1000);
     }
 
+    #[test]
+    fn test_revert_to_checkpoint() {
+        let mut state = create_test_state();
+        let alice = KeyPair::generate();
+        let bob = KeyPair::generate();
+        let ns_id = hash_blake3(b"ns");
+
+        state.credit_token(&alice.public, &NATIVE_TOKEN_ID, 1000);
+        let root_before = state.compute_state_root().unwrap();
+
+        let checkpoint = state.checkpoint();
+        state.transfer(&alice.public, &bob.public, 400).unwrap();
+        state.kv_put(ns_id, "key".to_string(), KvValue::inline("raw", b"value".to_vec()));
+        state.revert_to_checkpoint(checkpoint);
+
+        assert_eq!(state.get_balance(&alice.public), 1000);
+        assert!(state.get_account(&bob.public).is_none());
+        assert!(state.kv_get(&ns_id, "key").is_none());
+        assert_eq!(state.compute_state_root().unwrap(), root_before);
+    }
+
+    #[test]
+    fn test_nested_checkpoints() {
+        let mut state = create_test_state();
+        let alice = KeyPair::generate();
+        let bob = KeyPair::generate();
+
+        state.credit_token(&alice.public, &NATIVE_TOKEN_ID, 1000);
+
+        let outer = state.checkpoint();
+        state.transfer(&alice.public, &bob.public, 100).unwrap();
+
+        let inner = state.checkpoint();
+        state.transfer(&alice.public, &bob.public, 200).unwrap();
+        state.revert_to_checkpoint(inner);
+        assert_eq!(state.get_balance(&bob.public), 100);
+
+        let inner = state.checkpoint();
+        state.transfer(&alice.public, &bob.public, 300).unwrap();
+        state.commit_checkpoint(inner);
+        assert_eq!(state.get_balance(&bob.public), 400);
+
+        state.revert_to_checkpoint(outer);
+        assert_eq!(state.get_balance(&alice.public), 1000);
+        assert_eq!(state.get_balance(&bob.public), 0);
+    }
+
+    #[test]
+    fn test_remove_validator_reverts() {
+        let mut state = create_test_state();
+        let v1 = KeyPair::generate();
+        let v2 = KeyPair::generate();
+        state.validators = vec![v1.public, v2.public];
+
+        let checkpoint = state.checkpoint();
+        assert!(state.remove_validator(&v1.public));
+        assert!(!state.remove_validator(&v1.public));
+        assert_eq!(state.validators, vec![v2.public]);
+        state.revert_to_checkpoint(checkpoint);
+
+        assert_eq!(state.validators, vec![v1.public, v2.public]);
+    }
+
+    #[test]
+    fn test_bonded_candidates_join_at_epoch_end() {
+        let mut state = create_test_state();
+        let genesis = KeyPair::generate();
+        let large = KeyPair::generate();
+        let small = KeyPair::generate();
+        state.validators = vec![genesis.public];
+        for (candidate, bond) in [
+            (&large, 3 * MIN_VALIDATOR_BOND),
+            (&genesis, 2 * MIN_VALIDATOR_BOND),
+            (&small, MIN_VALIDATOR_BOND),
+        ] {
+            state.credit_token(&candidate.public, &NATIVE_TOKEN_ID, bond);
+            assert_eq!(state.bond_validator(&candidate.public, bond).unwrap(), bond);
+        }
+
+        // The set only changes at the end of an epoch
+        state.end_block(EPOCH_LENGTH_BLOCKS - 1);
+        assert_eq!(state.validators, vec![genesis.public]);
+        state.end_block(EPOCH_LENGTH_BLOCKS);
+        let set = state.validator_set();
+        assert_eq!(set.pubkeys(), vec![large.public, genesis.public, small.public]);
+        assert_eq!(set.power_of(&large.public), 3 * MIN_VALIDATOR_BOND);
+
+        // Unbonded stake stays locked until its release height
+        let release = EPOCH_LENGTH_BLOCKS + 10;
+        let remaining = state
+            .unbond_validator(&small.public, MIN_VALIDATOR_BOND, release)
+            .unwrap();
+        assert_eq!(remaining, 0);
+        state.end_block(release - 1);
+        assert_eq!(state.get_balance(&small.public), 0);
+        state.end_block(release);
+        assert_eq!(state.get_balance(&small.public), MIN_VALIDATOR_BOND);
+
+        state.end_block(2 * EPOCH_LENGTH_BLOCKS);
+        assert_eq!(state.validators, vec![large.public, genesis.public]);
+    }
+
+    #[test]
+    fn test_outside_bond_does_not_replace_unbonded_genesis_set() {
+        let mut state = create_test_state();
+        let validators: Vec<_> = (0..4).map(|_| KeyPair::generate().public).collect();
+        let config = GenesisConfig {
+            chain_id: 1,
+            timestamp: 0,
+            initial_balances: vec![],
+            trusted_issuers: vec![],
+            validators: validators.clone(),
+            validator_stake: 0,
+        };
+        state.init_genesis(&config).unwrap();
+        let outsider = KeyPair::generate();
+        state.credit_token(&outsider.public, &NATIVE_TOKEN_ID, MIN_VALIDATOR_BOND);
+        state.bond_validator(&outsider.public, MIN_VALIDATOR_BOND).unwrap();
+
+        state.end_block(EPOCH_LENGTH_BLOCKS);
+        assert_eq!(state.validators, validators);
+        assert_eq!(state.validator_set(), ValidatorSet::equal(&validators));
+
+        // Once the genesis validators bond, the outsider joins them
+        for validator in &validators {
+            state.credit_token(validator, &NATIVE_TOKEN_ID, MIN_VALIDATOR_BOND);
+            state.bond_validator(validator, MIN_VALIDATOR_BOND).unwrap();
+        }
+        state.end_block(2 * EPOCH_LENGTH_BLOCKS);
+        assert_eq!(state.validators.len(), 5);
+        assert!(state.validators.contains(&outsider.public));
+    }
+
+    #[test]
+    fn test_epoch_history_keeps_past_sets() {
+        let mut state = create_test_state();
+        let genesis = KeyPair::generate();
+        let config = GenesisConfig {
+            chain_id: 1,
+            timestamp: 0,
+            initial_balances: vec![],
+            trusted_issuers: vec![],
+            validators: vec![genesis.public],
+            validator_stake: 0,
+        };
+        state.init_genesis(&config).unwrap();
+        let first = state.validator_set();
+
+        // A set scheduled for epoch 1 takes over after its last block
+        let next = ValidatorSet::equal(&[KeyPair::generate().public, KeyPair::generate().public]);
+        state.schedule_validator_set(1, next.clone());
+        state.end_block(EPOCH_LENGTH_BLOCKS - 1);
+        assert_eq!(state.validator_set(), first);
+        state.end_block(EPOCH_LENGTH_BLOCKS);
+        assert_eq!(state.validator_set(), next);
+        assert!(state.epochs.scheduled.is_none());
+
+        assert_eq!(state.validator_set_at(1), first);
+        assert_eq!(state.validator_set_at(EPOCH_LENGTH_BLOCKS), first);
+        assert_eq!(state.validator_set_at(EPOCH_LENGTH_BLOCKS + 1), next);
+        assert_eq!(state.epoch_at(EPOCH_LENGTH_BLOCKS + 1).unwrap().number, 1);
+
+        // Removing a member starts a new entry from the next block
+        let removed = next.validators[0].pubkey;
+        assert!(state.remove_validator(&removed));
+        state.end_block(EPOCH_LENGTH_BLOCKS + 5);
+        assert_eq!(state.validator_set_at(EPOCH_LENGTH_BLOCKS + 5), next);
+        assert!(!state.validator_set_at(EPOCH_LENGTH_BLOCKS + 6).contains(&removed));
+        assert_eq!(state.epochs.epochs.len(), 3);
+
+        // The history survives a reload
+        state.persist_state().unwrap();
+        state.load_from_storage().unwrap();
+        assert_eq!(state.validator_set_at(EPOCH_LENGTH_BLOCKS), first);
+        assert_eq!(state.validator_set_at(EPOCH_LENGTH_BLOCKS + 1), next);
+    }
+
+    #[test]
+    fn test_receipts_persisted() {
+        let mut state = create_test_state();
+        let sender = KeyPair::generate();
+        let proposer = KeyPair::generate();
+
+        let tx = seloria_core::Transaction::new_signed(
+            1,
+            sender.public,
+            1,
+            10,
+            vec![seloria_core::Op::Transfer {
+                to: proposer.public,
+                amount: 1,
+            }],
+            &sender.secret,
+        )
+        .unwrap();
+        let tx_hash = tx.hash().unwrap();
+        let receipt = TxReceipt {
+            tx_hash,
+            success: false,
+            error_code: Some("insufficient_balance".to_string()),
+            error: None,
+            fee_used: 10,
+            events: vec![],
+        };
+        let header = seloria_core::BlockHeader {
+            proposer_pubkey: proposer.public,
+            ..testing::header(1, 1)
+        };
+        let block = Block::new(header, vec![tx]);
+
+        assert!(matches!(
+            state.apply_block(block.clone(), vec![]),
+            Err(StateError::ReceiptCountMismatch { .. })
+        ));
+        state.apply_block(block, vec![receipt]).unwrap();
+        state.persist_state().unwrap();
+        state.load_from_storage().unwrap();
+
+        let record = state.get_receipt(&tx_hash).unwrap();
+        assert_eq!(record.block_height, 1);
+        assert_eq!(record.index, 0);
+        assert_eq!(record.receipt.fee_used, 10);
+    }
+
     #[test]
     fn test_state_root_deterministic() {
         let mut state = create_test_state();
@@ -0,0 +1,53 @@
+use std::collections::BTreeMap;
+
+use super::Storage;
+
+/// Set of writes applied to storage in one atomic commit.
+///
+/// Later writes to a key replace earlier ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteBatch {
+    ops: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Put a key-value pair
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.ops.insert(key.to_vec(), Some(value.to_vec()));
+    }
+
+    /// Delete a key
+    pub fn delete(&mut self, key: &[u8]) {
+        self.ops.insert(key.to_vec(), None);
+    }
+
+    /// Number of keys written
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Writes in key order (`None` deletes the key)
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], Option<&[u8]>)> {
+        self.ops
+            .iter()
+            .map(|(key, value)| (key.as_slice(), value.as_deref()))
+    }
+
+    /// Stage the writes as pending writes of `storage`
+    pub(crate) fn stage<S: Storage + ?Sized>(self, storage: &mut S) {
+        for (key, value) in self.ops {
+            match value {
+                Some(value) => storage.put(&key, &value),
+                None => storage.delete(&key),
+            }
+        }
+    }
+}
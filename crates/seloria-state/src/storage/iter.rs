@@ -0,0 +1,99 @@
+use std::cmp::Ordering;
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::ops::Bound;
+
+/// Lazy iterator over (key, value) entries in key order
+pub type StorageIter<'a> = Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a>;
+
+/// Pending writes of a storage backend (`None` deletes the key)
+pub(crate) type PendingWrites = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// Iterate `data` with `pending` written over it, in `start..end`
+pub(crate) fn merged_range<'a>(
+    data: &'a BTreeMap<Vec<u8>, Vec<u8>>,
+    pending: &'a PendingWrites,
+    start: Bound<&[u8]>,
+    end: Bound<&[u8]>,
+) -> StorageIter<'a> {
+    if !is_valid_range(start, end) {
+        return Box::new(std::iter::empty());
+    }
+    let base = data
+        .range::<[u8], _>((start, end))
+        .map(|(key, value)| (key.clone(), value.clone()));
+    Box::new(MergeIter::new(base, pending, start, end))
+}
+
+/// Whether `start..end` can be passed to `BTreeMap::range` without panicking
+pub(crate) fn is_valid_range(start: Bound<&[u8]>, end: Bound<&[u8]>) -> bool {
+    match (start, end) {
+        (Bound::Included(start), Bound::Included(end)) => start <= end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Included(end) | Bound::Excluded(end)) => {
+            start < end
+        }
+        _ => true,
+    }
+}
+
+/// Smallest key above every key starting with `prefix`, or `None` if no
+/// such key exists (the prefix is empty or all `0xff`)
+pub(crate) fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+/// Entries of a base iterator with pending writes applied over them
+pub(crate) struct MergeIter<'a, B: Iterator<Item = (Vec<u8>, Vec<u8>)>> {
+    base: Peekable<B>,
+    pending: Peekable<btree_map::Range<'a, Vec<u8>, Option<Vec<u8>>>>,
+}
+
+impl<'a, B: Iterator<Item = (Vec<u8>, Vec<u8>)>> MergeIter<'a, B> {
+    /// `base` must already be limited to `start..end`, which must be a
+    /// valid range
+    pub(crate) fn new(
+        base: B,
+        pending: &'a PendingWrites,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Self {
+        MergeIter {
+            base: base.peekable(),
+            pending: pending.range::<[u8], _>((start, end)).peekable(),
+        }
+    }
+}
+
+impl<B: Iterator<Item = (Vec<u8>, Vec<u8>)>> Iterator for MergeIter<'_, B> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.base.peek(), self.pending.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((base_key, _)), Some((pending_key, _))) => base_key.cmp(pending_key),
+            };
+            if order == Ordering::Less {
+                return self.base.next();
+            }
+            if order == Ordering::Equal {
+                // Overwritten or deleted by a pending write
+                self.base.next();
+            }
+            let (key, value) = self.pending.next()?;
+            if let Some(value) = value {
+                return Some((key.clone(), value.clone()));
+            }
+        }
+    }
+}
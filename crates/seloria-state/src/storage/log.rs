@@ -0,0 +1,521 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use seloria_core::serialize;
+use tracing::{debug, info, warn};
+
+use super::iter::merged_range;
+use super::persistent::{replay_log, FileStorage};
+use super::{Storage, StorageIter};
+use crate::error::StateError;
+
+/// A committed batch of writes (`None` deletes the key)
+type Batch = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// Record header: payload length (u32 LE) followed by a payload checksum
+const RECORD_HEADER_LEN: usize = 4 + CHECKSUM_LEN;
+const CHECKSUM_LEN: usize = 8;
+
+/// Configuration for the log-structured storage
+#[derive(Debug, Clone)]
+pub struct LogStorageConfig {
+    /// Roll the write-ahead log into a segment file once it reaches this size
+    pub wal_segment_bytes: u64,
+    /// Merge all segments into the base snapshot once this many exist
+    pub max_segments: usize,
+}
+
+impl Default for LogStorageConfig {
+    fn default() -> Self {
+        LogStorageConfig {
+            wal_segment_bytes: 16 * 1024 * 1024, // 16 MiB
+            max_segments: 8,
+        }
+    }
+}
+
+/// Crash-safe storage built from an append-only write-ahead log.
+///
+/// Files, all next to the base snapshot at `path`:
+/// - `path` — base snapshot, same format as [`FileStorage`]
+/// - `path.NNNNNNNN.seg` — immutable segments of changes, applied in order
+/// - `path.wal` — checksummed batches committed since the last segment
+///
+/// Every `commit()` appends one batch to the WAL and fsyncs it. Segments and
+/// the base snapshot are replaced atomically (write, fsync, rename), so after
+/// a crash the state recovers to the last batch whose record is complete and
+/// whose checksum matches.
+#[derive(Debug, Clone)]
+pub struct LogStorage {
+    path: PathBuf,
+    config: LogStorageConfig,
+    data: BTreeMap<Vec<u8>, Vec<u8>>,
+    pending_writes: Batch,
+    /// Changes held in the WAL, merged per key
+    wal_batch: Batch,
+    /// Size of the WAL on disk
+    wal_len: u64,
+    /// Sequence numbers of the segment files, ascending
+    segments: Vec<u64>,
+}
+
+impl LogStorage {
+    /// Open (or create) log storage with the default configuration
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, StateError> {
+        Self::open_with_config(path, LogStorageConfig::default())
+    }
+
+    /// Open (or create) log storage, recovering from the files on disk
+    pub fn open_with_config<P: Into<PathBuf>>(
+        path: P,
+        config: LogStorageConfig,
+    ) -> Result<Self, StateError> {
+        let path = path.into();
+        let mut data: BTreeMap<Vec<u8>, Vec<u8>> = if path.exists() {
+            let bytes = fs::read(&path).map_err(storage_err)?;
+            if bytes.is_empty() {
+                BTreeMap::new()
+            } else {
+                serialize::from_bytes(&bytes)
+                    .map_err(|e| StateError::Serialization(e.to_string()))?
+            }
+        } else {
+            BTreeMap::new()
+        };
+
+        let segments = list_segments(&path)?;
+        for seq in &segments {
+            let segment_path = segment_path(&path, *seq);
+            let bytes = fs::read(&segment_path).map_err(storage_err)?;
+            let batch = match decode_record(&bytes) {
+                Some((batch, len)) if len == bytes.len() => batch,
+                _ => {
+                    return Err(StateError::Storage(format!(
+                        "Corrupt segment {}",
+                        segment_path.display()
+                    )))
+                }
+            };
+            apply_batch(&mut data, batch);
+        }
+
+        let (wal_batch, wal_len) = recover_wal(&wal_path(&path))?;
+        apply_batch(&mut data, wal_batch.clone());
+
+        let mut storage = LogStorage {
+            path,
+            config,
+            data,
+            pending_writes: BTreeMap::new(),
+            wal_batch,
+            wal_len,
+            segments,
+        };
+
+        // Absorb a delta log left by `FileStorage`
+        let legacy_log = FileStorage::log_path_for(&storage.path);
+        if legacy_log.exists() {
+            info!("Migrating {} into log storage", legacy_log.display());
+            replay_log(&legacy_log, &mut storage.data)?;
+            storage.write_base()?;
+        }
+
+        debug!(
+            "Opened log storage at {} ({} segments, {} WAL bytes)",
+            storage.path.display(),
+            storage.segments.len(),
+            storage.wal_len
+        );
+        Ok(storage)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Remove the WAL and segment files that belong to the base snapshot at
+    /// `path`, e.g. before replacing the snapshot with a downloaded one
+    pub fn discard_logs(path: &Path) -> Result<(), StateError> {
+        for seq in list_segments(path)? {
+            fs::remove_file(segment_path(path, seq)).map_err(storage_err)?;
+        }
+        for log in [wal_path(path), FileStorage::log_path_for(path)] {
+            if log.exists() {
+                fs::remove_file(&log).map_err(storage_err)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Append a batch to the WAL and fsync it
+    fn append_to_wal(&mut self, batch: &Batch) -> Result<(), StateError> {
+        let record = encode_record(batch)?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(storage_err)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(wal_path(&self.path))
+            .map_err(storage_err)?;
+        file.write_all(&record).map_err(storage_err)?;
+        file.sync_data().map_err(storage_err)?;
+        self.wal_len += record.len() as u64;
+        Ok(())
+    }
+
+    /// Move the WAL contents into a new segment file and start a fresh WAL
+    fn roll_wal(&mut self) -> Result<(), StateError> {
+        if self.wal_batch.is_empty() {
+            return Ok(());
+        }
+        let seq = self.segments.last().map_or(1, |last| last + 1);
+        let record = encode_record(&self.wal_batch)?;
+        write_atomic(&segment_path(&self.path, seq), &record)?;
+        self.segments.push(seq);
+
+        remove_synced(&wal_path(&self.path))?;
+        self.wal_batch.clear();
+        self.wal_len = 0;
+        debug!("Rolled WAL into segment {}", seq);
+
+        if self.segments.len() >= self.config.max_segments {
+            self.write_base()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the base snapshot from the full key space and drop every
+    /// segment and log it now contains
+    fn write_base(&mut self) -> Result<(), StateError> {
+        let bytes =
+            serialize::to_bytes(&self.data).map_err(|e| StateError::Serialization(e.to_string()))?;
+        write_atomic(&self.path, &bytes)?;
+
+        for seq in std::mem::take(&mut self.segments) {
+            remove_synced(&segment_path(&self.path, seq))?;
+        }
+        remove_synced(&wal_path(&self.path))?;
+        remove_synced(&FileStorage::log_path_for(&self.path))?;
+        self.wal_batch.clear();
+        self.wal_len = 0;
+        info!("Compacted log storage into {}", self.path.display());
+        Ok(())
+    }
+}
+
+impl Storage for LogStorage {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(pending) = self.pending_writes.get(key) {
+            return pending.clone();
+        }
+        self.data.get(key).cloned()
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.pending_writes
+            .insert(key.to_vec(), Some(value.to_vec()));
+    }
+
+    fn delete(&mut self, key: &[u8]) {
+        self.pending_writes.insert(key.to_vec(), None);
+    }
+
+    fn commit(&mut self) -> Result<(), StateError> {
+        let pending = std::mem::take(&mut self.pending_writes);
+        if pending.is_empty() {
+            return Ok(());
+        }
+        self.append_to_wal(&pending)?;
+        for (key, value) in &pending {
+            self.wal_batch.insert(key.clone(), value.clone());
+        }
+        apply_batch(&mut self.data, pending);
+
+        if self.wal_len >= self.config.wal_segment_bytes {
+            self.roll_wal()?;
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self) {
+        self.pending_writes.clear();
+    }
+
+    fn compact(&mut self) -> Result<(), StateError> {
+        if !self.wal_batch.is_empty() || !self.segments.is_empty() || !self.path.exists() {
+            self.write_base()?;
+        }
+        Ok(())
+    }
+
+    fn iter_range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> StorageIter<'_> {
+        merged_range(&self.data, &self.pending_writes, start, end)
+    }
+}
+
+pub(crate) fn storage_err(e: std::io::Error) -> StateError {
+    StateError::Storage(e.to_string())
+}
+
+fn wal_path(path: &Path) -> PathBuf {
+    let mut wal = path.as_os_str().to_owned();
+    wal.push(".wal");
+    PathBuf::from(wal)
+}
+
+fn segment_path(path: &Path, seq: u64) -> PathBuf {
+    let mut segment = path.as_os_str().to_owned();
+    segment.push(format!(".{:08}.seg", seq));
+    PathBuf::from(segment)
+}
+
+/// Sequence numbers of the segment files next to `path`, ascending
+fn list_segments(path: &Path) -> Result<Vec<u64>, StateError> {
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Ok(Vec::new());
+    };
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    if !parent.exists() {
+        return Ok(Vec::new());
+    }
+
+    let prefix = format!("{}.", name.to_string_lossy());
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(parent).map_err(storage_err)? {
+        let file_name = entry.map_err(storage_err)?.file_name();
+        let file_name = file_name.to_string_lossy();
+        if let Some(seq) = file_name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(".seg"))
+            .and_then(|seq| seq.parse::<u64>().ok())
+        {
+            segments.push(seq);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let mut out = [0u8; CHECKSUM_LEN];
+    out.copy_from_slice(&blake3::hash(payload).as_bytes()[..CHECKSUM_LEN]);
+    out
+}
+
+/// Frame a payload as `len | checksum | payload`
+pub(crate) fn frame_record(payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&checksum(payload));
+    record.extend_from_slice(payload);
+    record
+}
+
+/// Payload of the record at the start of `bytes` and the record length, or
+/// `None` if the record is incomplete or fails its checksum
+pub(crate) fn read_record(bytes: &[u8]) -> Option<(&[u8], usize)> {
+    if bytes.len() < RECORD_HEADER_LEN {
+        return None;
+    }
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&bytes[..4]);
+    let end = RECORD_HEADER_LEN.checked_add(u32::from_le_bytes(len) as usize)?;
+    if end > bytes.len() {
+        return None;
+    }
+    let payload = &bytes[RECORD_HEADER_LEN..end];
+    if checksum(payload) != bytes[4..RECORD_HEADER_LEN] {
+        return None;
+    }
+    Some((payload, end))
+}
+
+/// Frame a batch as a record
+fn encode_record(batch: &Batch) -> Result<Vec<u8>, StateError> {
+    let payload =
+        serialize::to_bytes(batch).map_err(|e| StateError::Serialization(e.to_string()))?;
+    Ok(frame_record(&payload))
+}
+
+/// Decode the batch record at the start of `bytes`, returning the batch and
+/// the record length
+fn decode_record(bytes: &[u8]) -> Option<(Batch, usize)> {
+    let (payload, len) = read_record(bytes)?;
+    let batch = serialize::from_bytes(payload).ok()?;
+    Some((batch, len))
+}
+
+/// Read every valid batch from the WAL, merged per key, and truncate
+/// anything after the last one (a torn or corrupt write)
+fn recover_wal(path: &Path) -> Result<(Batch, u64), StateError> {
+    if !path.exists() {
+        return Ok((BTreeMap::new(), 0));
+    }
+    let bytes = fs::read(path).map_err(storage_err)?;
+
+    let mut merged = BTreeMap::new();
+    let mut offset = 0usize;
+    while let Some((batch, len)) = decode_record(&bytes[offset..]) {
+        merged.extend(batch);
+        offset += len;
+    }
+
+    if offset < bytes.len() {
+        warn!(
+            "Discarding {} bytes after the last complete batch in {}",
+            bytes.len() - offset,
+            path.display()
+        );
+        let file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .map_err(storage_err)?;
+        file.set_len(offset as u64).map_err(storage_err)?;
+        file.sync_all().map_err(storage_err)?;
+    }
+
+    Ok((merged, offset as u64))
+}
+
+fn apply_batch(data: &mut BTreeMap<Vec<u8>, Vec<u8>>, batch: Batch) {
+    for (key, value) in batch {
+        match value {
+            Some(v) => {
+                data.insert(key, v);
+            }
+            None => {
+                data.remove(&key);
+            }
+        }
+    }
+}
+
+/// Durably replace `path` with `bytes` (write temp file, fsync, rename)
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), StateError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(storage_err)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let mut file = File::create(&tmp).map_err(storage_err)?;
+    file.write_all(bytes).map_err(storage_err)?;
+    file.sync_all().map_err(storage_err)?;
+    fs::rename(&tmp, path).map_err(storage_err)?;
+    sync_parent(path);
+    Ok(())
+}
+
+/// Remove a file if present and make the removal durable
+pub(crate) fn remove_synced(path: &Path) -> Result<(), StateError> {
+    if path.exists() {
+        fs::remove_file(path).map_err(storage_err)?;
+        sync_parent(path);
+    }
+    Ok(())
+}
+
+/// Fsync the directory holding `path` so renames and removals persist
+/// (best effort: not every platform can open a directory)
+fn sync_parent(path: &Path) {
+    if let Some(parent) = path.parent() {
+        if let Ok(dir) = File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commits_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.bin");
+
+        let mut storage = LogStorage::open(&path).unwrap();
+        storage.put(b"a", b"1");
+        storage.put(b"b", b"2");
+        storage.commit().unwrap();
+        storage.delete(b"a");
+        storage.put(b"c", b"3");
+        storage.commit().unwrap();
+        storage.put(b"d", b"uncommitted");
+
+        let reopened = LogStorage::open(&path).unwrap();
+        assert_eq!(reopened.get(b"a"), None);
+        assert_eq!(reopened.get(b"b"), Some(b"2".to_vec()));
+        assert_eq!(reopened.get(b"c"), Some(b"3".to_vec()));
+        assert_eq!(reopened.get(b"d"), None);
+    }
+
+    #[test]
+    fn test_recovers_to_last_complete_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.bin");
+
+        let mut storage = LogStorage::open(&path).unwrap();
+        storage.put(b"a", b"1");
+        storage.commit().unwrap();
+        storage.put(b"b", b"2");
+        storage.commit().unwrap();
+        let wal = wal_path(&path);
+        let good_len = fs::metadata(&wal).unwrap().len();
+
+        // Corrupt one payload byte of the last batch, then tear a third one
+        let mut bytes = fs::read(&wal).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        bytes.extend_from_slice(&[9, 0, 0, 0, 1]);
+        fs::write(&wal, &bytes).unwrap();
+
+        let reopened = LogStorage::open(&path).unwrap();
+        assert_eq!(reopened.get(b"a"), Some(b"1".to_vec()));
+        assert_eq!(reopened.get(b"b"), None);
+        assert!(fs::metadata(&wal).unwrap().len() < good_len);
+    }
+
+    #[test]
+    fn test_segments_and_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.bin");
+        let config = LogStorageConfig {
+            wal_segment_bytes: 1,
+            max_segments: 3,
+        };
+
+        let mut storage = LogStorage::open_with_config(&path, config.clone()).unwrap();
+        storage.put(b"a", b"1");
+        storage.commit().unwrap();
+        storage.put(b"b", b"2");
+        storage.commit().unwrap();
+        assert_eq!(list_segments(&path).unwrap(), vec![1, 2]);
+        assert!(!wal_path(&path).exists());
+
+        let reopened = LogStorage::open_with_config(&path, config.clone()).unwrap();
+        assert_eq!(reopened.get(b"b"), Some(b"2".to_vec()));
+
+        // The third segment triggers a merge into the base snapshot
+        storage.delete(b"a");
+        storage.commit().unwrap();
+        assert!(list_segments(&path).unwrap().is_empty());
+
+        let reopened = LogStorage::open_with_config(&path, config).unwrap();
+        assert_eq!(reopened.get(b"a"), None);
+        assert_eq!(reopened.get(b"b"), Some(b"2".to_vec()));
+
+        // The base snapshot stays readable by `FileStorage`
+        let file = FileStorage::new(&path).unwrap();
+        assert_eq!(file.get(b"b"), Some(b"2".to_vec()));
+    }
+}
@@ -1,6 +1,8 @@
 use std::collections::BTreeMap;
+use std::ops::Bound;
 
-use super::Storage;
+use super::iter::merged_range;
+use super::{Storage, StorageIter};
 use crate::error::StateError;
 
 /// In-memory storage implementation using BTreeMap
@@ -73,39 +75,15 @@ impl Storage for MemoryStorage {
         self.pending_writes.clear();
     }
 
-    fn keys_with_prefix(&self, prefix: &[u8]) -> Vec<Vec<u8>> {
-        let mut keys = Vec::new();
-
-        // Get from committed data
-        for key in self.data.keys() {
-            if key.starts_with(prefix) {
-                // Check if deleted in pending
-                if let Some(pending) = self.pending_writes.get(key) {
-                    if pending.is_some() {
-                        keys.push(key.clone());
-                    }
-                } else {
-                    keys.push(key.clone());
-                }
-            }
-        }
-
-        // Add new keys from pending writes
-        for (key, value) in &self.pending_writes {
-            if key.starts_with(prefix) && value.is_some() {
-                if !self.data.contains_key(key) {
-                    keys.push(key.clone());
-                }
-            }
-        }
-
-        keys
+    fn iter_range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> StorageIter<'_> {
+        merged_range(&self.data, &self.pending_writes, start, end)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::WriteBatch;
 
     #[test]
     fn test_basic_operations() {
@@ -164,6 +142,56 @@ mod tests {
         assert!(user_keys.contains(&b"users:2".to_vec()));
     }
 
+    #[test]
+    fn test_iter_prefix_with_cursor() {
+        let mut storage = MemoryStorage::new();
+
+        storage.put(b"users:1", b"alice");
+        storage.put(b"users:2", b"bob");
+        storage.put(b"users:4", b"dave");
+        storage.put(b"usersx", b"other");
+        storage.commit().unwrap();
+        // Pending writes are merged in key order
+        storage.put(b"users:3", b"carol");
+        storage.put(b"users:2", b"bobby");
+        storage.delete(b"users:4");
+
+        let entries: Vec<_> = storage.iter_prefix(b"users:", None).collect();
+        assert_eq!(
+            entries,
+            vec![
+                (b"users:1".to_vec(), b"alice".to_vec()),
+                (b"users:2".to_vec(), b"bobby".to_vec()),
+                (b"users:3".to_vec(), b"carol".to_vec()),
+            ]
+        );
+
+        let keys: Vec<_> = storage
+            .iter_prefix(b"users:", Some(b"users:1"))
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec![b"users:2".to_vec(), b"users:3".to_vec()]);
+        assert_eq!(storage.iter_prefix(b"users:", Some(b"users:9")).count(), 0);
+        assert_eq!(storage.iter_prefix(b"users:", Some(b"zzz")).count(), 0);
+    }
+
+    #[test]
+    fn test_write_batch() {
+        let mut storage = MemoryStorage::new();
+        storage.put(b"a", b"1");
+        storage.commit().unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"b", b"2");
+        batch.delete(b"a");
+        batch.put(b"c", b"3");
+        batch.delete(b"c");
+        storage.write_batch(batch).unwrap();
+
+        let keys: Vec<_> = storage.all_data().keys().cloned().collect();
+        assert_eq!(keys, vec![b"b".to_vec()]);
+    }
+
     #[test]
     fn test_overwrite() {
         let mut storage = MemoryStorage::new();
@@ -1,6 +1,12 @@
+pub mod batch;
+pub mod iter;
+pub mod log;
 pub mod memory;
+pub mod overlay;
 pub mod persistent;
 
+use std::ops::Bound;
+
 use crate::error::StateError;
 
 /// Storage trait for chain state persistence
@@ -20,14 +26,49 @@ pub trait Storage: Send + Sync {
     /// Rollback pending changes
     fn rollback(&mut self);
 
+    /// Fold incrementally written changes into a single compact on-disk
+    /// image (no-op for backends without one)
+    fn compact(&mut self) -> Result<(), StateError> {
+        Ok(())
+    }
+
     /// Check if a key exists
     fn exists(&self, key: &[u8]) -> bool {
         self.get(key).is_some()
     }
 
+    /// Iterate the entries with keys in `start..end` in key order, pending
+    /// writes included. Entries are read as the iterator advances.
+    fn iter_range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> StorageIter<'_>;
+
+    /// Iterate the entries whose keys start with `prefix` in key order,
+    /// beginning after the key `start_after` if given (a page cursor)
+    fn iter_prefix(&self, prefix: &[u8], start_after: Option<&[u8]>) -> StorageIter<'_> {
+        let start = match start_after {
+            Some(after) if after >= prefix => Bound::Excluded(after),
+            _ => Bound::Included(prefix),
+        };
+        let end = iter::prefix_end(prefix);
+        let end = end.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+        self.iter_range(start, end)
+    }
+
+    /// Apply `batch` and any pending writes in a single atomic commit
+    fn write_batch(&mut self, batch: WriteBatch) -> Result<(), StateError> {
+        batch.stage(self);
+        self.commit()
+    }
+
     /// Get all keys with a given prefix
-    fn keys_with_prefix(&self, prefix: &[u8]) -> Vec<Vec<u8>>;
+    fn keys_with_prefix(&self, prefix: &[u8]) -> Vec<Vec<u8>> {
+        self.iter_prefix(prefix, None).map(|(key, _)| key).collect()
+    }
 }
 
+pub use batch::WriteBatch;
+pub use iter::StorageIter;
+
+pub use log::{LogStorage, LogStorageConfig};
 pub use memory::MemoryStorage;
+pub use overlay::OverlayStorage;
 pub use persistent::FileStorage;
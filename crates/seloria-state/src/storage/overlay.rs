@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use super::iter::{is_valid_range, MergeIter};
+use super::{Storage, StorageIter, WriteBatch};
+use crate::error::StateError;
+
+/// Storage view over a borrowed base storage.
+///
+/// Reads fall through to the base; writes are buffered in the view and never
+/// reach the base, so `commit()` always fails. Used by
+/// [`StateOverlay`](crate::StateOverlay), whose changes are carried back to the
+/// base state as a [`StateDiff`](crate::StateDiff) instead.
+#[derive(Debug)]
+pub struct OverlayStorage<'a, S: Storage> {
+    base: &'a S,
+    pending_writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'a, S: Storage> OverlayStorage<'a, S> {
+    pub fn new(base: &'a S) -> Self {
+        OverlayStorage {
+            base,
+            pending_writes: BTreeMap::new(),
+        }
+    }
+}
+
+impl<S: Storage> Storage for OverlayStorage<'_, S> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(pending) = self.pending_writes.get(key) {
+            return pending.clone();
+        }
+        self.base.get(key)
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.pending_writes
+            .insert(key.to_vec(), Some(value.to_vec()));
+    }
+
+    fn delete(&mut self, key: &[u8]) {
+        self.pending_writes.insert(key.to_vec(), None);
+    }
+
+    fn commit(&mut self) -> Result<(), StateError> {
+        Err(StateError::Storage(
+            "Overlay storage cannot be committed".to_string(),
+        ))
+    }
+
+    fn rollback(&mut self) {
+        self.pending_writes.clear();
+    }
+
+    fn write_batch(&mut self, _batch: WriteBatch) -> Result<(), StateError> {
+        self.commit()
+    }
+
+    fn iter_range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> StorageIter<'_> {
+        if !is_valid_range(start, end) {
+            return Box::new(std::iter::empty());
+        }
+        let base = self.base.iter_range(start, end);
+        Box::new(MergeIter::new(base, &self.pending_writes, start, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_writes_stay_in_overlay() {
+        let mut base = MemoryStorage::new();
+        base.put(b"key:a", b"1");
+        base.put(b"key:b", b"2");
+        base.commit().unwrap();
+
+        let mut overlay = OverlayStorage::new(&base);
+        overlay.put(b"key:c", b"3");
+        overlay.delete(b"key:a");
+
+        assert_eq!(overlay.get(b"key:a"), None);
+        assert_eq!(overlay.get(b"key:b"), Some(b"2".to_vec()));
+        let mut keys = overlay.keys_with_prefix(b"key:");
+        keys.sort();
+        assert_eq!(keys, vec![b"key:b".to_vec(), b"key:c".to_vec()]);
+        assert!(overlay.commit().is_err());
+
+        assert_eq!(base.get(b"key:a"), Some(b"1".to_vec()));
+        assert_eq!(base.get(b"key:c"), None);
+    }
+}
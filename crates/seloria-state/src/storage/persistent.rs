@@ -1,24 +1,40 @@
 use std::collections::BTreeMap;
-use std::fs;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::ops::Bound;
 use std::path::{Path, PathBuf};
 
 use seloria_core::serialize;
+use tracing::{debug, warn};
 
-use super::Storage;
+use super::iter::merged_range;
+use super::{Storage, StorageIter};
 use crate::error::StateError;
 
-/// File-backed storage using a single snapshot file.
+/// A committed batch of writes (`None` deletes the key)
+type Batch = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// File-backed storage using a snapshot file plus an append-only delta log.
+///
+/// Each `commit()` appends only the changed keys to `<path>.log`. Once the log
+/// grows past half the size of the snapshot, it is folded into a freshly
+/// written snapshot and removed, so rewrite cost stays proportional to the
+/// amount of data changed.
 #[derive(Debug, Clone)]
 pub struct FileStorage {
     path: PathBuf,
     data: BTreeMap<Vec<u8>, Vec<u8>>,
-    pending_writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    pending_writes: Batch,
+    /// Size of the snapshot file on disk
+    snapshot_len: u64,
+    /// Size of the delta log on disk
+    log_len: u64,
 }
 
 impl FileStorage {
     pub fn new<P: Into<PathBuf>>(path: P) -> Result<Self, StateError> {
         let path = path.into();
-        let data = if path.exists() {
+        let mut data = if path.exists() {
             let bytes = fs::read(&path).map_err(|e| StateError::Storage(e.to_string()))?;
             if bytes.is_empty() {
                 BTreeMap::new()
@@ -29,11 +45,15 @@ impl FileStorage {
         } else {
             BTreeMap::new()
         };
+        let snapshot_len = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        let log_len = replay_log(&Self::log_path_for(&path), &mut data)?;
 
         Ok(FileStorage {
             path,
             data,
             pending_writes: BTreeMap::new(),
+            snapshot_len,
+            log_len,
         })
     }
 
@@ -41,7 +61,14 @@ impl FileStorage {
         &self.path
     }
 
-    fn flush_to_disk(&self) -> Result<(), StateError> {
+    /// Path of the delta log that belongs to the snapshot at `path`
+    pub fn log_path_for(path: &Path) -> PathBuf {
+        let mut log = path.as_os_str().to_owned();
+        log.push(".log");
+        PathBuf::from(log)
+    }
+
+    fn flush_to_disk(&mut self) -> Result<(), StateError> {
         if let Some(parent) = self.path.parent() {
             fs::create_dir_all(parent).map_err(|e| StateError::Storage(e.to_string()))?;
         }
@@ -51,10 +78,97 @@ impl FileStorage {
         let tmp_path = self.path.with_extension("tmp");
         fs::write(&tmp_path, &bytes).map_err(|e| StateError::Storage(e.to_string()))?;
         fs::rename(&tmp_path, &self.path).map_err(|e| StateError::Storage(e.to_string()))?;
+        self.snapshot_len = bytes.len() as u64;
+
+        // The snapshot now contains every logged batch
+        let log_path = Self::log_path_for(&self.path);
+        if log_path.exists() {
+            fs::remove_file(&log_path).map_err(|e| StateError::Storage(e.to_string()))?;
+        }
+        self.log_len = 0;
+        Ok(())
+    }
+
+    /// Append a committed batch to the delta log
+    fn append_to_log(&mut self, batch: &Batch) -> Result<(), StateError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| StateError::Storage(e.to_string()))?;
+        }
+
+        let bytes =
+            serialize::to_bytes(batch).map_err(|e| StateError::Serialization(e.to_string()))?;
+        let mut record = Vec::with_capacity(4 + bytes.len());
+        record.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        record.extend_from_slice(&bytes);
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::log_path_for(&self.path))
+            .map_err(|e| StateError::Storage(e.to_string()))?;
+        file.write_all(&record)
+            .map_err(|e| StateError::Storage(e.to_string()))?;
+        self.log_len += record.len() as u64;
         Ok(())
     }
 }
 
+/// Apply every complete batch in the delta log to `data` and return the log
+/// length. A torn trailing record (from a crash mid-append) is truncated.
+pub(crate) fn replay_log(
+    log_path: &Path,
+    data: &mut BTreeMap<Vec<u8>, Vec<u8>>,
+) -> Result<u64, StateError> {
+    if !log_path.exists() {
+        return Ok(0);
+    }
+    let bytes = fs::read(log_path).map_err(|e| StateError::Storage(e.to_string()))?;
+
+    let mut offset = 0usize;
+    let mut batches = 0usize;
+    while offset + 4 <= bytes.len() {
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&bytes[offset..offset + 4]);
+        let end = offset + 4 + u32::from_le_bytes(len) as usize;
+        if end > bytes.len() {
+            break;
+        }
+        let batch: Batch = match serialize::from_bytes(&bytes[offset + 4..end]) {
+            Ok(batch) => batch,
+            Err(_) => break,
+        };
+        for (key, value) in batch {
+            match value {
+                Some(v) => {
+                    data.insert(key, v);
+                }
+                None => {
+                    data.remove(&key);
+                }
+            }
+        }
+        offset = end;
+        batches += 1;
+    }
+
+    if offset < bytes.len() {
+        warn!(
+            "Truncating {} bytes of incomplete data from {}",
+            bytes.len() - offset,
+            log_path.display()
+        );
+        let file = OpenOptions::new()
+            .write(true)
+            .open(log_path)
+            .map_err(|e| StateError::Storage(e.to_string()))?;
+        file.set_len(offset as u64)
+            .map_err(|e| StateError::Storage(e.to_string()))?;
+    }
+
+    debug!("Replayed {} batches from {}", batches, log_path.display());
+    Ok(offset as u64)
+}
+
 impl Storage for FileStorage {
     fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
         if let Some(pending) = self.pending_writes.get(key) {
@@ -74,6 +188,10 @@ impl Storage for FileStorage {
 
     fn commit(&mut self) -> Result<(), StateError> {
         let pending = std::mem::take(&mut self.pending_writes);
+        if pending.is_empty() {
+            return Ok(());
+        }
+        self.append_to_log(&pending)?;
         for (key, value) in pending {
             match value {
                 Some(v) => {
@@ -84,7 +202,9 @@ impl Storage for FileStorage {
                 }
             }
         }
-        self.flush_to_disk()?;
+        if self.log_len * 2 > self.snapshot_len {
+            self.flush_to_disk()?;
+        }
         Ok(())
     }
 
@@ -92,27 +212,72 @@ impl Storage for FileStorage {
         self.pending_writes.clear();
     }
 
-    fn keys_with_prefix(&self, prefix: &[u8]) -> Vec<Vec<u8>> {
-        let mut keys = Vec::new();
-
-        for key in self.data.keys() {
-            if key.starts_with(prefix) {
-                if let Some(pending) = self.pending_writes.get(key) {
-                    if pending.is_some() {
-                        keys.push(key.clone());
-                    }
-                } else {
-                    keys.push(key.clone());
-                }
-            }
+    fn compact(&mut self) -> Result<(), StateError> {
+        if self.log_len > 0 || !self.path.exists() {
+            self.flush_to_disk()?;
         }
+        Ok(())
+    }
 
-        for (key, value) in &self.pending_writes {
-            if key.starts_with(prefix) && value.is_some() && !self.data.contains_key(key) {
-                keys.push(key.clone());
-            }
+    fn iter_range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> StorageIter<'_> {
+        merged_range(&self.data, &self.pending_writes, start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_incremental_commits_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.bin");
+
+        let mut storage = FileStorage::new(&path).unwrap();
+        for i in 0..64u32 {
+            storage.put(format!("key:{:03}", i).as_bytes(), &[0u8; 64]);
         }
+        storage.commit().unwrap();
+        let snapshot_len = fs::metadata(&path).unwrap().len();
+
+        // A small change is appended to the log, not rewritten into the snapshot
+        storage.put(b"key:000", b"updated");
+        storage.delete(b"key:001");
+        storage.commit().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), snapshot_len);
+        assert!(FileStorage::log_path_for(&path).exists());
+
+        let reopened = FileStorage::new(&path).unwrap();
+        assert_eq!(reopened.get(b"key:000"), Some(b"updated".to_vec()));
+        assert_eq!(reopened.get(b"key:001"), None);
+        assert_eq!(reopened.keys_with_prefix(b"key:").len(), 63);
+
+        // Compaction folds the log into the snapshot
+        storage.compact().unwrap();
+        assert!(!FileStorage::log_path_for(&path).exists());
+        let reopened = FileStorage::new(&path).unwrap();
+        assert_eq!(reopened.get(b"key:000"), Some(b"updated".to_vec()));
+    }
+
+    #[test]
+    fn test_torn_log_record_is_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.bin");
+
+        let mut storage = FileStorage::new(&path).unwrap();
+        storage.put(b"base", &[0u8; 256]);
+        storage.commit().unwrap();
+        storage.put(b"a", b"1");
+        storage.commit().unwrap();
+
+        // Simulate a crash halfway through appending the next batch
+        let log_path = FileStorage::log_path_for(&path);
+        let good_len = fs::metadata(&log_path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&log_path).unwrap();
+        file.write_all(&[42, 0, 0, 0, 1, 2]).unwrap();
 
-        keys
+        let reopened = FileStorage::new(&path).unwrap();
+        assert_eq!(reopened.get(b"a"), Some(b"1".to_vec()));
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), good_len);
     }
 }
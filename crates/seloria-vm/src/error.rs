@@ -5,6 +5,12 @@ pub enum VmError {
     #[error("Invalid signature")]
     InvalidSignature,
 
+    #[error("Wrong chain ID: expected {expected}, got {got}")]
+    WrongChainId { expected: u64, got: u64 },
+
+    #[error("Transaction expired")]
+    TransactionExpired,
+
     #[error("Invalid nonce: expected {expected}, got {got}")]
     InvalidNonce { expected: u64, got: u64 },
 
@@ -59,6 +65,15 @@ pub enum VmError {
     #[error("Key not found: {0}")]
     KeyNotFound(String),
 
+    #[error("Invalid evidence: {0}")]
+    InvalidEvidence(String),
+
+    #[error("Validator not found: {0}")]
+    ValidatorNotFound(String),
+
+    #[error("Validator bond of {bond} is below the minimum of {min}")]
+    BondBelowMinimum { bond: u64, min: u64 },
+
     #[error("Invalid operation: {0}")]
     InvalidOperation(String),
 
@@ -68,3 +83,41 @@ pub enum VmError {
     #[error("Core error: {0}")]
     Core(#[from] seloria_core::CoreError),
 }
+
+impl VmError {
+    /// Stable machine-readable code recorded in transaction receipts
+    pub fn code(&self) -> &'static str {
+        match self {
+            VmError::InvalidSignature => "invalid_signature",
+            VmError::WrongChainId { .. } => "wrong_chain_id",
+            VmError::TransactionExpired => "tx_expired",
+            VmError::InvalidNonce { .. } => "invalid_nonce",
+            VmError::InsufficientBalance { .. } => "insufficient_balance",
+            VmError::AgentNotCertified => "agent_not_certified",
+            VmError::MissingCapability(_) => "missing_capability",
+            VmError::IssuerNotTrusted(_) => "issuer_not_trusted",
+            VmError::ClaimNotFound(_) => "claim_not_found",
+            VmError::ClaimAlreadyFinalized => "claim_already_finalized",
+            VmError::AlreadyAttested => "already_attested",
+            VmError::NamespaceNotFound(_) => "namespace_not_found",
+            VmError::NamespaceExists(_) => "namespace_exists",
+            VmError::NamespaceUnauthorized => "namespace_unauthorized",
+            VmError::AppExists(_) => "app_exists",
+            VmError::TokenNotFound(_) => "token_not_found",
+            VmError::TokenExists(_) => "token_exists",
+            VmError::PoolNotFound(_) => "pool_not_found",
+            VmError::PoolExists(_) => "pool_exists",
+            VmError::SlippageExceeded => "slippage_exceeded",
+            VmError::KeyNotFound(_) => "key_not_found",
+            VmError::InvalidEvidence(_) => "invalid_evidence",
+            VmError::ValidatorNotFound(_) => "validator_not_found",
+            VmError::BondBelowMinimum { .. } => "bond_below_minimum",
+            VmError::InvalidOperation(_) => "invalid_operation",
+            VmError::State(seloria_state::StateError::InsufficientBalance { .. }) => {
+                "insufficient_balance"
+            }
+            VmError::State(_) => "state_error",
+            VmError::Core(_) => "core_error",
+        }
+    }
+}
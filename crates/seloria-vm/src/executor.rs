@@ -1,4 +1,5 @@
-use seloria_core::{AppMeta, Hash, Op, Transaction};
+pub use seloria_core::ExecutionEvent;
+use seloria_core::{AppMeta, Hash, Op, Transaction, TxReceipt};
 use seloria_state::{ChainState, Storage};
 use tracing::{debug, error, info};
 
@@ -6,7 +7,9 @@ use crate::error::VmError;
 use crate::opcodes::{
     execute_agent_cert_register, execute_attest, execute_claim_create, execute_kv_append,
     execute_kv_del, execute_kv_put, execute_namespace_create, execute_pool_add, execute_pool_create,
-    execute_pool_remove, execute_swap, execute_token_create, execute_token_transfer, execute_transfer,
+    execute_pool_remove, execute_submit_evidence, execute_swap, execute_token_create,
+    execute_token_transfer, execute_transfer, execute_update_validator_set, execute_validator_bond,
+    execute_validator_unbond,
 };
 use crate::validation::validate_transaction;
 
@@ -17,92 +20,45 @@ pub struct ExecutionResult {
     pub tx_hash: Hash,
     /// Whether execution succeeded
     pub success: bool,
+    /// Whether the transaction passed validation and was charged its fee.
+    /// A valid transaction may still fail; it can then be included in a
+    /// block with a failed receipt.
+    pub valid: bool,
+    /// Stable error code if failed
+    pub error_code: Option<String>,
     /// Error message if failed
     pub error: Option<String>,
+    /// Fee charged to the sender
+    pub fee_used: u64,
     /// Events generated during execution
     pub events: Vec<ExecutionEvent>,
 }
 
-/// Events emitted during transaction execution
-#[derive(Debug, Clone)]
-pub enum ExecutionEvent {
-    Transfer {
-        from: seloria_core::PublicKey,
-        to: seloria_core::PublicKey,
-        amount: u64,
-    },
-    AgentRegistered {
-        agent_pubkey: seloria_core::PublicKey,
-    },
-    ClaimCreated {
-        claim_id: Hash,
-        claim_type: String,
-        creator: seloria_core::PublicKey,
-        stake: u64,
-    },
-    AttestationAdded {
-        claim_id: Hash,
-        attester: seloria_core::PublicKey,
-        vote: seloria_core::Vote,
-        stake: u64,
-    },
-    ClaimFinalized {
-        claim_id: Hash,
-        status: seloria_core::ClaimStatus,
-        yes_stake: u64,
-        no_stake: u64,
-    },
-    NamespaceCreated {
-        ns_id: Hash,
-        owner: seloria_core::PublicKey,
-    },
-    KvUpdated {
-        ns_id: Hash,
-        key: String,
-    },
-    KvDeleted {
-        ns_id: Hash,
-        key: String,
-    },
-    TokenCreated {
-        token_id: Hash,
-        symbol: String,
-        total_supply: u64,
-        creator: seloria_core::PublicKey,
-    },
-    TokenTransfer {
-        token_id: Hash,
-        from: seloria_core::PublicKey,
-        to: seloria_core::PublicKey,
-        amount: u64,
-    },
-    PoolCreated {
-        pool_id: Hash,
-        token_a: Hash,
-        token_b: Hash,
-    },
-    PoolLiquidityAdded {
-        pool_id: Hash,
-        provider: seloria_core::PublicKey,
-        lp_minted: u64,
-    },
-    PoolLiquidityRemoved {
-        pool_id: Hash,
-        provider: seloria_core::PublicKey,
-        amount_a: u64,
-        amount_b: u64,
-    },
-    SwapExecuted {
-        pool_id: Hash,
-        trader: seloria_core::PublicKey,
-        token_in: Hash,
-        amount_in: u64,
-        token_out: Hash,
-        amount_out: u64,
-    },
-    AppRegistered {
-        app_id: Hash,
-    },
+impl ExecutionResult {
+    /// Result for a transaction rejected before any fee was charged
+    fn rejected(tx_hash: Hash, error: &VmError) -> Self {
+        ExecutionResult {
+            tx_hash,
+            success: false,
+            valid: false,
+            error_code: Some(error.code().to_string()),
+            error: Some(error.to_string()),
+            fee_used: 0,
+            events: vec![],
+        }
+    }
+
+    /// Build the on-chain receipt for this result
+    pub fn to_receipt(&self) -> TxReceipt {
+        TxReceipt {
+            tx_hash: self.tx_hash,
+            success: self.success,
+            error_code: self.error_code.clone(),
+            error: self.error.clone(),
+            fee_used: self.fee_used,
+            events: self.events.clone(),
+        }
+    }
 }
 
 /// Transaction executor
@@ -129,14 +85,7 @@ impl Executor {
     ) -> ExecutionResult {
         let tx_hash = match tx.hash() {
             Ok(h) => h,
-            Err(e) => {
-                return ExecutionResult {
-                    tx_hash: Hash::ZERO,
-                    success: false,
-                    error: Some(format!("Failed to hash transaction: {}", e)),
-                    events: vec![],
-                }
-            }
+            Err(e) => return ExecutionResult::rejected(Hash::ZERO, &VmError::Core(e)),
         };
 
         debug!("Executing transaction {}", tx_hash);
@@ -144,58 +93,53 @@ impl Executor {
         // Validate transaction
         let validation = validate_transaction(tx, state, self.current_time);
         if !validation.is_valid {
-            let error_msg = validation
-                .error
-                .map(|e| e.to_string())
-                .unwrap_or_else(|| "Unknown validation error".to_string());
-            error!("Transaction {} validation failed: {}", tx_hash, error_msg);
-            return ExecutionResult {
-                tx_hash,
-                success: false,
-                error: Some(error_msg),
-                events: vec![],
-            };
+            let error = validation.error.unwrap_or_else(|| {
+                VmError::InvalidOperation("Unknown validation error".to_string())
+            });
+            error!("Transaction {} validation failed: {}", tx_hash, error);
+            return ExecutionResult::rejected(tx_hash, &error);
         }
 
         // Deduct fee
         if let Err(e) = state.deduct_fee(&tx.sender_pubkey, tx.fee) {
-            return ExecutionResult {
-                tx_hash,
-                success: false,
-                error: Some(format!("Failed to deduct fee: {}", e)),
-                events: vec![],
-            };
+            return ExecutionResult::rejected(tx_hash, &VmError::State(e));
         }
         state.distribute_fee_to_validators(tx.fee);
 
-        // Execute operations
+        // The fee and nonce are consumed whether or not the operations succeed
+        state.increment_nonce(&tx.sender_pubkey);
+
+        // Execute operations atomically: a failing op reverts every earlier op
+        let checkpoint = state.checkpoint();
         let mut events = Vec::new();
 
         for op in &tx.ops {
-            match self.execute_op(op, &tx.sender_pubkey, state, &mut events) {
-                Ok(()) => {}
-                Err(e) => {
-                    error!("Operation execution failed: {}", e);
-                    // Rollback would happen here in a real implementation
-                    return ExecutionResult {
-                        tx_hash,
-                        success: false,
-                        error: Some(e.to_string()),
-                        events,
-                    };
-                }
+            if let Err(e) = self.execute_op(op, &tx.sender_pubkey, state, &mut events) {
+                error!("Operation execution failed: {}", e);
+                state.revert_to_checkpoint(checkpoint);
+                return ExecutionResult {
+                    tx_hash,
+                    success: false,
+                    valid: true,
+                    error_code: Some(e.code().to_string()),
+                    error: Some(e.to_string()),
+                    fee_used: tx.fee,
+                    events: vec![],
+                };
             }
         }
 
-        // Increment nonce
-        state.increment_nonce(&tx.sender_pubkey);
+        state.commit_checkpoint(checkpoint);
 
         info!("Transaction {} executed successfully", tx_hash);
 
         ExecutionResult {
             tx_hash,
             success: true,
+            valid: true,
+            error_code: None,
             error: None,
+            fee_used: tx.fee,
             events,
         }
     }
@@ -462,6 +406,44 @@ impl Executor {
                     amount_out,
                 });
             }
+
+            Op::SubmitEvidence { evidence } => {
+                let (validator, slashed, reward) =
+                    execute_submit_evidence(state, sender, evidence, self.current_height)?;
+                events.push(ExecutionEvent::ValidatorSlashed {
+                    validator,
+                    reporter: *sender,
+                    height: evidence.height(),
+                    slashed,
+                    reward,
+                });
+            }
+
+            Op::ValidatorBond { amount } => {
+                execute_validator_bond(state, sender, *amount)?;
+                events.push(ExecutionEvent::ValidatorBonded {
+                    validator: *sender,
+                    amount: *amount,
+                });
+            }
+
+            Op::ValidatorUnbond { amount } => {
+                let release_height =
+                    execute_validator_unbond(state, sender, *amount, self.current_height)?;
+                events.push(ExecutionEvent::ValidatorUnbonding {
+                    validator: *sender,
+                    amount: *amount,
+                    release_height,
+                });
+            }
+
+            Op::UpdateValidatorSet { update } => {
+                execute_update_validator_set(state, update, self.current_height)?;
+                events.push(ExecutionEvent::ValidatorSetScheduled {
+                    epoch: update.epoch,
+                    validators: update.validators.len(),
+                });
+            }
         }
 
         Ok(())
@@ -499,6 +481,7 @@ mod tests {
             initial_balances: vec![(agent.public, 1_000_000)],
             trusted_issuers: vec![issuer.public],
             validators: vec![],
+            validator_stake: 0,
         };
         state.init_genesis(&config).unwrap();
 
@@ -516,7 +499,7 @@ mod tests {
             ],
             Hash::ZERO,
         );
-        let signed_cert = SignedAgentCertificate::new(cert, &issuer.secret).unwrap();
+        let signed_cert = SignedAgentCertificate::new(cert, 1, &issuer.secret).unwrap();
         state.register_agent(signed_cert);
 
         (state, issuer, agent)
@@ -529,6 +512,7 @@ mod tests {
         let executor = Executor::new(100, 1);
 
         let tx = Transaction::new_signed(
+            1,
             agent.public,
             1,
             100,
@@ -564,7 +548,7 @@ mod tests {
             Hash::ZERO,
         );
         let signed_attester_cert =
-            SignedAgentCertificate::new(attester_cert, &issuer.secret).unwrap();
+            SignedAgentCertificate::new(attester_cert, 1, &issuer.secret).unwrap();
         state.register_agent(signed_attester_cert);
 
         let executor = Executor::new(100, 1);
@@ -572,6 +556,7 @@ mod tests {
         // Create claim
         let payload_hash = hash_blake3(b"test claim payload");
         let tx_create = Transaction::new_signed(
+            1,
             agent.public,
             1,
             100,
@@ -595,6 +580,7 @@ mod tests {
         // Attest with enough to finalize
         let executor2 = Executor::new(100, 2);
         let tx_attest = Transaction::new_signed(
+            1,
             attester.public,
             1,
             100,
@@ -615,6 +601,46 @@ mod tests {
             .any(|e| matches!(e, ExecutionEvent::ClaimFinalized { .. })));
     }
 
+    #[test]
+    fn test_failed_op_reverts_transaction() {
+        let (mut state, _, agent) = setup_state_and_agent();
+        let receiver = KeyPair::generate();
+        let executor = Executor::new(100, 1);
+
+        // The transfer succeeds, then the KV write fails on a missing namespace
+        let tx = Transaction::new_signed(
+            1,
+            agent.public,
+            1,
+            100,
+            vec![
+                Op::Transfer {
+                    to: receiver.public,
+                    amount: 1000,
+                },
+                Op::KvPut {
+                    ns_id: hash_blake3(b"missing"),
+                    key: "key".to_string(),
+                    value: seloria_core::KvValue::inline("raw", b"value".to_vec()),
+                },
+            ],
+            &agent.secret,
+        )
+        .unwrap();
+
+        let result = executor.execute_transaction(&tx, &mut state);
+
+        assert!(!result.success);
+        assert!(result.valid);
+        assert_eq!(result.error_code.as_deref(), Some("namespace_not_found"));
+        assert_eq!(result.fee_used, 100);
+        assert!(result.events.is_empty());
+        assert!(state.get_account(&receiver.public).is_none());
+        // Fee is still charged and the nonce consumed
+        assert_eq!(state.get_balance(&agent.public), 1_000_000 - 100);
+        assert_eq!(state.get_account(&agent.public).unwrap().nonce, 1);
+    }
+
     #[test]
     fn test_execute_invalid_nonce() {
         let (mut state, _, agent) = setup_state_and_agent();
@@ -622,6 +648,7 @@ mod tests {
         let executor = Executor::new(100, 1);
 
         let tx = Transaction::new_signed(
+            1,
             agent.public,
             5, // Wrong nonce
             100,
@@ -636,6 +663,8 @@ mod tests {
         let result = executor.execute_transaction(&tx, &mut state);
 
         assert!(!result.success);
+        assert!(!result.valid);
+        assert_eq!(result.fee_used, 0);
         assert!(result.error.unwrap().contains("Invalid nonce"));
     }
 }
@@ -21,8 +21,8 @@ pub fn execute_agent_cert_register<S: Storage>(
     // For now, we check if there's a trusted issuer whose pubkey hashes to issuer_id
     let issuer_pubkey = find_issuer_by_id(state, &cert.cert.issuer_id)?;
 
-    // 2. Verify the issuer signature
-    cert.verify_signature(&issuer_pubkey)?;
+    // 2. Verify the issuer signature for this chain
+    cert.verify_signature(&issuer_pubkey, state.chain_id)?;
 
     // 3. Check issuer is trusted
     if !state.is_trusted_issuer(&issuer_pubkey) {
@@ -86,7 +86,7 @@ mod tests {
             vec![Capability::TxSubmit],
             Hash::ZERO,
         );
-        let signed_cert = SignedAgentCertificate::new(cert, &issuer.secret).unwrap();
+        let signed_cert = SignedAgentCertificate::new(cert, state.chain_id, &issuer.secret).unwrap();
 
         // Register
         execute_agent_cert_register(&mut state, &agent.public, &signed_cert, 500).unwrap();
@@ -113,7 +113,7 @@ mod tests {
             vec![Capability::TxSubmit],
             Hash::ZERO,
         );
-        let signed_cert = SignedAgentCertificate::new(cert, &untrusted_issuer.secret).unwrap();
+        let signed_cert = SignedAgentCertificate::new(cert, state.chain_id, &untrusted_issuer.secret).unwrap();
 
         let result = execute_agent_cert_register(&mut state, &agent.public, &signed_cert, 500);
         assert!(matches!(result, Err(VmError::IssuerNotTrusted(_))));
@@ -136,7 +136,7 @@ mod tests {
             vec![Capability::TxSubmit],
             Hash::ZERO,
         );
-        let signed_cert = SignedAgentCertificate::new(cert, &issuer.secret).unwrap();
+        let signed_cert = SignedAgentCertificate::new(cert, state.chain_id, &issuer.secret).unwrap();
 
         let result = execute_agent_cert_register(&mut state, &agent.public, &signed_cert, 500); // Current time 500
         assert!(matches!(result, Err(VmError::InvalidOperation(_))));
@@ -90,7 +90,6 @@ pub fn execute_pool_add<S: Storage>(
 
     let pool = state
         .get_pool(pool_id)
-        .cloned()
         .ok_or_else(|| VmError::PoolNotFound(pool_id.to_hex()))?;
     if pool.lp_supply == 0 || pool.reserve_a == 0 || pool.reserve_b == 0 {
         return Err(VmError::InvalidOperation("Pool has zero liquidity".to_string()));
@@ -154,7 +153,6 @@ pub fn execute_pool_remove<S: Storage>(
 
     let pool = state
         .get_pool(pool_id)
-        .cloned()
         .ok_or_else(|| VmError::PoolNotFound(pool_id.to_hex()))?;
     if pool.lp_supply == 0 || pool.reserve_a == 0 || pool.reserve_b == 0 {
         return Err(VmError::InvalidOperation("Pool has zero liquidity".to_string()));
@@ -210,7 +208,6 @@ pub fn execute_swap<S: Storage>(
 
     let pool = state
         .get_pool(pool_id)
-        .cloned()
         .ok_or_else(|| VmError::PoolNotFound(pool_id.to_hex()))?;
 
     let (reserve_in, reserve_out, token_out) = if *token_in == pool.token_a {
@@ -0,0 +1,254 @@
+use seloria_core::{
+    validator_bond_lock, validator_unbonding_lock, Evidence, PublicKey,
+    EQUIVOCATION_SLASH_PERCENTAGE, EVIDENCE_REWARD_PERCENTAGE, NATIVE_TOKEN_ID,
+    UNBONDING_DELAY_BLOCKS,
+};
+use seloria_state::{ChainState, Storage};
+use tracing::{info, warn};
+
+use crate::error::VmError;
+
+/// Number of blocks after an equivocation during which evidence of it is
+/// accepted
+pub const MAX_EVIDENCE_AGE_BLOCKS: u64 = 100_000;
+
+/// Execute SUBMIT_EVIDENCE operation: slash the offending validator's
+/// bonded and unbonding stake, paying a share of it to the submitter and
+/// burning the rest, remove the validator from the set (unless it is the
+/// last one) and unbond what is left of its bond. Returns the offender, the
+/// amount slashed and the submitter's reward.
+pub fn execute_submit_evidence<S: Storage>(
+    state: &mut ChainState<S>,
+    sender: &PublicKey,
+    evidence: &Evidence,
+    block_height: u64,
+) -> Result<(PublicKey, u64, u64), VmError> {
+    evidence
+        .verify(state.chain_id)
+        .map_err(|e| VmError::InvalidEvidence(e.to_string()))?;
+
+    let height = evidence.height();
+    if height > block_height {
+        return Err(VmError::InvalidEvidence(format!(
+            "Evidence is for future height {}",
+            height
+        )));
+    }
+    if block_height - height > MAX_EVIDENCE_AGE_BLOCKS {
+        return Err(VmError::InvalidEvidence(format!(
+            "Evidence from height {} has expired",
+            height
+        )));
+    }
+
+    // A validator that left the set can still be slashed while its stake
+    // is unbonding
+    let offender = evidence.offender();
+    let (bond_lock, unbonding_lock) = (validator_bond_lock(), validator_unbonding_lock());
+    let (bonded, unbonding) = state.get_account(&offender).map_or((0, 0), |account| {
+        (account.get_locked(&bond_lock), account.get_locked(&unbonding_lock))
+    });
+    let is_member = state.validators.contains(&offender);
+    if !is_member && bonded == 0 && unbonding == 0 {
+        return Err(VmError::ValidatorNotFound(offender.to_hex()));
+    }
+    // Each slashing covers every equivocation up to its height, so the same
+    // evidence cannot be used twice
+    if let Some(slashed_at) = state.staking.last_slashed.get(&offender) {
+        if height <= *slashed_at {
+            return Err(VmError::InvalidEvidence(format!(
+                "Validator was already slashed for height {}",
+                slashed_at
+            )));
+        }
+    }
+
+    let account = state.get_or_create_account(&offender);
+    let slashed = account.slash_locked(&bond_lock, share(bonded, EQUIVOCATION_SLASH_PERCENTAGE))
+        + account.slash_locked(&unbonding_lock, share(unbonding, EQUIVOCATION_SLASH_PERCENTAGE));
+    let reward = share(slashed, EVIDENCE_REWARD_PERCENTAGE);
+    state.credit_token(sender, &NATIVE_TOKEN_ID, reward);
+    // The chain cannot run without validators, so the last one is slashed
+    // but stays in the set
+    if is_member && state.validators.len() == 1 {
+        warn!("Keeping {} as the last validator after slashing it", offender);
+    } else {
+        state.remove_validator(&offender);
+    }
+    let remaining = state.validator_bond(&offender);
+    if remaining > 0 {
+        state.unbond_validator(&offender, remaining, block_height + UNBONDING_DELAY_BLOCKS)?;
+    }
+    state.record_slash(&offender, height);
+
+    info!(
+        "Validator {} equivocated at height {}: slashed {} of {} staked",
+        offender,
+        height,
+        slashed,
+        bonded.saturating_add(unbonding)
+    );
+
+    Ok((offender, slashed, reward))
+}
+
+/// `percentage` percent of `amount`
+fn share(amount: u64, percentage: u64) -> u64 {
+    // Widened so that amounts near u64::MAX do not overflow
+    (amount as u128 * percentage as u128 / 100) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seloria_core::testing::{self, vote};
+    use seloria_core::{BlockHeader, KeyPair, VotePhase};
+    use seloria_state::MemoryStorage;
+
+    fn header(height: u64, timestamp: u64) -> BlockHeader {
+        BlockHeader {
+            timestamp,
+            ..testing::header(0, height)
+        }
+    }
+
+    fn equivocation(validator: &KeyPair, height: u64) -> Evidence {
+        let (a, b) = (header(height, 1), header(height, 2));
+        let vote_a = vote(validator, VotePhase::Commit, &a);
+        let vote_b = vote(validator, VotePhase::Commit, &b);
+        Evidence::new(VotePhase::Commit, a, vote_a, b, vote_b).unwrap()
+    }
+
+    #[test]
+    fn test_submit_evidence_slashes_and_removes_validator() {
+        let mut state = ChainState::new(MemoryStorage::new());
+        let offender = KeyPair::generate();
+        let honest = KeyPair::generate();
+        let reporter = KeyPair::generate();
+        state.validators = vec![offender.public, honest.public];
+        state.credit_token(&offender.public, &NATIVE_TOKEN_ID, 1000);
+        state
+            .lock_stake(&offender.public, validator_bond_lock(), 1000)
+            .unwrap();
+
+        let evidence = equivocation(&offender, 3);
+        let (slashed_validator, slashed, reward) =
+            execute_submit_evidence(&mut state, &reporter.public, &evidence, 4).unwrap();
+
+        assert_eq!(slashed_validator, offender.public);
+        assert_eq!((slashed, reward), (200, 20));
+        assert_eq!(state.get_balance(&reporter.public), 20);
+        // The rest of the bond is unbonding and stays slashable
+        let account = state.get_account(&offender.public).unwrap();
+        assert_eq!(account.get_locked(&validator_bond_lock()), 0);
+        assert_eq!(account.get_locked(&validator_unbonding_lock()), 800);
+        assert_eq!(state.validators, vec![honest.public]);
+
+        // The evidence cannot be used twice...
+        let result = execute_submit_evidence(&mut state, &reporter.public, &evidence, 4);
+        assert!(matches!(result, Err(VmError::InvalidEvidence(_))));
+
+        // ...but a later equivocation slashes the unbonding stake
+        let later = equivocation(&offender, 4);
+        let (_, slashed, _) =
+            execute_submit_evidence(&mut state, &reporter.public, &later, 5).unwrap();
+        assert_eq!(slashed, 160);
+
+        // Validators without stake that left the set cannot be slashed
+        let outsider = KeyPair::generate();
+        let evidence = equivocation(&outsider, 4);
+        let result = execute_submit_evidence(&mut state, &reporter.public, &evidence, 5);
+        assert!(matches!(result, Err(VmError::ValidatorNotFound(_))));
+    }
+
+    #[test]
+    fn test_slashing_a_large_bond_does_not_overflow() {
+        let mut state = ChainState::new(MemoryStorage::new());
+        let offender = KeyPair::generate();
+        let reporter = KeyPair::generate();
+        state.validators = vec![offender.public, KeyPair::generate().public];
+        state.credit_token(&offender.public, &NATIVE_TOKEN_ID, u64::MAX);
+        state
+            .lock_stake(&offender.public, validator_bond_lock(), u64::MAX)
+            .unwrap();
+
+        let evidence = equivocation(&offender, 3);
+        let (_, slashed, _) =
+            execute_submit_evidence(&mut state, &reporter.public, &evidence, 4).unwrap();
+        assert_eq!(slashed, u64::MAX / 5);
+    }
+
+    /// Native tokens held by `pubkey`, spendable or staked
+    fn holdings(state: &ChainState<MemoryStorage>, pubkey: &PublicKey) -> u64 {
+        state.get_account(pubkey).map_or(0, |account| {
+            account.balance(&NATIVE_TOKEN_ID)
+                + account.get_locked(&validator_bond_lock())
+                + account.get_locked(&validator_unbonding_lock())
+        })
+    }
+
+    #[test]
+    fn test_reporting_oneself_still_costs_stake() {
+        let mut state = ChainState::new(MemoryStorage::new());
+        let offender = KeyPair::generate();
+        let second_account = KeyPair::generate();
+        state.validators = vec![offender.public, KeyPair::generate().public];
+        state.credit_token(&offender.public, &NATIVE_TOKEN_ID, 1000);
+        state
+            .lock_stake(&offender.public, validator_bond_lock(), 1000)
+            .unwrap();
+
+        let evidence = equivocation(&offender, 3);
+        execute_submit_evidence(&mut state, &second_account.public, &evidence, 4).unwrap();
+        let total = holdings(&state, &offender.public) + holdings(&state, &second_account.public);
+        assert_eq!(total, 1000 - 200 + 20);
+    }
+
+    #[test]
+    fn test_last_validator_is_slashed_but_kept() {
+        let mut state = ChainState::new(MemoryStorage::new());
+        let offender = KeyPair::generate();
+        let reporter = KeyPair::generate();
+        state.validators = vec![offender.public];
+        state.credit_token(&offender.public, &NATIVE_TOKEN_ID, 1000);
+        state
+            .lock_stake(&offender.public, validator_bond_lock(), 1000)
+            .unwrap();
+
+        let evidence = equivocation(&offender, 3);
+        let (_, slashed, _) =
+            execute_submit_evidence(&mut state, &reporter.public, &evidence, 4).unwrap();
+        assert_eq!(slashed, 200);
+        assert_eq!(holdings(&state, &offender.public), 800);
+        assert_eq!(state.validators, vec![offender.public]);
+    }
+
+    #[test]
+    fn test_submit_evidence_rejects_invalid_evidence() {
+        let mut state = ChainState::new(MemoryStorage::new());
+        let offender = KeyPair::generate();
+        let reporter = KeyPair::generate();
+        state.validators = vec![offender.public, KeyPair::generate().public];
+
+        // Evidence from the future or past the age limit
+        let evidence = equivocation(&offender, 10);
+        let result = execute_submit_evidence(&mut state, &reporter.public, &evidence, 9);
+        assert!(matches!(result, Err(VmError::InvalidEvidence(_))));
+        let result = execute_submit_evidence(
+            &mut state,
+            &reporter.public,
+            &evidence,
+            10 + MAX_EVIDENCE_AGE_BLOCKS + 1,
+        );
+        assert!(matches!(result, Err(VmError::InvalidEvidence(_))));
+
+        // Two votes for the same block
+        let mut same_block = evidence.clone();
+        same_block.header_b = same_block.header_a.clone();
+        same_block.vote_b = same_block.vote_a.clone();
+        let result = execute_submit_evidence(&mut state, &reporter.public, &same_block, 10);
+        assert!(matches!(result, Err(VmError::InvalidEvidence(_))));
+
+        assert_eq!(state.validators.len(), 2);
+    }
+}
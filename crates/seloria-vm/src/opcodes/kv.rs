@@ -112,7 +112,7 @@ pub fn execute_kv_append<S: Storage>(
     }
 
     // Get existing value or create empty
-    let existing = state.kv_get(ns_id, key).cloned();
+    let existing = state.kv_get(ns_id, key);
 
     let new_value = match existing {
         Some(existing_val) => {
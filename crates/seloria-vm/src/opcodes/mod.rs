@@ -1,13 +1,19 @@
 pub mod agent_cert;
 pub mod amm;
 pub mod claim;
+pub mod evidence;
 pub mod kv;
+pub mod staking;
 pub mod token;
 pub mod transfer;
 
 pub use agent_cert::execute_agent_cert_register;
 pub use amm::{execute_pool_add, execute_pool_create, execute_pool_remove, execute_swap};
 pub use claim::{execute_attest, execute_claim_create};
+pub use evidence::execute_submit_evidence;
 pub use kv::{execute_kv_append, execute_kv_del, execute_kv_put, execute_namespace_create};
+pub use staking::{
+    execute_update_validator_set, execute_validator_bond, execute_validator_unbond,
+};
 pub use token::{execute_token_create, execute_token_transfer};
 pub use transfer::execute_transfer;
@@ -0,0 +1,177 @@
+use seloria_core::{
+    epoch_of, PublicKey, ValidatorSetUpdate, MIN_VALIDATOR_BOND, UNBONDING_DELAY_BLOCKS,
+};
+use seloria_state::{ChainState, Storage};
+use tracing::{debug, info};
+
+use crate::error::VmError;
+
+/// Execute VALIDATOR_BOND operation: lock `amount` as validator stake. The
+/// total bond must reach [`MIN_VALIDATOR_BOND`]. Returns the total bond.
+pub fn execute_validator_bond<S: Storage>(
+    state: &mut ChainState<S>,
+    sender: &PublicKey,
+    amount: u64,
+) -> Result<u64, VmError> {
+    let bond = state.validator_bond(sender).saturating_add(amount);
+    if bond < MIN_VALIDATOR_BOND {
+        return Err(VmError::BondBelowMinimum {
+            bond,
+            min: MIN_VALIDATOR_BOND,
+        });
+    }
+
+    let balance = state.get_balance(sender);
+    if balance < amount {
+        return Err(VmError::InsufficientBalance {
+            have: balance,
+            need: amount,
+        });
+    }
+    let bond = state.bond_validator(sender, amount)?;
+
+    debug!("Validator {} bonded {} (total {})", sender, amount, bond);
+
+    Ok(bond)
+}
+
+/// Execute VALIDATOR_UNBOND operation: start returning `amount` of the
+/// sender's bond. What remains must be zero or at least
+/// [`MIN_VALIDATOR_BOND`]. Returns the height the stake is released at.
+pub fn execute_validator_unbond<S: Storage>(
+    state: &mut ChainState<S>,
+    sender: &PublicKey,
+    amount: u64,
+    block_height: u64,
+) -> Result<u64, VmError> {
+    let bonded = state.validator_bond(sender);
+    if bonded == 0 {
+        return Err(VmError::ValidatorNotFound(sender.to_hex()));
+    }
+    if amount == 0 || amount > bonded {
+        return Err(VmError::InvalidOperation(format!(
+            "Cannot unbond {} of a {} bond",
+            amount, bonded
+        )));
+    }
+    let remaining = bonded - amount;
+    if remaining > 0 && remaining < MIN_VALIDATOR_BOND {
+        return Err(VmError::BondBelowMinimum {
+            bond: remaining,
+            min: MIN_VALIDATOR_BOND,
+        });
+    }
+
+    let release_height = block_height + UNBONDING_DELAY_BLOCKS;
+    state.unbond_validator(sender, amount, release_height)?;
+
+    debug!(
+        "Validator {} unbonding {} until height {}",
+        sender, amount, release_height
+    );
+
+    Ok(release_height)
+}
+
+/// Execute UPDATE_VALIDATOR_SET operation: schedule a validator set
+/// approved by a quorum of the current validators for a coming epoch. It
+/// replaces any set scheduled before.
+pub fn execute_update_validator_set<S: Storage>(
+    state: &mut ChainState<S>,
+    update: &ValidatorSetUpdate,
+    block_height: u64,
+) -> Result<(), VmError> {
+    let current_epoch = epoch_of(block_height);
+    if update.epoch <= current_epoch {
+        return Err(VmError::InvalidOperation(format!(
+            "Validator set for epoch {} must be for an epoch after {}",
+            update.epoch, current_epoch
+        )));
+    }
+    update.verify(state.chain_id, &state.validator_set())?;
+
+    state.schedule_validator_set(update.epoch, update.validators.clone());
+
+    info!(
+        "Scheduled {} validators for epoch {}",
+        update.validators.len(),
+        update.epoch
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seloria_core::{
+        sign, validator_unbonding_lock, KeyPair, ValidatorSet, ValidatorSignature,
+        EPOCH_LENGTH_BLOCKS, NATIVE_TOKEN_ID,
+    };
+    use seloria_state::MemoryStorage;
+
+    #[test]
+    fn test_bond_and_unbond() {
+        let mut state = ChainState::new(MemoryStorage::new());
+        let validator = KeyPair::generate();
+        state.credit_token(&validator.public, &NATIVE_TOKEN_ID, 3 * MIN_VALIDATOR_BOND);
+
+        // A bond below the minimum does not make a candidate
+        let result = execute_validator_bond(&mut state, &validator.public, MIN_VALIDATOR_BOND - 1);
+        assert!(matches!(result, Err(VmError::BondBelowMinimum { .. })));
+
+        let bond =
+            execute_validator_bond(&mut state, &validator.public, 2 * MIN_VALIDATOR_BOND).unwrap();
+        assert_eq!(bond, 2 * MIN_VALIDATOR_BOND);
+        assert_eq!(state.get_balance(&validator.public), MIN_VALIDATOR_BOND);
+
+        // Unbonding may not leave a bond below the minimum
+        let result = execute_validator_unbond(&mut state, &validator.public, bond - 1, 10);
+        assert!(matches!(result, Err(VmError::BondBelowMinimum { .. })));
+
+        let release =
+            execute_validator_unbond(&mut state, &validator.public, MIN_VALIDATOR_BOND, 10)
+                .unwrap();
+        assert_eq!(release, 10 + UNBONDING_DELAY_BLOCKS);
+        assert_eq!(state.validator_bond(&validator.public), MIN_VALIDATOR_BOND);
+        let account = state.get_account(&validator.public).unwrap();
+        assert_eq!(account.get_locked(&validator_unbonding_lock()), MIN_VALIDATOR_BOND);
+
+        state.end_block(release);
+        assert_eq!(state.get_balance(&validator.public), 2 * MIN_VALIDATOR_BOND);
+    }
+
+    #[test]
+    fn test_update_validator_set() {
+        let mut state = ChainState::new(MemoryStorage::new());
+        let validators: Vec<_> = (0..3).map(|_| KeyPair::generate()).collect();
+        state.validators = validators.iter().map(|v| v.public).collect();
+
+        let mut update = ValidatorSetUpdate {
+            epoch: 1,
+            validators: ValidatorSet::equal(&[KeyPair::generate().public]),
+            signatures: Vec::new(),
+        };
+        let message = update.message(state.chain_id).unwrap();
+        update.signatures = validators
+            .iter()
+            .map(|v| ValidatorSignature {
+                validator_pubkey: v.public,
+                signature: sign(&v.secret, &message),
+            })
+            .collect();
+
+        // Only coming epochs can be changed
+        let late = execute_update_validator_set(&mut state, &update, EPOCH_LENGTH_BLOCKS + 1);
+        assert!(matches!(late, Err(VmError::InvalidOperation(_))));
+
+        // Two of three validators are not a quorum
+        let mut partial = update.clone();
+        partial.signatures.pop();
+        assert!(execute_update_validator_set(&mut state, &partial, 5).is_err());
+
+        execute_update_validator_set(&mut state, &update, 5).unwrap();
+        state.end_block(EPOCH_LENGTH_BLOCKS);
+        assert_eq!(state.validator_set(), update.validators);
+    }
+}
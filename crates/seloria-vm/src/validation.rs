@@ -36,12 +36,24 @@ pub fn validate_transaction<S: Storage>(
     state: &ChainState<S>,
     current_time: u64,
 ) -> ValidationResult {
-    // 1. Verify signature
+    // 1. Check the transaction targets this chain, then verify the signature
+    // (which also covers the chain ID)
+    if tx.chain_id != state.chain_id {
+        return ValidationResult::err(VmError::WrongChainId {
+            expected: state.chain_id,
+            got: tx.chain_id,
+        });
+    }
     if let Err(_) = tx.verify_signature() {
         return ValidationResult::err(VmError::InvalidSignature);
     }
 
-    // 2. Check sender is certified agent (unless they're registering a certificate)
+    // 2. Reject transactions past their expiry for the next block
+    if tx.is_expired(state.current_height() + 1, current_time) {
+        return ValidationResult::err(VmError::TransactionExpired);
+    }
+
+    // 3. Check sender is certified agent (unless they're registering a certificate)
     let is_cert_registration = tx.ops.iter().any(|op| {
         matches!(op, seloria_core::Op::AgentCertRegister { .. })
     });
@@ -63,7 +75,7 @@ pub fn validate_transaction<S: Storage>(
         }
     }
 
-    // 3. Verify nonce
+    // 4. Verify nonce
     let account_nonce = state
         .get_account(&tx.sender_pubkey)
         .map_or(0, |a| a.nonce);
@@ -76,7 +88,7 @@ pub fn validate_transaction<S: Storage>(
         });
     }
 
-    // 4. Calculate required balances and check
+    // 5. Calculate required balances and check
     let mut token_spend: BTreeMap<Hash, u64> = BTreeMap::new();
     if tx.fee > 0 {
         *token_spend.entry(NATIVE_TOKEN_ID).or_insert(0) += tx.fee;
@@ -93,6 +105,9 @@ pub fn validate_transaction<S: Storage>(
             Op::Attest { stake, .. } => {
                 *token_spend.entry(NATIVE_TOKEN_ID).or_insert(0) += *stake;
             }
+            Op::ValidatorBond { amount } => {
+                *token_spend.entry(NATIVE_TOKEN_ID).or_insert(0) += *amount;
+            }
             Op::TokenTransfer { token_id, amount, .. } => {
                 if *token_id != NATIVE_TOKEN_ID && state.get_token(token_id).is_none() {
                     return ValidationResult::err(VmError::TokenNotFound(token_id.to_hex()));
@@ -192,6 +207,9 @@ fn get_required_capability(op: &seloria_core::Op) -> Option<Capability> {
         | Op::PoolAdd { .. }
         | Op::PoolRemove { .. }
         | Op::Swap { .. } => Some(Capability::TxSubmit),
+        Op::SubmitEvidence { .. } => Some(Capability::TxSubmit),
+        Op::ValidatorBond { .. } | Op::ValidatorUnbond { .. } => Some(Capability::TxSubmit),
+        Op::UpdateValidatorSet { .. } => Some(Capability::TxSubmit),
     }
 }
 
@@ -206,6 +224,7 @@ mod tests {
 
     fn setup_test_state() -> (ChainState<MemoryStorage>, KeyPair, KeyPair) {
         let mut state = ChainState::new(MemoryStorage::new());
+        state.chain_id = 1;
         let issuer = KeyPair::generate();
         let agent = KeyPair::generate();
 
@@ -229,7 +248,7 @@ mod tests {
             ],
             Hash::ZERO,
         );
-        let signed_cert = SignedAgentCertificate::new(cert, &issuer.secret).unwrap();
+        let signed_cert = SignedAgentCertificate::new(cert, 1, &issuer.secret).unwrap();
         state.register_agent(signed_cert);
 
         (state, issuer, agent)
@@ -241,6 +260,7 @@ mod tests {
         let receiver = KeyPair::generate();
 
         let tx = Transaction::new_signed(
+            1,
             agent.public,
             1,
             100,
@@ -263,6 +283,7 @@ mod tests {
         let receiver = KeyPair::generate();
 
         let tx = Transaction::new_signed(
+            1,
             agent.public,
             1,
             100,
@@ -279,12 +300,63 @@ mod tests {
         assert!(matches!(result.error, Some(VmError::InvalidSignature)));
     }
 
+    #[test]
+    fn test_validate_wrong_chain_id() {
+        let (state, _, agent) = setup_test_state();
+        let receiver = KeyPair::generate();
+
+        let tx = Transaction::new_signed(
+            2, // Signed for a different chain
+            agent.public,
+            1,
+            100,
+            vec![Op::Transfer {
+                to: receiver.public,
+                amount: 500,
+            }],
+            &agent.secret,
+        )
+        .unwrap();
+
+        let result = validate_transaction(&tx, &state, 100);
+        assert!(!result.is_valid);
+        assert!(matches!(
+            result.error,
+            Some(VmError::WrongChainId { expected: 1, got: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_validate_expired_transaction() {
+        let (state, _, agent) = setup_test_state();
+        let receiver = KeyPair::generate();
+        let transfer = vec![Op::Transfer {
+            to: receiver.public,
+            amount: 500,
+        }];
+
+        // The next block is height 1, so a tx valid until height 0 is stale
+        let mut tx = Transaction::new(1, agent.public, 1, 100, transfer.clone())
+            .with_expiry(Some(0), None);
+        tx.sign(&agent.secret).unwrap();
+        let result = validate_transaction(&tx, &state, 100);
+        assert!(matches!(result.error, Some(VmError::TransactionExpired)));
+
+        let mut tx = Transaction::new(1, agent.public, 1, 100, transfer)
+            .with_expiry(Some(1), Some(99));
+        tx.sign(&agent.secret).unwrap();
+        let result = validate_transaction(&tx, &state, 100);
+        assert!(matches!(result.error, Some(VmError::TransactionExpired)));
+        assert!(validate_transaction(&tx, &state, 99).is_valid);
+    }
+
     #[test]
     fn test_validate_invalid_nonce() {
         let (state, _, agent) = setup_test_state();
         let receiver = KeyPair::generate();
 
         let tx = Transaction::new_signed(
+            1,
             agent.public,
             5, // Wrong nonce (should be 1)
             100,
@@ -307,6 +379,7 @@ mod tests {
         let receiver = KeyPair::generate();
 
         let tx = Transaction::new_signed(
+            1,
             agent.public,
             1,
             100,
@@ -328,11 +401,13 @@ mod tests {
 
     #[test]
     fn test_validate_uncertified_agent() {
-        let state = ChainState::new(MemoryStorage::new());
+        let mut state = ChainState::new(MemoryStorage::new());
+        state.chain_id = 1;
         let uncertified = KeyPair::generate();
         let receiver = KeyPair::generate();
 
         let tx = Transaction::new_signed(
+            1,
             uncertified.public,
             1,
             100,